@@ -0,0 +1,59 @@
+use anyhow::Result;
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::time::{timeout, Duration};
+use tracing::debug;
+
+/// How long a clipboard tool gets to take the text; they fork off and return at once
+const COPY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Clipboard tools for this platform, in order of preference, with their arguments
+fn candidates() -> Vec<(&'static str, Vec<&'static str>)> {
+    if cfg!(windows) {
+        vec![("clip", vec![])]
+    } else if cfg!(target_os = "macos") {
+        vec![("pbcopy", vec![])]
+    } else {
+        vec![
+            ("wl-copy", vec![]),
+            ("xclip", vec!["-selection", "clipboard"]),
+            ("xsel", vec!["--clipboard", "--input"]),
+        ]
+    }
+}
+
+/// Put text on the system clipboard using whichever external tool is available
+pub async fn copy(text: &str) -> Result<()> {
+    for (program, args) in candidates() {
+        match timeout(COPY_TIMEOUT, run_tool(program, &args, text)).await {
+            Ok(Ok(())) => {
+                debug!("Copied {} bytes with {}", text.len(), program);
+                return Ok(());
+            }
+            Ok(Err(e)) => debug!("{} failed: {}", program, e),
+            Err(_) => debug!("{} timed out", program),
+        }
+    }
+    
+    let hint = if cfg!(any(windows, target_os = "macos")) { "clipboard tool missing" } else { "install wl-clipboard, xclip or xsel" };
+    anyhow::bail!("Could not copy to the clipboard ({})", hint);
+}
+
+async fn run_tool(program: &str, args: &[&str], text: &str) -> Result<()> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()?;
+    
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(text.as_bytes()).await?;
+    } // Dropping stdin closes it so the tool sees the end of the text
+    
+    let status = child.wait().await?;
+    anyhow::ensure!(status.success(), "exited with {}", status);
+    Ok(())
+}
@@ -0,0 +1,177 @@
+use crate::network::glitch::GlitchFilter;
+use crate::network::hooks::Hooks;
+use crate::network::properties::PropertySync;
+use crate::network::template::LineTemplate;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Settings from `config.toml`, for options you'd otherwise pass on every run
+///
+/// Command-line flags win over anything set here.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub client: ClientConfig,
+}
+
+/// The `[client]` table
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClientConfig {
+    /// Milliseconds between state updates (`--update-interval`)
+    pub update_interval: Option<u64>,
+    /// Where group screenshots are saved (`--screenshot-dir`)
+    pub screenshot_dir: Option<PathBuf>,
+    /// Poll faster after page turns and slower while idle (`--adaptive-updates`)
+    pub adaptive_updates: bool,
+    /// `[client.glitch_filter]`: when to believe large backward jumps (`--no-glitch-filter` turns it off)
+    pub glitch_filter: GlitchFilter,
+    /// `[client.properties]`: MPV properties to share (`--sync-property`) and ones to keep local
+    pub properties: PropertySync,
+    /// `[client.hooks]`: shell commands to run on session events
+    pub hooks: Hooks,
+    /// Each reader's line in the terminal, like `"{user} p.{page} {status}"`
+    pub display_line: Option<LineTemplate>,
+    /// MPV binary (`--mpv-path`); saved here the first time the flag is used
+    pub mpv_path: Option<PathBuf>,
+}
+
+/// Where the config file is looked for when `--config` isn't given
+///
+/// `$XDG_CONFIG_HOME/syncread/config.toml` (`~/.config/...`) on Linux and
+/// next to the data directory elsewhere.
+pub fn default_path() -> PathBuf {
+    #[cfg(all(unix, not(target_os = "macos")))]
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .map(|dir| dir.join("syncread"));
+    
+    #[cfg(not(all(unix, not(target_os = "macos"))))]
+    let base: Option<PathBuf> = None;
+    
+    base.unwrap_or_else(crate::storage::data_dir).join("config.toml")
+}
+
+/// Load the config file, or the defaults if there is none
+pub fn load(path: &Path) -> Result<Config> {
+    if !path.exists() {
+        return Ok(Config::default());
+    }
+    
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {:?}", path))?;
+    toml::from_str(&content)
+        .with_context(|| format!("Failed to parse {:?}", path))
+}
+
+/// Save `mpv_path` in the `[client]` table of the config file at `path`, creating the file if need be
+///
+/// The file is edited line by line, so comments and layout survive.
+pub fn save_mpv_path(path: &Path, mpv_path: &Path) -> Result<()> {
+    let content = if path.exists() {
+        fs::read_to_string(path).with_context(|| format!("Failed to read {:?}", path))?
+    } else {
+        String::new()
+    };
+    let value = toml::Value::String(mpv_path.to_string_lossy().into_owned());
+    let updated = with_client_key(&content, "mpv_path", &value.to_string());
+    
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {:?}", dir))?;
+    }
+    fs::write(path, updated).with_context(|| format!("Failed to write {:?}", path))
+}
+
+/// `content` with `key = value` set in its `[client]` table, replacing any earlier value
+fn with_client_key(content: &str, key: &str, value: &str) -> String {
+    let setting = format!("{} = {}", key, value);
+    let mut lines: Vec<String> = content.lines().map(str::to_string).collect();
+    let mut in_client = false;
+    let mut insert_at = None; // After the last line of [client]
+    
+    for i in 0..lines.len() {
+        let line = lines[i].trim();
+        if line.starts_with('[') {
+            in_client = line == "[client]";
+            if in_client {
+                insert_at = Some(i + 1);
+            }
+        } else if in_client && !line.is_empty() {
+            if line.split('=').next().map(str::trim) == Some(key) {
+                lines[i] = setting;
+                return lines.join("\n") + "\n";
+            }
+            insert_at = Some(i + 1);
+        }
+    }
+    
+    match insert_at {
+        Some(i) => lines.insert(i, setting),
+        None => {
+            if lines.last().is_some_and(|line| !line.trim().is_empty()) {
+                lines.push(String::new());
+            }
+            lines.push("[client]".to_string());
+            lines.push(setting);
+        }
+    }
+    lines.join("\n") + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_parse_config() {
+        let config: Config = toml::from_str("[client]\nupdate_interval = 500\nadaptive_updates = true\n").unwrap();
+        assert_eq!(config.client.update_interval, Some(500));
+        assert!(config.client.adaptive_updates);
+        
+        let empty: Config = toml::from_str("").unwrap();
+        assert_eq!(empty.client.update_interval, None);
+        
+        assert!(empty.client.glitch_filter.enabled);
+        
+        let omnibus: Config = toml::from_str("[client.glitch_filter]\nbackward_jump = 50\n").unwrap();
+        assert_eq!(omnibus.client.glitch_filter.backward_jump, 50);
+        assert_eq!(omnibus.client.glitch_filter.confirmations, 2);
+        
+        let properties: Config = toml::from_str("[client.properties]\nsync = [\"sid\", \"speed\"]\nlocal = [\"volume\"]\n").unwrap();
+        assert_eq!(properties.client.properties.generic(), vec!["sid".to_string(), "speed".to_string()]);
+        
+        let hooks: Config = toml::from_str("[client.hooks]\non_page_change = \"echo $SYNCREAD_PAGE\"\n").unwrap();
+        assert_eq!(hooks.client.hooks.on_page_change.as_deref(), Some("echo $SYNCREAD_PAGE"));
+        
+        let display: Config = toml::from_str("[client]\ndisplay_line = \"{user} p.{page}\"\n").unwrap();
+        assert_eq!(display.client.display_line, Some(LineTemplate::parse("{user} p.{page}").unwrap()));
+        assert!(toml::from_str::<Config>("[client]\ndisplay_line = \"{username}\"\n").is_err());
+        
+        // Typos are reported rather than silently ignored
+        assert!(toml::from_str::<Config>("[client]\nupdate_intervall = 500\n").is_err());
+    }
+    
+    #[test]
+    fn test_with_client_key() {
+        let set = |content: &str| with_client_key(content, "mpv_path", "\"/opt/mpv\"");
+        assert_eq!(set(""), "[client]\nmpv_path = \"/opt/mpv\"\n");
+        
+        // Added at the end of [client], before the tables after it, keeping comments
+        let content = "# mine\n[client]\nupdate_interval = 500\n\n[client.hooks]\non_page_change = \"true\"\n";
+        let updated = set(content);
+        assert_eq!(updated, "# mine\n[client]\nupdate_interval = 500\nmpv_path = \"/opt/mpv\"\n\n[client.hooks]\non_page_change = \"true\"\n");
+        let config: Config = toml::from_str(&updated).unwrap();
+        assert_eq!(config.client.mpv_path, Some(PathBuf::from("/opt/mpv")));
+        assert_eq!(config.client.hooks.on_page_change.as_deref(), Some("true"));
+        
+        // An earlier value is replaced, not repeated
+        assert_eq!(set("[client]\nmpv_path = \"/usr/bin/mpv\"\n"), "[client]\nmpv_path = \"/opt/mpv\"\n");
+        
+        // Only subtables so far: [client] itself goes after them
+        let updated = set("[client.hooks]\non_page_change = \"true\"\n");
+        assert_eq!(toml::from_str::<Config>(&updated).unwrap().client.mpv_path, Some(PathBuf::from("/opt/mpv")));
+    }
+}
@@ -0,0 +1,129 @@
+//! Errors from the library's entry points
+//!
+//! Most of the crate works with `anyhow`. `MpvController`, `SyncClient` and
+//! `SyncServer` sort their failures into the kinds below instead, so callers
+//! can retry what may go away by itself and explain the rest.
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process::ExitStatus;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Ipc(#[from] IpcError),
+    #[error(transparent)]
+    Protocol(#[from] ProtocolError),
+    #[error(transparent)]
+    Connection(#[from] ConnectionError),
+    #[error(transparent)]
+    Media(#[from] MediaError),
+}
+
+impl Error {
+    /// Whether trying the same thing again could work
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Ipc(e) => e.is_retryable(),
+            Error::Protocol(e) => e.is_retryable(),
+            Error::Connection(e) => e.is_retryable(),
+            Error::Media(_) => false,
+        }
+    }
+}
+
+/// Starting MPV or talking to it over its IPC socket
+#[derive(Debug, thiserror::Error)]
+pub enum IpcError {
+    #[error("MPV not found on PATH or in the usual install locations")]
+    NotFound,
+    #[error("Failed to spawn MPV from {path:?}")]
+    Spawn { path: PathBuf, #[source] source: std::io::Error },
+    #[error("MPV exited before opening its IPC endpoint ({0})")]
+    Exited(ExitStatus),
+    #[error("MPV IPC not available within timeout")]
+    Timeout,
+    #[error("Failed to connect to MPV IPC at {address}")]
+    Connect { address: String, #[source] source: std::io::Error },
+    #[error("Not connected to MPV")]
+    NotConnected,
+    #[error("MPV closed the IPC connection")]
+    HungUp,
+    #[error("Failed to get valid response from MPV")]
+    NoResponse,
+    #[error("Failed to encode an MPV command")]
+    Encode(#[from] serde_json::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+impl IpcError {
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, IpcError::Timeout | IpcError::Connect { .. } | IpcError::NotConnected | IpcError::NoResponse | IpcError::Io(_))
+    }
+}
+
+/// What the server told us instead of letting us take part
+#[derive(Debug, thiserror::Error)]
+pub enum ProtocolError {
+    #[error("The session is full, try again later")]
+    SessionFull,
+    #[error("Removed from the session: {0}")]
+    Removed(String),
+}
+
+impl ProtocolError {
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, ProtocolError::SessionFull)
+    }
+}
+
+/// Reaching the server, keeping the connection, or listening for others
+#[derive(Debug, thiserror::Error)]
+pub enum ConnectionError {
+    #[error("Failed to connect to {addr}")]
+    Connect { addr: SocketAddr, #[source] source: std::io::Error },
+    #[error("Failed to bind to {addr}")]
+    Bind { addr: SocketAddr, #[source] source: std::io::Error },
+    #[error("Lost the connection to the server")]
+    Lost(#[source] anyhow::Error),
+    #[error("Failed to open the control socket")]
+    Control(#[source] anyhow::Error),
+}
+
+impl ConnectionError {
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, ConnectionError::Connect { .. } | ConnectionError::Lost(_))
+    }
+}
+
+/// The player or the files it plays
+#[derive(Debug, thiserror::Error)]
+pub enum MediaError {
+    #[error("Failed to read the player's state")]
+    Player(#[source] anyhow::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_retryable() {
+        let refused = || std::io::Error::from(std::io::ErrorKind::ConnectionRefused);
+        let addr: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+        assert!(Error::from(ConnectionError::Connect { addr, source: refused() }).is_retryable());
+        assert!(!Error::from(ConnectionError::Bind { addr, source: refused() }).is_retryable());
+        assert!(Error::from(IpcError::Timeout).is_retryable());
+        assert!(!Error::from(IpcError::HungUp).is_retryable());
+        assert!(Error::from(ProtocolError::SessionFull).is_retryable());
+        assert!(!Error::from(ProtocolError::Removed("banned".to_string())).is_retryable());
+        assert!(!Error::from(MediaError::Player(anyhow::anyhow!("no"))).is_retryable());
+        
+        // Callers still get the whole story through anyhow
+        let e = anyhow::Error::from(Error::from(ConnectionError::Connect { addr, source: refused() }));
+        assert_eq!(format!("{:#}", e), format!("Failed to connect to 127.0.0.1:8080: {}", refused()));
+    }
+}
@@ -0,0 +1,10 @@
+pub mod clipboard;
+pub mod config;
+pub mod error;
+pub mod logging;
+pub mod media;
+pub mod mpv;
+pub mod network;
+pub mod player;
+pub mod storage;
+pub mod terminal;
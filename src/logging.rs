@@ -0,0 +1,230 @@
+use crate::terminal;
+use anyhow::{Context, Result};
+use serde_json::{Map, Value};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::time::{FormatTime, SystemTime};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, MakeWriter};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Rotate the log file once it grows past this size
+const MAX_LOG_SIZE: u64 = 10 * 1024 * 1024;
+
+/// Number of rotated files (`.1`, `.2`, ...) kept next to the active log
+const MAX_ROTATED_FILES: usize = 3;
+
+/// Output format for log lines
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormat {
+    /// Human-readable text
+    #[default]
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+/// Install the global tracing subscriber
+///
+/// With a log file, output goes only to the file so it doesn't fight with
+/// the client's screen-clearing display loop.
+pub fn init(level: Level, format: LogFormat, log_file: Option<&Path>) -> Result<()> {
+    let writer = match log_file {
+        Some(path) => BoxMakeWriter::new(RollingFile::open(path)?),
+        None => BoxMakeWriter::new(|| terminal::Stdout),
+    };
+    let builder = tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_ansi(log_file.is_none())
+        .with_writer(writer);
+    
+    match format {
+        LogFormat::Text => builder.init(),
+        LogFormat::Json => builder.event_format(JsonFormat).init(),
+    }
+    
+    Ok(())
+}
+
+/// Append-only log file that rotates by size
+#[derive(Clone)]
+pub struct RollingFile {
+    inner: Arc<Mutex<RollingFileState>>,
+}
+
+struct RollingFileState {
+    path: PathBuf,
+    file: File,
+    size: u64,
+}
+
+impl RollingFile {
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create log directory {:?}", parent))?;
+        }
+        
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open log file {:?}", path))?;
+        let size = file.metadata().map(|m| m.len()).unwrap_or(0);
+        
+        Ok(Self {
+            inner: Arc::new(Mutex::new(RollingFileState {
+                path: path.to_path_buf(),
+                file,
+                size,
+            })),
+        })
+    }
+}
+
+impl RollingFileState {
+    /// Shift `log.N` -> `log.N+1`, move the active file to `log.1`, and start fresh
+    fn rotate(&mut self) -> io::Result<()> {
+        let rotated = |n: usize| {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{}", n));
+            PathBuf::from(name)
+        };
+        
+        for n in (1..MAX_ROTATED_FILES).rev() {
+            let from = rotated(n);
+            if from.exists() {
+                fs::rename(&from, rotated(n + 1))?;
+            }
+        }
+        fs::rename(&self.path, rotated(1))?;
+        
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RollingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        
+        if state.size + buf.len() as u64 > MAX_LOG_SIZE && state.size > 0 {
+            state.rotate()?;
+        }
+        
+        let written = state.file.write(buf)?;
+        state.size += written as u64;
+        Ok(written)
+    }
+    
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner()).file.flush()
+    }
+}
+
+impl<'a> MakeWriter<'a> for RollingFile {
+    type Writer = RollingFile;
+    
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+/// Formats each event as a single-line JSON object
+pub struct JsonFormat;
+
+impl<S, N> FormatEvent<S, N> for JsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(&self, ctx: &FmtContext<'_, S, N>, mut writer: Writer<'_>, event: &Event<'_>) -> fmt::Result {
+        let mut timestamp = String::new();
+        SystemTime.format_time(&mut Writer::new(&mut timestamp))?;
+        
+        let metadata = event.metadata();
+        let mut fields = Map::new();
+        event.record(&mut JsonVisitor(&mut fields));
+        
+        let mut object = Map::new();
+        object.insert("timestamp".into(), timestamp.into());
+        object.insert("level".into(), metadata.level().as_str().into());
+        object.insert("target".into(), metadata.target().into());
+        if let Some(message) = fields.remove("message") {
+            object.insert("message".into(), message);
+        }
+        if !fields.is_empty() {
+            object.insert("fields".into(), Value::Object(fields));
+        }
+        
+        let spans: Vec<Value> = ctx.event_scope()
+            .into_iter()
+            .flat_map(|scope| scope.from_root())
+            .map(|span| Value::from(span.name()))
+            .collect();
+        if !spans.is_empty() {
+            object.insert("spans".into(), Value::Array(spans));
+        }
+        
+        writeln!(writer, "{}", Value::Object(object))
+    }
+}
+
+struct JsonVisitor<'a>(&'a mut Map<String, Value>);
+
+impl Visit for JsonVisitor<'_> {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+    
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+    
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+    
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().into(), value.into());
+    }
+    
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().into(), value.into());
+    }
+    
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0.insert(field.name().into(), format!("{:?}", value).into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_rolling_file_rotates() {
+        let dir = std::env::temp_dir().join(format!("syncread_test_logs_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("syncread.log");
+        
+        let mut log = RollingFile::open(&path).unwrap();
+        log.write_all(b"first\n").unwrap();
+        log.inner.lock().unwrap().size = MAX_LOG_SIZE; // Pretend the file is full
+        log.write_all(b"second\n").unwrap();
+        
+        let current = fs::read_to_string(&path).unwrap();
+        let rotated = fs::read_to_string(dir.join("syncread.log.1")).unwrap();
+        let _ = fs::remove_dir_all(&dir);
+        
+        assert_eq!(current, "second\n");
+        assert_eq!(rotated, "first\n");
+    }
+}
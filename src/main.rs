@@ -1,15 +1,38 @@
-mod mpv;
-mod network;
-
 use anyhow::{Context, Result};
-use clap::{Parser, Subcommand};
-use std::net::SocketAddr;
-use std::path::PathBuf;
-use tracing::{info, Level};
-use tracing_subscriber;
+use clap::{Args, Parser, Subcommand};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn, Level};
+
+use syncread::config::{self, ClientConfig};
+use syncread::error::{ConnectionError, Error, IpcError};
+use syncread::logging::{self, LogFormat};
+use syncread::media::{archive, expand_media_files, playlist_fingerprint, ExpandOptions, MediaSource, SortOrder, SpreadLayout};
+use syncread::mpv::{chat_input, hud, install_hint, read_keybind_file, socket_path_for, KeybindProfile, LaunchOptions, MpvConf, MpvController, ProfileKind};
+use syncread::network::appearance::{parse_icon, UserColor};
+use syncread::network::bridge::{self, ChatRoom, DiscordChannel, MatrixRoom};
+use syncread::network::cadence::{DEFAULT_UPDATE_INTERVAL, MIN_UPDATE_INTERVAL};
+use syncread::network::checksums::playlist_checksums;
+use syncread::network::event_log::EventLog;
+use syncread::network::identity::{Identity, KeyPins};
+use syncread::network::protocol::DEFAULT_IDLE_AFTER;
+use syncread::network::sync_client::shutdown_signal;
+use syncread::network::sync_server::DEFAULT_RESYNC_INTERVAL;
+use syncread::network::{file_share, properties, slideshow, systemd};
+use syncread::network::{discovery, headless, request_session, request_stats, send_admin_command, AdminCommand, AllowList, BanList, DiscoveredServer, DuplicateIds, HeadlessInput, PeerNode, SyncClient, SyncServer};
+#[cfg(unix)]
+use syncread::player::imv::ImvPlayer;
+use syncread::player::{MediaPlayer, Viewer};
+use syncread::storage::{self, BookmarkStore, ResumeStore};
+use syncread::terminal;
 
-use mpv::{KeybindProfile, MpvController};
-use network::{SyncClient, SyncServer};
+/// How long `client --discover` listens for server announcements
+const DISCOVERY_WAIT: Duration = Duration::from_secs(2);
+
+/// How often `syncread resume` saves the position
+const RESUME_SAVE_INTERVAL: Duration = Duration::from_secs(2);
 
 #[derive(Parser)]
 #[command(name = "syncread")]
@@ -20,129 +43,949 @@ struct Cli {
     /// Enable debug logging
     #[arg(long, global = true, default_value_t = false)]
     debug: bool,
+    /// Write logs to this file (rotated by size) instead of the terminal
+    #[arg(long, global = true)]
+    log_file: Option<PathBuf>,
+    /// Log output format
+    #[arg(long, global = true, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+    /// Plain ASCII instead of emoji in the display and logs (default: only without a UTF-8 locale); --ascii=false turns it off
+    #[arg(long, global = true, num_args = 0..=1, default_missing_value = "true")]
+    ascii: Option<bool>,
+    /// Settings file (default: ~/.config/syncread/config.toml); flags override it
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
 }
 
 #[derive(Subcommand)]
 enum Commands {
     /// Start a sync server (host mode)
-    Server {
-        /// Address to bind the server to
+    Server(ServerArgs),
+    /// Connect to a sync server (client mode)
+    Client(ClientArgs),
+    /// Sync directly with other readers, without a server
+    P2p(P2pArgs),
+    /// Send a moderation command to a running server
+    Admin {
+        /// Server address to connect to
+        #[arg(short, long, default_value = "127.0.0.1:8080")]
+        server: SocketAddr,
+        /// Admin token configured on the server with --admin-token
+        #[arg(long)]
+        token: String,
+        #[command(subcommand)]
+        action: AdminAction,
+    },
+    /// Show reading stats the server has gathered this session
+    Stats {
+        /// Server address to connect to
         #[arg(short, long, default_value = "127.0.0.1:8080")]
-        bind: SocketAddr,
+        server: SocketAddr,
     },
-    /// Connect to a sync server (client mode)
-    Client {
+    /// Print everyone's position on a server once and exit
+    Status {
         /// Server address to connect to
         #[arg(short, long, default_value = "127.0.0.1:8080")]
         server: SocketAddr,
-        /// User ID for this client
-        #[arg(short, long)]
-        user_id: String,
-        /// Show only relative position info (minimal display)
+        /// Print the whole session state as JSON instead of a table
         #[arg(long, default_value_t = false)]
-        minimal: bool,
-        /// Path to MPV binary (if not in PATH)
-        #[arg(long)]
-        mpv_path: Option<PathBuf>,
-        /// Media files or directory to load
-        #[arg(required = true)]
-        files: Vec<PathBuf>,
+        json: bool,
+    },
+    /// Mirror chat and major events to a Matrix room or Discord channel and relay its messages back as chat
+    Bridge {
+        /// Server address to connect to
+        #[arg(short, long, default_value = "127.0.0.1:8080")]
+        server: SocketAddr,
+        /// Homeserver URL (e.g. https://matrix.example.org)
+        #[arg(long, value_name = "URL", requires_all = ["room", "access_token"], required_unless_present = "discord_channel")]
+        homeserver: Option<String>,
+        /// Room id to bridge, like !abc123:example.org
+        #[arg(long, requires = "homeserver")]
+        room: Option<String>,
+        /// Access token of the Matrix account that posts to the room
+        #[arg(long, requires = "homeserver")]
+        access_token: Option<String>,
+        /// Discord channel id to bridge instead of a Matrix room
+        #[arg(long, value_name = "ID", requires = "bot_token", conflicts_with = "homeserver")]
+        discord_channel: Option<String>,
+        /// Token of the Discord bot that posts to the channel
+        #[arg(long, requires = "discord_channel")]
+        bot_token: Option<String>,
+    },
+    /// Reopen the playlist read most recently where you left off, without a server
+    Resume {
+        #[command(flatten)]
+        player: PlayerArgs,
+    },
+    /// Inspect the MPV keybinds syncread generates, and check keybind files before using them
+    Keybinds {
+        #[command(subcommand)]
+        action: KeybindsAction,
     },
     /// Test MPV controller only (no networking)
     Test {
-        /// Path to MPV binary (if not in PATH)
-        #[arg(long)]
-        mpv_path: Option<PathBuf>,
+        #[command(flatten)]
+        player: PlayerArgs,
         /// Media files to test with
         files: Vec<PathBuf>,
     },
 }
 
+#[derive(Args)]
+struct ServerArgs {
+    /// Address to bind the server to (repeat to listen on several)
+    #[arg(short, long, default_value = "127.0.0.1:8080")]
+    bind: Vec<SocketAddr>,
+    /// Listen on all IPv4 and IPv6 interfaces, using the --bind port
+    #[arg(long, default_value_t = false)]
+    bind_all: bool,
+    /// Snapshot the session to this file and restore it on restart
+    #[arg(long)]
+    state_file: Option<PathBuf>,
+    /// Log joins, leaves, page turns and messages to this SQLite database
+    #[arg(long, value_name = "FILE")]
+    event_log: Option<PathBuf>,
+    /// Write a summary (participants, pace, messages) here when the session ends: JSON, or CSV for a .csv name
+    #[arg(long, value_name = "FILE")]
+    summary: Option<PathBuf>,
+    /// Token that admin clients must present to kick or ban users
+    #[arg(long)]
+    admin_token: Option<String>,
+    /// File of banned user ids / IP addresses (one per line), updated by admin bans
+    #[arg(long)]
+    ban_list: Option<PathBuf>,
+    /// Only let in the user ids listed in this TOML file (users = ["alice", "bob"])
+    #[arg(long, value_name = "FILE")]
+    allow_users: Option<PathBuf>,
+    /// File tying user ids to the key they first signed in with (--identity); others can't take those ids
+    #[arg(long, value_name = "FILE")]
+    pin_keys: Option<PathBuf>,
+    /// Only let in users already in the --pin-keys file, signed in with their key
+    #[arg(long, default_value_t = false, requires = "pin_keys")]
+    known_keys_only: bool,
+    /// When a user id that is already connected joins again: disconnect the old connection, turn the new one away, or rename it "alice (2)"
+    #[arg(long, value_enum, default_value_t = DuplicateIds::Replace)]
+    duplicate_ids: DuplicateIds,
+    /// Turn away clients once this many have joined
+    #[arg(long, value_name = "N")]
+    max_clients: Option<usize>,
+    /// Queue clients that find the session full and let them in as others leave
+    #[arg(long, default_value_t = false, requires = "max_clients")]
+    waiting_room: bool,
+    /// Don't announce this server to `syncread client --discover` on the LAN
+    #[arg(long, default_value_t = false)]
+    no_advertise: bool,
+    /// Forward the port on your home router automatically (UPnP / NAT-PMP)
+    #[arg(long, default_value_t = false)]
+    upnp: bool,
+    /// Serve session status as JSON on this address (GET /session, GET /users)
+    #[arg(long, value_name = "ADDR")]
+    http: Option<SocketAddr>,
+    /// POST joins, leaves and the session finishing to this http(s):// URL as JSON
+    #[arg(long, value_name = "URL")]
+    webhook: Option<String>,
+    /// Don't let clients negotiate compression of large messages
+    #[arg(long, default_value_t = false)]
+    no_compression: bool,
+    /// Show users as idle after this many minutes without turning a page, pausing or playing
+    #[arg(long, value_name = "MINUTES", default_value_t = 5)]
+    idle_after: u64,
+    /// Seconds per page for slideshows started with P in MPV
+    #[arg(long, value_name = "SECONDS", default_value_t = slideshow::DEFAULT_INTERVAL_SECS, value_parser = clap::value_parser!(u64).range(1..))]
+    slideshow_interval: u64,
+    /// Send everyone the whole session this often, so anyone who missed an update catches up (0 for never)
+    #[arg(long, value_name = "SECONDS", default_value_t = DEFAULT_RESYNC_INTERVAL.as_secs())]
+    resync_interval: u64,
+}
+
+#[derive(Args)]
+struct P2pArgs {
+    /// Address to accept other peers on
+    #[arg(short, long, default_value = "0.0.0.0:8090")]
+    bind: SocketAddr,
+    /// Peer to link with (repeat for several; one is enough to reach the whole group)
+    #[arg(short, long = "peer")]
+    peers: Vec<SocketAddr>,
+    #[command(flatten)]
+    client: ClientArgs,
+}
+
+#[derive(Subcommand)]
+enum AdminAction {
+    /// Disconnect a user from the session
+    Kick {
+        user_id: String,
+        /// Reason shown to the kicked user
+        #[arg(long)]
+        reason: Option<String>,
+    },
+    /// Ban a user id or IP address and disconnect matching users
+    Ban {
+        target: String,
+    },
+    /// Lift a ban
+    Unban {
+        target: String,
+    },
+    /// End the session, writing the server's --summary file, and start a new one
+    EndSession,
+}
+
+#[derive(Subcommand)]
+enum KeybindsAction {
+    /// Write a profile's keybinds as an input.conf, to start a --keybinds file from
+    Dump {
+        /// File to write (default: print them)
+        output: Option<PathBuf>,
+        #[command(flatten)]
+        profile: KeybindsProfileArgs,
+    },
+    /// Show what a keybind file adds or rebinds in a profile
+    Diff {
+        /// Keybind file (.toml or mpv input.conf)
+        file: PathBuf,
+        #[command(flatten)]
+        profile: KeybindsProfileArgs,
+    },
+    /// Check a keybind file the way --keybinds would, reporting every problem
+    Check {
+        /// Keybind file (.toml or mpv input.conf)
+        file: PathBuf,
+        #[command(flatten)]
+        profile: KeybindsProfileArgs,
+    },
+}
+
+#[derive(Args)]
+struct KeybindsProfileArgs {
+    /// Keybind preset to start from
+    #[arg(long, value_enum, default_value_t = ProfileKind::Video)]
+    profile: ProfileKind,
+    /// Page keys skip between chapters, as with the client's --chapters
+    #[arg(long, default_value_t = false)]
+    chapters: bool,
+}
+
+impl KeybindsProfileArgs {
+    fn build(&self) -> KeybindProfile {
+        let mut profile = self.profile.build();
+        if self.chapters {
+            profile.navigate_chapters();
+        }
+        profile
+    }
+}
+
+#[derive(Args)]
+struct ClientArgs {
+    /// Server address to connect to
+    #[arg(short, long, default_value = "127.0.0.1:8080")]
+    server: SocketAddr,
+    /// Find servers on the local network; with no user id and files, just list them
+    #[arg(long, default_value_t = false)]
+    discover: bool,
+    /// User ID for this client
+    #[arg(short, long, required_unless_present = "discover")]
+    user_id: Option<String>,
+    /// Show only relative position info (minimal display)
+    #[arg(long, default_value_t = false)]
+    minimal: bool,
+    /// Color to show you in (red, orange, yellow, green, cyan, blue, purple, pink); picked from your user id by default
+    #[arg(long)]
+    color: Option<UserColor>,
+    /// Emoji avatar shown before your name, e.g. --icon 🐱
+    #[arg(long, value_parser = parse_icon)]
+    icon: Option<String>,
+    /// Reopen this playlist where you left off last session
+    #[arg(long, default_value_t = false)]
+    resume: bool,
+    /// Keep video playback times in sync, seeking forward when behind other viewers
+    #[arg(long, default_value_t = false)]
+    sync_time: bool,
+    /// How far behind (seconds) a viewer may drift before --sync-time seeks
+    #[arg(long, default_value_t = 1.0)]
+    sync_threshold: f64,
+    /// Don't let anyone turn more than --wait-tolerance pages past the slowest reader
+    #[arg(long, default_value_t = false)]
+    wait_for_all: bool,
+    /// How many pages ahead of the slowest reader --wait-for-all allows
+    #[arg(long, default_value_t = 1)]
+    wait_tolerance: i32,
+    /// Offer to host the session on this port if the server goes away
+    #[arg(long, value_name = "PORT")]
+    host_port: Option<u16>,
+    /// Let others download playlist items they are missing from you, on this port
+    #[arg(long, value_name = "PORT")]
+    share_port: Option<u16>,
+    /// Hash your playlist files and have the server report pages others have a different version of
+    #[arg(long, default_value_t = false)]
+    checksums: bool,
+    /// Where to save playlist items downloaded from others (press D in MPV)
+    #[arg(long, value_name = "DIR")]
+    download_dir: Option<PathBuf>,
+    /// Largest playlist item to download from others, in MiB
+    #[arg(long, value_name = "MIB", default_value_t = file_share::DEFAULT_MAX_DOWNLOAD_SIZE >> 20)]
+    max_download_size: u64,
+    /// Where to save group screenshots (Ctrl+s in MPV) [default: screenshots in the data directory]
+    #[arg(long, value_name = "DIR")]
+    screenshot_dir: Option<PathBuf>,
+    /// Pause with an "N/M finished" banner whenever someone finishes the playlist before you
+    #[arg(long, default_value_t = false)]
+    finish_pause: bool,
+    /// Pause MPV while the server connection is lost and resume once reconnected
+    #[arg(long, default_value_t = false)]
+    pause_on_disconnect: bool,
+    /// Pause and resume everyone together when anyone pauses or resumes
+    #[arg(long, default_value_t = false)]
+    shared_pause: bool,
+    /// Show which chapter of a long video everyone is in, with the page keys skipping chapters
+    #[arg(long, default_value_t = false)]
+    chapters: bool,
+    /// Present: everyone else's MPV zooms and pans to show what yours does
+    #[arg(long, default_value_t = false)]
+    present: bool,
+    /// Keep your own zoom and pan when someone presents
+    #[arg(long, default_value_t = false)]
+    ignore_presenter: bool,
+    /// Rotate everyone's MPV along with yours (Ctrl+LEFT/RIGHT), for sideways scans
+    #[arg(long, default_value_t = false)]
+    sync_rotation: bool,
+    /// Share changes to this MPV property, e.g. sid, speed or video-zoom (repeatable; 'playlist-pos' turns pages together)
+    #[arg(long = "sync-property", value_name = "NAME")]
+    sync_properties: Vec<String>,
+    /// Show other users' positions in a corner of the MPV window
+    #[arg(long, default_value_t = false)]
+    osd: bool,
+    /// Also show links others share on the MPV OSD (copy the last one with y)
+    #[arg(long, default_value_t = false)]
+    show_links: bool,
+    /// Show others as idle after this many minutes without turning a page, pausing or playing
+    #[arg(long, value_name = "MINUTES", default_value_t = 5)]
+    idle_after: u64,
+    /// Milliseconds between polls of MPV and state updates [default: 1000]
+    #[arg(long, value_name = "MS", value_parser = clap::value_parser!(u64).range(100..))]
+    update_interval: Option<u64>,
+    /// Update faster for a few seconds after each page turn
+    #[arg(long, default_value_t = false)]
+    adaptive_updates: bool,
+    /// Send every position MPV reports, even large backward jumps it might report while switching files
+    #[arg(long, default_value_t = false)]
+    no_glitch_filter: bool,
+    /// Load the in-player session HUD (toggle with Ctrl+h in MPV)
+    #[arg(long, default_value_t = false)]
+    hud: bool,
+    /// Run without MPV or a display, taking commands from stdin, --script or --control
+    #[arg(long, default_value_t = false)]
+    headless: bool,
+    /// Headless command script (page N, next, prev, seek S, pause, play, note TEXT, link URL, react EMOJI, wait S, quit)
+    #[arg(long, requires = "headless", conflicts_with = "control")]
+    script: Option<PathBuf>,
+    /// Accept headless commands from TCP connections on this address
+    #[arg(long, requires = "headless")]
+    control: Option<SocketAddr>,
+    /// Take commands (next, prev, goto N, follow NAME, jump NAME, chat TEXT) from other programs on this Unix socket (a named pipe on Windows)
+    #[arg(long, value_name = "PATH")]
+    control_socket: Option<PathBuf>,
+    /// Don't take commands from keys pressed in this terminal (f follow, j jump to, c chat, q quit)
+    #[arg(long, default_value_t = false)]
+    no_keys: bool,
+    /// Encrypt page turns, notes, chat and the like end to end; everyone in the session must use the same passphrase
+    #[arg(long)]
+    passphrase: Option<String>,
+    /// Sign in with this machine's identity key (created on first use), so servers that pin keys know it's you
+    #[arg(long, default_value_t = false)]
+    identity: bool,
+    /// Don't offer to compress large messages (for debugging the wire protocol)
+    #[arg(long, default_value_t = false)]
+    no_compression: bool,
+    /// Use length-prefixed MessagePack frames instead of JSON lines on the wire
+    #[arg(long, default_value_t = false)]
+    binary: bool,
+    #[command(flatten)]
+    player: PlayerArgs,
+    /// Media files, directories, archives, playlists or http(s) URLs to load
+    #[arg(required_unless_present_any = ["discover", "headless"])]
+    files: Vec<PathBuf>,
+}
+
+/// Options for launching MPV and building its playlist
+#[derive(Args)]
+struct PlayerArgs {
+    /// Program that shows the media ('imv' syncs pages only, without keybinds, HUD or spreads)
+    #[arg(long, value_enum, default_value_t = Viewer::Mpv)]
+    viewer: Viewer,
+    /// Path to MPV binary, if it isn't on PATH or in a usual install location (remembered in the config)
+    #[arg(long)]
+    mpv_path: Option<PathBuf>,
+    /// Keybind and MPV option preset (picked from the playlist when not given: comic for images, audio for music, video otherwise)
+    #[arg(long, value_enum)]
+    profile: Option<ProfileKind>,
+    /// Custom keybind file (.toml or mpv input.conf) merged over the profile
+    #[arg(long)]
+    keybinds: Option<PathBuf>,
+    /// Ordering for files found in directories and archives
+    #[arg(long, value_enum, default_value_t = SortOrder::Natural)]
+    sort: SortOrder,
+    /// Descend into subdirectories (one chapter per folder)
+    #[arg(short, long, default_value_t = false)]
+    recursive: bool,
+    /// Show images as double-page spreads, cover on its own (realign with 'a' in MPV)
+    #[arg(long, default_value_t = false)]
+    spreads: bool,
+    /// Show each page and the next in two MPV windows side by side, turning two at a time
+    #[arg(long, default_value_t = false, conflicts_with = "spreads")]
+    dual_window: bool,
+    /// Put the second page of a spread (or its window) on the left (right-to-left manga)
+    #[arg(long, default_value_t = false)]
+    rtl: bool,
+}
+
+impl PlayerArgs {
+    fn expand_options(&self) -> ExpandOptions {
+        ExpandOptions {
+            sort: self.sort,
+            recursive: self.recursive,
+        }
+    }
+    
+    /// The `--profile` preset, or the one suiting most of the playlist
+    fn profile_for(&self, media_files: &[MediaSource]) -> ProfileKind {
+        self.profile.unwrap_or_else(|| {
+            let detected = ProfileKind::detect(media_files);
+            info!("Using the {} profile for this playlist (choose another with --profile)", detected);
+            detected
+        })
+    }
+    
+    /// Fall back to the config's `mpv_path`, and save a new `--mpv-path` there for next time
+    fn remember_mpv_path(&mut self, config: &ClientConfig, config_path: &Path) {
+        let Some(mpv_path) = &self.mpv_path else {
+            self.mpv_path = config.mpv_path.clone();
+            return;
+        };
+        // Launching says what's wrong with a path that isn't there
+        let Ok(mpv_path) = std::fs::canonicalize(mpv_path) else {
+            return;
+        };
+        if !mpv_path.is_file() || config.mpv_path.as_ref() == Some(&mpv_path) {
+            return;
+        }
+        match config::save_mpv_path(config_path, &mpv_path) {
+            Ok(()) => info!("Saved --mpv-path in {:?} for next time", config_path),
+            Err(e) => warn!("Couldn't save --mpv-path in the config: {:#}", e),
+        }
+    }
+    
+    /// Write the keybind profile and mpv.conf for `profile`, and say how to launch MPV with them
+    fn launch_options(&self, profile: ProfileKind, keybinds: &KeybindProfile, screenshot_dir: &Path, media_files: &[MediaSource]) -> Result<LaunchOptions> {
+        anyhow::ensure!(!self.rtl || self.spreads || self.dual_window, "--rtl needs --spreads or --dual-window");
+        Ok(LaunchOptions {
+            keybind_config: Some(keybinds.create_temp_config()?),
+            mpv_config: Some(MpvConf::for_session(profile, screenshot_dir).create_temp_config()?),
+            mpv_binary_path: self.mpv_path.clone(),
+            spreads: self.spreads.then(|| SpreadLayout::new(media_files, true, self.rtl || profile == ProfileKind::MangaRtl)),
+            dual_window: self.dual_window,
+            rtl: self.rtl || profile == ProfileKind::MangaRtl,
+            ..Default::default()
+        })
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     // Initialize logging
     let cli = Cli::parse();
-
+    
     let log_level = if cli.debug {
         Level::DEBUG
     } else {
         Level::INFO
     };
-
-    tracing_subscriber::fmt()
-        .with_max_level(log_level)
-        .init();
-
-    let cli = Cli::parse();
-
-    match cli.command {
-        Commands::Server { bind } => {
-            info!("🚀 Starting SyncRead server mode");
-            start_server(bind).await
+    
+    terminal::set_ascii(cli.ascii.unwrap_or_else(|| !terminal::unicode_terminal()));
+    logging::init(log_level, cli.log_format, cli.log_file.as_deref())?;
+    
+    let config_path = cli.config.unwrap_or_else(config::default_path);
+    let config = config::load(&config_path)?;
+    
+    let result = match cli.command {
+        Commands::Server(args) => {
+            info!("{}Starting SyncRead server mode", terminal::decoration("🚀 "));
+            start_server(args).await
         }
-        Commands::Client { server, user_id, minimal, mpv_path, files } => {
-            info!("🔗 Starting SyncRead client mode");
-            start_client(server, user_id, minimal, mpv_path, files).await
+        Commands::Admin { server, token, action } => {
+            let command = match action {
+                AdminAction::Kick { user_id, reason } => AdminCommand::Kick { user_id, reason },
+                AdminAction::Ban { target } => AdminCommand::Ban { target },
+                AdminAction::Unban { target } => AdminCommand::Unban { target },
+                AdminAction::EndSession => AdminCommand::EndSession,
+            };
+            let reply = send_admin_command(server, &token, command).await?;
+            println!("{}", reply);
+            Ok(())
         }
-        Commands::Test { mpv_path, files } => {
-            info!("🧪 Testing MPV controller");
-            test_mpv_controller(mpv_path, files).await
+        Commands::Client(mut args) => {
+            info!("{}Starting SyncRead client mode", terminal::decoration("🔗 "));
+            args.player.remember_mpv_path(&config.client, &config_path);
+            start_client(args, &config.client).await
         }
-    }
+        Commands::P2p(mut args) => {
+            info!("{}Starting SyncRead peer-to-peer mode", terminal::decoration("🔗 "));
+            args.client.player.remember_mpv_path(&config.client, &config_path);
+            start_p2p(args, &config.client).await
+        }
+        Commands::Stats { server } => {
+            let users = request_stats(server).await?;
+            if users.is_empty() {
+                println!("No reading recorded on {} yet", server);
+            }
+            
+            let mut users: Vec<_> = users.into_iter().collect();
+            users.sort_by(|a, b| a.0.cmp(&b.0));
+            for (user_id, stats) in users {
+                println!("{}: {}", user_id, stats.format_summary());
+            }
+            Ok(())
+        }
+        Commands::Status { server, json } => {
+            let session = request_session(server).await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&session)?);
+            } else {
+                let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?.as_secs();
+                println!("{}", session.format_table(DEFAULT_IDLE_AFTER, now));
+            }
+            Ok(())
+        }
+        Commands::Bridge { server, homeserver, room, access_token, discord_channel, bot_token } => {
+            let room = match (homeserver, room, access_token, discord_channel, bot_token) {
+                (Some(homeserver), Some(room), Some(access_token), None, None) => ChatRoom::Matrix(MatrixRoom::new(&homeserver, room, access_token)?),
+                (None, None, None, Some(channel), Some(bot_token)) => ChatRoom::Discord(DiscordChannel::new(channel, bot_token)?),
+                _ => anyhow::bail!("Pass --homeserver, --room and --access-token for Matrix, or --discord-channel and --bot-token for Discord"),
+            };
+            info!("{}Bridging the session on {}", terminal::decoration("🌉 "), server);
+            bridge::run(server, room).await
+        }
+        Commands::Resume { mut player } => {
+            info!("{}Resuming the last playlist", terminal::decoration("📖 "));
+            player.remember_mpv_path(&config.client, &config_path);
+            resume_reading(player).await
+        }
+        Commands::Keybinds { action } => keybinds_command(action),
+        Commands::Test { mut player, files } => {
+            info!("{}Testing MPV controller", terminal::decoration("🧪 "));
+            player.remember_mpv_path(&config.client, &config_path);
+            test_mpv_controller(player, files).await
+        }
+    };
+    
+    // Pages unpacked from archives aren't needed once we're done reading
+    archive::clean_up();
+    result
 }
 
-async fn start_server(bind_addr: SocketAddr) -> Result<()> {
-    let server = SyncServer::new();
-    info!("Starting sync server on {}", bind_addr);
-    info!("Clients can connect with: syncread client --server {} --user-id <name> <files...>", bind_addr);
+async fn start_server(args: ServerArgs) -> Result<()> {
+    let ServerArgs {
+        bind, bind_all, state_file, event_log, summary, admin_token, ban_list, allow_users, pin_keys, known_keys_only, duplicate_ids, max_clients, waiting_room, no_advertise, upnp, http, webhook, no_compression, idle_after, slideshow_interval, resync_interval,
+    } = args;
     
-    server.start(bind_addr).await?;
+    let mut server = SyncServer::new();
+    server.set_idle_after(Duration::from_secs(idle_after * 60));
+    server.set_slideshow_interval(Duration::from_secs(slideshow_interval));
+    server.set_resync_interval((resync_interval > 0).then(|| Duration::from_secs(resync_interval)));
+    server.set_advertise(!no_advertise);
+    server.set_upnp(upnp);
+    server.set_compression(!no_compression);
+    if let Some(addr) = http {
+        server.set_http_addr(addr);
+    }
+    if let Some(url) = webhook {
+        server.set_webhook(&url)?;
+    }
+    if let Some(path) = state_file {
+        server.set_state_file(path);
+    }
+    if let Some(path) = event_log {
+        info!("Logging session events to {:?}", path);
+        server.set_event_log(Arc::new(EventLog::open(path)?));
+    }
+    if let Some(path) = summary {
+        server.set_summary_path(path)?;
+    }
+    if let Some(token) = admin_token {
+        server.set_admin_token(token);
+    }
+    if let Some(path) = ban_list {
+        server.set_ban_list(BanList::load(path)?);
+    }
+    if let Some(path) = allow_users {
+        server.set_allow_list(AllowList::load(&path)?);
+    }
+    if let Some(path) = pin_keys {
+        server.set_key_pins(KeyPins::load(path, known_keys_only)?);
+    }
+    server.set_duplicate_ids(duplicate_ids);
+    if let Some(max) = max_clients {
+        server.set_max_clients(max);
+        server.set_waiting_room(waiting_room);
+    }
+    
+    let bind_addrs = if bind_all {
+        let port = bind[0].port();
+        vec![
+            SocketAddr::from((Ipv4Addr::UNSPECIFIED, port)),
+            SocketAddr::from((Ipv6Addr::UNSPECIFIED, port)),
+        ]
+    } else {
+        bind
+    };
+    
+    // Under a systemd socket unit the sockets are already bound, and --bind doesn't apply
+    let activated = systemd::listeners().context("Failed to take the sockets systemd passed in")?;
+    let listeners = if activated.is_empty() {
+        info!("Starting sync server on {}", format_addrs(&bind_addrs));
+        info!("Clients can connect with: syncread client --server {} --user-id <name> <files...>", bind_addrs[0]);
+        SyncServer::bind(&bind_addrs).map_err(explain)?
+    } else {
+        info!("Starting sync server on {} socket(s) passed in by systemd", activated.len());
+        activated.into_iter().map(tokio::net::TcpListener::from_std).collect::<std::io::Result<_>>()?
+    };
+    systemd::notify("READY=1");
+    
+    tokio::select! {
+        result = server.serve(listeners) => result.map_err(explain)?,
+        _ = shutdown_signal() => {
+            info!("Shutting down");
+            systemd::notify("STOPPING=1");
+            server.shutdown().await;
+            if let Some(summary) = server.end_session().await? {
+                info!("Session summary: {}", summary);
+            }
+        }
+    }
     Ok(())
 }
 
-async fn start_client(server_addr: SocketAddr, user_id: String, minimal: bool, mpv_path: Option<PathBuf>, files: Vec<PathBuf>) -> Result<()> {
+async fn start_p2p(args: P2pArgs, config: &ClientConfig) -> Result<()> {
+    let P2pArgs { bind, peers, mut client } = args;
+    anyhow::ensure!(!client.discover, "--discover finds servers; in peer-to-peer mode, pass --peer instead");
+    anyhow::ensure!(client.passphrase.is_none(), "--passphrase needs the session salt a server hands out, so it only works with a server");
+    let user_id = client.user_id.clone().context("--user-id is required")?;
+    
+    let mut node = PeerNode::new(user_id, bind);
+    for peer in peers {
+        node.add_peer(peer);
+    }
+    
+    // Our client talks to the node as if it were the server
+    client.server = node.start().await?;
+    info!("Others can join with: syncread p2p --peer <this machine's IP>:{} --user-id <name> <files...>", bind.port());
+    start_client(client, config).await
+}
+
+async fn start_client(args: ClientArgs, config: &ClientConfig) -> Result<()> {
+    let ClientArgs {
+        server, discover, user_id, minimal, color, icon, resume, sync_time, sync_threshold, wait_for_all, wait_tolerance, host_port,
+        share_port, checksums, download_dir, max_download_size, screenshot_dir, shared_pause, chapters, present, ignore_presenter, sync_rotation, sync_properties, finish_pause, pause_on_disconnect, osd, show_links, idle_after, update_interval, adaptive_updates, no_glitch_filter,
+        hud, headless, script, control, control_socket, no_keys, passphrase, identity, no_compression, binary, player, files,
+    } = args;
+    
+    let update_interval = update_interval.or(config.update_interval).map_or(DEFAULT_UPDATE_INTERVAL, Duration::from_millis);
+    anyhow::ensure!(update_interval >= MIN_UPDATE_INTERVAL, "update_interval must be at least {} ms", MIN_UPDATE_INTERVAL.as_millis());
+    let adaptive_updates = adaptive_updates || config.adaptive_updates;
+    let mut glitch_filter = config.glitch_filter.clone();
+    glitch_filter.enabled &= !no_glitch_filter;
+    
+    let mut properties = config.properties.clone();
+    properties.sync.extend(sync_properties);
+    if sync_rotation {
+        properties.sync.push(properties::VIDEO_ROTATE.to_string());
+    }
+    properties.validate()?;
+    let shared_pause = shared_pause || properties.syncs(properties::PAUSE);
+    let sync_time = sync_time || properties.syncs(properties::PLAYBACK_TIME);
+    anyhow::ensure!(!(sync_time && properties.syncs("speed")), "speed can't be synced while --sync-time adjusts it to close drift");
+    
+    let server_addr = if discover {
+        let servers = discovery::discover(DISCOVERY_WAIT).await?;
+        
+        let Some(user_id) = user_id.as_ref().filter(|_| !files.is_empty() || headless) else {
+            print_discovered(&servers);
+            return Ok(());
+        };
+        
+        let Some(first) = servers.first() else {
+            anyhow::bail!("No servers found on the local network");
+        };
+        if servers.len() > 1 {
+            warn!("Found {} servers, joining '{}' as {}; use --server to pick another", servers.len(), first.name, user_id);
+        }
+        first.addr
+    } else {
+        server
+    };
+    let user_id = user_id.context("--user-id is required to join a session")?;
+    let identity = if identity {
+        let identity = Identity::load_or_create(&Identity::default_path())?;
+        println!("{}Signing in as {} with key {}", terminal::decoration("🔑 "), user_id, identity.public_key());
+        Some(identity)
+    } else {
+        None
+    };
+    
     info!("Connecting to server {} as user '{}'", server_addr, user_id);
     
+    if headless {
+        // Files are optional here; without them pages are just numbers
+        let media_files = expand_media_files(files, player.expand_options())?;
+        let input = match (script, control) {
+            (Some(path), _) => HeadlessInput::Script(path),
+            (None, Some(addr)) => HeadlessInput::Control(addr),
+            (None, None) => HeadlessInput::Stdin,
+        };
+        
+        let mut sync_client = SyncClient::new(user_id);
+        if let Some(color) = color {
+            sync_client.set_color(color);
+        }
+        sync_client.set_icon(icon);
+        if sync_time {
+            sync_client.set_time_sync(sync_threshold);
+        }
+        if wait_for_all {
+            sync_client.set_wait_for_all(wait_tolerance);
+        }
+        sync_client.set_shared_pause(shared_pause);
+        sync_client.set_synced_properties(properties.generic());
+        sync_client.set_update_interval(update_interval);
+        sync_client.set_compression(!no_compression);
+        sync_client.set_binary(binary);
+        sync_client.set_hooks(config.hooks.clone());
+        if let Some(path) = control_socket {
+            sync_client.set_control_socket(path);
+        }
+        if let Some(passphrase) = &passphrase {
+            sync_client.set_passphrase(passphrase);
+        }
+        if let Some(identity) = identity {
+            sync_client.set_identity(identity);
+        }
+        let commands = headless::spawn_input(input).await?;
+        return sync_client.run_headless(server_addr, media_files, commands).await.map_err(explain);
+    }
+    
     // Expand directories and validate files
-    let media_files = expand_media_files(files)?;
+    let opened = saved_paths(&files);
+    let media_files = expand_media_files(files, player.expand_options())?;
     if media_files.is_empty() {
         anyhow::bail!("No media files found");
     }
     
     info!("Loaded {} media files", media_files.len());
+    let screenshot_dir = screenshot_dir.or_else(|| config.screenshot_dir.clone())
+        .unwrap_or_else(|| storage::data_dir().join("screenshots"));
     
-    // Create keybind profile
-    let keybind_profile = KeybindProfile::default();
-    let keybind_path = keybind_profile.create_temp_config()?;
-    
-    // Launch MPV with unique socket for each user
-    let socket_path = std::env::temp_dir().join(format!("syncread_{}.socket", user_id));
+    // Launch the viewer with a unique MPV socket for each user
+    let mut viewer = launch_viewer(&player, &media_files, &user_id, &screenshot_dir, chapters, hud).await?;
+            
+    // Positions are always recorded so a later --resume or `syncread resume` has something to restore
+    let fingerprint = playlist_fingerprint(&media_files);
+    let mut resume_store = ResumeStore::open_default()?;
+    resume_store.record_files(&fingerprint, &opened)?;
     
-    let mpv_controller = MpvController::launch(
-        &socket_path,
-        Some(&keybind_path),
-        media_files.iter().collect(),
-        mpv_path.as_deref(),
-    ).await?;
-    
-    info!("MPV launched successfully!");
+    if resume {
+        match resume_store.get(&fingerprint) {
+            Some(entry) => {
+                info!("Resuming at item {} ({:.1}s)", entry.playlist_position + 1, entry.playback_time);
+                viewer.restore_position(entry.playlist_position, entry.playback_time).await?;
+            }
+            None => info!("No saved position for this playlist, starting from the beginning"),
+        }
+    }
     
     // Connect to sync server
     let mut sync_client = SyncClient::new(user_id);
-    sync_client.connect_and_sync(server_addr, mpv_controller, media_files, minimal).await?;
+    if let Some(color) = color {
+        sync_client.set_color(color);
+    }
+    sync_client.set_icon(icon);
+    sync_client.set_bookmark_store(BookmarkStore::open_default()?, fingerprint.clone());
+    sync_client.set_resume_store(resume_store, fingerprint);
+    if sync_time {
+        sync_client.set_time_sync(sync_threshold);
+    }
+    if wait_for_all {
+        sync_client.set_wait_for_all(wait_tolerance);
+    }
+    if let Some(port) = host_port {
+        sync_client.set_host_port(port);
+    }
+    if let Some(port) = share_port {
+        sync_client.set_share_port(port);
+    }
+    if checksums {
+        let files = media_files.clone();
+        info!("Hashing {} playlist files to compare with the others", files.len());
+        let hashes = tokio::task::spawn_blocking(move || playlist_checksums(&files)).await?;
+        sync_client.set_checksums(hashes);
+    }
+    if let Some(dir) = download_dir {
+        sync_client.set_download_dir(dir);
+    }
+    sync_client.set_max_download_size(max_download_size.saturating_mul(1 << 20));
+    sync_client.set_screenshot_dir(screenshot_dir);
+    sync_client.set_shared_pause(shared_pause);
+    sync_client.set_synced_properties(properties.generic());
+    sync_client.set_chapter_mode(chapters);
+    sync_client.set_presenting(present);
+    sync_client.set_follow_presenter(!ignore_presenter);
+    sync_client.set_finish_pause(finish_pause);
+    sync_client.set_pause_on_disconnect(pause_on_disconnect);
+    sync_client.set_osd_overlay(osd);
+    sync_client.set_show_links(show_links);
+    sync_client.set_idle_after(Duration::from_secs(idle_after * 60));
+    sync_client.set_update_interval(update_interval);
+    sync_client.set_adaptive_updates(adaptive_updates);
+    sync_client.set_glitch_filter(glitch_filter);
+    sync_client.set_hud(hud);
+    sync_client.set_compression(!no_compression);
+    sync_client.set_binary(binary);
+    sync_client.set_hooks(config.hooks.clone());
+    sync_client.set_terminal_keys(!no_keys);
+    if let Some(template) = &config.display_line {
+        sync_client.set_display_line(template.clone());
+    }
+    if let Some(path) = control_socket {
+        sync_client.set_control_socket(path);
+    }
+    if let Some(passphrase) = &passphrase {
+        sync_client.set_passphrase(passphrase);
+    }
+    if let Some(identity) = identity {
+        sync_client.set_identity(identity);
+    }
+    sync_client.connect_and_sync(server_addr, viewer, media_files, minimal).await.map_err(explain)?;
     
     Ok(())
 }
 
-async fn test_mpv_controller(mpv_path: Option<PathBuf>, files: Vec<PathBuf>) -> Result<()> {
-    info!("Testing MPV controller...");
+/// Start the chosen viewer on a playlist, MPV talking on a socket named after `socket_name`
+async fn launch_viewer(
+    player: &PlayerArgs,
+    media_files: &[MediaSource],
+    socket_name: &str,
+    screenshot_dir: &Path,
+    chapters: bool,
+    hud: bool,
+) -> Result<Box<dyn MediaPlayer>> {
+    match player.viewer {
+        Viewer::Mpv => {
+            // Create keybind profile
+            let profile = player.profile_for(media_files);
+            let mut keybind_profile = load_keybind_profile(profile, player.keybinds.as_deref())?;
+            if chapters {
+                keybind_profile.navigate_chapters();
+            }
+            
+            let mut launch_options = player.launch_options(profile, &keybind_profile, screenshot_dir, media_files)?;
+            launch_options.scripts.push(chat_input::create_temp_script()?);
+            if hud {
+                launch_options.scripts.push(hud::create_temp_script()?);
+            }
+            
+            let mpv_controller = MpvController::launch(&socket_path_for(socket_name), media_files, launch_options).await.map_err(explain)?;
+            
+            info!("MPV launched successfully!");
+            Ok(Box::new(mpv_controller))
+        }
+        Viewer::Imv => launch_imv(player, media_files, hud).await,
+    }
+}
+
+/// The paths given on the command line, made absolute so the playlist can be reopened from anywhere
+fn saved_paths(files: &[PathBuf]) -> Vec<String> {
+    files.iter()
+        .map(|path| std::fs::canonicalize(path).unwrap_or_else(|_| path.clone()))
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect()
+}
+
+/// Read the most recently saved playlist alone, from where it was left, saving progress until the viewer closes
+async fn resume_reading(player: PlayerArgs) -> Result<()> {
+    let mut store = ResumeStore::open_default()?;
+    let Some((saved_fingerprint, entry)) = store.most_recent()? else {
+        anyhow::bail!("Nothing to resume yet; progress is saved while reading with `syncread client`");
+    };
+    
+    let paths = entry.files.iter().map(PathBuf::from).collect();
+    let media_files = expand_media_files(paths, player.expand_options())
+        .context("The saved playlist can't be opened any more")?;
+    anyhow::ensure!(!media_files.is_empty(), "None of the saved playlist's files are left");
+    
+    // Files added or removed since change the fingerprint; progress carries over to the new one
+    let fingerprint = playlist_fingerprint(&media_files);
+    if fingerprint != saved_fingerprint {
+        warn!("The playlist has changed since it was last read");
+        store.record_files(&fingerprint, &entry.files)?;
+    }
+    
+    let finished = if entry.finished { ", finished before" } else { "" };
+    info!("Resuming {} files at item {} ({:.1}s{})", media_files.len(), entry.playlist_position + 1, entry.playback_time, finished);
+    let mut viewer = launch_viewer(&player, &media_files, "resume", &storage::data_dir().join("screenshots"), false, false).await?;
+    viewer.restore_position(entry.playlist_position, entry.playback_time).await?;
+    
+    let last_index = media_files.len() as i32 - 1;
+    let mut finished = entry.finished;
+    let mut ticks = tokio::time::interval(RESUME_SAVE_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = ticks.tick() => {}
+            _ = tokio::signal::ctrl_c() => {
+                viewer.quit().await;
+                break;
+            }
+        }
+        if viewer.exit_status().is_some() {
+            break;
+        }
+        
+        let Ok(position) = viewer.get_playlist_pos().await else {
+            continue;
+        };
+        let time = viewer.get_position().await.unwrap_or(0.0);
+        finished |= position == last_index;
+        store.record(&fingerprint, position, time, finished)?;
+    }
+    
+    info!("Progress saved");
+    Ok(())
+}
 
+/// Start imv in place of MPV, warning about the options it can't honour
+#[cfg(unix)]
+async fn launch_imv(player: &PlayerArgs, media_files: &[MediaSource], hud: bool) -> Result<Box<dyn MediaPlayer>> {
+    if hud || player.spreads || player.dual_window || player.keybinds.is_some() {
+        warn!("imv has no HUD, spreads, second window or custom keybinds; use its own keys to turn pages");
+    }
+    let imv = ImvPlayer::launch(media_files, None).await?;
+    info!("imv launched successfully!");
+    Ok(Box::new(imv))
+}
+
+#[cfg(not(unix))]
+async fn launch_imv(_player: &PlayerArgs, _media_files: &[MediaSource], _hud: bool) -> Result<Box<dyn MediaPlayer>> {
+    anyhow::bail!("imv is only available on Unix")
+}
+
+async fn test_mpv_controller(player: PlayerArgs, files: Vec<PathBuf>) -> Result<()> {
+    info!("Testing MPV controller...");
+    
     // Expand directories and validate files
     let media_files = if files.is_empty() {
         // Default test files
-        vec![PathBuf::from("/dev/null")]
+        vec![MediaSource::Local(PathBuf::from("/dev/null"))]
     } else {
-        expand_media_files(files)?
+        expand_media_files(files, player.expand_options())?
     };
     
     if media_files.is_empty() {
@@ -150,101 +993,132 @@ async fn test_mpv_controller(mpv_path: Option<PathBuf>, files: Vec<PathBuf>) ->
     }
     
     info!("Testing with {} files", media_files.len());
-
+    
     // Create keybind profile
-    let keybind_profile = KeybindProfile::default();
-    let keybind_path = keybind_profile.create_temp_config()?;
-
+    let profile = player.profile_for(&media_files);
+    let keybind_profile = load_keybind_profile(profile, player.keybinds.as_deref())?;
+    let launch_options = player.launch_options(profile, &keybind_profile, &storage::data_dir().join("screenshots"), &media_files)?;
+    
     // Socket path in temp directory
-    let socket_path = std::env::temp_dir().join("syncread_mpv.socket");
-
+    let socket_path = socket_path_for("mpv");
+    
+    
+    info!("Keybind config at: {:?}", launch_options.keybind_config);
+    info!("MPV config at: {:?}", launch_options.mpv_config);
     
-    info!("Keybind config at: {:?}", keybind_path);
-    info!("Keybind config exists: {}", keybind_path.exists());
-
     // Launch MPV
-    let mut controller = MpvController::launch(
-        &socket_path,
-        Some(&keybind_path),
-        media_files.iter().collect(),
-        mpv_path.as_deref(),
-    ).await?;
-
+    let mut controller = MpvController::launch(&socket_path, &media_files, launch_options).await.map_err(explain)?;
+    
     info!("MPV launched successfully!");
-
+    
     // Test basic commands
     tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
-
+    
     info!("Testing MPV commands...");
-
+    
     // Get initial state
     let pos = controller.get_position().await?;
     let playlist_pos = controller.get_playlist_pos().await?;
     let paused = controller.is_paused().await?;
-
+    
     info!(
         "Initial state - Position: {:.2}s, Playlist: {}, Paused: {}",
         pos, playlist_pos, paused
     );
-
+    
     // Test play/pause
     if paused {
         info!("Starting playback...");
         controller.play().await?;
         tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
-
+        
         info!("Pausing playback...");
         controller.pause().await?;
     }
-
+    
     info!("MPV controller test completed!");
     info!("MPV should be running. Press 'q' in MPV to quit, or Ctrl+C here.");
-
+    
     // Keep the program running so you can interact with MPV
     tokio::signal::ctrl_c().await?;
     info!("Shutting down...");
-
+    
     Ok(())
 }
 
-/// Expand directories and filter for media files
-fn expand_media_files(paths: Vec<PathBuf>) -> Result<Vec<PathBuf>> {
-    let mut media_files = Vec::new();
-    
-    for path in paths {
-        if path.is_file() {
-            media_files.push(path);
-        } else if path.is_dir() {
-            // Read directory and add media files
-            let entries = std::fs::read_dir(&path)
-                .with_context(|| format!("Failed to read directory: {:?}", path))?;
-                
-            let mut dir_files: Vec<PathBuf> = entries
-                .filter_map(|entry| entry.ok())
-                .map(|entry| entry.path())
-                .filter(|p| p.is_file() && is_media_file(p))
-                .collect();
-                
-            dir_files.sort(); // Sort for consistent ordering
-            media_files.extend(dir_files);
-        } else {
-            anyhow::bail!("Path does not exist: {:?}", path);
-        }
-    }
-    
-    Ok(media_files)
-}
-
-/// Check if a file appears to be a media file based on extension
-fn is_media_file(path: &PathBuf) -> bool {
-    if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
-        let ext = ext.to_lowercase();
-        matches!(ext.as_str(), 
-            "jpg" | "jpeg" | "png" | "gif" | "bmp" | "webp" | "tiff" |
-            "mp4" | "mkv" | "avi" | "mov" | "wmv" | "flv" | "webm" |
-            "mp3" | "wav" | "flac" | "ogg" | "m4a" | "aac"
-        )
-    } else {
-        false
+fn format_addrs(addrs: &[SocketAddr]) -> String {
+    addrs.iter().map(|a| a.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+/// Add a hint about what to do next to a library error
+fn explain(e: Error) -> anyhow::Error {
+    let hint = match &e {
+        Error::Connection(ConnectionError::Connect { .. }) => Some("is the server running, and reachable at --server?".to_string()),
+        Error::Connection(ConnectionError::Bind { .. }) => Some("is something else using the port? Pick another with --bind".to_string()),
+        Error::Ipc(IpcError::NotFound) => Some(format!("{}. If it's installed somewhere else, point --mpv-path at it", install_hint())),
+        Error::Ipc(IpcError::Spawn { .. }) => Some("--mpv-path (or mpv_path in the config) should be the MPV executable itself".to_string()),
+        e if e.is_retryable() => Some("this may be temporary, try again in a moment".to_string()),
+        _ => None,
+    };
+    match hint {
+        Some(hint) => anyhow::anyhow!("{:#}\n\nHint: {}", e, hint),
+        None => e.into(),
+    }
+}
+
+/// Print servers found by `client --discover`
+fn print_discovered(servers: &[DiscoveredServer]) {
+    if servers.is_empty() {
+        println!("No servers found on the local network");
+        return;
+    }
+    
+    for server in servers {
+        println!("{:<30} {}", server.name, server.addr);
     }
 }
+
+/// `syncread keybinds ...`
+fn keybinds_command(action: KeybindsAction) -> Result<()> {
+    match action {
+        KeybindsAction::Dump { output, profile } => match output {
+            Some(path) => profile.build().write_to_file(path),
+            None => {
+                print!("{}", profile.build().generate_config());
+                Ok(())
+            }
+        },
+        KeybindsAction::Diff { file, profile } => {
+            let changes = profile.build().changes(&read_keybind_file(&file)?);
+            if changes.is_empty() {
+                println!("{:?} doesn't change the {} profile", file, profile.profile);
+            }
+            for change in changes {
+                println!("{}", change);
+            }
+            Ok(())
+        }
+        KeybindsAction::Check { file, profile } => {
+            let bindings = read_keybind_file(&file)?;
+            let mut merged = profile.build();
+            for (key, command) in &bindings {
+                merged.set_keybind(key.clone(), command.clone());
+            }
+            merged.validate().with_context(|| format!("{:?} breaks the {} profile", file, profile.profile))?;
+            println!("{:?} is fine: {} keybinds over the {} profile", file, bindings.len(), profile.profile);
+            Ok(())
+        }
+    }
+}
+
+/// Build the sync keybind profile, merging user overrides if given
+fn load_keybind_profile(kind: ProfileKind, keybinds: Option<&Path>) -> Result<KeybindProfile> {
+    let mut profile = kind.build();
+    
+    if let Some(path) = keybinds {
+        profile.merge_from_file(path)?;
+    }
+    
+    profile.validate().context("Keybind profile is invalid")?;
+    Ok(profile)
+}
@@ -0,0 +1,185 @@
+use super::{is_media_file, SortOrder};
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use tempfile::TempDir;
+use tracing::{debug, info};
+use zip::ZipArchive;
+
+/// Check if a file is a comic/zip archive we know how to unpack
+pub fn is_archive(path: &Path) -> bool {
+    archive_kind(path).is_some()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ArchiveKind {
+    Zip,
+    Rar,
+}
+
+fn archive_kind(path: &Path) -> Option<ArchiveKind> {
+    let ext = path.extension()?.to_str()?.to_lowercase();
+    match ext.as_str() {
+        "cbz" | "zip" => Some(ArchiveKind::Zip),
+        "cbr" | "rar" => Some(ArchiveKind::Rar),
+        _ => None,
+    }
+}
+
+/// Every extraction directory so far, kept until `clean_up`
+static EXTRACTED: Mutex<Vec<TempDir>> = Mutex::new(Vec::new());
+
+/// Extract an archive into a temp directory and return its pages in the given order
+///
+/// Each extraction gets a directory of its own, so two clients on one
+/// machine can open the same archive; the pages stay until `clean_up`.
+pub fn extract_archive(archive: &Path, sort: SortOrder) -> Result<Vec<PathBuf>> {
+    let kind = archive_kind(archive)
+        .with_context(|| format!("Not a supported archive: {:?}", archive))?;
+    
+    let stem = archive.file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("archive");
+    let target = tempfile::Builder::new()
+        .prefix(&format!("syncread_archive_{}_", stem))
+        .tempdir()
+        .context("Failed to create extraction directory")?;
+    
+    info!("Extracting {:?} to {:?}", archive, target.path());
+    match kind {
+        ArchiveKind::Zip => extract_zip(archive, target.path())?,
+        ArchiveKind::Rar => run_extractor(archive, target.path())?,
+    }
+    
+    let mut pages = Vec::new();
+    collect_media_files(target.path(), &mut pages)?;
+    sort.sort_paths(&mut pages);
+    EXTRACTED.lock().unwrap().push(target);
+    
+    info!("Extracted {} pages from {:?}", pages.len(), archive);
+    Ok(pages)
+}
+
+/// Delete everything `extract_archive` unpacked; its pages are gone afterwards
+pub fn clean_up() {
+    EXTRACTED.lock().unwrap().clear();
+}
+
+/// Unpack a ZIP in-process, refusing it if an entry's path would leave `target`
+fn extract_zip(archive: &Path, target: &Path) -> Result<()> {
+    let file = fs::File::open(archive)
+        .with_context(|| format!("Failed to open {:?}", archive))?;
+    let mut zip = ZipArchive::new(file)
+        .with_context(|| format!("Not a ZIP archive: {:?}", archive))?;
+    zip.extract(target)
+        .with_context(|| format!("Failed to extract {:?}", archive))
+}
+
+/// Try the available RAR extractors in order of preference
+fn run_extractor(archive: &Path, target: &Path) -> Result<()> {
+    let archive_arg = archive.as_os_str();
+    let target_arg = target.as_os_str();
+    
+    let candidates: [(&str, Vec<&std::ffi::OsStr>); 3] = [
+        ("unrar", vec!["x".as_ref(), "-o+".as_ref(), "-inul".as_ref(), archive_arg, target_arg]),
+        ("bsdtar", vec!["-xf".as_ref(), archive_arg, "-C".as_ref(), target_arg]),
+        ("7z", vec!["x".as_ref(), "-y".as_ref(), archive_arg]),
+    ];
+    
+    for (program, args) in candidates {
+        let mut cmd = Command::new(program);
+        cmd.args(&args)
+           .stdout(Stdio::null())
+           .stderr(Stdio::null());
+        
+        // 7z takes its output directory as a glued -o flag
+        if program == "7z" {
+            let mut out_flag = std::ffi::OsString::from("-o");
+            out_flag.push(target_arg);
+            cmd.arg(out_flag);
+        }
+        
+        match cmd.status() {
+            Ok(status) if status.success() => {
+                debug!("Extracted {:?} with {}", archive, program);
+                return Ok(());
+            }
+            Ok(status) => debug!("{} failed on {:?} with {}", program, archive, status),
+            Err(e) => debug!("{} not available: {}", program, e),
+        }
+    }
+    
+    anyhow::bail!("Could not extract {:?} (install unrar, bsdtar or 7z)", archive);
+}
+
+/// Recursively gather media files, since archives often wrap pages in a folder
+fn collect_media_files(dir: &Path, out: &mut Vec<PathBuf>) -> Result<()> {
+    let entries = fs::read_dir(dir)
+        .with_context(|| format!("Failed to read directory: {:?}", dir))?;
+    
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let path = entry.path();
+        let name = entry.file_name();
+        
+        // Skip macOS resource forks and other hidden metadata
+        if name.to_string_lossy().starts_with('.') || name == "__MACOSX" {
+            continue;
+        }
+        
+        if path.is_dir() {
+            collect_media_files(&path, out)?;
+        } else if is_media_file(&path) {
+            out.push(path);
+        }
+    }
+    
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_archive_detection() {
+        assert!(is_archive(Path::new("vol1.cbz")));
+        assert!(is_archive(Path::new("vol1.CBR")));
+        assert!(is_archive(Path::new("pages.zip")));
+        assert!(!is_archive(Path::new("page1.png")));
+        assert!(!is_archive(Path::new("noext")));
+    }
+    
+    fn write_zip(path: &Path, names: &[&str]) {
+        use std::io::Write;
+        
+        let mut zip = zip::ZipWriter::new(fs::File::create(path).unwrap());
+        for name in names {
+            zip.start_file(*name, zip::write::SimpleFileOptions::default()).unwrap();
+            zip.write_all(b"page").unwrap();
+        }
+        zip.finish().unwrap();
+    }
+    
+    #[test]
+    fn test_extract_zip() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("vol1.cbz");
+        write_zip(&archive, &["vol1/p10.png", "vol1/p2.png", "__MACOSX/._p2.png", "notes.txt"]);
+        
+        let first = extract_archive(&archive, SortOrder::Natural).unwrap();
+        let names: Vec<_> = first.iter().map(|page| page.file_name().unwrap().to_str().unwrap()).collect();
+        assert_eq!(names, ["p2.png", "p10.png"]);
+        
+        // Opening it again doesn't touch the pages already handed out
+        let second = extract_archive(&archive, SortOrder::Natural).unwrap();
+        assert_ne!(first[0].parent(), second[0].parent());
+        assert!(first[0].exists());
+        
+        let escaping = dir.path().join("escape.cbz");
+        write_zip(&escaping, &["p1.png", "../escape.png"]);
+        assert!(extract_archive(&escaping, SortOrder::Natural).is_err());
+        assert!(!dir.path().join("escape.png").exists());
+    }
+}
@@ -0,0 +1,270 @@
+pub mod archive;
+pub mod playlist_file;
+pub mod sort;
+pub mod source;
+pub mod spread;
+
+pub use archive::{extract_archive, is_archive};
+pub use playlist_file::{is_playlist_file, parse_playlist_file};
+pub use sort::{natural_cmp, SortOrder};
+pub use source::MediaSource;
+pub use spread::SpreadLayout;
+
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// Options controlling how input paths are expanded into a playlist
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ExpandOptions {
+    pub sort: SortOrder,
+    /// Descend into subdirectories (one chapter per folder)
+    pub recursive: bool,
+}
+
+/// Expand directories and archives, filtering for media files
+///
+/// URL inputs are passed through untouched for mpv to stream.
+pub fn expand_media_files(paths: Vec<PathBuf>, options: ExpandOptions) -> Result<Vec<MediaSource>> {
+    let mut media_files = Vec::new();
+    let mut visited = HashSet::new();
+    
+    for path in paths {
+        if let MediaSource::Remote(url) = MediaSource::parse(&path.to_string_lossy()) {
+            media_files.push(MediaSource::Remote(url));
+        } else if path.is_file() {
+            push_media_file(path, options, &mut media_files)?;
+        } else if path.is_dir() {
+            expand_dir(&path, options, &mut visited, &mut media_files)?;
+        } else {
+            anyhow::bail!("Path does not exist: {:?}", path);
+        }
+    }
+    
+    Ok(media_files)
+}
+
+/// Add a directory's media files, then (when recursive) each subdirectory depth-first
+///
+/// Files come before subfolders so a cover page at the top level stays first.
+fn expand_dir(
+    dir: &Path,
+    options: ExpandOptions,
+    visited: &mut HashSet<PathBuf>,
+    media_files: &mut Vec<MediaSource>,
+) -> Result<()> {
+    // Guard against symlink loops when recursing
+    let canonical = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+    if !visited.insert(canonical) {
+        return Ok(());
+    }
+    
+    let entries = std::fs::read_dir(dir)
+        .with_context(|| format!("Failed to read directory: {:?}", dir))?;
+    
+    let mut dir_files = Vec::new();
+    let mut subdirs = Vec::new();
+    
+    for path in entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()) {
+        if path.is_file() && (is_media_file(&path) || is_archive(&path)) {
+            dir_files.push(path);
+        } else if options.recursive && path.is_dir() && !is_hidden(&path) {
+            subdirs.push(path);
+        }
+    }
+    
+    // Same order on every client
+    options.sort.sort_paths(&mut dir_files);
+    options.sort.sort_paths(&mut subdirs);
+    
+    for file in dir_files {
+        push_media_file(file, options, media_files)?;
+    }
+    
+    for subdir in subdirs {
+        expand_dir(&subdir, options, visited, media_files)?;
+    }
+    
+    Ok(())
+}
+
+/// Add a single file, unpacking it first if it is an archive or playlist
+fn push_media_file(path: PathBuf, options: ExpandOptions, media_files: &mut Vec<MediaSource>) -> Result<()> {
+    if is_archive(&path) {
+        media_files.extend(extract_archive(&path, options.sort)?.into_iter().map(MediaSource::Local));
+    } else if is_playlist_file(&path) {
+        push_playlist_entries(&path, options, media_files)?;
+    } else {
+        media_files.push(MediaSource::Local(path));
+    }
+    Ok(())
+}
+
+/// Add the entries of an M3U/PLS playlist, keeping the playlist's own order
+fn push_playlist_entries(playlist: &Path, options: ExpandOptions, media_files: &mut Vec<MediaSource>) -> Result<()> {
+    for entry in parse_playlist_file(playlist)? {
+        let path = match playlist_file::resolve_entry(playlist, &entry) {
+            MediaSource::Local(path) => path,
+            remote => {
+                media_files.push(remote);
+                continue;
+            }
+        };
+        
+        if path.is_dir() {
+            expand_dir(&path, options, &mut HashSet::new(), media_files)?;
+        } else if path.is_file() {
+            // Nested playlists are not followed to avoid include cycles
+            if is_playlist_file(&path) {
+                warn!("Ignoring nested playlist {:?} in {:?}", path, playlist);
+            } else {
+                push_media_file(path, options, media_files)?;
+            }
+        } else {
+            anyhow::bail!("Playlist {:?} references missing file: {:?}", playlist, path);
+        }
+    }
+    Ok(())
+}
+
+/// Stable identifier for a playlist, independent of where its files live
+///
+/// Built from entry names only, so two users with the same chapter in
+/// different folders get the same fingerprint.
+pub fn playlist_fingerprint(files: &[MediaSource]) -> String {
+    let mut hasher = Sha256::new();
+    for file in files {
+        hasher.update(file.display_name().unwrap_or_default().as_bytes());
+        hasher.update(b"\n");
+    }
+    
+    hasher.finalize()
+        .iter()
+        .take(8)
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|n| n.to_str())
+        .is_some_and(|n| n.starts_with('.'))
+}
+
+/// What a media file holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaKind {
+    Image,
+    Video,
+    Audio,
+}
+
+/// What kind of media a file holds, based on extension
+pub fn media_kind(path: &Path) -> Option<MediaKind> {
+    let ext = path.extension()?.to_str()?.to_lowercase();
+    match ext.as_str() {
+        "jpg" | "jpeg" | "png" | "gif" | "bmp" | "webp" | "tiff" => Some(MediaKind::Image),
+        "mp4" | "mkv" | "avi" | "mov" | "wmv" | "flv" | "webm" => Some(MediaKind::Video),
+        "mp3" | "wav" | "flac" | "ogg" | "m4a" | "aac" => Some(MediaKind::Audio),
+        _ => None,
+    }
+}
+
+/// The kind most local files in a playlist are, ties going to video; None if none are recognised
+pub fn majority_kind(files: &[MediaSource]) -> Option<MediaKind> {
+    let count = |kind| files.iter()
+        .filter(|file| file.as_path().and_then(media_kind) == Some(kind))
+        .count();
+    [MediaKind::Audio, MediaKind::Image, MediaKind::Video].into_iter()
+        .map(|kind| (kind, count(kind)))
+        .filter(|(_, n)| *n > 0)
+        .max_by_key(|(_, n)| *n)
+        .map(|(kind, _)| kind)
+}
+
+/// Check if a file appears to be a media file based on extension
+pub fn is_media_file(path: &Path) -> bool {
+    media_kind(path).is_some()
+}
+
+/// Check if a file is a still image (a page) based on extension
+pub fn is_image_file(path: &Path) -> bool {
+    media_kind(path) == Some(MediaKind::Image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    
+    #[test]
+    fn test_fingerprint_ignores_directories() {
+        let a = vec![MediaSource::parse("/home/a/manga/p1.png"), MediaSource::parse("/home/a/manga/p2.png")];
+        let b = vec![MediaSource::parse("/mnt/b/p1.png"), MediaSource::parse("/mnt/b/p2.png")];
+        let c = vec![MediaSource::parse("/mnt/b/p2.png"), MediaSource::parse("/mnt/b/p1.png")];
+        
+        assert_eq!(playlist_fingerprint(&a), playlist_fingerprint(&b));
+        assert_ne!(playlist_fingerprint(&a), playlist_fingerprint(&c));
+        assert_eq!(playlist_fingerprint(&a).len(), 16);
+    }
+    
+    #[test]
+    fn test_majority_kind() {
+        let playlist: Vec<MediaSource> = ["cover.JPG", "p1.png", "p2.png", "bonus.mkv", "https://example.com/p3.png"]
+            .into_iter().map(MediaSource::parse).collect();
+        assert_eq!(majority_kind(&playlist), Some(MediaKind::Image));
+        assert_eq!(majority_kind(&playlist[2..4]), Some(MediaKind::Video));
+        assert_eq!(majority_kind(&[]), None);
+    }
+    
+    #[test]
+    fn test_playlist_file_expansion() {
+        let root = std::env::temp_dir().join(format!("syncread_test_m3u_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("pages")).unwrap();
+        for file in ["pages/b.png", "pages/a.png"] {
+            fs::write(root.join(file), b"").unwrap();
+        }
+        fs::write(root.join("order.m3u"), "#EXTM3U\npages/b.png\npages/a.png\n").unwrap();
+        
+        let files = expand_media_files(vec![root.join("order.m3u")], ExpandOptions::default()).unwrap();
+        let _ = fs::remove_dir_all(&root);
+        
+        assert_eq!(files, vec![
+            MediaSource::Local(root.join("pages/b.png")),
+            MediaSource::Local(root.join("pages/a.png")),
+        ]);
+    }
+    
+    #[test]
+    fn test_recursive_expansion_order() {
+        let root = std::env::temp_dir().join(format!("syncread_test_expand_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("ch10")).unwrap();
+        fs::create_dir_all(root.join("ch2")).unwrap();
+        for file in ["cover.jpg", "ch2/p1.png", "ch2/p10.png", "ch2/p2.png", "ch10/p1.png"] {
+            fs::write(root.join(file), b"").unwrap();
+        }
+        
+        let flat = expand_media_files(vec![root.clone()], ExpandOptions::default()).unwrap();
+        let deep = expand_media_files(
+            vec![root.clone()],
+            ExpandOptions { recursive: true, ..Default::default() },
+        ).unwrap();
+        let _ = fs::remove_dir_all(&root);
+        
+        assert_eq!(flat, vec![MediaSource::Local(root.join("cover.jpg"))]);
+        let relative: Vec<_> = deep.iter()
+            .map(|p| p.as_path().unwrap().strip_prefix(&root).unwrap().to_path_buf())
+            .collect();
+        assert_eq!(relative, vec![
+            PathBuf::from("cover.jpg"),
+            PathBuf::from("ch2/p1.png"),
+            PathBuf::from("ch2/p2.png"),
+            PathBuf::from("ch2/p10.png"),
+            PathBuf::from("ch10/p1.png"),
+        ]);
+    }
+}
@@ -0,0 +1,125 @@
+use super::MediaSource;
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+/// Check if a file is an M3U/PLS playlist
+pub fn is_playlist_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| matches!(e.to_lowercase().as_str(), "m3u" | "m3u8" | "pls"))
+}
+
+/// Read a playlist file and return its entries in order
+///
+/// Relative entries are resolved against the playlist's own directory, so a
+/// shared playlist works wherever the media folder lives.
+pub fn parse_playlist_file(path: &Path) -> Result<Vec<String>> {
+    let bytes = fs::read(path)
+        .with_context(|| format!("Failed to read playlist {:?}", path))?;
+    // Plain .m3u files are frequently Latin-1; don't reject them outright
+    let content = String::from_utf8_lossy(&bytes);
+    
+    let is_pls = path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("pls"));
+    
+    let entries = if is_pls {
+        parse_pls(&content)
+    } else {
+        parse_m3u(&content)
+    };
+    
+    debug!("Playlist {:?} contains {} entries", path, entries.len());
+    Ok(entries)
+}
+
+/// Resolve a playlist entry relative to the playlist file
+///
+/// URLs are kept as remote sources for mpv to stream.
+pub fn resolve_entry(playlist: &Path, entry: &str) -> MediaSource {
+    let path = match MediaSource::parse(entry) {
+        MediaSource::Local(_) if entry.starts_with("file://") => {
+            PathBuf::from(percent_decode(&entry["file://".len()..]))
+        }
+        MediaSource::Local(path) => path,
+        remote => return remote,
+    };
+    
+    if path.is_absolute() {
+        MediaSource::Local(path)
+    } else {
+        let base = playlist.parent().unwrap_or_else(|| Path::new("."));
+        MediaSource::Local(base.join(path))
+    }
+}
+
+fn parse_m3u(content: &str) -> Vec<String> {
+    content.lines()
+        .map(|line| line.trim().trim_start_matches('\u{feff}'))
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.to_string())
+        .collect()
+}
+
+fn parse_pls(content: &str) -> Vec<String> {
+    let mut entries: Vec<(u32, String)> = content.lines()
+        .filter_map(|line| {
+            let (key, value) = line.trim().split_once('=')?;
+            let index = key.trim().strip_prefix("File")?.parse().ok()?;
+            Some((index, value.trim().to_string()))
+        })
+        .collect();
+    
+    // PLS entries are numbered and may appear in any order
+    entries.sort_by_key(|(index, _)| *index);
+    entries.into_iter().map(|(_, value)| value).collect()
+}
+
+/// Decode %XX escapes in file:// URIs
+fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = |b: u8| (b as char).to_digit(16);
+            if let (Some(hi), Some(lo)) = (hex(bytes[i + 1]), hex(bytes[i + 2])) {
+                out.push((hi * 16 + lo) as u8);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_parse_m3u() {
+        let content = "#EXTM3U\n#EXTINF:-1,Chapter 1\nch1/p1.png\n\n/abs/p2.png\n";
+        assert_eq!(parse_m3u(content), vec!["ch1/p1.png", "/abs/p2.png"]);
+    }
+    
+    #[test]
+    fn test_parse_pls_ordering() {
+        let content = "[playlist]\nFile2=b.png\nTitle2=B\nFile1=a.png\nNumberOfEntries=2\n";
+        assert_eq!(parse_pls(content), vec!["a.png", "b.png"]);
+    }
+    
+    #[test]
+    fn test_resolve_entry() {
+        let playlist = Path::new("/library/reading.m3u");
+        assert_eq!(resolve_entry(playlist, "vol1/p1.png"), MediaSource::Local(PathBuf::from("/library/vol1/p1.png")));
+        assert_eq!(resolve_entry(playlist, "file:///x/My%20Page.png"), MediaSource::Local(PathBuf::from("/x/My Page.png")));
+        assert!(resolve_entry(playlist, "https://example.com/a.mp4").is_remote());
+    }
+}
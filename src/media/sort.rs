@@ -0,0 +1,119 @@
+use std::cmp::Ordering;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// Ordering applied to files discovered in directories and archives
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum SortOrder {
+    /// Numeric-aware ordering (page2 before page10)
+    #[default]
+    Natural,
+    /// Plain byte-wise path ordering
+    Lexical,
+    /// Oldest modification time first
+    Mtime,
+}
+
+impl SortOrder {
+    /// Sort paths in place according to this order
+    pub fn sort_paths(self, paths: &mut [PathBuf]) {
+        match self {
+            SortOrder::Natural => {
+                paths.sort_by(|a, b| natural_cmp(&a.to_string_lossy(), &b.to_string_lossy()));
+            }
+            SortOrder::Lexical => paths.sort(),
+            SortOrder::Mtime => {
+                let mtime = |p: &PathBuf| {
+                    p.metadata()
+                        .and_then(|m| m.modified())
+                        .unwrap_or(SystemTime::UNIX_EPOCH)
+                };
+                // Fall back to natural order for identical timestamps (e.g. freshly extracted archives)
+                paths.sort_by(|a, b| {
+                    mtime(a).cmp(&mtime(b))
+                        .then_with(|| natural_cmp(&a.to_string_lossy(), &b.to_string_lossy()))
+                });
+            }
+        }
+    }
+}
+
+/// Compare two strings the way a human would order page names
+///
+/// Runs of ASCII digits are compared by numeric value, so `page2` sorts
+/// before `page10`. Everything else compares case-insensitively, with the
+/// raw string as a final tie-breaker to keep the order total.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a_chars = a.chars().peekable();
+    let mut b_chars = b.chars().peekable();
+    
+    loop {
+        match (a_chars.peek().copied(), b_chars.peek().copied()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(ca), Some(cb)) if ca.is_ascii_digit() && cb.is_ascii_digit() => {
+                let num_a = take_digits(&mut a_chars);
+                let num_b = take_digits(&mut b_chars);
+                
+                // Compare by magnitude first (ignoring leading zeros), then digit-wise
+                let trimmed_a = num_a.trim_start_matches('0');
+                let trimmed_b = num_b.trim_start_matches('0');
+                let ordering = trimmed_a.len().cmp(&trimmed_b.len())
+                    .then_with(|| trimmed_a.cmp(trimmed_b));
+                
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(ca), Some(cb)) => {
+                let ordering = ca.to_lowercase().cmp(cb.to_lowercase());
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                a_chars.next();
+                b_chars.next();
+            }
+        }
+    }
+}
+
+fn take_digits(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    let mut digits = String::new();
+    while let Some(c) = chars.peek().copied().filter(|c| c.is_ascii_digit()) {
+        digits.push(c);
+        chars.next();
+    }
+    digits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_numeric_runs() {
+        let mut names = vec!["page10.png", "page2.png", "page1.png", "Page3.png"];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(names, vec!["page1.png", "page2.png", "Page3.png", "page10.png"]);
+    }
+    
+    #[test]
+    fn test_sort_orders() {
+        let files = vec![PathBuf::from("a/p10.png"), PathBuf::from("a/p9.png")];
+        
+        let mut natural = files.clone();
+        SortOrder::Natural.sort_paths(&mut natural);
+        assert_eq!(natural[0], PathBuf::from("a/p9.png"));
+        
+        let mut lexical = files;
+        SortOrder::Lexical.sort_paths(&mut lexical);
+        assert_eq!(lexical[0], PathBuf::from("a/p10.png"));
+    }
+    
+    #[test]
+    fn test_leading_zeros() {
+        assert_eq!(natural_cmp("p007", "p7"), "p007".cmp("p7"));
+        assert_eq!(natural_cmp("p009", "p10"), Ordering::Less);
+    }
+}
@@ -0,0 +1,133 @@
+use serde::{Deserialize, Serialize};
+use std::ffi::OsStr;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// A playlist entry: either a local file or a URL that mpv streams itself
+///
+/// Serialized as a plain string so the wire format matches plain paths.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub enum MediaSource {
+    Local(PathBuf),
+    Remote(String),
+}
+
+impl MediaSource {
+    /// Classify a raw input string as a URL or a local path
+    pub fn parse(input: &str) -> Self {
+        if let Some(rest) = input.strip_prefix("file://") {
+            return MediaSource::Local(PathBuf::from(rest));
+        }
+        
+        if is_url(input) {
+            MediaSource::Remote(input.to_string())
+        } else {
+            MediaSource::Local(PathBuf::from(input))
+        }
+    }
+    
+    pub fn is_remote(&self) -> bool {
+        matches!(self, MediaSource::Remote(_))
+    }
+    
+    /// Local path, if this entry is a file on disk
+    pub fn as_path(&self) -> Option<&Path> {
+        match self {
+            MediaSource::Local(path) => Some(path),
+            MediaSource::Remote(_) => None,
+        }
+    }
+    
+    /// Short name for displays: file name, or last URL path segment
+    pub fn display_name(&self) -> Option<String> {
+        match self {
+            MediaSource::Local(path) => path.file_name()
+                .and_then(|n| n.to_str())
+                .map(|s| s.to_string()),
+            MediaSource::Remote(url) => {
+                let without_query = url.split(['?', '#']).next().unwrap_or(url);
+                let segment = without_query.trim_end_matches('/')
+                    .rsplit('/')
+                    .next()
+                    .filter(|s| !s.is_empty() && !s.contains(':'));
+                Some(segment.unwrap_or(url).to_string())
+            }
+        }
+    }
+}
+
+/// `scheme://...` with an alphanumeric scheme, as mpv accepts for streams
+fn is_url(input: &str) -> bool {
+    match input.split_once("://") {
+        Some((scheme, rest)) => {
+            scheme.len() > 1
+                && !rest.is_empty()
+                && scheme.chars().all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.')
+        }
+        None => false,
+    }
+}
+
+impl From<String> for MediaSource {
+    fn from(value: String) -> Self {
+        MediaSource::parse(&value)
+    }
+}
+
+impl From<MediaSource> for String {
+    fn from(value: MediaSource) -> Self {
+        value.to_string()
+    }
+}
+
+impl From<PathBuf> for MediaSource {
+    fn from(path: PathBuf) -> Self {
+        MediaSource::Local(path)
+    }
+}
+
+impl AsRef<OsStr> for MediaSource {
+    fn as_ref(&self) -> &OsStr {
+        match self {
+            MediaSource::Local(path) => path.as_os_str(),
+            MediaSource::Remote(url) => OsStr::new(url),
+        }
+    }
+}
+
+impl fmt::Display for MediaSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MediaSource::Local(path) => write!(f, "{}", path.display()),
+            MediaSource::Remote(url) => write!(f, "{}", url),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_parse_sources() {
+        assert!(MediaSource::parse("https://example.com/v.mp4").is_remote());
+        assert!(MediaSource::parse("ytdl://abc").is_remote());
+        assert!(!MediaSource::parse("/home/me/p1.png").is_remote());
+        assert!(!MediaSource::parse("C:\\Manga\\p1.png").is_remote());
+        assert_eq!(MediaSource::parse("file:///tmp/a.png"), MediaSource::Local(PathBuf::from("/tmp/a.png")));
+    }
+    
+    #[test]
+    fn test_display_name_and_serde() {
+        let url = MediaSource::parse("https://example.com/videos/ep1.mkv?token=x");
+        assert_eq!(url.display_name().as_deref(), Some("ep1.mkv"));
+        
+        let json = serde_json::to_string(&url).unwrap();
+        assert_eq!(json, "\"https://example.com/videos/ep1.mkv?token=x\"");
+        assert_eq!(serde_json::from_str::<MediaSource>(&json).unwrap(), url);
+        
+        let path: MediaSource = serde_json::from_str("\"/tmp/p1.png\"").unwrap();
+        assert_eq!(path, MediaSource::Local(PathBuf::from("/tmp/p1.png")));
+    }
+}
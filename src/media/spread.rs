@@ -0,0 +1,104 @@
+use super::{is_image_file, MediaSource};
+
+/// Grouping of pages into double-page spreads, one group per MPV playlist entry
+///
+/// Positions exchanged with other clients stay in page units (the first page
+/// of the visible spread), so spread and single-page readers still line up.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpreadLayout {
+    entries: Vec<Vec<usize>>,
+    /// Show the first image on its own, like a book cover
+    pub cover_alone: bool,
+    /// Place the second page on the left (manga order)
+    pub rtl: bool,
+}
+
+impl SpreadLayout {
+    /// Pair consecutive images; videos, remote entries and (optionally) the cover stay single
+    pub fn new(files: &[MediaSource], cover_alone: bool, rtl: bool) -> Self {
+        let mut entries: Vec<Vec<usize>> = Vec::new();
+        let mut open_pair: Option<usize> = None;
+        let mut seen_image = false;
+        
+        for (page, file) in files.iter().enumerate() {
+            let is_image = file.as_path().is_some_and(is_image_file);
+            
+            if !is_image {
+                entries.push(vec![page]);
+                open_pair = None;
+                continue;
+            }
+            
+            if cover_alone && !seen_image {
+                seen_image = true;
+                entries.push(vec![page]);
+                continue;
+            }
+            seen_image = true;
+            
+            match open_pair.take() {
+                Some(entry) => entries[entry].push(page),
+                None => {
+                    open_pair = Some(entries.len());
+                    entries.push(vec![page]);
+                }
+            }
+        }
+        
+        Self { entries, cover_alone, rtl }
+    }
+    
+    /// The same pages shifted by one, for books whose spreads start on the other side
+    pub fn realigned(&self, files: &[MediaSource]) -> Self {
+        Self::new(files, !self.cover_alone, self.rtl)
+    }
+    
+    /// Page indices shown by each playlist entry
+    pub fn entries(&self) -> &[Vec<usize>] {
+        &self.entries
+    }
+    
+    /// Playlist entry containing `page`
+    pub fn entry_for_page(&self, page: usize) -> usize {
+        self.entries.iter()
+            .position(|pages| pages.contains(&page))
+            .unwrap_or_else(|| self.entries.len().saturating_sub(1))
+    }
+    
+    /// First page shown by playlist `entry`
+    pub fn first_page(&self, entry: usize) -> usize {
+        self.entries.get(entry)
+            .and_then(|pages| pages.first().copied())
+            .unwrap_or(entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn pages(names: &[&str]) -> Vec<MediaSource> {
+        names.iter().map(|name| MediaSource::parse(name)).collect()
+    }
+    
+    #[test]
+    fn test_cover_alone_pairing() {
+        let files = pages(&["c.png", "1.png", "2.png", "3.png", "4.png"]);
+        let layout = SpreadLayout::new(&files, true, false);
+        
+        assert_eq!(layout.entries(), &[vec![0], vec![1, 2], vec![3, 4]]);
+        assert_eq!(layout.entry_for_page(2), 1);
+        assert_eq!(layout.first_page(2), 3);
+        
+        let shifted = layout.realigned(&files);
+        assert_eq!(shifted.entries(), &[vec![0, 1], vec![2, 3], vec![4]]);
+    }
+    
+    #[test]
+    fn test_videos_break_pairs() {
+        let files = pages(&["1.png", "clip.mp4", "2.png", "3.png", "https://example.com/4.png"]);
+        let layout = SpreadLayout::new(&files, false, false);
+        
+        assert_eq!(layout.entries(), &[vec![0], vec![1], vec![2, 3], vec![4]]);
+    }
+}
@@ -0,0 +1,91 @@
+//! Finding the MPV binary when `--mpv-path` isn't given
+//!
+//! PATH comes first. Installs that don't touch PATH (the Windows builds,
+//! Homebrew outside a login shell, Flatpak, Scoop) still leave MPV in a
+//! handful of known places, so those are tried after it.
+
+use std::env;
+use std::path::PathBuf;
+
+#[cfg(windows)]
+const BINARY_NAME: &str = "mpv.exe";
+#[cfg(not(windows))]
+const BINARY_NAME: &str = "mpv";
+
+/// The MPV to run: the first one on PATH, or in a common install location
+pub fn find_mpv() -> Option<PathBuf> {
+    let on_path = env::var_os("PATH")
+        .map(|path| env::split_paths(&path).map(|dir| dir.join(BINARY_NAME)).collect::<Vec<_>>())
+        .unwrap_or_default();
+    first_existing(on_path.into_iter().chain(install_locations()))
+}
+
+/// How to get MPV on this platform
+pub fn install_hint() -> &'static str {
+    if cfg!(windows) {
+        "Install MPV with `scoop install mpv` or `winget install mpv`, or download it from https://mpv.io/installation/"
+    } else if cfg!(target_os = "macos") {
+        "Install MPV with `brew install mpv`, or download it from https://mpv.io/installation/"
+    } else {
+        "Install MPV from your distribution's packages (`sudo apt install mpv`, `sudo dnf install mpv`, ...) or from Flathub (`flatpak install flathub io.mpv.Mpv`)"
+    }
+}
+
+fn first_existing(candidates: impl IntoIterator<Item = PathBuf>) -> Option<PathBuf> {
+    candidates.into_iter().find(|path| path.is_file())
+}
+
+/// Where installers put MPV without adding it to PATH
+fn install_locations() -> Vec<PathBuf> {
+    let mut locations = Vec::new();
+    let from_env = |var: &str, rest: &[&str]| {
+        env::var_os(var).map(|base| rest.iter().fold(PathBuf::from(base), |path, part| path.join(part)))
+    };
+    
+    if cfg!(windows) {
+        for var in ["ProgramFiles", "ProgramFiles(x86)", "LOCALAPPDATA"] {
+            locations.extend(from_env(var, &["mpv", BINARY_NAME]));
+            locations.extend(from_env(var, &["Programs", "mpv", BINARY_NAME]));
+        }
+        locations.extend(from_env("SCOOP", &["apps", "mpv", "current", BINARY_NAME]));
+        locations.extend(from_env("USERPROFILE", &["scoop", "apps", "mpv", "current", BINARY_NAME]));
+        locations.extend(from_env("ProgramData", &["chocolatey", "bin", BINARY_NAME]));
+    } else if cfg!(target_os = "macos") {
+        locations.extend(["/opt/homebrew/bin/mpv", "/usr/local/bin/mpv", "/opt/local/bin/mpv", "/Applications/mpv.app/Contents/MacOS/mpv"].map(PathBuf::from));
+        locations.extend(from_env("HOME", &["Applications", "mpv.app", "Contents", "MacOS", "mpv"]));
+    } else {
+        locations.extend(["/usr/bin/mpv", "/usr/local/bin/mpv", "/snap/bin/mpv", "/home/linuxbrew/.linuxbrew/bin/mpv"].map(PathBuf::from));
+        // Flatpak exports a launcher named after the app, which takes mpv's own arguments
+        locations.extend(from_env("HOME", &[".local", "share", "flatpak", "exports", "bin", "io.mpv.Mpv"]));
+        locations.push(PathBuf::from("/var/lib/flatpak/exports/bin/io.mpv.Mpv"));
+    }
+    locations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_first_existing() {
+        let dir = env::temp_dir().join(format!("syncread_test_mpv_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let installed = dir.join(BINARY_NAME);
+        std::fs::write(&installed, "").unwrap();
+        
+        let missing = dir.join("missing").join(BINARY_NAME);
+        assert_eq!(first_existing([missing.clone(), installed.clone()]), Some(installed));
+        assert_eq!(first_existing([missing]), None);
+        // A directory named like the binary isn't it
+        assert_eq!(first_existing([dir.clone()]), None);
+        
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    
+    #[test]
+    fn test_install_locations() {
+        let locations = install_locations();
+        assert!(!locations.is_empty());
+        assert!(locations.iter().all(|path| path.is_absolute()));
+    }
+}
@@ -0,0 +1,18 @@
+use anyhow::{Context, Result};
+use std::path::PathBuf;
+
+/// Lua chat prompt shipped with syncread; its file name is the script name MPV registers
+const CHAT_SCRIPT: &str = include_str!("scripts/syncread_chat.lua");
+
+/// Script name used in `script-binding syncread_chat/open`
+pub const CHAT_SCRIPT_NAME: &str = "syncread_chat";
+
+/// Write the chat script to the temp directory for `--script=`
+pub fn create_temp_script() -> Result<PathBuf> {
+    let script_path = std::env::temp_dir().join(format!("{}.lua", CHAT_SCRIPT_NAME));
+    
+    std::fs::write(&script_path, CHAT_SCRIPT)
+        .with_context(|| format!("Failed to write chat script: {:?}", script_path))?;
+    
+    Ok(script_path)
+}
@@ -0,0 +1,101 @@
+//! The mpv.conf every syncread MPV is started with
+//!
+//! Written to a temporary file next to the keybind profile and passed with
+//! `--include`, so everyone in a session gets the same viewer behaviour
+//! whatever their own mpv.conf says.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{Result, Context};
+use tracing::info;
+
+use super::keybinds::ProfileKind;
+
+/// Options written to the generated mpv.conf, in order
+#[derive(Debug, Clone, Default)]
+pub struct MpvConf {
+    options: Vec<(String, String)>, // (name, value)
+}
+
+impl MpvConf {
+    /// Options for a syncread session with the given preset
+    pub fn for_session(profile: ProfileKind, screenshot_dir: &Path) -> Self {
+        let mut conf = Self::default();
+        
+        // Stay on the last page or frame at the end instead of going idle
+        conf.set("keep-open", "yes");
+        // Only our own messages and seek feedback on screen
+        conf.set("osd-level", "1");
+        conf.set("osd-duration", "2000");
+        // MPV's own screenshots go with the group ones
+        conf.set("screenshot-directory", &screenshot_dir.to_string_lossy());
+        conf.set("screenshot-format", "png");
+        
+        for (name, value) in profile.mpv_options() {
+            conf.set(name, value);
+        }
+        
+        conf
+    }
+    
+    /// Set an option, replacing any earlier value
+    pub fn set(&mut self, name: &str, value: &str) {
+        self.options.retain(|(n, _)| n != name);
+        self.options.push((name.to_string(), value.to_string()));
+    }
+    
+    /// Generate the mpv.conf content
+    pub fn generate_config(&self) -> String {
+        let mut config = String::new();
+        
+        config.push_str("# SyncRead MPV Options\n");
+        config.push_str("# Generated automatically - do not edit manually\n\n");
+        
+        for (name, value) in &self.options {
+            config.push_str(&format!("{}={}\n", name, quote_value(value)));
+        }
+        
+        config
+    }
+    
+    /// Create a temporary mpv.conf file
+    pub fn create_temp_config(&self) -> Result<PathBuf> {
+        let config_path = std::env::temp_dir().join("syncread_mpv.conf");
+        
+        fs::write(&config_path, self.generate_config())
+            .with_context(|| format!("Failed to write MPV config to {:?}", config_path))?;
+        
+        info!("MPV config written to: {:?}", config_path);
+        Ok(config_path)
+    }
+}
+
+/// Write a value so mpv reads it back verbatim
+///
+/// Anything mpv could misread (spaces, quotes, `#`) uses its fixed-length
+/// `%<bytes>%value` form.
+fn quote_value(value: &str) -> String {
+    if value.is_empty() || value.contains(|c: char| c.is_whitespace() || matches!(c, '"' | '\'' | '#' | '%')) {
+        format!("%{}%{}", value.len(), value)
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_session_config() {
+        let conf = MpvConf::for_session(ProfileKind::Comic, Path::new("/home/a b/shots"));
+        let config = conf.generate_config();
+        
+        assert!(config.contains("keep-open=yes\n"));
+        assert!(config.contains("image-display-duration=inf\n"));
+        assert!(config.contains("screenshot-directory=%15%/home/a b/shots\n"));
+        
+        let video = MpvConf::for_session(ProfileKind::Video, Path::new("/tmp")).generate_config();
+        assert!(!video.contains("image-display-duration"));
+    }
+}
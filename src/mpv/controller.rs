@@ -1,9 +1,19 @@
+use std::ffi::OsString;
 use std::path::{Path, PathBuf};
-use std::process::{Child, Command, Stdio};
+use std::process::{Child, Command, ExitStatus, Stdio};
 use serde::{Deserialize, Serialize};
 use anyhow::{Result, Context};
 use tracing::{debug, error, info, warn};
 
+use super::binary::find_mpv;
+use super::events::MpvEvent;
+use crate::error::{self, IpcError};
+use crate::media::{MediaSource, SpreadLayout};
+use crate::network::protocol::ViewTransform;
+use crate::player::{Chapter, MediaPlayer};
+use async_trait::async_trait;
+use tokio::sync::broadcast;
+
 #[cfg(unix)]
 use tokio::net::UnixStream;
 #[cfg(unix)]
@@ -14,6 +24,10 @@ use tokio::net::windows::named_pipe::{ClientOptions, NamedPipeClient};
 #[cfg(windows)]
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 
+/// Win32 error returned while every instance of a named pipe is in use
+#[cfg(windows)]
+const ERROR_PIPE_BUSY: i32 = 231;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MpvCommand {
     pub command: Vec<serde_json::Value>,
@@ -36,106 +50,317 @@ type IpcStream = UnixStream;
 #[cfg(windows)]
 type IpcStream = NamedPipeClient;
 
+/// First argument of `script-message` commands addressed to the syncread client
+pub const SYNC_ACTION_MESSAGE: &str = "syncread";
+
+/// Overlay id used for the sync info corner display
+const SYNC_OVERLAY_ID: i64 = 1;
+
+/// How long show-text messages stay up when osd-overlay is unavailable (ms)
+const SHOW_TEXT_DURATION: i64 = 2000;
+
+/// Events kept for subscribers that fall behind
+const EVENT_BUFFER: usize = 64;
+
+/// Lines that aren't events or our reply before send_command gives up
+const MAX_RESPONSE_ATTEMPTS: u32 = 10;
+
+/// `--geometry` for each window in dual-window mode
+const LEFT_HALF: &str = "50%x100%+0+0";
+const RIGHT_HALF: &str = "50%x100%-0+0";
+
+/// IPC socket path for one MPV instance, unique per name and per syncread process
+///
+/// Two clients on one machine (even with the same user id) get different
+/// sockets, and on Windows different pipe names derived from the file stem.
+pub fn socket_path_for(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("syncread_{}_{}.socket", sanitize_ipc_name(name), std::process::id()))
+}
+
+/// Address MPV's `--input-ipc-server` listens on for a socket path
+///
+/// The path itself on Unix; on Windows a named pipe named after the file stem.
+pub fn ipc_address(socket_path: &Path) -> String {
+    #[cfg(windows)]
+    {
+        pipe_name(socket_path)
+    }
+    
+    #[cfg(not(windows))]
+    {
+        socket_path.display().to_string()
+    }
+}
+
+/// `\\.\pipe\<stem>` for a socket path, with characters pipe names can't hold replaced
+#[cfg_attr(not(windows), allow(dead_code))]
+fn pipe_name(socket_path: &Path) -> String {
+    let stem = socket_path.file_stem()
+        .map(|s| s.to_string_lossy())
+        .unwrap_or_default();
+    let stem = match sanitize_ipc_name(&stem) {
+        stem if stem.is_empty() => "syncread_mpv".to_string(),
+        stem => stem,
+    };
+    format!(r"\\.\pipe\{}", stem)
+}
+
+/// Keep only characters that are safe in both file names and pipe names
+fn sanitize_ipc_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect()
+}
+
+/// Where a group screenshot goes: `<moment>_<user>_p<page>.png`, so one moment's shots sort together
+pub fn screenshot_path(dir: &Path, moment: u64, user_id: &str, page: i32) -> PathBuf {
+    dir.join(format!("{}_{}_p{}.png", moment, sanitize_ipc_name(user_id), page + 1))
+}
+
+/// Open a client connection to MPV's IPC endpoint
+#[cfg(unix)]
+async fn open_ipc(address: &str) -> std::io::Result<IpcStream> {
+    UnixStream::connect(address).await
+}
+
+/// Open a client connection to MPV's IPC endpoint, waiting out busy pipe instances
+#[cfg(windows)]
+async fn open_ipc(address: &str) -> std::io::Result<IpcStream> {
+    use tokio::time::{sleep, Duration};
+    
+    for _ in 0..20 {
+        match ClientOptions::new().open(address) {
+            Err(e) if e.raw_os_error() == Some(ERROR_PIPE_BUSY) => sleep(Duration::from_millis(50)).await,
+            result => return result,
+        }
+    }
+    ClientOptions::new().open(address)
+}
+
+/// How MPV is started; kept so it can be started again after a crash
+#[derive(Debug, Clone, Default)]
+pub struct LaunchOptions {
+    /// input.conf holding the keybind profile
+    pub keybind_config: Option<PathBuf>,
+    /// Extra Lua scripts loaded with `--script=`
+    pub scripts: Vec<PathBuf>,
+    /// MPV binary to run instead of the one `find_mpv` turns up
+    pub mpv_binary_path: Option<PathBuf>,
+    /// Show images as double-page spreads
+    pub spreads: Option<SpreadLayout>,
+    /// Generated mpv.conf loaded with `--include`
+    pub mpv_config: Option<PathBuf>,
+    /// Show the next page in a second MPV window beside this one
+    pub dual_window: bool,
+    /// In dual-window mode, put the next page's window on the left (right-to-left manga)
+    pub rtl: bool,
+    /// MPV's `--geometry`: where the window goes and how big it is
+    pub geometry: Option<String>,
+}
+
 pub struct MpvController {
-    process: Child,
+    process: Option<Child>, // None when attached to an MPV started by someone else
     socket_path: PathBuf,
+    ipc_address: String, // What MPV listens on: the socket path, or a pipe name on Windows
     connection: Option<IpcStream>,
     next_request_id: u32,
+    media_files: Vec<MediaSource>,
+    options: LaunchOptions,
+    script_actions: Vec<Vec<String>>, // `script-message syncread ...` args seen on the socket
+    events: broadcast::Sender<MpvEvent>,
+    hung_up: bool, // MPV closed the connection; commands fail until a restart
+    partner: Option<Box<MpvController>>, // Second window in dual-window mode, showing the next page
+    pair_page: i32, // Page this window was last put on in dual-window mode
 }
 
 impl MpvController {
-    /// Launch MPV with IPC socket and keybind profile
+    /// Launch MPV with IPC socket, loading `media_files` as the playlist
+    ///
+    /// With `dual_window`, a second MPV showing the next page is launched
+    /// beside the first, at a socket path of its own.
     pub async fn launch<P: AsRef<Path>>(
         socket_path: P,
-        keybind_config: Option<P>,
-        media_files: Vec<P>,
-        mpv_binary_path: Option<&Path>,
-    ) -> Result<Self> {
+        media_files: &[MediaSource],
+        options: LaunchOptions,
+    ) -> error::Result<Self> {
+        if !options.dual_window {
+            return Self::launch_window(socket_path.as_ref(), media_files, options).await;
+        }
+        
+        let (this_side, next_side) = if options.rtl { (RIGHT_HALF, LEFT_HALF) } else { (LEFT_HALF, RIGHT_HALF) };
+        let first = LaunchOptions { dual_window: false, geometry: Some(this_side.to_string()), ..options };
+        let next = LaunchOptions { geometry: Some(next_side.to_string()), ..first.clone() };
+        
+        let controller = Self::launch_window(socket_path.as_ref(), media_files, first).await?;
+        let partner = Self::launch_window(&partner_socket_path(socket_path.as_ref()), media_files, next).await?;
+        controller.pair(partner).await
+    }
+    
+    /// Launch one MPV window
+    async fn launch_window(socket_path: &Path, media_files: &[MediaSource], options: LaunchOptions) -> error::Result<Self> {
+        let socket_path = socket_path.to_path_buf();
+        let ipc_address = ipc_address(&socket_path);
+        let process = Self::spawn_process(&ipc_address, media_files, &options)?;
+        
+        let mut controller = Self {
+            process: Some(process),
+            socket_path,
+            ipc_address,
+            connection: None,
+            next_request_id: 1,
+            media_files: media_files.to_vec(),
+            options,
+            script_actions: Vec::new(),
+            events: broadcast::channel(EVENT_BUFFER).0,
+            hung_up: false,
+            partner: None,
+            pair_page: 0,
+        };
+        
+        // Wait for socket to be ready
+        controller.wait_for_socket().await?;
+        
+        Ok(controller)
+    }
+    
+    /// Connect to an MPV that is already listening on `socket_path`, playing `media_files`
+    ///
+    /// We don't own its process, so it is neither restarted nor killed.
+    pub async fn attach<P: AsRef<Path>>(socket_path: P, media_files: &[MediaSource]) -> error::Result<Self> {
         let socket_path = socket_path.as_ref().to_path_buf();
+        let mut controller = Self {
+            process: None,
+            ipc_address: ipc_address(&socket_path),
+            socket_path,
+            connection: None,
+            next_request_id: 1,
+            media_files: media_files.to_vec(),
+            options: LaunchOptions::default(),
+            script_actions: Vec::new(),
+            events: broadcast::channel(EVENT_BUFFER).0,
+            hung_up: false,
+            partner: None,
+            pair_page: 0,
+        };
         
+        controller.wait_for_socket().await?;
+        Ok(controller)
+    }
+    
+    /// Have `partner`, a second window, show the page after this one's, the two turning as one page
+    pub async fn pair(mut self, partner: MpvController) -> error::Result<Self> {
+        self.partner = Some(Box::new(partner));
+        let page = self.get_playlist_entry().await?.max(0);
+        self.show_pair(page).await?;
+        Ok(self)
+    }
+    
+    /// Put this window on `page` and the partner on the page after it, blank past the end
+    async fn show_pair(&mut self, page: i32) -> error::Result<()> {
+        let next = next_page(page, self.media_files.len());
+        self.send_command(vec!["set_property".into(), "playlist-pos".into(), page.into()]).await?;
+        if let Some(partner) = self.partner.as_mut() {
+            partner.send_command(vec!["set_property".into(), "playlist-pos".into(), next.into()]).await?;
+        }
+        self.pair_page = page;
+        Ok(())
+    }
+    
+    /// The pair's page after the user moved either window, putting both back side by side if needed
+    async fn follow_pair(&mut self) -> error::Result<i32> {
+        let this = self.get_playlist_entry().await?;
+        let Some(partner) = self.partner.as_mut() else {
+            return Ok(this);
+        };
+        let next = partner.get_playlist_entry().await?;
+        
+        let len = self.media_files.len();
+        let page = turned_pair(self.pair_page, this, next, len);
+        if page != self.pair_page || this != page || next != next_page(page, len) {
+            self.show_pair(page).await?;
+        }
+        Ok(page)
+    }
+    
+    fn spawn_process(ipc_address: &str, media_files: &[MediaSource], options: &LaunchOptions) -> Result<Child, IpcError> {
         // Build MPV command with custom binary path if provided
-        let mpv_binary = mpv_binary_path
-            .map(|p| p.as_os_str())
-            .unwrap_or_else(|| std::ffi::OsStr::new("mpv"));
-        let mut cmd = Command::new(mpv_binary);
+        let mpv_binary = match &options.mpv_binary_path {
+            Some(path) => path.clone(),
+            None => find_mpv().ok_or(IpcError::NotFound)?,
+        };
+        let mut cmd = Command::new(&mpv_binary);
         
         // Essential IPC setup
-        #[cfg(unix)]
-        cmd.arg(format!("--input-ipc-server={}", socket_path.display()));
-        
-        #[cfg(windows)]
-        {
-            let pipe_name = format!("\\\\.\\pipe\\{}", 
-                socket_path.file_stem()
-                    .and_then(|s| s.to_str())
-                    .unwrap_or("syncread_mpv"));
-            cmd.arg(format!("--input-ipc-server={}", pipe_name));
-        }
+        cmd.arg(format!("--input-ipc-server={}", ipc_address));
         
         cmd.arg("--idle=yes")
            .arg("--force-window=yes")
            .arg("--pause=yes"); // Start paused
         
         // Add keybind config if provided
-        if let Some(config_path) = keybind_config {
-            cmd.arg(format!("--input-conf={}", config_path.as_ref().display()));
+        if let Some(config_path) = &options.keybind_config {
+            cmd.arg(format!("--input-conf={}", config_path.display()));
         }
         
-        // Add media files
-        for file in media_files {
-            cmd.arg(file.as_ref());
+        for script in &options.scripts {
+            cmd.arg(format!("--script={}", script.display()));
         }
         
-        // Suppress MPV output to keep client display clean
-        cmd.stdout(Stdio::null())
-           .stderr(Stdio::null());
+        if let Some(config_path) = &options.mpv_config {
+            cmd.arg(format!("--include={}", config_path.display()));
+        }
         
-        if let Some(custom_path) = mpv_binary_path {
-            info!("Launching MPV from {:?} with socket: {:?}", custom_path, socket_path);
-        } else {
-            info!("Launching MPV with socket: {:?}", socket_path);
+        if let Some(geometry) = &options.geometry {
+            cmd.arg(format!("--geometry={}", geometry));
         }
         
-        let process = cmd.spawn()
-            .context("Failed to spawn MPV process")?;
+        // Add media files, two pages per entry in spread mode
+        match &options.spreads {
+            Some(layout) => {
+                for pages in layout.entries() {
+                    push_spread_args(&mut cmd, pages, media_files, layout.rtl);
+                }
+            }
+            None => {
+                for file in media_files {
+                    cmd.arg(file);
+                }
+            }
+        }
         
-        let mut controller = Self {
-            process,
-            socket_path,
-            connection: None,
-            next_request_id: 1,
-        };
+        // Suppress MPV output to keep client display clean, and leave keys typed in the terminal to us
+        cmd.stdin(Stdio::null())
+           .stdout(Stdio::null())
+           .stderr(Stdio::null());
         
-        // Wait for socket to be ready
-        controller.wait_for_socket().await?;
+        info!("Launching MPV from {:?} with IPC at: {}", mpv_binary, ipc_address);
         
-        Ok(controller)
+        cmd.spawn().map_err(|source| IpcError::Spawn { path: mpv_binary, source })
     }
     
-    /// Wait for MPV to create the IPC socket
-    async fn wait_for_socket(&mut self) -> Result<()> {
+    /// Wait for MPV's IPC endpoint to come up and connect to it
+    ///
+    /// The connection made while probing is kept, so no stray clients are
+    /// left open on MPV's side.
+    async fn wait_for_socket(&mut self) -> Result<(), IpcError> {
         use tokio::time::{sleep, Duration};
         
-        info!("Waiting for MPV IPC at: {:?}", self.socket_path);
+        info!("Waiting for MPV IPC at: {}", self.ipc_address);
         
         for attempt in 1..=50 { // 5 second timeout
-            #[cfg(unix)]
-            let ready = self.socket_path.exists();
-            
-            #[cfg(windows)]
-            let ready = {
-                // On Windows, try to connect to see if pipe is ready
-                let pipe_name = format!("\\\\.\\pipe\\{}", 
-                    self.socket_path.file_stem()
-                        .and_then(|s| s.to_str())
-                        .unwrap_or("syncread_mpv"));
-                // Note: ClientOptions::new().open() is not async, remove .await
-                ClientOptions::new().open(&pipe_name).is_ok()
-            };
+            match open_ipc(&self.ipc_address).await {
+                Ok(stream) => {
+                    debug!("MPV IPC ready after {} attempts", attempt);
+                    self.connection = Some(stream);
+                    info!("Connected to MPV IPC");
+                    return Ok(());
+                }
+                Err(e) => debug!("MPV IPC not ready: {}", e),
+            }
             
-            if ready {
-                debug!("MPV IPC ready after {} attempts", attempt);
-                return Ok(());
+            // No point waiting on an MPV that already gave up
+            if let Some(status) = self.exit_status() {
+                return Err(IpcError::Exited(status));
             }
             
             if attempt % 10 == 0 {
@@ -145,42 +370,21 @@ impl MpvController {
             sleep(Duration::from_millis(100)).await;
         }
         
-        error!("MPV IPC not ready after timeout: {:?}", self.socket_path);
-        
-        // Check if MPV process is still running
-        match self.process.try_wait() {
-            Ok(Some(status)) => error!("MPV process exited with status: {:?}", status),
-            Ok(None) => error!("MPV process is still running but no IPC available"),
-            Err(e) => error!("Failed to check MPV process status: {}", e),
-        }
+        error!("MPV IPC not ready after timeout: {}", self.ipc_address);
+        error!("MPV process is still running but no IPC available");
         
-        anyhow::bail!("MPV IPC not available within timeout");
+        Err(IpcError::Timeout)
     }
     
     /// Connect to MPV IPC socket
-    pub async fn connect(&mut self) -> Result<()> {
+    pub async fn connect(&mut self) -> error::Result<()> {
         if self.connection.is_some() {
             return Ok(()); // Already connected
         }
         
-        #[cfg(unix)]
-        {
-            let stream = UnixStream::connect(&self.socket_path).await
-                .context("Failed to connect to MPV socket")?;
-            self.connection = Some(stream);
-        }
-        
-        #[cfg(windows)]
-        {
-            let pipe_name = format!("\\\\.\\pipe\\{}", 
-                self.socket_path.file_stem()
-                    .and_then(|s| s.to_str())
-                    .unwrap_or("syncread_mpv"));
-            let stream = ClientOptions::new()
-                .open(&pipe_name)
-                .context("Failed to connect to MPV named pipe")?;
-            self.connection = Some(stream);
-        }
+        let stream = open_ipc(&self.ipc_address).await
+            .map_err(|source| IpcError::Connect { address: self.ipc_address.clone(), source })?;
+        self.connection = Some(stream);
         
         info!("Connected to MPV IPC");
         
@@ -188,7 +392,10 @@ impl MpvController {
     }
     
     /// Send command to MPV and get response
-    pub async fn send_command(&mut self, command: Vec<serde_json::Value>) -> Result<MpvResponse> {
+    pub async fn send_command(&mut self, command: Vec<serde_json::Value>) -> error::Result<MpvResponse> {
+        if self.hung_up {
+            return Err(IpcError::HungUp.into());
+        }
         self.connect().await?;
         
         let request_id = self.next_request_id;
@@ -199,28 +406,50 @@ impl MpvController {
             request_id: Some(request_id),
         };
         
-        let json_command = serde_json::to_string(&mpv_command)?;
+        let json_command = serde_json::to_string(&mpv_command).map_err(IpcError::Encode)?;
         debug!("Sending MPV command: {}", json_command);
         
         // Send command
         if let Some(ref mut connection) = self.connection {
-            connection.write_all(json_command.as_bytes()).await?;
-            connection.write_all(b"\n").await?;
-            connection.flush().await?;
+            let written = async {
+                connection.write_all(json_command.as_bytes()).await?;
+                connection.write_all(b"\n").await?;
+                connection.flush().await
+            }.await;
+            if let Err(e) = written {
+                self.hung_up = e.kind() == std::io::ErrorKind::BrokenPipe;
+                return Err(if self.hung_up { IpcError::HungUp } else { IpcError::Io(e) }.into());
+            }
             
             // Read response - retry on empty/invalid responses
             let mut reader = BufReader::new(connection);
             
-            for attempt in 1..=10 {
+            let mut attempt = 0;
+            while attempt < MAX_RESPONSE_ATTEMPTS {
                 let mut response_line = String::new();
-                reader.read_line(&mut response_line).await?;
+                if reader.read_line(&mut response_line).await.map_err(IpcError::Io)? == 0 {
+                    self.hung_up = true;
+                    return Err(IpcError::HungUp.into());
+                }
                 
                 let trimmed = response_line.trim();
                 if trimmed.is_empty() {
-                    debug!("Empty response, attempt {}/10", attempt);
+                    attempt += 1;
+                    debug!("Empty response, attempt {}/{}", attempt, MAX_RESPONSE_ATTEMPTS);
                     continue;
                 }
                 
+                // Events arrive between replies, keypresses bound to syncread actions among them
+                if let Some(event) = MpvEvent::parse(trimmed) {
+                    debug!("MPV event: {:?}", event);
+                    if let Some(action) = event.sync_action() {
+                        self.script_actions.push(action);
+                    }
+                    let _ = self.events.send(event); // Nobody may be listening
+                    continue;
+                }
+                attempt += 1;
+                
                 match serde_json::from_str::<MpvResponse>(trimmed) {
                     Ok(response) => {
                         debug!("MPV response: {:?}", response);
@@ -244,40 +473,68 @@ impl MpvController {
                 }
             }
             
-            anyhow::bail!("Failed to get valid response from MPV");
+            Err(IpcError::NoResponse.into())
         } else {
-            anyhow::bail!("Not connected to MPV");
+            Err(IpcError::NotConnected.into())
         }
     }
     
-    /// Convenience methods for common MPV commands
-    
-    pub async fn play(&mut self) -> Result<()> {
-        self.send_command(vec!["set_property".into(), "pause".into(), false.into()]).await?;
-        Ok(())
+    /// Events read from the socket from now on
+    ///
+    /// They're read while waiting for replies, so they come in as often as
+    /// commands are sent.
+    pub fn subscribe(&self) -> broadcast::Receiver<MpvEvent> {
+        self.events.subscribe()
     }
     
-    pub async fn pause(&mut self) -> Result<()> {
-        self.send_command(vec!["set_property".into(), "pause".into(), true.into()]).await?;
+    /// Have MPV send a `PropertyChange` event whenever `name` changes
+    pub async fn observe_property(&mut self, name: &str) -> error::Result<()> {
+        let id = self.next_request_id; // Any number; MPV only uses it to unobserve
+        self.send_command(vec!["observe_property".into(), id.into(), name.into()]).await?;
         Ok(())
     }
     
-    pub async fn seek(&mut self, seconds: f64) -> Result<()> {
+    // Convenience methods for common MPV commands
+    
+    pub async fn seek(&mut self, seconds: f64) -> error::Result<()> {
         self.send_command(vec!["seek".into(), seconds.into()]).await?;
         Ok(())
     }
     
-    pub async fn next_file(&mut self) -> Result<()> {
+    pub async fn next_file(&mut self) -> error::Result<()> {
         self.send_command(vec!["playlist-next".into()]).await?;
         Ok(())
     }
     
-    pub async fn prev_file(&mut self) -> Result<()> {
+    pub async fn prev_file(&mut self) -> error::Result<()> {
         self.send_command(vec!["playlist-prev".into()]).await?;
         Ok(())
     }
     
-    pub async fn get_position(&mut self) -> Result<f64> {
+    /// MPV's own playlist index
+    async fn get_playlist_entry(&mut self) -> error::Result<i32> {
+        let response = self.send_command(vec!["get_property".into(), "playlist-pos".into()]).await?;
+        
+        if let Some(data) = response.data {
+            if let Some(pos) = data.as_i64() {
+                return Ok(pos as i32);
+            }
+        }
+        
+        Ok(0)
+    }
+    
+    fn entry_for_page(&self, page: i32) -> i32 {
+        match &self.options.spreads {
+            Some(layout) if page >= 0 => layout.entry_for_page(page as usize) as i32,
+            _ => page,
+        }
+    }
+}
+
+#[async_trait]
+impl MediaPlayer for MpvController {
+    async fn get_position(&mut self) -> Result<f64> {
         let response = self.send_command(vec!["get_property".into(), "playback-time".into()]).await?;
         
         if let Some(data) = response.data {
@@ -289,19 +546,23 @@ impl MpvController {
         Ok(0.0) // Default if not available
     }
     
-    pub async fn get_playlist_pos(&mut self) -> Result<i32> {
-        let response = self.send_command(vec!["get_property".into(), "playlist-pos".into()]).await?;
-        
-        if let Some(data) = response.data {
-            if let Some(pos) = data.as_i64() {
-                return Ok(pos as i32);
-            }
+    /// Current page: the playlist index, or the first page of the spread in spread mode
+    ///
+    /// In dual-window mode it is the first window's page, which the pair
+    /// follows if the user turned a page in either window.
+    async fn get_playlist_pos(&mut self) -> Result<i32> {
+        if self.partner.is_some() {
+            return Ok(self.follow_pair().await?);
         }
+        let entry = self.get_playlist_entry().await?;
         
-        Ok(0)
+        Ok(match &self.options.spreads {
+            Some(layout) if entry >= 0 => layout.first_page(entry as usize) as i32,
+            _ => entry,
+        })
     }
     
-    pub async fn is_paused(&mut self) -> Result<bool> {
+    async fn is_paused(&mut self) -> Result<bool> {
         let response = self.send_command(vec!["get_property".into(), "pause".into()]).await?;
         
         if let Some(data) = response.data {
@@ -312,13 +573,308 @@ impl MpvController {
         
         Ok(true) // Default to paused if unknown
     }
+    
+    async fn play(&mut self) -> Result<()> {
+        self.send_command(vec!["set_property".into(), "pause".into(), false.into()]).await?;
+        Ok(())
+    }
+    
+    async fn pause(&mut self) -> Result<()> {
+        self.send_command(vec!["set_property".into(), "pause".into(), true.into()]).await?;
+        Ok(())
+    }
+    
+    /// Seek to an absolute time in the current file
+    async fn seek_to(&mut self, seconds: f64) -> Result<()> {
+        self.send_command(vec!["seek".into(), seconds.into(), "absolute".into()]).await?;
+        Ok(())
+    }
+    
+    /// Jump to a page (the spread containing it in spread mode, with the next page beside it in dual-window mode)
+    async fn set_playlist_pos(&mut self, index: i32) -> Result<()> {
+        if self.partner.is_some() {
+            return Ok(self.show_pair(index).await?);
+        }
+        let entry = self.entry_for_page(index);
+        self.send_command(vec!["set_property".into(), "playlist-pos".into(), entry.into()]).await?;
+        Ok(())
+    }
+    
+    /// Flash a message on the OSD
+    async fn show_text(&mut self, text: &str, duration_ms: i64) -> Result<()> {
+        self.send_command(vec!["show-text".into(), text.into(), duration_ms.into()]).await?;
+        Ok(())
+    }
+    
+    /// Ask MPV to exit and wait briefly for it to do so
+    async fn quit(&mut self) {
+        use tokio::time::{sleep, Duration};
+        
+        if let Some(partner) = self.partner.as_mut() {
+            partner.quit().await;
+        }
+        
+        // MPV closes the socket instead of replying, so errors here are expected
+        let _ = self.send_command(vec!["quit".into()]).await;
+        if self.process.is_none() {
+            return;
+        }
+        
+        for _ in 0..20 {
+            if self.exit_status().is_some() {
+                return;
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+        warn!("MPV did not exit after quit command");
+    }
+    
+    /// Jump to a playlist index and, once that file has loaded, to a time within it
+    async fn restore_position(&mut self, index: i32, seconds: f64) -> Result<()> {
+        use tokio::time::{sleep, Duration};
+        
+        self.set_playlist_pos(index).await?;
+        
+        // Images and the very start of a file don't need a seek
+        if seconds < 0.5 {
+            return Ok(());
+        }
+        
+        // Seeking before the new file is loaded is silently ignored by MPV
+        for _ in 0..30 {
+            sleep(Duration::from_millis(100)).await;
+            
+            let response = self.send_command(vec!["get_property".into(), "duration".into()]).await?;
+            if self.get_playlist_entry().await? == self.entry_for_page(index) && response.data.is_some() {
+                return self.seek_to(seconds).await;
+            }
+        }
+        
+        warn!("File at index {} did not load in time, skipping seek to {:.1}s", index, seconds);
+        Ok(())
+    }
+    
+    /// MPV's `chapter`; files without chapters, and the stretch before the first, have none
+    async fn get_chapter(&mut self) -> Result<Option<i32>> {
+        let response = self.send_command(vec!["get_property".into(), "chapter".into()]).await?;
+        Ok(response.data.and_then(|data| data.as_i64()).filter(|&chapter| chapter >= 0).map(|chapter| chapter as i32))
+    }
+    
+    async fn get_chapters(&mut self) -> Result<Vec<Chapter>> {
+        let response = self.send_command(vec!["get_property".into(), "chapter-list".into()]).await?;
+        let Some(data) = response.data else {
+            return Ok(Vec::new());
+        };
+        serde_json::from_value(data).context("unexpected chapter-list")
+    }
+    
+    /// Set the playback speed multiplier (1.0 is normal)
+    async fn set_speed(&mut self, speed: f64) -> Result<()> {
+        self.send_command(vec!["set_property".into(), "speed".into(), speed.into()]).await?;
+        Ok(())
+    }
+    
+    async fn get_property(&mut self, name: &str) -> Result<serde_json::Value> {
+        let response = self.send_command(vec!["get_property".into(), name.into()]).await?;
+        anyhow::ensure!(response.error == "success", "can't read '{}': {}", name, response.error);
+        Ok(response.data.unwrap_or_default())
+    }
+    
+    async fn set_property(&mut self, name: &str, value: serde_json::Value) -> Result<()> {
+        let response = self.send_command(vec!["set_property".into(), name.into(), value]).await?;
+        anyhow::ensure!(response.error == "success", "can't set '{}': {}", name, response.error);
+        Ok(())
+    }
+    
+    /// Show multi-line ASS markup in the top-right corner of the video until replaced or hidden
+    ///
+    /// Plain text in `ass` must already be escaped (see `appearance::ass_escape`).
+    /// Falls back to `show-text` on MPV builds without `osd-overlay` (pre 0.31).
+    async fn show_overlay(&mut self, ass: &str) -> Result<()> {
+        let ass = ass.lines().collect::<Vec<_>>().join("\\N");
+        let data = format!("{{\\an9\\fs28\\bord2}}{}", ass);
+        
+        let response = self.send_command(vec![
+            "osd-overlay".into(), SYNC_OVERLAY_ID.into(), "ass-events".into(), data.clone().into(),
+        ]).await?;
+        
+        if response.error != "success" {
+            // osd-ass-cc turns on ASS interpretation for show-text
+            self.show_text(&format!("${{osd-ass-cc/0}}{}", data), SHOW_TEXT_DURATION).await?;
+        }
+        Ok(())
+    }
+    
+    /// Remove the overlay set by `show_overlay`
+    async fn hide_overlay(&mut self) -> Result<()> {
+        self.send_command(vec!["osd-overlay".into(), SYNC_OVERLAY_ID.into(), "none".into(), "".into()]).await?;
+        Ok(())
+    }
+    
+    /// Send a `script-message-to` a loaded Lua script
+    async fn script_message_to(&mut self, script: &str, args: &[&str]) -> Result<()> {
+        let mut command: Vec<serde_json::Value> = vec!["script-message-to".into(), script.into()];
+        command.extend(args.iter().map(|&arg| arg.into()));
+        self.send_command(command).await?;
+        Ok(())
+    }
+    
+    async fn get_view(&mut self) -> Result<ViewTransform> {
+        let zoom = self.get_property("video-zoom").await?;
+        let pan_x = self.get_property("video-pan-x").await?;
+        let pan_y = self.get_property("video-pan-y").await?;
+        Ok(ViewTransform {
+            zoom: zoom.as_f64().unwrap_or_default(),
+            pan_x: pan_x.as_f64().unwrap_or_default(),
+            pan_y: pan_y.as_f64().unwrap_or_default(),
+        })
+    }
+    
+    async fn set_view(&mut self, view: ViewTransform) -> Result<()> {
+        if let Some(partner) = self.partner.as_mut() {
+            partner.set_view(view).await?;
+        }
+        self.set_property("video-zoom", view.zoom.into()).await?;
+        self.set_property("video-pan-x", view.pan_x.into()).await?;
+        self.set_property("video-pan-y", view.pan_y.into()).await
+    }
+    
+    /// Screenshot with `screenshot-to-file`, which works even though the profile unbinds `s`
+    async fn screenshot(&mut self, path: &Path) -> Result<()> {
+        let response = self.send_command(vec!["screenshot-to-file".into(), path.to_string_lossy().into(), "subtitles".into()]).await?;
+        anyhow::ensure!(response.error == "success", "screenshot failed: {}", response.error);
+        Ok(())
+    }
+    
+    /// Add a file to the playlist at `index`, moving later entries along
+    ///
+    /// Spreads are laid out at launch, so new pages can't be added to them.
+    async fn insert_file(&mut self, index: usize, file: MediaSource) -> Result<()> {
+        anyhow::ensure!(!self.spreads_enabled(), "can't add pages in spread mode");
+        if let Some(partner) = self.partner.as_mut() {
+            partner.insert_file(index, file.clone()).await?;
+        }
+        let index = index.min(self.media_files.len());
+        
+        // `loadfile ... insert-at` needs MPV 0.38, so append and move instead
+        self.send_command(vec!["loadfile".into(), file.to_string().into(), "append".into()]).await?;
+        let appended = self.media_files.len();
+        if index < appended {
+            self.send_command(vec!["playlist-move".into(), appended.into(), index.into()]).await?;
+        }
+        self.media_files.insert(index, file);
+        Ok(())
+    }
+    
+    async fn remove_file(&mut self, index: usize) -> Result<()> {
+        anyhow::ensure!(!self.spreads_enabled(), "can't remove pages in spread mode");
+        anyhow::ensure!(index < self.media_files.len(), "no playlist entry {}", index + 1);
+        if let Some(partner) = self.partner.as_mut() {
+            partner.remove_file(index).await?;
+        }
+        self.send_command(vec!["playlist-remove".into(), index.into()]).await?;
+        self.media_files.remove(index);
+        Ok(())
+    }
+    
+    async fn move_file(&mut self, from: usize, to: usize) -> Result<()> {
+        anyhow::ensure!(!self.spreads_enabled(), "can't reorder pages in spread mode");
+        let last = self.media_files.len().saturating_sub(1);
+        anyhow::ensure!(from <= last, "no playlist entry {}", from + 1);
+        if let Some(partner) = self.partner.as_mut() {
+            partner.move_file(from, to).await?;
+        }
+        let to = to.min(last);
+        
+        // MPV puts the entry before whatever is at the target index, so moving down means aiming one further
+        let target = if to > from { to + 1 } else { to };
+        self.send_command(vec!["playlist-move".into(), from.into(), target.into()]).await?;
+        let file = self.media_files.remove(from);
+        self.media_files.insert(to, file);
+        Ok(())
+    }
+    
+    /// Whether pages are shown as double-page spreads
+    fn spreads_enabled(&self) -> bool {
+        self.options.spreads.is_some()
+    }
+    
+    /// Shift spread pairing by one page and reload MPV on the same page
+    ///
+    /// Returns false when spreads are not enabled.
+    async fn realign_spreads(&mut self) -> Result<bool> {
+        if self.options.spreads.is_none() {
+            return Ok(false);
+        }
+        
+        let page = self.get_playlist_pos().await?;
+        self.options.spreads = self.options.spreads.as_ref().map(|layout| layout.realigned(&self.media_files));
+        self.restart().await?;
+        self.set_playlist_pos(page).await?;
+        Ok(true)
+    }
+    
+    /// Exit status of the MPV process, if it has exited
+    fn exit_status(&mut self) -> Option<ExitStatus> {
+        self.process.as_mut()?.try_wait().ok().flatten()
+    }
+    
+    /// Start a fresh MPV with the original playlist and options (it starts paused at the first file)
+    async fn restart(&mut self) -> Result<()> {
+        let Some(process) = self.process.as_mut() else {
+            anyhow::bail!("MPV wasn't started by syncread, so it can't be restarted");
+        };
+        if process.try_wait().ok().flatten().is_none() {
+            let _ = process.kill();
+            let _ = process.wait();
+        }
+        
+        self.connection = None;
+        self.hung_up = false;
+        if self.socket_path.exists() {
+            let _ = std::fs::remove_file(&self.socket_path);
+        }
+        
+        self.process = Some(Self::spawn_process(&self.ipc_address, &self.media_files, &self.options)?);
+        self.wait_for_socket().await?;
+        
+        if let Some(partner) = self.partner.as_mut() {
+            partner.restart().await?;
+            self.show_pair(0).await?;
+        }
+        Ok(())
+    }
+    
+    /// Sync actions triggered from inside MPV since the last call (action name first, then its arguments)
+    fn take_script_actions(&mut self) -> Vec<Vec<String>> {
+        let mut actions = std::mem::take(&mut self.script_actions);
+        if let Some(partner) = self.partner.as_mut() {
+            actions.extend(partner.take_script_actions());
+        }
+        actions
+    }
+    
+    fn subscribe_events(&self) -> Option<broadcast::Receiver<MpvEvent>> {
+        Some(self.subscribe())
+    }
+    
+    /// Closing either window of a pair ends it too
+    fn hung_up(&self) -> bool {
+        self.hung_up || self.partner.as_ref().is_some_and(|partner| partner.hung_up)
+    }
 }
 
 impl Drop for MpvController {
     fn drop(&mut self) {
-        // Terminate MPV process when controller is dropped
-        if let Err(e) = self.process.kill() {
-            error!("Failed to kill MPV process: {}", e);
+        // Terminate MPV process when controller is dropped, unless it already quit
+        let Some(process) = self.process.as_mut() else {
+            return; // Not ours to stop, nor its socket to remove
+        };
+        if process.try_wait().ok().flatten().is_none() {
+            if let Err(e) = process.kill() {
+                error!("Failed to kill MPV process: {}", e);
+            }
+            let _ = process.wait();
         }
         
         // Clean up socket file
@@ -329,3 +885,138 @@ impl Drop for MpvController {
         }
     }
 }
+
+/// Socket path for the second window in dual-window mode
+fn partner_socket_path(socket_path: &Path) -> PathBuf {
+    let stem = socket_path.file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
+    socket_path.with_file_name(format!("{}_next.socket", stem))
+}
+
+/// Playlist index for the window beside `page`: the page after it, or -1 (no file) past the end
+fn next_page(page: i32, len: usize) -> i32 {
+    if page + 1 < len as i32 { page + 1 } else { -1 }
+}
+
+/// Where a pair of windows goes after the user moved one of them from `shown` (and the page after)
+///
+/// Turning either window by one page turns the pair by two, like a book;
+/// any other jump lands the pair there. A window gone blank (MPV idle) is
+/// put back rather than followed.
+fn turned_pair(shown: i32, this: i32, next: i32, len: usize) -> i32 {
+    let page = if this != shown && this >= 0 {
+        match this - shown {
+            1 => shown + 2,
+            -1 => shown - 2,
+            _ => this,
+        }
+    } else if next != next_page(shown, len) && next >= 0 {
+        match next - (shown + 1) {
+            1 => shown + 2,
+            -1 => shown - 2,
+            _ => next - 1,
+        }
+    } else {
+        shown
+    };
+    page.clamp(0, (len as i32 - 1).max(0))
+}
+
+/// Add one spread: the first page as the file, the second as an extra video track stacked beside it
+fn push_spread_args(cmd: &mut Command, pages: &[usize], media_files: &[MediaSource], rtl: bool) {
+    let [left, right] = pages else {
+        for &page in pages {
+            cmd.arg(&media_files[page]);
+        }
+        return;
+    };
+    
+    // Scale the second page to the first page's height, then stack them
+    let order = if rtl { "[b][a]" } else { "[a][b]" };
+    let filter = format!("[vid2][vid1]scale2ref=w=oh*mdar:h=ih[b][a];{}hstack[vo]", order);
+    
+    let mut external = OsString::from("--external-file=");
+    external.push(&media_files[*right]);
+    
+    // `--{ ... --}` scopes the options to this playlist entry
+    cmd.arg("--{")
+        .arg(external)
+        .arg(format!("--lavfi-complex={}", filter))
+        .arg(&media_files[*left])
+        .arg("--}");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_screenshot_path() {
+        let path = screenshot_path(Path::new("shots"), 1700000000, "bob/../x", 4);
+        assert_eq!(path, Path::new("shots").join("1700000000_bob____x_p5.png"));
+    }
+    
+    #[test]
+    fn test_ipc_names_are_unique_and_safe() {
+        let alice = socket_path_for("alice");
+        assert_ne!(ipc_address(&alice), ipc_address(&socket_path_for("bob")));
+        assert!(alice.file_name().unwrap().to_str().unwrap().starts_with("syncread_alice_"));
+        assert!(socket_path_for("a/b\\c").file_name().unwrap().to_str().unwrap().starts_with("syncread_a_b_c_"));
+        
+        assert_eq!(pipe_name(Path::new("/tmp/syncread_bob_42.socket")), r"\\.\pipe\syncread_bob_42");
+        assert_eq!(pipe_name(Path::new("")), r"\\.\pipe\syncread_mpv");
+    }
+    
+    #[test]
+    fn test_turned_pair() {
+        // Pages 2 and 3 showing, in a ten-page playlist
+        assert_eq!(turned_pair(2, 2, 3, 10), 2);
+        
+        // A step in either window turns the pair
+        assert_eq!(turned_pair(2, 3, 3, 10), 4);
+        assert_eq!(turned_pair(2, 1, 3, 10), 0);
+        assert_eq!(turned_pair(2, 2, 4, 10), 4);
+        assert_eq!(turned_pair(2, 2, 2, 10), 0);
+        
+        // A jump lands there, with the second window after it
+        assert_eq!(turned_pair(2, 7, 3, 10), 7);
+        assert_eq!(turned_pair(2, 2, 7, 10), 6);
+        
+        // The ends hold, and the window past the last page stays blank
+        assert_eq!(turned_pair(8, 9, 9, 10), 9);
+        assert_eq!(turned_pair(9, 9, -1, 10), 9);
+        assert_eq!(turned_pair(0, 0, 0, 10), 0);
+        assert_eq!(next_page(9, 10), -1);
+        
+        // An idle window is put back rather than followed
+        assert_eq!(turned_pair(2, -1, 3, 10), 2);
+        assert_eq!(turned_pair(2, 2, -1, 10), 2);
+    }
+    
+    #[test]
+    fn test_partner_socket_path() {
+        let path = socket_path_for("alice");
+        let partner = partner_socket_path(&path);
+        assert_eq!(partner.parent(), path.parent());
+        assert_ne!(ipc_address(&partner), ipc_address(&path));
+        assert!(partner.to_string_lossy().ends_with("_next.socket"));
+    }
+    
+    #[tokio::test]
+    async fn test_open_ipc_once_listening() {
+        let path = socket_path_for("ipc_test");
+        let address = ipc_address(&path);
+        assert!(open_ipc(&address).await.is_err());
+        
+        #[cfg(unix)]
+        let _listener = tokio::net::UnixListener::bind(&path).unwrap();
+        #[cfg(windows)]
+        let _server = tokio::net::windows::named_pipe::ServerOptions::new()
+            .first_pipe_instance(true)
+            .create(&address)
+            .unwrap();
+        
+        let connected = open_ipc(&address).await;
+        let _ = std::fs::remove_file(&path);
+        assert!(connected.is_ok());
+    }
+}
@@ -0,0 +1,133 @@
+//! Events MPV sends on the IPC socket between command replies
+//!
+//! Replies carry a `request_id`; events carry an `event` name instead, and
+//! can arrive at any time. `MpvController` reads them while waiting for its
+//! replies and passes them on to anyone subscribed.
+
+use super::controller::SYNC_ACTION_MESSAGE;
+use serde_json::Value;
+
+/// Why MPV stopped playing a file (`end-file`'s `reason`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EndFileReason {
+    /// Played to the end
+    Eof,
+    /// Another file was loaded, or the playlist was cleared
+    Stop,
+    /// The user quit MPV
+    Quit,
+    /// The file couldn't be played
+    Error,
+    Redirect,
+    Unknown,
+}
+
+impl EndFileReason {
+    fn parse(reason: &str) -> Self {
+        match reason {
+            "eof" => EndFileReason::Eof,
+            "stop" => EndFileReason::Stop,
+            "quit" => EndFileReason::Quit,
+            "error" => EndFileReason::Error,
+            "redirect" => EndFileReason::Redirect,
+            _ => EndFileReason::Unknown,
+        }
+    }
+}
+
+/// One event from MPV
+#[derive(Debug, Clone, PartialEq)]
+pub enum MpvEvent {
+    /// A file stopped playing
+    EndFile { reason: EndFileReason, playlist_entry_id: Option<i64> },
+    /// A file started playing
+    FileLoaded,
+    /// MPV is exiting
+    Shutdown,
+    /// An observed property changed (see `MpvController::observe_property`)
+    PropertyChange { name: String, data: Value },
+    /// `script-message` arguments
+    ClientMessage(Vec<String>),
+    /// Any other event, by name
+    Other(String),
+}
+
+impl MpvEvent {
+    /// The event on a line read from the socket, or None for a reply or anything unreadable
+    pub fn parse(line: &str) -> Option<Self> {
+        let value: Value = serde_json::from_str(line).ok()?;
+        let name = value.get("event")?.as_str()?;
+        
+        Some(match name {
+            "end-file" => MpvEvent::EndFile {
+                reason: EndFileReason::parse(value.get("reason").and_then(Value::as_str).unwrap_or_default()),
+                playlist_entry_id: value.get("playlist_entry_id").and_then(Value::as_i64),
+            },
+            "file-loaded" => MpvEvent::FileLoaded,
+            "shutdown" => MpvEvent::Shutdown,
+            "property-change" => MpvEvent::PropertyChange {
+                name: value.get("name")?.as_str()?.to_string(),
+                data: value.get("data").cloned().unwrap_or(Value::Null),
+            },
+            "client-message" => MpvEvent::ClientMessage(
+                value.get("args")?
+                    .as_array()?
+                    .iter()
+                    .filter_map(|arg| arg.as_str().map(str::to_string))
+                    .collect(),
+            ),
+            name => MpvEvent::Other(name.to_string()),
+        })
+    }
+    
+    /// Arguments after "syncread" if this is a `script-message syncread ...` for us
+    pub fn sync_action(&self) -> Option<Vec<String>> {
+        let MpvEvent::ClientMessage(args) = self else {
+            return None;
+        };
+        match args.split_first() {
+            Some((first, rest)) if first == SYNC_ACTION_MESSAGE && !rest.is_empty() => Some(rest.to_vec()),
+            _ => None,
+        }
+    }
+    
+    /// Whether this means the user closed MPV
+    pub fn is_quit(&self) -> bool {
+        matches!(self, MpvEvent::Shutdown | MpvEvent::EndFile { reason: EndFileReason::Quit, .. })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    
+    #[test]
+    fn test_parse_events() {
+        let end = MpvEvent::parse(r#"{"event":"end-file","reason":"quit","playlist_entry_id":3}"#).unwrap();
+        assert_eq!(end, MpvEvent::EndFile { reason: EndFileReason::Quit, playlist_entry_id: Some(3) });
+        assert!(end.is_quit());
+        assert!(!MpvEvent::parse(r#"{"event":"end-file","reason":"eof"}"#).unwrap().is_quit());
+        assert!(MpvEvent::parse(r#"{"event":"shutdown"}"#).unwrap().is_quit());
+        
+        let change = MpvEvent::parse(r#"{"event":"property-change","id":1,"name":"pause","data":true}"#).unwrap();
+        assert_eq!(change, MpvEvent::PropertyChange { name: "pause".to_string(), data: json!(true) });
+        assert_eq!(MpvEvent::parse(r#"{"event":"seek"}"#), Some(MpvEvent::Other("seek".to_string())));
+        
+        // Replies aren't events
+        assert_eq!(MpvEvent::parse(r#"{"error":"success","request_id":3}"#), None);
+        assert_eq!(MpvEvent::parse("not json"), None);
+    }
+    
+    #[test]
+    fn test_sync_action() {
+        let action = |line: &str| MpvEvent::parse(line).and_then(|event| event.sync_action());
+        assert_eq!(
+            action(r#"{"event":"client-message","args":["syncread","jump-to-user","alice"]}"#),
+            Some(vec!["jump-to-user".to_string(), "alice".to_string()])
+        );
+        assert_eq!(action(r#"{"event":"client-message","args":["other-script","x"]}"#), None);
+        assert_eq!(action(r#"{"event":"client-message","args":["syncread"]}"#), None);
+        assert_eq!(action(r#"{"event":"pause"}"#), None);
+    }
+}
@@ -0,0 +1,90 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::network::protocol::{unix_millis, SessionState};
+
+/// Lua HUD shipped with syncread; its file name is the script name MPV registers
+const HUD_SCRIPT: &str = include_str!("scripts/syncread_hud.lua");
+
+/// Script name used as the target of `script-message-to`
+pub const HUD_SCRIPT_NAME: &str = "syncread_hud";
+
+/// Session status as sent to the HUD script
+#[derive(Debug, Serialize)]
+struct HudStatus<'a> {
+    in_sync: bool,
+    users: Vec<HudUser<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct HudUser<'a> {
+    user_id: &'a str,
+    icon: Option<&'a str>,
+    color: String, // #rrggbb
+    position: i32, // 1-based for display
+    file: Option<&'a str>,
+    paused: bool,
+    me: bool,
+    idle: Option<String>, // "idle 6m"
+}
+
+/// Write the HUD script to the temp directory for `--script=`
+pub fn create_temp_script() -> Result<PathBuf> {
+    let script_path = std::env::temp_dir().join(format!("{}.lua", HUD_SCRIPT_NAME));
+    
+    std::fs::write(&script_path, HUD_SCRIPT)
+        .with_context(|| format!("Failed to write HUD script: {:?}", script_path))?;
+    
+    Ok(script_path)
+}
+
+/// JSON payload for the script's `update` message
+pub fn hud_payload(session: &SessionState, current_user_id: &str, idle_after: Duration) -> String {
+    let now = unix_millis() / 1000;
+    let status = HudStatus {
+        in_sync: session.check_sync_status(1),
+        users: session.get_users_sorted()
+            .into_iter()
+            .map(|user| HudUser {
+                user_id: &user.user_id,
+                icon: user.icon.as_deref(),
+                color: user.color().hex(),
+                position: user.playlist_position + 1,
+                file: user.current_file_name.as_deref(),
+                paused: user.is_paused,
+                me: user.user_id == current_user_id,
+                idle: user.idle_label(idle_after, now).filter(|_| user.user_id != current_user_id),
+            })
+            .collect(),
+    };
+    
+    serde_json::to_string(&status).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::appearance::UserColor;
+    use crate::network::protocol::DEFAULT_IDLE_AFTER;
+    use crate::network::UserState;
+    
+    #[test]
+    fn test_hud_payload() {
+        let mut session = SessionState::new();
+        let mut alice = UserState::new("alice".to_string());
+        alice.playlist_position = 4;
+        alice.current_file_name = Some("p5.png".to_string());
+        session.update_user(alice);
+        session.update_user(UserState::new("me".to_string()));
+        
+        let payload: serde_json::Value = serde_json::from_str(&hud_payload(&session, "me", DEFAULT_IDLE_AFTER)).unwrap();
+        assert_eq!(payload["in_sync"], false);
+        assert_eq!(payload["users"][0]["user_id"], "alice");
+        assert_eq!(payload["users"][0]["position"], 5);
+        assert_eq!(payload["users"][0]["file"], "p5.png");
+        assert_eq!(payload["users"][0]["color"], UserColor::for_user("alice").hex());
+        assert_eq!(payload["users"][1]["me"], true);
+    }
+}
@@ -1,8 +1,131 @@
+use std::collections::{BTreeMap, BTreeSet};
 use std::path::{Path, PathBuf};
+use std::fmt;
 use std::fs;
+use clap::ValueEnum;
+use serde::Deserialize;
 use anyhow::{Result, Context};
 use tracing::info;
 
+use super::controller::SYNC_ACTION_MESSAGE;
+use crate::media::{majority_kind, MediaKind, MediaSource};
+
+/// MPV input commands accepted in user keybind files
+const KNOWN_MPV_COMMANDS: &[&str] = &[
+    "ab-loop", "add", "af", "apply-profile", "change-list", "cycle", "cycle-values",
+    "drop-buffers", "frame-back-step", "frame-step", "ignore", "keybind", "keypress",
+    "load-script", "loadfile", "loadlist", "mouse", "multiply", "playlist-clear",
+    "playlist-move", "playlist-next", "playlist-next-playlist", "playlist-play-index",
+    "playlist-prev", "playlist-prev-playlist", "playlist-remove", "playlist-shuffle",
+    "playlist-unshuffle", "print-text", "quit", "quit-watch-later", "revert-seek",
+    "rescan-external-files", "screenshot", "screenshot-to-file", "script-binding",
+    "script-message", "script-message-to", "seek", "set", "show-progress", "show-text",
+    "stop", "sub-add", "sub-reload", "sub-remove", "sub-seek", "sub-step", "toggle",
+    "vf", "video-reload", "write-watch-later-config",
+];
+
+/// Actions the client handles from `script-message syncread <action>`
+const SYNC_ACTIONS: &[&str] = &[
+    "ab-loop", "bookmark", "bookmarks", "catch-up", "chat", "copy-link", "fetch-missing", "follow",
+    "goto-bookmark", "jump-to", "note", "playlist-add", "playlist-move", "playlist-remove",
+    "react", "realign", "screenshot", "share-link", "slideshow", "status",
+];
+
+/// Prefixes that may precede an MPV command without changing its meaning
+const MPV_COMMAND_PREFIXES: &[&str] = &[
+    "no-osd", "osd-auto", "osd-bar", "osd-msg", "osd-msg-bar", "raw",
+    "expand-properties", "repeatable", "nonrepeatable", "nonscalable", "async", "sync",
+];
+
+/// TOML keybind file layout: a `[keybinds]` table of `"KEY" = "command"`
+#[derive(Debug, Deserialize)]
+struct KeybindFile {
+    #[serde(default)]
+    keybinds: BTreeMap<String, String>,
+}
+
+/// Built-in keybind presets selectable with `--profile`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ProfileKind {
+    /// Video and general media: the arrows turn pages, UP/DOWN and Shift+arrows seek
+    #[default]
+    #[value(alias = "sync")]
+    Video,
+    /// Left-to-right comics: no seek keys, UP/DOWN scroll tall pages, pages stay until turned
+    Comic,
+    /// Right-to-left manga: the comic keys with the left arrow advancing
+    #[value(alias = "manga")]
+    MangaRtl,
+    /// Music: no zoom, pan or rotation keys
+    Audio,
+    /// Images that advance by themselves every 10 seconds while playing
+    Slideshow,
+}
+
+impl ProfileKind {
+    /// The preset's keybinds, adjusted for the platform we're running on
+    pub fn build(self) -> KeybindProfile {
+        let mut profile = match self {
+            ProfileKind::Video => KeybindProfile::new_sync_profile(),
+            ProfileKind::Comic | ProfileKind::Slideshow => KeybindProfile::new_comic_profile(),
+            ProfileKind::MangaRtl => KeybindProfile::new_manga_rtl_profile(),
+            ProfileKind::Audio => KeybindProfile::new_audio_profile(),
+        };
+        profile.adapt_to(Platform::current());
+        profile
+    }
+    
+    /// MPV options (name, value) the preset launches with
+    pub fn mpv_options(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            ProfileKind::Comic | ProfileKind::MangaRtl => &[("image-display-duration", "inf")],
+            ProfileKind::Slideshow => &[("image-display-duration", "10")],
+            ProfileKind::Video | ProfileKind::Audio => &[],
+        }
+    }
+    
+    /// The preset for whatever most of the playlist is
+    ///
+    /// Reading direction can't be told from the files, so images get the
+    /// left-to-right comic preset.
+    pub fn detect(media_files: &[MediaSource]) -> Self {
+        match majority_kind(media_files) {
+            Some(MediaKind::Image) => ProfileKind::Comic,
+            Some(MediaKind::Audio) => ProfileKind::Audio,
+            Some(MediaKind::Video) | None => ProfileKind::Video,
+        }
+    }
+}
+
+impl fmt::Display for ProfileKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.to_possible_value() {
+            Some(value) => f.write_str(value.get_name()),
+            None => write!(f, "{:?}", self),
+        }
+    }
+}
+
+/// Operating systems whose own shortcuts the built-in keys have to stay clear of
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Platform {
+    Linux,
+    MacOs,
+    Windows,
+}
+
+impl Platform {
+    pub fn current() -> Self {
+        if cfg!(target_os = "macos") {
+            Platform::MacOs
+        } else if cfg!(windows) {
+            Platform::Windows
+        } else {
+            Platform::Linux
+        }
+    }
+}
+
 pub struct KeybindProfile {
     keybinds: Vec<(String, String)>, // (key, command)
 }
@@ -10,70 +133,177 @@ pub struct KeybindProfile {
 impl KeybindProfile {
     /// Create a new keybind profile optimized for synchronized media viewing
     pub fn new_sync_profile() -> Self {
-        let mut keybinds = Vec::new();
-        
-        // Basic playback controls
-        keybinds.push(("SPACE".to_string(), "cycle pause".to_string()));
-        keybinds.push(("p".to_string(), "cycle pause".to_string()));
-        
-        // Navigation - LEFT/RIGHT for prev/next file
-        keybinds.push(("LEFT".to_string(), "playlist-prev".to_string()));
-        keybinds.push(("RIGHT".to_string(), "playlist-next".to_string()));
-        
-        // Seeking with other keys
-        keybinds.push(("DOWN".to_string(), "seek -30".to_string()));
-        keybinds.push(("UP".to_string(), "seek 30".to_string()));
-        keybinds.push(("Shift+LEFT".to_string(), "seek -5".to_string()));
-        keybinds.push(("Shift+RIGHT".to_string(), "seek 5".to_string()));
-        
-        // Playlist navigation
-        keybinds.push(("n".to_string(), "playlist-next".to_string()));
-        keybinds.push(("N".to_string(), "playlist-prev".to_string()));
-        keybinds.push((">".to_string(), "playlist-next".to_string()));
-        keybinds.push(("<".to_string(), "playlist-prev".to_string()));
-        
-        // Picture/media specific controls
-        keybinds.push(("z".to_string(), "add video-zoom 0.1".to_string()));
-        keybinds.push(("Z".to_string(), "add video-zoom -0.1".to_string()));
-        keybinds.push(("r".to_string(), "set video-zoom 0; set video-pan-x 0; set video-pan-y 0".to_string()));
+        let keybinds = [
+            // Basic playback controls
+            ("SPACE", "cycle pause"),
+            ("p", "cycle pause"),
+            
+            // Navigation - LEFT/RIGHT for prev/next file
+            ("LEFT", "playlist-prev"),
+            ("RIGHT", "playlist-next"),
+            
+            // Seeking with other keys
+            ("DOWN", "seek -30"),
+            ("UP", "seek 30"),
+            ("Shift+LEFT", "seek -5"),
+            ("Shift+RIGHT", "seek 5"),
+            
+            // Playlist navigation
+            ("n", "playlist-next"),
+            ("N", "playlist-prev"),
+            (">", "playlist-next"),
+            ("<", "playlist-prev"),
+            
+            // Picture/media specific controls
+            ("z", "add video-zoom 0.1"),
+            ("Z", "add video-zoom -0.1"),
+            ("r", "set video-zoom 0; set video-pan-x 0; set video-pan-y 0"),
+            
+            // Pan controls for images
+            ("h", "add video-pan-x -0.05"),
+            ("l", "add video-pan-x 0.05"),
+            ("k", "add video-pan-y -0.05"),
+            ("j", "add video-pan-y 0.05"),
+            
+            // Rotation
+            ("Ctrl+LEFT", "add video-rotate -90"),
+            ("Ctrl+RIGHT", "add video-rotate 90"),
+            
+            // Speed controls
+            ("=", "add speed 0.1"),
+            ("-", "add speed -0.1"),
+            ("BS", "set speed 1.0"),
+            
+            // Volume
+            ("9", "add volume -5"),
+            ("0", "add volume 5"),
+            ("m", "cycle mute"),
+            
+            // Fullscreen and window controls
+            ("f", "cycle fullscreen"),
+            ("ESC", "set fullscreen no"),
+            
+            // Info display
+            ("i", "script-binding stats/display-stats-toggle"),
+            ("I", "script-binding stats/display-page-4"),
+            
+            // SyncRead actions (handled by the client, see `bind_sync_action`)
+            ("TAB", "script-message syncread status"),
+            ("a", "script-message syncread realign"),
+            ("b", "script-message syncread bookmark"),
+            ("B", "script-message syncread goto-bookmark"),
+            ("Ctrl+b", "script-message syncread bookmarks"),
+            ("c", "script-message syncread catch-up"),
+            ("C", "script-message syncread catch-up behind"),
+            ("F1", "script-message syncread jump-to 1"),
+            ("F2", "script-message syncread jump-to 2"),
+            ("F3", "script-message syncread jump-to 3"),
+            ("F4", "script-message syncread jump-to 4"),
+            ("D", "script-message syncread fetch-missing"),
+            ("Ctrl+n", "script-message-to console type \"script-message syncread note \""),
+            ("Ctrl+l", "script-message-to console type \"script-message syncread share-link \""),
+            ("Ctrl+t", "script-binding syncread_chat/open"),
+            ("y", "script-message syncread copy-link"),
+            ("L", "script-message syncread ab-loop"),
+            ("Ctrl+s", "script-message syncread screenshot"),
+            ("P", "script-message syncread slideshow"),
+            ("Ctrl+1", "script-message syncread react like"),
+            ("Ctrl+2", "script-message syncread react laugh"),
+            ("Ctrl+3", "script-message syncread react wow"),
+            ("Ctrl+4", "script-message syncread react love"),
+            ("Ctrl+5", "script-message syncread react sad"),
+            ("Ctrl+6", "script-message syncread react fire"),
+            
+            // Quit
+            ("q", "quit"),
+            ("Q", "quit-watch-later"),
+            
+            // Disable some default keys that might interfere with sync
+            ("s", "ignore"), // Disable screenshot
+            ("S", "ignore"), // Disable screenshot
+        ];
         
-        // Pan controls for images
-        keybinds.push(("h".to_string(), "add video-pan-x -0.05".to_string()));
-        keybinds.push(("l".to_string(), "add video-pan-x 0.05".to_string()));
-        keybinds.push(("k".to_string(), "add video-pan-y -0.05".to_string()));
-        keybinds.push(("j".to_string(), "add video-pan-y 0.05".to_string()));
-        
-        // Rotation
-        keybinds.push(("Ctrl+LEFT".to_string(), "add video-rotate -90".to_string()));
-        keybinds.push(("Ctrl+RIGHT".to_string(), "add video-rotate 90".to_string()));
-        
-        // Speed controls
-        keybinds.push(("=".to_string(), "add speed 0.1".to_string()));
-        keybinds.push(("-".to_string(), "add speed -0.1".to_string()));
-        keybinds.push(("BS".to_string(), "set speed 1.0".to_string()));
-        
-        // Volume
-        keybinds.push(("9".to_string(), "add volume -5".to_string()));
-        keybinds.push(("0".to_string(), "add volume 5".to_string()));
-        keybinds.push(("m".to_string(), "cycle mute".to_string()));
+        Self {
+            keybinds: keybinds.iter()
+                .map(|(key, command)| (key.to_string(), command.to_string()))
+                .collect(),
+        }
+    }
+    
+    /// Sync profile for still pages: no seeking, speed or volume keys, with UP/DOWN scrolling a tall page
+    pub fn new_comic_profile() -> Self {
+        let mut profile = Self::new_sync_profile();
+        for key in ["Shift+LEFT", "Shift+RIGHT", "=", "-", "BS", "9", "0", "m"] {
+            profile.remove_keybind(key);
+        }
         
-        // Fullscreen and window controls
-        keybinds.push(("f".to_string(), "cycle fullscreen".to_string()));
-        keybinds.push(("ESC".to_string(), "set fullscreen no".to_string()));
+        for (key, command) in &mut profile.keybinds {
+            match key.as_str() {
+                "UP" => *command = "add video-pan-y -0.05".to_string(),
+                "DOWN" => *command = "add video-pan-y 0.05".to_string(),
+                _ => {}
+            }
+        }
         
-        // Info display
-        keybinds.push(("i".to_string(), "script-binding stats/display-stats-toggle".to_string()));
-        keybinds.push(("I".to_string(), "script-binding stats/display-page-4".to_string()));
+        profile
+    }
+    
+    /// Comic profile for right-to-left manga, with LEFT/RIGHT swapped so LEFT turns to the next page
+    pub fn new_manga_rtl_profile() -> Self {
+        let mut profile = Self::new_comic_profile();
         
-        // Quit
-        keybinds.push(("q".to_string(), "quit".to_string()));
-        keybinds.push(("Q".to_string(), "quit-watch-later".to_string()));
+        for (key, _) in &mut profile.keybinds {
+            match key.as_str() {
+                "LEFT" => *key = "RIGHT".to_string(),
+                "RIGHT" => *key = "LEFT".to_string(),
+                _ => {}
+            }
+        }
         
-        // Disable some default keys that might interfere with sync
-        keybinds.push(("s".to_string(), "ignore".to_string())); // Disable screenshot
-        keybinds.push(("S".to_string(), "ignore".to_string())); // Disable screenshot
+        profile
+    }
+    
+    /// Sync profile for music: no keys that zoom, pan or rotate a picture
+    pub fn new_audio_profile() -> Self {
+        let mut profile = Self::new_sync_profile();
+        for key in ["z", "Z", "r", "h", "l", "k", "j", "Ctrl+LEFT", "Ctrl+RIGHT"] {
+            profile.remove_keybind(key);
+        }
+        profile
+    }
+    
+    /// Move keys that clash with the platform's own shortcuts
+    ///
+    /// On macOS, Ctrl+arrows and Ctrl+digits switch Spaces, so every Ctrl
+    /// binding moves to Cmd (`Meta`), the usual modifier there. On macOS
+    /// and Windows laptops F1-F4 control brightness, volume or Mission
+    /// Control unless Fn is held, so jumping to a reader moves to 1-4.
+    pub fn adapt_to(&mut self, platform: Platform) {
+        if platform == Platform::Linux {
+            return;
+        }
         
-        Self { keybinds }
+        for (key, _) in &mut self.keybinds {
+            if let Some(rest) = key.strip_prefix("Ctrl+").filter(|_| platform == Platform::MacOs) {
+                *key = format!("Meta+{}", rest);
+            } else if let Some(n @ ("1" | "2" | "3" | "4")) = key.strip_prefix('F') {
+                *key = n.to_string();
+            }
+        }
+    }
+    
+    /// Make the page keys skip between chapters of the current file instead
+    ///
+    /// MPV moves on to the next file from the last chapter, so the keys
+    /// still get through the whole playlist.
+    pub fn navigate_chapters(&mut self) {
+        for (_, command) in &mut self.keybinds {
+            match command.as_str() {
+                "playlist-next" => *command = "add chapter 1".to_string(),
+                "playlist-prev" => *command = "add chapter -1".to_string(),
+                _ => {}
+            }
+        }
     }
     
     /// Add a custom keybind
@@ -81,17 +311,107 @@ impl KeybindProfile {
         self.keybinds.push((key, command));
     }
     
+    /// Bind a key to a syncread client action (e.g. "status"), replacing any existing binding
+    pub fn bind_sync_action(&mut self, key: &str, action: &str) {
+        self.set_keybind(key.to_string(), format!("script-message {} {}", SYNC_ACTION_MESSAGE, action));
+    }
+    
     /// Remove keybind for a specific key
     pub fn remove_keybind(&mut self, key: &str) {
         self.keybinds.retain(|(k, _)| k != key);
     }
     
+    /// Bind a key, replacing any existing binding for it
+    pub fn set_keybind(&mut self, key: String, command: String) {
+        self.remove_keybind(&key);
+        self.add_keybind(key, command);
+    }
+    
+    /// Merge user keybinds from a file over this profile
+    ///
+    /// Every command in the file is validated before any binding is applied.
+    pub fn merge_from_file<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let bindings = read_keybind_file(path)?;
+        
+        let count = bindings.len();
+        for (key, command) in bindings {
+            self.set_keybind(key, command);
+        }
+        
+        info!("Merged {} custom keybinds from {:?}", count, path);
+        Ok(())
+    }
+    
+    /// Check the whole profile before MPV gets it, reporting every problem at once
+    ///
+    /// Flags keys bound twice, malformed key names, commands MPV doesn't know,
+    /// unknown syncread actions, and keys the built-in profile gives to a
+    /// syncread action that were bound to something else without the action
+    /// getting another key.
+    pub fn validate(&self) -> Result<()> {
+        let mut problems = Vec::new();
+        let mut seen = BTreeSet::new();
+        
+        for (key, command) in &self.keybinds {
+            if key.is_empty() || key.contains(char::is_whitespace) {
+                problems.push(format!("'{}': not a key name", key));
+            }
+            if !seen.insert(key.as_str()) {
+                problems.push(format!("{}: bound more than once", key));
+            }
+            if let Err(e) = validate_command(command) {
+                problems.push(format!("{}: {}", key, e));
+                continue;
+            }
+            for message in sync_messages(command) {
+                let action = message.split_whitespace().next().unwrap_or_default();
+                if !SYNC_ACTIONS.contains(&action) {
+                    problems.push(format!("{}: unknown syncread action '{}'", key, action));
+                }
+            }
+        }
+        
+        let bound: BTreeSet<&str> = self.keybinds.iter()
+            .flat_map(|(_, command)| sync_messages(command))
+            .collect();
+        for (key, command) in &Self::new_sync_profile().keybinds {
+            for message in sync_messages(command).into_iter().filter(|m| !bound.contains(m)) {
+                let now = match self.keybinds.iter().find(|(k, _)| k == key) {
+                    Some((_, command)) => format!("bound to '{}'", command),
+                    None => "unbound".to_string(),
+                };
+                problems.push(format!("{}: {}, leaving syncread's '{}' without a key", key, now, message));
+            }
+        }
+        
+        if !problems.is_empty() {
+            anyhow::bail!("{}", problems.join("\n"));
+        }
+        
+        Ok(())
+    }
+    
+    /// What merging `bindings` over this profile would change, in the order they'd apply
+    ///
+    /// Keys the bindings leave out keep the profile's command, so there are
+    /// no removals.
+    pub fn changes(&self, bindings: &[(String, String)]) -> Vec<KeybindChange> {
+        bindings.iter()
+            .filter_map(|(key, command)| match self.keybinds.iter().find(|(k, _)| k == key) {
+                Some((_, current)) if current == command => None,
+                Some((_, current)) => Some(KeybindChange::Changed { key: key.clone(), from: current.clone(), to: command.clone() }),
+                None => Some(KeybindChange::Added { key: key.clone(), command: command.clone() }),
+            })
+            .collect()
+    }
+    
     /// Generate the keybind config file content
     pub fn generate_config(&self) -> String {
         let mut config = String::new();
         
         config.push_str("# SyncRead MPV Keybind Profile\n");
-        config.push_str("# Generated automatically - do not edit manually\n\n");
+        config.push_str("# Generated by syncread - edit a copy and pass it to --keybinds\n\n");
         
         for (key, command) in &self.keybinds {
             config.push_str(&format!("{:<20} {}\n", key, command));
@@ -122,6 +442,148 @@ impl KeybindProfile {
     }
 }
 
+/// One binding a keybind file adds or rebinds
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeybindChange {
+    Added { key: String, command: String },
+    Changed { key: String, from: String, to: String },
+}
+
+impl fmt::Display for KeybindChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeybindChange::Added { key, command } => write!(f, "+ {:<20} {}", key, command),
+            KeybindChange::Changed { key, from, to } => write!(f, "~ {:<20} {} -> {}", key, from, to),
+        }
+    }
+}
+
+/// The validated bindings in a keybind file
+///
+/// `.toml` files use a `[keybinds]` table; anything else is read as an
+/// mpv input.conf (`KEY command` per line).
+pub fn read_keybind_file(path: &Path) -> Result<Vec<(String, String)>> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read keybind file {:?}", path))?;
+    
+    let is_toml = path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("toml"));
+    
+    if is_toml {
+        parse_toml_keybinds(&content)
+    } else {
+        parse_input_conf(&content)
+    }.with_context(|| format!("Invalid keybind file {:?}", path))
+}
+
+/// Parse a TOML keybind file into validated bindings
+fn parse_toml_keybinds(content: &str) -> Result<Vec<(String, String)>> {
+    let file: KeybindFile = toml::from_str(content)?;
+    
+    let mut problems = Vec::new();
+    for (key, command) in &file.keybinds {
+        if let Err(e) = validate_command(command) {
+            problems.push(format!("{}: {}", key, e));
+        }
+    }
+    
+    if !problems.is_empty() {
+        anyhow::bail!("{}", problems.join("\n"));
+    }
+    
+    Ok(file.keybinds.into_iter().collect())
+}
+
+/// Parse mpv input.conf lines into validated bindings
+fn parse_input_conf(content: &str) -> Result<Vec<(String, String)>> {
+    let mut bindings: Vec<(String, String)> = Vec::new();
+    let mut problems = Vec::new();
+    
+    for (line_no, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        
+        let Some((key, command)) = line.split_once(char::is_whitespace) else {
+            problems.push(format!("line {}: missing command for key '{}'", line_no + 1, line));
+            continue;
+        };
+        
+        let command = command.trim();
+        if bindings.iter().any(|(bound, _)| bound == key) {
+            problems.push(format!("line {}: '{}' is already bound above", line_no + 1, key));
+            continue;
+        }
+        match validate_command(command) {
+            Ok(()) => bindings.push((key.to_string(), command.to_string())),
+            Err(e) => problems.push(format!("line {}: {}", line_no + 1, e)),
+        }
+    }
+    
+    if !problems.is_empty() {
+        anyhow::bail!("{}", problems.join("\n"));
+    }
+    
+    Ok(bindings)
+}
+
+/// Check that every `;`-separated part of a binding names a known MPV command
+pub fn validate_command(command: &str) -> Result<()> {
+    // Strip trailing comments the way mpv does
+    let command = command.split(" #").next().unwrap_or("").trim();
+    if command.is_empty() {
+        anyhow::bail!("empty command");
+    }
+    
+    if !command.matches('"').count().is_multiple_of(2) {
+        anyhow::bail!("unbalanced quotes in '{}'", command);
+    }
+    
+    for part in split_commands(command) {
+        let name = part.split_whitespace()
+            .find(|word| !MPV_COMMAND_PREFIXES.contains(word));
+        
+        match name {
+            Some(name) if KNOWN_MPV_COMMANDS.contains(&name) => {}
+            Some(name) => anyhow::bail!("unknown mpv command '{}'", name),
+            None => anyhow::bail!("empty command in '{}'", command),
+        }
+    }
+    
+    Ok(())
+}
+
+/// The `<action> [args]` of every `script-message syncread` in a binding
+fn sync_messages(command: &str) -> Vec<&str> {
+    split_commands(command).into_iter()
+        .filter_map(|part| part.strip_prefix("script-message")?.trim_start().strip_prefix(SYNC_ACTION_MESSAGE))
+        .map(str::trim)
+        .collect()
+}
+
+/// Split a command list on `;` while respecting double quotes
+fn split_commands(command: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut in_quotes = false;
+    let mut start = 0;
+    
+    for (i, c) in command.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            ';' if !in_quotes => {
+                parts.push(command[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(command[start..].trim());
+    
+    parts
+}
+
 impl Default for KeybindProfile {
     fn default() -> Self {
         Self::new_sync_profile()
@@ -142,6 +604,72 @@ mod tests {
         assert!(config.contains("playlist-next"));
     }
     
+    #[test]
+    fn test_manga_profile_swaps_arrows() {
+        let profile = KeybindProfile::new_manga_rtl_profile();
+        let command = |key: &str| profile.keybinds.iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, c)| c.as_str());
+        
+        assert_eq!(command("LEFT"), Some("playlist-next"));
+        assert_eq!(command("RIGHT"), Some("playlist-prev"));
+        assert_eq!(command("Shift+LEFT"), None); // Pages don't seek
+    }
+    
+    #[test]
+    fn test_platform_adjustments() {
+        let command = |profile: &KeybindProfile, key: &str| profile.keybinds.iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, c)| c.clone());
+        
+        let mut mac = KeybindProfile::new_sync_profile();
+        mac.adapt_to(Platform::MacOs);
+        assert_eq!(command(&mac, "Meta+s").as_deref(), Some("script-message syncread screenshot"));
+        assert_eq!(command(&mac, "Meta+RIGHT").as_deref(), Some("add video-rotate 90"));
+        assert_eq!(command(&mac, "2").as_deref(), Some("script-message syncread jump-to 2"));
+        assert!(!mac.keybinds.iter().any(|(k, _)| k.starts_with("Ctrl+") || k == "F1"));
+        assert!(mac.validate().is_ok());
+        
+        let mut windows = KeybindProfile::new_sync_profile();
+        windows.adapt_to(Platform::Windows);
+        assert_eq!(command(&windows, "1").as_deref(), Some("script-message syncread jump-to 1"));
+        assert_eq!(command(&windows, "Ctrl+s").as_deref(), Some("script-message syncread screenshot"));
+        assert_eq!(command(&windows, "Ctrl+t").as_deref(), Some("script-binding syncread_chat/open"));
+        assert!(windows.validate().is_ok());
+    }
+    
+    #[test]
+    fn test_presets() {
+        for kind in ProfileKind::value_variants() {
+            assert!(kind.build().validate().is_ok(), "{}", kind);
+        }
+        
+        let comic = KeybindProfile::new_comic_profile();
+        assert!(!comic.keybinds.iter().any(|(_, c)| c.starts_with("seek")));
+        assert!(comic.generate_config().contains("add video-pan-y 0.05"));
+        let audio = KeybindProfile::new_audio_profile();
+        assert!(!audio.keybinds.iter().any(|(_, c)| c.contains("video-")));
+        assert_eq!(ProfileKind::MangaRtl.mpv_options(), [("image-display-duration", "inf")]);
+        
+        let pages = [MediaSource::parse("p1.png"), MediaSource::parse("p2.png"), MediaSource::parse("op.mkv")];
+        assert_eq!(ProfileKind::detect(&pages), ProfileKind::Comic);
+        assert_eq!(ProfileKind::detect(&[MediaSource::parse("01.flac")]), ProfileKind::Audio);
+        assert_eq!(ProfileKind::MangaRtl.to_string(), "manga-rtl");
+    }
+    
+    #[test]
+    fn test_chapter_navigation() {
+        let mut profile = KeybindProfile::new_sync_profile();
+        profile.navigate_chapters();
+        let command = |key: &str| profile.keybinds.iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, c)| c.as_str());
+        
+        assert_eq!(command("RIGHT"), Some("add chapter 1"));
+        assert_eq!(command("N"), Some("add chapter -1"));
+        assert_eq!(command("UP"), Some("seek 30"));
+    }
+    
     #[test]
     fn test_add_custom_keybind() {
         let mut profile = KeybindProfile::new_sync_profile();
@@ -151,4 +679,74 @@ mod tests {
         assert!(config.contains("x"));
         assert!(config.contains("show-text hello"));
     }
+    
+    #[test]
+    fn test_merge_input_conf_overrides() {
+        let mut profile = KeybindProfile::new_sync_profile();
+        let path = std::env::temp_dir().join("syncread_test_merge.conf");
+        fs::write(&path, "# comment\nRIGHT playlist-prev\nx show-text \"a;b\"\n").unwrap();
+        
+        profile.merge_from_file(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        
+        let right: Vec<_> = profile.keybinds.iter().filter(|(k, _)| k == "RIGHT").collect();
+        assert_eq!(right.len(), 1);
+        assert_eq!(right[0].1, "playlist-prev");
+        assert!(profile.generate_config().contains("show-text \"a;b\""));
+    }
+    
+    #[test]
+    fn test_changes() {
+        let profile = KeybindProfile::new_sync_profile();
+        let bindings = |pairs: &[(&str, &str)]| pairs.iter()
+            .map(|(key, command)| (key.to_string(), command.to_string()))
+            .collect::<Vec<_>>();
+        
+        // A dump of the profile itself changes nothing
+        let dumped = parse_input_conf(&profile.generate_config()).unwrap();
+        assert!(profile.changes(&dumped).is_empty());
+        
+        let changes = profile.changes(&bindings(&[("RIGHT", "playlist-prev"), ("x", "show-text hi"), ("SPACE", "cycle pause")]));
+        assert_eq!(changes, [
+            KeybindChange::Changed { key: "RIGHT".into(), from: "playlist-next".into(), to: "playlist-prev".into() },
+            KeybindChange::Added { key: "x".into(), command: "show-text hi".into() },
+        ]);
+        assert_eq!(changes[1].to_string(), format!("+ {:<20} show-text hi", "x"));
+    }
+    
+    #[test]
+    fn test_toml_keybinds() {
+        let bindings = parse_toml_keybinds("[keybinds]\n\"Shift+n\" = \"no-osd playlist-next\"\n").unwrap();
+        assert_eq!(bindings, vec![("Shift+n".to_string(), "no-osd playlist-next".to_string())]);
+    }
+    
+    #[test]
+    fn test_rejects_broken_commands() {
+        assert!(validate_command("cycle pause; set speed 1").is_ok());
+        assert!(validate_command("cylce pause").is_err());
+        assert!(validate_command("show-text \"oops").is_err());
+        assert!(parse_input_conf("a\n").is_err());
+        assert!(parse_input_conf("x quit\nx stop\n").is_err());
+    }
+    
+    #[test]
+    fn test_validate_profile() {
+        assert!(KeybindProfile::new_sync_profile().validate().is_ok());
+        assert!(KeybindProfile::new_manga_rtl_profile().validate().is_ok());
+        
+        let mut duplicate = KeybindProfile::new_sync_profile();
+        duplicate.add_keybind("x".to_string(), "quit".to_string());
+        duplicate.add_keybind("x".to_string(), "script-message syncread stauts".to_string());
+        let report = duplicate.validate().unwrap_err().to_string();
+        assert!(report.contains("x: bound more than once"), "{}", report);
+        assert!(report.contains("unknown syncread action 'stauts'"), "{}", report);
+        
+        // Taking the bookmark key is fine once bookmarking has a key of its own
+        let mut rebound = KeybindProfile::new_sync_profile();
+        rebound.set_keybind("b".to_string(), "cycle sub".to_string());
+        let report = rebound.validate().unwrap_err().to_string();
+        assert_eq!(report, "b: bound to 'cycle sub', leaving syncread's 'bookmark' without a key");
+        rebound.bind_sync_action("Alt+b", "bookmark");
+        assert!(rebound.validate().is_ok());
+    }
 }
@@ -0,0 +1,306 @@
+//! In-process stand-in for MPV, for tests that shouldn't need the real thing
+//!
+//! Serves MPV's JSON IPC on a Unix socket and keeps just enough state
+//! (playlist position, pause, playback time, speed) for a SyncClient to
+//! drive through an ordinary `MpvController`. Tests turn pages and press
+//! keys on it as a user would, and read back what the client did to it.
+
+use super::controller::{socket_path_for, MpvController, SYNC_ACTION_MESSAGE};
+use crate::media::MediaSource;
+use anyhow::Result;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::task::JoinHandle;
+
+/// What a mock MPV is showing
+#[derive(Debug, Clone)]
+pub struct MockState {
+    pub playlist_pos: i32,
+    pub playlist_count: usize,
+    pub paused: bool,
+    pub playback_time: f64,
+    pub speed: f64,
+    pub osd_messages: Vec<String>, // Everything shown with show-text, oldest first
+    pub screenshots: Vec<String>, // Paths passed to screenshot-to-file (nothing is written)
+    pub properties: HashMap<String, Value>, // Any other property: no subtitles, full volume, no loop and no zoom or rotation until set
+    pub queries: usize, // get_property commands answered, to tell when a client is polling
+    pub quit: bool,
+    killed: bool, // Hang up on the next command without answering
+    events: Vec<String>, // Sent before the next reply, as MPV interleaves events
+}
+
+/// A fake MPV listening on its own IPC socket until dropped
+pub struct MockMpv {
+    socket_path: PathBuf,
+    state: Arc<Mutex<MockState>>,
+    server: JoinHandle<()>,
+}
+
+impl MockMpv {
+    /// Start serving a playlist of `playlist_count` entries, paused on the first like a real launch
+    pub fn start(name: &str, playlist_count: usize) -> Result<Self> {
+        let socket_path = socket_path_for(&format!("mock_{}", name));
+        let _ = std::fs::remove_file(&socket_path);
+        let listener = UnixListener::bind(&socket_path)?;
+        
+        let state = Arc::new(Mutex::new(MockState {
+            playlist_pos: 0,
+            playlist_count,
+            paused: true,
+            playback_time: 0.0,
+            speed: 1.0,
+            osd_messages: Vec::new(),
+            screenshots: Vec::new(),
+            properties: HashMap::from([
+                ("sid".to_string(), json!(false)),
+                ("volume".to_string(), json!(100)),
+                ("ab-loop-a".to_string(), json!("no")),
+                ("ab-loop-b".to_string(), json!("no")),
+                ("video-zoom".to_string(), json!(0.0)),
+                ("video-pan-x".to_string(), json!(0.0)),
+                ("video-pan-y".to_string(), json!(0.0)),
+                ("video-rotate".to_string(), json!(0)),
+            ]),
+            queries: 0,
+            quit: false,
+            killed: false,
+            events: Vec::new(),
+        }));
+        
+        let shared = state.clone();
+        let server = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(stream, shared.clone()));
+            }
+        });
+        
+        Ok(Self { socket_path, state, server })
+    }
+    
+    /// A controller connected to this MPV, as `MpvController::launch` would return for a real one
+    pub async fn controller(&self, media_files: &[MediaSource]) -> Result<MpvController> {
+        Ok(MpvController::attach(&self.socket_path, media_files).await?)
+    }
+    
+    /// Snapshot of the current state
+    pub fn state(&self) -> MockState {
+        self.state.lock().unwrap().clone()
+    }
+    
+    /// Turn to a playlist entry, as the user pressing keys in MPV would
+    pub fn set_playlist_pos(&self, pos: i32) {
+        self.state.lock().unwrap().playlist_pos = pos;
+    }
+    
+    /// Pause or resume, as the user pressing space in MPV would
+    pub fn set_paused(&self, paused: bool) {
+        self.state.lock().unwrap().paused = paused;
+    }
+    
+    /// Jump to a time in the current file, as the user seeking would
+    pub fn seek(&self, seconds: f64) {
+        self.state.lock().unwrap().playback_time = seconds;
+    }
+    
+    /// Change any other property, as the user cycling subtitles or zooming would
+    pub fn set_property(&self, name: &str, value: Value) {
+        self.state.lock().unwrap().properties.insert(name.to_string(), value);
+    }
+    
+    /// Trigger `script-message syncread <args>`, as a key bound to it would
+    pub fn press(&self, args: &[&str]) {
+        let mut message = vec![SYNC_ACTION_MESSAGE];
+        message.extend_from_slice(args);
+        let event = json!({ "event": "client-message", "args": message });
+        self.state.lock().unwrap().events.push(event.to_string());
+    }
+    
+    /// Quit from inside MPV, as pressing `q` would: the events go out with the next reply, then the socket closes
+    pub fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        let end_file = json!({ "event": "end-file", "reason": "quit", "playlist_entry_id": state.playlist_pos + 1 });
+        state.events.push(end_file.to_string());
+        state.events.push(json!({ "event": "shutdown" }).to_string());
+        state.quit = true;
+    }
+    
+    /// Go away without a word, as MPV killed from outside would
+    pub fn kill(&self) {
+        self.state.lock().unwrap().killed = true;
+    }
+}
+
+impl Drop for MockMpv {
+    fn drop(&mut self) {
+        self.server.abort();
+        let _ = std::fs::remove_file(&self.socket_path);
+    }
+}
+
+/// Answer one IPC client's commands until it disconnects or sends `quit`
+async fn serve(stream: UnixStream, state: Arc<Mutex<MockState>>) {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let Ok(request) = serde_json::from_str::<Value>(&line) else {
+            continue;
+        };
+        let command = request["command"].as_array().cloned().unwrap_or_default();
+        
+        let (reply, events) = {
+            let mut state = state.lock().unwrap();
+            if state.killed {
+                return;
+            }
+            let reply = match execute(&mut state, &command) {
+                Ok(data) => json!({ "error": "success", "data": data, "request_id": request["request_id"] }),
+                Err(error) => json!({ "error": error, "request_id": request["request_id"] }),
+            };
+            (reply, std::mem::take(&mut state.events))
+        };
+        
+        let mut out = String::new();
+        for event in events {
+            out.push_str(&event);
+            out.push('\n');
+        }
+        out.push_str(&reply.to_string());
+        out.push('\n');
+        if writer.write_all(out.as_bytes()).await.is_err() || state.lock().unwrap().quit {
+            return;
+        }
+    }
+}
+
+/// Apply one command to the state, returning its `data` or MPV's error string
+fn execute(state: &mut MockState, command: &[Value]) -> Result<Value, &'static str> {
+    let name = command.first().and_then(Value::as_str).unwrap_or_default();
+    let arg = |index: usize| command.get(index).cloned().unwrap_or(Value::Null);
+    let last_entry = state.playlist_count as i32 - 1;
+    
+    match name {
+        "get_property" => {
+            state.queries += 1;
+            match arg(1).as_str().unwrap_or_default() {
+                "playlist-pos" => Ok(json!(state.playlist_pos)),
+                "playlist-count" => Ok(json!(state.playlist_count)),
+                "pause" => Ok(json!(state.paused)),
+                "playback-time" => Ok(json!(state.playback_time)),
+                "speed" => Ok(json!(state.speed)),
+                "duration" => Ok(Value::Null),
+                name => state.properties.get(name).cloned().ok_or("property unavailable"),
+            }
+        }
+        "set_property" => {
+            let value = arg(2);
+            match arg(1).as_str().unwrap_or_default() {
+                "playlist-pos" => state.playlist_pos = value.as_i64().ok_or("invalid parameter")? as i32,
+                "pause" => state.paused = value.as_bool().ok_or("invalid parameter")?,
+                "speed" => state.speed = value.as_f64().ok_or("invalid parameter")?,
+                name => {
+                    state.properties.insert(name.to_string(), value);
+                }
+            }
+            Ok(Value::Null)
+        }
+        "seek" => {
+            let seconds = arg(1).as_f64().ok_or("invalid parameter")?;
+            state.playback_time = match arg(2).as_str() {
+                Some("absolute") => seconds,
+                _ => state.playback_time + seconds,
+            }.max(0.0);
+            Ok(Value::Null)
+        }
+        "playlist-next" => {
+            state.playlist_pos = (state.playlist_pos + 1).min(last_entry);
+            Ok(Value::Null)
+        }
+        "playlist-prev" => {
+            state.playlist_pos = (state.playlist_pos - 1).max(0);
+            Ok(Value::Null)
+        }
+        "loadfile" => {
+            state.playlist_count += 1;
+            Ok(Value::Null)
+        }
+        "show-text" => {
+            state.osd_messages.push(arg(1).as_str().unwrap_or_default().to_string());
+            Ok(Value::Null)
+        }
+        "screenshot-to-file" => {
+            state.screenshots.push(arg(1).as_str().unwrap_or_default().to_string());
+            Ok(Value::Null)
+        }
+        "quit" => {
+            state.quit = true;
+            Ok(Value::Null)
+        }
+        "playlist-remove" => {
+            let index = arg(1).as_i64().ok_or("invalid parameter")? as i32;
+            if index > last_entry {
+                return Err("invalid parameter");
+            }
+            state.playlist_count -= 1;
+            if state.playlist_pos > index || state.playlist_pos == state.playlist_count as i32 {
+                state.playlist_pos -= 1;
+            }
+            Ok(Value::Null)
+        }
+        "playlist-move" => {
+            // The current entry keeps playing wherever it ends up
+            let (from, target) = (arg(1).as_i64().ok_or("invalid parameter")? as i32, arg(2).as_i64().ok_or("invalid parameter")? as i32);
+            let to = if target > from { target - 1 } else { target };
+            let pos = state.playlist_pos;
+            state.playlist_pos = if pos == from {
+                to
+            } else if from < pos && pos <= to {
+                pos - 1
+            } else if to <= pos && pos < from {
+                pos + 1
+            } else {
+                pos
+            };
+            Ok(Value::Null)
+        }
+        "osd-overlay" | "script-message-to" => Ok(Value::Null),
+        _ => Err("invalid parameter"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mpv::MpvEvent;
+    use crate::player::MediaPlayer;
+    
+    #[tokio::test]
+    async fn test_controller_drives_mock() {
+        let mpv = MockMpv::start("controller_test", 3).unwrap();
+        let files: Vec<MediaSource> = (1..=3).map(|i| MediaSource::Local(PathBuf::from(format!("p{}.png", i)))).collect();
+        let mut controller = mpv.controller(&files).await.unwrap();
+        
+        controller.set_playlist_pos(2).await.unwrap();
+        controller.play().await.unwrap();
+        controller.seek_to(12.5).await.unwrap();
+        controller.show_text("hello", 1000).await.unwrap();
+        let state = mpv.state();
+        assert_eq!((state.playlist_pos, state.paused, state.playback_time), (2, false, 12.5));
+        assert_eq!(state.osd_messages, vec!["hello".to_string()]);
+        
+        // What the user does shows up when the controller next asks
+        mpv.set_playlist_pos(1);
+        mpv.press(&["status"]);
+        assert_eq!(controller.get_playlist_pos().await.unwrap(), 1);
+        assert_eq!(controller.take_script_actions(), vec![vec!["status".to_string()]]);
+        
+        let mut events = controller.subscribe();
+        mpv.close();
+        assert!(controller.get_playlist_pos().await.is_ok());
+        assert!(events.try_recv().unwrap().is_quit());
+        assert_eq!(events.try_recv().unwrap(), MpvEvent::Shutdown);
+    }
+}
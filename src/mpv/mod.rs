@@ -1,7 +1,17 @@
+pub mod binary;
+pub mod chat_input;
+pub mod conf;
 pub mod controller;
+pub mod events;
+pub mod hud;
 pub mod keybinds;
+#[cfg(all(unix, any(test, feature = "mock-mpv")))]
+pub mod mock;
 pub mod playlist;
 
-pub use controller::MpvController;
-pub use keybinds::KeybindProfile;
+pub use binary::{find_mpv, install_hint};
+pub use conf::MpvConf;
+pub use controller::{ipc_address, screenshot_path, socket_path_for, LaunchOptions, MpvController};
+pub use events::{EndFileReason, MpvEvent};
+pub use keybinds::{read_keybind_file, KeybindChange, KeybindProfile, ProfileKind};
 pub use playlist::PlaylistState;
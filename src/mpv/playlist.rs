@@ -1,6 +1,5 @@
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
-use anyhow::Result;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct PlaylistItem {
@@ -61,6 +60,7 @@ impl PlaylistState {
     }
     
     /// Move to next item in playlist
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> bool {
         if (self.current_index as usize) < self.items.len().saturating_sub(1) {
             self.current_index += 1;
@@ -150,7 +150,7 @@ impl Default for PlaylistState {
 }
 
 /// Format seconds as MM:SS or HH:MM:SS
-fn format_time(seconds: f64) -> String {
+pub fn format_time(seconds: f64) -> String {
     let total_seconds = seconds as u64;
     let hours = total_seconds / 3600;
     let minutes = (total_seconds % 3600) / 60;
@@ -0,0 +1,36 @@
+-- SyncRead chat input
+--
+-- Opens a one-line prompt in MPV (Ctrl+t in the syncread profiles, or
+-- `script-binding syncread_chat/open` in input.conf) and hands what you
+-- type to the syncread client as
+--   script-message syncread chat <text>
+-- The text goes over as a single argument, so quotes and semicolons are
+-- sent as typed. MPV before 0.39 has no mp.input; there the console opens
+-- prefilled with the command instead.
+
+local has_input, input = pcall(require, "mp.input")
+
+local function send(text)
+    text = (text:gsub("^%s+", ""):gsub("%s+$", ""))
+    if text ~= "" then
+        mp.commandv("script-message", "syncread", "chat", text)
+    end
+end
+
+local function open()
+    if not has_input then
+        mp.commandv("script-message-to", "console", "type", "script-message syncread chat ")
+        return
+    end
+
+    input.get({
+        prompt = "Chat:",
+        id = "syncread-chat", -- Up/Down recall earlier messages
+        submit = function(text)
+            send(text)
+            input.terminate()
+        end,
+    })
+end
+
+mp.add_key_binding(nil, "open", open)
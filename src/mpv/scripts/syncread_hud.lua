@@ -0,0 +1,73 @@
+-- SyncRead in-player HUD
+--
+-- The syncread client pushes session status with
+--   script-message-to syncread_hud update <json>
+-- and this script draws it in the top-left corner. Toggle with Ctrl+h
+-- (rebind via `script-binding syncread_hud/toggle` in input.conf).
+
+local utils = require("mp.utils")
+
+local overlay = mp.create_osd_overlay("ass-events")
+local visible = true
+local status = nil
+
+local function escape(text)
+    return (tostring(text):gsub("\\", "\\\\"):gsub("{", "\\{"):gsub("}", "\\}"))
+end
+
+-- "#rrggbb" from the client to an ASS color override
+local function color_tag(hex)
+    local r, g, b = tostring(hex):match("^#(%x%x)(%x%x)(%x%x)$")
+    if r == nil then
+        return ""
+    end
+    return string.format("{\\c&H%s%s%s&}", b, g, r)
+end
+
+local function render()
+    if not visible or status == nil then
+        overlay:remove()
+        return
+    end
+
+    local lines = {}
+    table.insert(lines, string.format("{\\b1}SyncRead{\\b0}  %d users  %s",
+        #status.users, status.in_sync and "in sync" or "{\\c&H00A5FF&}out of sync{\\c}"))
+
+    for _, user in ipairs(status.users) do
+        local marker = user.me and "▸ " or "  "
+        local state = user.paused and "⏸" or "▶"
+        local file = user.file and ("  " .. escape(user.file)) or ""
+        local name = escape(user.user_id)
+        if user.icon then
+            name = escape(user.icon) .. " " .. name
+        end
+        local idle = user.idle and ("  {\\c&H888888&}" .. escape(user.idle) .. "{\\c}") or ""
+        table.insert(lines, string.format("%s%s %s%s{\\c}  p.%d%s%s",
+            marker, state, color_tag(user.color), name, user.position, file, idle))
+    end
+
+    overlay.data = "{\\an7\\fs22\\bord2}" .. table.concat(lines, "\\N")
+    overlay:update()
+end
+
+mp.register_script_message("update", function(json)
+    local parsed, err = utils.parse_json(json)
+    if parsed == nil then
+        mp.msg.warn("Ignoring malformed status: " .. tostring(err))
+        return
+    end
+    status = parsed
+    render()
+end)
+
+mp.register_script_message("clear", function()
+    status = nil
+    render()
+end)
+
+mp.add_key_binding("Ctrl+h", "toggle", function()
+    visible = not visible
+    render()
+    mp.osd_message(visible and "SyncRead HUD on" or "SyncRead HUD off")
+end)
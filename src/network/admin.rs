@@ -0,0 +1,75 @@
+use super::protocol::{AdminCommand, SessionState, SyncEvent, SyncMessage, UserId};
+use super::stats::ReadingStats;
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::time::{timeout, Duration};
+use tracing::debug;
+
+/// Send a moderation command to a server and return its reply message
+pub async fn send_admin_command(server_addr: SocketAddr, token: &str, command: AdminCommand) -> Result<String> {
+    let event = SyncEvent::Admin { token: token.to_string(), command };
+    let reply = request(server_addr, event, |event| match event {
+        SyncEvent::AdminResult { ok, message } => Some((ok, message)),
+        _ => None,
+    }).await?;
+    
+    match reply {
+        (true, message) => Ok(message),
+        (false, message) => anyhow::bail!("Server rejected command: {}", message),
+    }
+}
+
+/// Fetch the reading stats a server has gathered for this session
+pub async fn request_stats(server_addr: SocketAddr) -> Result<HashMap<UserId, ReadingStats>> {
+    request(server_addr, SyncEvent::StatsRequest, |event| match event {
+        SyncEvent::Stats { users } => Some(users),
+        _ => None,
+    }).await
+}
+
+/// Fetch a snapshot of the server's session: every user's state, notes and the last shared link
+pub async fn request_session(server_addr: SocketAddr) -> Result<SessionState> {
+    request(server_addr, SyncEvent::SessionRequest, |event| match event {
+        SyncEvent::Session { session } => Some(session),
+        _ => None,
+    }).await
+}
+
+/// Send one event to a server and wait for the reply `extract` picks out
+async fn request<T>(
+    server_addr: SocketAddr,
+    event: SyncEvent,
+    extract: impl Fn(SyncEvent) -> Option<T>,
+) -> Result<T> {
+    let stream = TcpStream::connect(server_addr).await
+        .with_context(|| format!("Failed to connect to {}", server_addr))?;
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    
+    let message = SyncMessage::new(event, 1);
+    let json = serde_json::to_string(&message)?;
+    writer.write_all(format!("{}\n", json).as_bytes()).await?;
+    writer.flush().await?;
+    
+    // Other session traffic may arrive first; wait for our reply
+    timeout(Duration::from_secs(5), async {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if reader.read_line(&mut line).await? == 0 {
+                anyhow::bail!("Server closed the connection without replying");
+            }
+            
+            match serde_json::from_str::<SyncMessage>(line.trim()) {
+                Ok(message) => match extract(message.event) {
+                    Some(reply) => return Ok(reply),
+                    None => debug!("Ignoring session message"),
+                },
+                Err(e) => debug!("Ignoring unparsable line: {}", e),
+            }
+        }
+    }).await.context("Timed out waiting for the server to reply")?
+}
@@ -0,0 +1,174 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Longest avatar accepted, in characters (enough for flag and ZWJ emoji)
+pub const MAX_ICON_CHARS: usize = 8;
+
+/// Color a user is drawn in, in terminals and on the MPV OSD
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UserColor {
+    Red,
+    Orange,
+    Yellow,
+    Green,
+    Cyan,
+    Blue,
+    Purple,
+    Pink,
+}
+
+impl UserColor {
+    pub const ALL: [UserColor; 8] = [
+        UserColor::Red,
+        UserColor::Orange,
+        UserColor::Yellow,
+        UserColor::Green,
+        UserColor::Cyan,
+        UserColor::Blue,
+        UserColor::Purple,
+        UserColor::Pink,
+    ];
+    
+    /// The color a user gets unless they pick one, the same in every session
+    pub fn for_user(user_id: &str) -> Self {
+        // FNV-1a, so the choice doesn't change between builds like `DefaultHasher` may
+        let hash = user_id.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        });
+        Self::ALL[(hash % Self::ALL.len() as u64) as usize]
+    }
+    
+    pub fn name(self) -> &'static str {
+        match self {
+            UserColor::Red => "red",
+            UserColor::Orange => "orange",
+            UserColor::Yellow => "yellow",
+            UserColor::Green => "green",
+            UserColor::Cyan => "cyan",
+            UserColor::Blue => "blue",
+            UserColor::Purple => "purple",
+            UserColor::Pink => "pink",
+        }
+    }
+    
+    pub fn rgb(self) -> (u8, u8, u8) {
+        match self {
+            UserColor::Red => (235, 85, 85),
+            UserColor::Orange => (240, 150, 50),
+            UserColor::Yellow => (230, 210, 60),
+            UserColor::Green => (95, 200, 95),
+            UserColor::Cyan => (60, 200, 210),
+            UserColor::Blue => (95, 145, 240),
+            UserColor::Purple => (170, 115, 230),
+            UserColor::Pink => (235, 115, 185),
+        }
+    }
+    
+    /// `#rrggbb`, for the HUD script and other web-style consumers
+    pub fn hex(self) -> String {
+        let (r, g, b) = self.rgb();
+        format!("#{:02x}{:02x}{:02x}", r, g, b)
+    }
+    
+    /// Text wrapped in ANSI escapes for a truecolor terminal
+    pub fn paint(self, text: &str) -> String {
+        let (r, g, b) = self.rgb();
+        format!("\x1b[1;38;2;{};{};{}m{}\x1b[0m", r, g, b, text)
+    }
+    
+    /// Text escaped for ASS and wrapped in a color override, for MPV overlays
+    pub fn paint_ass(self, text: &str) -> String {
+        let (r, g, b) = self.rgb();
+        format!("{{\\c&H{:02X}{:02X}{:02X}&}}{}{{\\c}}", b, g, r, ass_escape(text))
+    }
+}
+
+impl fmt::Display for UserColor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for UserColor {
+    type Err = anyhow::Error;
+    
+    fn from_str(s: &str) -> Result<Self> {
+        Self::ALL.into_iter()
+            .find(|color| color.name().eq_ignore_ascii_case(s.trim()))
+            .ok_or_else(|| {
+                let names: Vec<&str> = Self::ALL.iter().map(|c| c.name()).collect();
+                anyhow::anyhow!("unknown color '{}', expected one of: {}", s, names.join(", "))
+            })
+    }
+}
+
+/// Check an avatar given on the command line: a short run of visible characters
+pub fn parse_icon(icon: &str) -> Result<String> {
+    let icon = icon.trim();
+    anyhow::ensure!(!icon.is_empty(), "icon is empty");
+    anyhow::ensure!(icon.chars().count() <= MAX_ICON_CHARS, "icon is longer than {} characters", MAX_ICON_CHARS);
+    anyhow::ensure!(!icon.chars().any(|c| c.is_whitespace() || c.is_control()), "icon can't contain spaces");
+    Ok(icon.to_string())
+}
+
+/// Reactions with a name, bound to Ctrl+1 to Ctrl+6 in MPV in this order
+pub const REACTIONS: [(&str, &str); 6] = [
+    ("like", "👍"),
+    ("laugh", "😂"),
+    ("wow", "😮"),
+    ("love", "❤️"),
+    ("sad", "😢"),
+    ("fire", "🔥"),
+];
+
+/// Emoji for a reaction given by name ("like") or as the emoji itself
+pub fn parse_reaction(reaction: &str) -> Result<String> {
+    let reaction = reaction.trim();
+    match REACTIONS.iter().find(|(name, _)| name.eq_ignore_ascii_case(reaction)) {
+        Some((_, emoji)) => Ok(emoji.to_string()),
+        None => parse_icon(reaction),
+    }
+}
+
+/// Escape text so MPV shows it literally inside ASS markup
+pub fn ass_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('{', "\\{").replace('}', "\\}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_color_for_user_is_stable() {
+        assert_eq!(UserColor::for_user("alice"), UserColor::for_user("alice"));
+        let spread: std::collections::HashSet<_> = ["alice", "bob", "carol", "dave", "erin", "frank"]
+            .iter()
+            .map(|user| UserColor::for_user(user))
+            .collect();
+        assert!(spread.len() > 2);
+        
+        assert_eq!("Purple".parse::<UserColor>().unwrap(), UserColor::Purple);
+        assert!("mauve".parse::<UserColor>().is_err());
+        assert_eq!(UserColor::Red.paint_ass("{a}"), "{\\c&H5555EB&}\\{a\\}{\\c}");
+    }
+    
+    #[test]
+    fn test_parse_icon() {
+        assert_eq!(parse_icon(" 🐱 ").unwrap(), "🐱");
+        assert!(parse_icon("").is_err());
+        assert!(parse_icon("a b").is_err());
+        assert!(parse_icon("way too long").is_err());
+    }
+    
+    #[test]
+    fn test_parse_reaction() {
+        assert_eq!(parse_reaction("Laugh").unwrap(), "😂");
+        assert_eq!(parse_reaction("🎉").unwrap(), "🎉");
+        assert!(parse_reaction("").is_err());
+        assert!(parse_reaction("applause please").is_err());
+    }
+}
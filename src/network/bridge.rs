@@ -0,0 +1,377 @@
+//! Mirror of a session's chat and major events in a Matrix room or Discord
+//! channel (`syncread bridge`)
+//!
+//! The bridge watches the session without joining it, the way `syncread
+//! status` does, so it never shows up as a reader. Chat, joins, leaves,
+//! notes, links and announcements are posted to the room; messages people
+//! send in the room come back into the session as chat from
+//! `<name> (matrix)` or `<name> (discord)`. Matrix is long-polled with
+//! `/sync`; Discord's REST API has no long poll, so the channel is checked
+//! every few seconds.
+
+use super::http_client::{self, encode_component, HttpUrl};
+use super::protocol::{chat_text, unix_millis, SyncEvent, SyncMessage};
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::time::Duration;
+use tracing::{debug, info, warn};
+
+/// How long each `/sync` waits for new room messages
+const SYNC_TIMEOUT_MS: u64 = 30_000;
+
+/// Pause before polling the room again after a failed poll
+const SYNC_RETRY: Duration = Duration::from_secs(5);
+
+/// Discord's REST API, which bot tokens authenticate against
+const DISCORD_API: &str = "https://discord.com/api/v10";
+
+/// How often a Discord channel is checked for new messages
+const DISCORD_POLL_INTERVAL: Duration = Duration::from_secs(3);
+
+/// A Matrix room and the account the bridge posts to it as
+#[derive(Debug, Clone)]
+pub struct MatrixRoom {
+    homeserver: HttpUrl,
+    room_id: String,
+    access_token: String,
+}
+
+impl MatrixRoom {
+    pub fn new(homeserver: &str, room_id: String, access_token: String) -> Result<Self> {
+        anyhow::ensure!(room_id.starts_with('!'), "{} is not a room id (they look like !abc123:example.org)", room_id);
+        Ok(Self { homeserver: HttpUrl::parse(homeserver)?, room_id, access_token })
+    }
+    
+    async fn call(&self, method: &str, path: &str, body: Option<&Value>) -> Result<Value> {
+        let body = body.map(Value::to_string);
+        let url = self.homeserver.with_path(path);
+        let authorization = format!("Bearer {}", self.access_token);
+        let response = http_client::request(method, &url, Some(&authorization), body.as_deref()).await?;
+        anyhow::ensure!(response.is_success(), "Homeserver answered {}: {}", response.status, response.body.trim());
+        serde_json::from_str(&response.body).context("Homeserver sent something other than JSON")
+    }
+    
+    /// Our own Matrix user id, so our messages aren't relayed back
+    async fn whoami(&self) -> Result<String> {
+        let reply = self.call("GET", "/_matrix/client/v3/account/whoami", None).await?;
+        reply["user_id"].as_str().map(str::to_string).context("No user_id in the whoami reply")
+    }
+    
+    /// Post a line of text to the room
+    async fn send(&self, txn_id: u64, text: &str) -> Result<()> {
+        let path = format!("/_matrix/client/v3/rooms/{}/send/m.room.message/syncread{}", encode_component(&self.room_id), txn_id);
+        self.call("PUT", &path, Some(&json!({ "msgtype": "m.text", "body": text }))).await?;
+        Ok(())
+    }
+    
+    /// Wait for room activity after `since`, returning the next batch token and the sync itself
+    async fn sync(&self, since: Option<&str>, timeout_ms: u64) -> Result<(String, Value)> {
+        let filter = json!({
+            "room": { "rooms": [self.room_id], "timeline": { "limit": 50 } },
+            "presence": { "types": [] },
+            "account_data": { "types": [] },
+        });
+        let mut path = format!("/_matrix/client/v3/sync?timeout={}&filter={}", timeout_ms, encode_component(&filter.to_string()));
+        if let Some(since) = since {
+            path.push_str(&format!("&since={}", encode_component(since)));
+        }
+        let reply = self.call("GET", &path, None).await?;
+        let next_batch = reply["next_batch"].as_str().context("No next_batch in the sync reply")?.to_string();
+        Ok((next_batch, reply))
+    }
+}
+
+/// A Discord channel and the bot that posts to it
+#[derive(Debug, Clone)]
+pub struct DiscordChannel {
+    api: HttpUrl,
+    channel_id: String,
+    bot_token: String,
+}
+
+impl DiscordChannel {
+    pub fn new(channel_id: String, bot_token: String) -> Result<Self> {
+        anyhow::ensure!(
+            !channel_id.is_empty() && channel_id.bytes().all(|b| b.is_ascii_digit()),
+            "{} is not a channel id (they are numbers; turn on Developer Mode and use Copy Channel ID)", channel_id
+        );
+        Ok(Self { api: HttpUrl::parse(DISCORD_API)?, channel_id, bot_token })
+    }
+    
+    async fn call(&self, method: &str, path: &str, body: Option<&Value>) -> Result<Value> {
+        let body = body.map(Value::to_string);
+        let url = self.api.with_path(path);
+        let authorization = format!("Bot {}", self.bot_token);
+        let response = http_client::request(method, &url, Some(&authorization), body.as_deref()).await?;
+        anyhow::ensure!(response.is_success(), "Discord answered {}: {}", response.status, response.body.trim());
+        serde_json::from_str(&response.body).context("Discord sent something other than JSON")
+    }
+    
+    /// The bot's own user id, so its messages aren't relayed back
+    async fn whoami(&self) -> Result<String> {
+        let reply = self.call("GET", "/users/@me", None).await?;
+        reply["id"].as_str().map(str::to_string).context("No id in the /users/@me reply")
+    }
+    
+    /// Post a line of text to the channel
+    async fn send(&self, text: &str) -> Result<()> {
+        let path = format!("/channels/{}/messages", self.channel_id);
+        // Mentions in relayed text shouldn't ping anyone
+        self.call("POST", &path, Some(&json!({ "content": text, "allowed_mentions": { "parse": [] } }))).await?;
+        Ok(())
+    }
+    
+    /// Messages posted after the message id `after` (all recent ones if empty), newest first
+    async fn messages(&self, after: &str) -> Result<Value> {
+        let mut path = format!("/channels/{}/messages?limit=50", self.channel_id);
+        if !after.is_empty() {
+            path.push_str(&format!("&after={}", after));
+        }
+        self.call("GET", &path, None).await
+    }
+}
+
+/// Where the bridge mirrors the session
+#[derive(Debug, Clone)]
+pub enum ChatRoom {
+    Matrix(MatrixRoom),
+    Discord(DiscordChannel),
+}
+
+impl ChatRoom {
+    /// The room or channel, for log messages
+    fn name(&self) -> &str {
+        match self {
+            ChatRoom::Matrix(room) => &room.room_id,
+            ChatRoom::Discord(channel) => &channel.channel_id,
+        }
+    }
+    
+    async fn whoami(&self) -> Result<String> {
+        match self {
+            ChatRoom::Matrix(room) => room.whoami().await,
+            ChatRoom::Discord(channel) => channel.whoami().await,
+        }
+    }
+    
+    async fn send(&self, txn_id: u64, text: &str) -> Result<()> {
+        match self {
+            ChatRoom::Matrix(room) => room.send(txn_id, text).await,
+            ChatRoom::Discord(channel) => channel.send(text).await,
+        }
+    }
+    
+    /// Where to start reading from: now, since the room's history isn't news to the session
+    async fn start(&self) -> Result<String> {
+        match self {
+            ChatRoom::Matrix(room) => Ok(room.sync(None, 0).await?.0),
+            ChatRoom::Discord(channel) => Ok(discord_messages(&channel.messages("").await?, "").0.unwrap_or_default()),
+        }
+    }
+    
+    /// Wait for what others post after `since`, returning where to read on from and the
+    /// messages as (session user id, text)
+    async fn poll(&self, since: &str, own_user_id: &str) -> Result<(String, Vec<(String, String)>)> {
+        match self {
+            ChatRoom::Matrix(room) => {
+                let (next_batch, reply) = room.sync(Some(since), SYNC_TIMEOUT_MS).await?;
+                let messages = room_messages(&reply, &room.room_id, own_user_id).into_iter()
+                    .map(|(sender, body)| (relay_user_id(&sender), body))
+                    .collect();
+                Ok((next_batch, messages))
+            }
+            ChatRoom::Discord(channel) => {
+                tokio::time::sleep(DISCORD_POLL_INTERVAL).await;
+                let (latest, messages) = discord_messages(&channel.messages(since).await?, own_user_id);
+                Ok((latest.unwrap_or_else(|| since.to_string()), messages))
+            }
+        }
+    }
+}
+
+/// The newest message id in a Discord messages reply, and the text messages others
+/// sent, oldest first, as (session user id, text)
+fn discord_messages(reply: &Value, own_user_id: &str) -> (Option<String>, Vec<(String, String)>) {
+    let Some(messages) = reply.as_array() else {
+        return (None, Vec::new());
+    };
+    // Snowflakes grow over time, but compare as numbers: they aren't all the same length
+    let latest = messages.iter()
+        .filter_map(|message| message["id"].as_str())
+        .max_by_key(|id| id.parse::<u64>().unwrap_or(0))
+        .map(str::to_string);
+    let relayed = messages.iter().rev()
+        .filter(|message| message["author"]["id"] != own_user_id)
+        .filter_map(|message| {
+            let text = message["content"].as_str().filter(|text| !text.is_empty())?;
+            let author = &message["author"];
+            let name = author["global_name"].as_str().or(author["username"].as_str())?;
+            Some((format!("{} (discord)", name), text.to_string()))
+        })
+        .collect();
+    (latest, relayed)
+}
+
+/// Text messages others sent to `room_id` in a sync reply, as (sender, body)
+fn room_messages(sync: &Value, room_id: &str, own_user_id: &str) -> Vec<(String, String)> {
+    let Some(events) = sync["rooms"]["join"][room_id]["timeline"]["events"].as_array() else {
+        return Vec::new();
+    };
+    events.iter()
+        .filter(|event| event["type"] == "m.room.message" && event["sender"] != own_user_id)
+        .filter(|event| matches!(event["content"]["msgtype"].as_str(), Some("m.text" | "m.emote")))
+        .filter_map(|event| Some((event["sender"].as_str()?.to_string(), event["content"]["body"].as_str()?.to_string())))
+        .collect()
+}
+
+/// Who a room message shows as in the session: `@bob:example.org` is `bob (matrix)`
+fn relay_user_id(sender: &str) -> String {
+    let localpart = sender.trim_start_matches('@').split(':').next().unwrap_or(sender);
+    format!("{} (matrix)", localpart)
+}
+
+/// The line posted to the room for a session event, if it is one worth mirroring
+pub fn describe(event: &SyncEvent) -> Option<String> {
+    match event {
+        SyncEvent::Chat { user_id, text } => Some(format!("{}: {}", user_id, text)),
+        SyncEvent::UserJoined { user_id, .. } => Some(format!("{} joined", user_id)),
+        SyncEvent::UserLeft { user_id } => Some(format!("{} left", user_id)),
+        SyncEvent::UserAction { user_id, action, .. } if action == "finished" => Some(format!("{} finished", user_id)),
+        SyncEvent::Annotation { annotation } => {
+            Some(format!("{} noted on p.{}: {}", annotation.user_id, annotation.playlist_position + 1, annotation.text))
+        }
+        SyncEvent::Link { link } => Some(format!("{} shared {}", link.user_id, link.url)),
+        SyncEvent::Announcement { text } => Some(text.clone()),
+        _ => None,
+    }
+}
+
+/// Relay between the session on `server_addr` and `room` until either goes away or Ctrl+C
+pub async fn run(server_addr: SocketAddr, room: ChatRoom) -> Result<()> {
+    let own_user_id = room.whoami().await.context("Failed to log in to the chat service")?;
+    let mut since = room.start().await?;
+    info!("Bridging {} as {}", room.name(), own_user_id);
+    
+    let stream = TcpStream::connect(server_addr).await
+        .with_context(|| format!("Failed to connect to {}", server_addr))?;
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    info!("Watching the session on {}", server_addr);
+    
+    let (from_room_tx, mut from_room) = mpsc::unbounded_channel::<(String, String)>();
+    let poller = room.clone();
+    tokio::spawn(async move {
+        loop {
+            match poller.poll(&since, &own_user_id).await {
+                Ok((next, messages)) => {
+                    for message in messages {
+                        if from_room_tx.send(message).is_err() {
+                            return;
+                        }
+                    }
+                    since = next;
+                }
+                Err(e) => {
+                    warn!("Failed to read the room: {:#}", e);
+                    tokio::time::sleep(SYNC_RETRY).await;
+                }
+            }
+        }
+    });
+    
+    let mut sequence = 0;
+    let mut txn_id = unix_millis();
+    loop {
+        tokio::select! {
+            line = lines.next_line() => {
+                let Some(line) = line? else {
+                    info!("Server connection closed");
+                    return Ok(());
+                };
+                let text = match serde_json::from_str::<SyncMessage>(line.trim()) {
+                    Ok(message) => describe(&message.event),
+                    Err(e) => {
+                        debug!("Ignoring unparsable line: {}", e);
+                        None
+                    }
+                };
+                if let Some(text) = text {
+                    txn_id += 1;
+                    if let Err(e) = room.send(txn_id, &text).await {
+                        warn!("Failed to post to the room: {:#}", e);
+                    }
+                }
+            }
+            Some((user_id, body)) = from_room.recv() => {
+                let Some(text) = chat_text(&body) else {
+                    continue;
+                };
+                sequence += 1;
+                let message = SyncMessage::new(SyncEvent::Chat { user_id, text }, sequence);
+                writer.write_all(format!("{}\n", serde_json::to_string(&message)?).as_bytes()).await?;
+            }
+            _ = tokio::signal::ctrl_c() => {
+                info!("Interrupted, stopping the bridge");
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::protocol::SharedLink;
+    
+    #[test]
+    fn test_room_messages() {
+        let sync = json!({
+            "next_batch": "s2",
+            "rooms": { "join": { "!r:x.org": { "timeline": { "events": [
+                { "type": "m.room.message", "sender": "@bob:x.org", "content": { "msgtype": "m.text", "body": "page 12 is wild" } },
+                { "type": "m.room.message", "sender": "@syncread:x.org", "content": { "msgtype": "m.text", "body": "alice joined" } },
+                { "type": "m.room.message", "sender": "@bot:x.org", "content": { "msgtype": "m.notice", "body": "beep" } },
+                { "type": "m.room.member", "sender": "@carol:x.org", "content": { "membership": "join" } },
+            ] } } } },
+        });
+        let messages = room_messages(&sync, "!r:x.org", "@syncread:x.org");
+        assert_eq!(messages, vec![("@bob:x.org".to_string(), "page 12 is wild".to_string())]);
+        assert!(room_messages(&sync, "!other:x.org", "@syncread:x.org").is_empty());
+        assert_eq!(relay_user_id("@bob:x.org"), "bob (matrix)");
+    }
+    
+    #[test]
+    fn test_discord_messages() {
+        let reply = json!([
+            { "id": "1100000000000000003", "content": "page 12 is wild", "author": { "id": "7", "username": "bob", "global_name": "Bob" } },
+            { "id": "1100000000000000002", "content": "alice joined", "author": { "id": "42", "username": "syncread" } },
+            { "id": "1100000000000000001", "content": "", "author": { "id": "8", "username": "carol" } },
+            { "id": "999999999999999999", "content": "first!", "author": { "id": "8", "username": "carol" } },
+        ]);
+        let (latest, messages) = discord_messages(&reply, "42");
+        assert_eq!(latest.as_deref(), Some("1100000000000000003"));
+        assert_eq!(messages, vec![
+            ("carol (discord)".to_string(), "first!".to_string()),
+            ("Bob (discord)".to_string(), "page 12 is wild".to_string()),
+        ]);
+        assert_eq!(discord_messages(&json!([]), "42"), (None, Vec::new()));
+        
+        assert!(DiscordChannel::new("#reading".into(), "token".into()).is_err());
+        assert!(DiscordChannel::new("1100000000000000000".into(), "token".into()).is_ok());
+    }
+    
+    #[test]
+    fn test_describe() {
+        let chat = SyncEvent::Chat { user_id: "alice".into(), text: "hi".into() };
+        assert_eq!(describe(&chat).as_deref(), Some("alice: hi"));
+        let link = SyncEvent::Link { link: SharedLink::new("bob".into(), "https://example.org").unwrap() };
+        assert_eq!(describe(&link).as_deref(), Some("bob shared https://example.org"));
+        assert_eq!(describe(&SyncEvent::StatsRequest), None);
+        
+        assert!(MatrixRoom::new("http://127.0.0.1:8008", "#reading:x.org".into(), "token".into()).is_err());
+    }
+}
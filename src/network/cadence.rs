@@ -0,0 +1,139 @@
+//! How often the client polls MPV and sends its state
+//!
+//! The configured interval by default, backing off once nothing has changed
+//! for a while (paused on a page, say) and snapping back on any change. The
+//! adaptive mode also polls faster right after a page turn, when a few more
+//! turns usually follow.
+
+use tokio::time::{Duration, Instant};
+
+/// Time between state updates unless configured otherwise
+pub const DEFAULT_UPDATE_INTERVAL: Duration = Duration::from_millis(1000);
+
+/// Shortest interval accepted; polling MPV itself takes about this long
+pub const MIN_UPDATE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long updates stay quick after a page turn
+const FAST_FOR: Duration = Duration::from_secs(3);
+
+/// How long without a change before updates slow down
+const SLOW_AFTER: Duration = Duration::from_secs(30);
+
+/// Quick updates are the configured interval divided by this
+const FAST_DIVISOR: u32 = 4;
+
+/// Slowed-down updates double each time nothing changed, up to the configured interval times this
+const MAX_SLOW_FACTOR: u32 = 4;
+
+/// Decides how long to wait before the next state update
+#[derive(Debug, Clone)]
+pub struct UpdateCadence {
+    interval: Duration,
+    adaptive: bool,
+    last_seen: Option<(i32, bool, f64)>, // (position, paused, playback time) at the last update
+    last_turn: Option<Instant>,
+    last_change: Option<Instant>,
+    slow_factor: u32, // What the interval is multiplied by while nothing changes
+}
+
+impl UpdateCadence {
+    pub fn new(interval: Duration, adaptive: bool) -> Self {
+        Self {
+            interval: interval.max(MIN_UPDATE_INTERVAL),
+            adaptive,
+            last_seen: None,
+            last_turn: None,
+            last_change: None,
+            slow_factor: 1,
+        }
+    }
+    
+    /// Account for the state just read at `now`, returning the wait until the next update
+    pub fn next(&mut self, position: i32, paused: bool, playback_time: f64, now: Instant) -> Duration {
+        if let Some((last_position, last_paused, last_time)) = self.last_seen {
+            if position != last_position {
+                self.last_turn = Some(now);
+            }
+            // Playing video counts as a change; a still image's time doesn't move
+            if position != last_position || paused != last_paused || playback_time != last_time {
+                self.wake(now);
+            }
+        }
+        self.last_seen = Some((position, paused, playback_time));
+        
+        if self.adaptive && self.last_turn.is_some_and(|at| now.duration_since(at) < FAST_FOR) {
+            return (self.interval / FAST_DIVISOR).max(MIN_UPDATE_INTERVAL);
+        }
+        let since_change = now.duration_since(*self.last_change.get_or_insert(now));
+        if since_change >= SLOW_AFTER {
+            self.slow_factor = (self.slow_factor * 2).min(MAX_SLOW_FACTOR);
+            return self.interval * self.slow_factor;
+        }
+        self.interval
+    }
+    
+    /// Go back to the configured interval, e.g. after a command from another reader
+    pub fn wake(&mut self, now: Instant) {
+        self.last_change = Some(now);
+        self.slow_factor = 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_fixed_cadence() {
+        let start = Instant::now();
+        let mut cadence = UpdateCadence::new(Duration::from_millis(500), false);
+        assert_eq!(cadence.next(0, false, 0.0, start), Duration::from_millis(500));
+        assert_eq!(cadence.next(1, false, 0.0, start + SLOW_AFTER * 2), Duration::from_millis(500));
+        
+        assert_eq!(UpdateCadence::new(Duration::ZERO, false).next(0, false, 0.0, start), MIN_UPDATE_INTERVAL);
+    }
+    
+    #[test]
+    fn test_backs_off_while_nothing_changes() {
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+        let mut cadence = UpdateCadence::new(Duration::from_millis(1000), false);
+        assert_eq!(cadence.next(3, true, 0.0, at(0)), Duration::from_millis(1000));
+        assert_eq!(cadence.next(3, true, 0.0, at(29)), Duration::from_millis(1000));
+        
+        // Paused on a page: slower and slower, to a limit
+        assert_eq!(cadence.next(3, true, 0.0, at(30)), Duration::from_millis(2000));
+        assert_eq!(cadence.next(3, true, 0.0, at(32)), Duration::from_millis(4000));
+        assert_eq!(cadence.next(3, true, 0.0, at(36)), Duration::from_millis(4000));
+        
+        // Any change snaps back, and it takes a while to slow down again
+        assert_eq!(cadence.next(4, true, 0.0, at(40)), Duration::from_millis(1000));
+        assert_eq!(cadence.next(4, true, 0.0, at(41)), Duration::from_millis(1000));
+        assert_eq!(cadence.next(4, true, 0.0, at(70)), Duration::from_millis(2000));
+        cadence.wake(at(71));
+        assert_eq!(cadence.next(4, true, 0.0, at(72)), Duration::from_millis(1000));
+    }
+    
+    #[test]
+    fn test_adaptive_cadence() {
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+        let mut cadence = UpdateCadence::new(Duration::from_millis(1000), true);
+        assert_eq!(cadence.next(0, false, 0.0, at(0)), Duration::from_millis(1000));
+        
+        // Quick right after a page turn, back to normal a few seconds later
+        assert_eq!(cadence.next(1, false, 0.0, at(1)), Duration::from_millis(250));
+        assert_eq!(cadence.next(1, false, 0.0, at(3)), Duration::from_millis(250));
+        assert_eq!(cadence.next(1, false, 0.0, at(5)), Duration::from_millis(1000));
+        
+        // Slow once nothing has changed for a while
+        assert_eq!(cadence.next(1, false, 0.0, at(40)), Duration::from_millis(2000));
+        cadence.wake(at(41));
+        assert_eq!(cadence.next(1, false, 0.0, at(42)), Duration::from_millis(1000));
+        
+        // Playing video keeps the normal pace; pausing it slows down in time
+        assert_eq!(cadence.next(1, false, 5.0, at(80)), Duration::from_millis(1000));
+        assert_eq!(cadence.next(1, true, 6.0, at(81)), Duration::from_millis(1000));
+        assert_eq!(cadence.next(1, true, 6.0, at(120)), Duration::from_millis(2000));
+    }
+}
@@ -0,0 +1,140 @@
+//! Comparing the contents of everyone's playlist files
+//!
+//! Fingerprints and page matching only look at names, so two scans of the
+//! same chapter look identical. With `--checksums` each reader hashes their
+//! local files and sends the hashes to the server, which reports the pages
+//! where someone has a different version from the rest.
+
+use super::protocol::{ChecksumMismatch, FileChecksum, UserId};
+use crate::media::MediaSource;
+use crate::terminal;
+use anyhow::{Context, Result};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use tracing::warn;
+use xxhash_rust::xxh3::Xxh3;
+
+/// Pages listed by name on the OSD, the rest only counted
+const MAX_LISTED: usize = 5;
+
+/// xxh3 hash of a file's contents
+pub fn hash_file(path: &Path) -> Result<u64> {
+    let mut file = File::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+    let mut hasher = Xxh3::new();
+    let mut buffer = vec![0; 1 << 16];
+    loop {
+        let read = file.read(&mut buffer).with_context(|| format!("Failed to read {:?}", path))?;
+        if read == 0 {
+            return Ok(hasher.digest());
+        }
+        hasher.update(&buffer[..read]);
+    }
+}
+
+/// Hashes of a playlist's local files, by item name; URLs and unreadable files are left out
+pub fn playlist_checksums(files: &[MediaSource]) -> Vec<FileChecksum> {
+    files.iter()
+        .filter_map(|file| match file {
+            MediaSource::Local(path) => Some((file, path)),
+            MediaSource::Remote(_) => None,
+        })
+        .filter_map(|(file, path)| match hash_file(path) {
+            Ok(hash) => Some(FileChecksum { name: file.display_name().unwrap_or_default(), hash }),
+            Err(e) => {
+                warn!("Not comparing {:?}: {:#}", path, e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Pages that readers have in more than one version, in name order
+///
+/// Whoever has a version other than the most common one is listed; when no
+/// version is the most common, everyone with the page is.
+pub fn compare(readers: &BTreeMap<UserId, Vec<FileChecksum>>) -> Vec<ChecksumMismatch> {
+    let mut versions: BTreeMap<&str, Vec<(&UserId, u64)>> = BTreeMap::new();
+    for (user_id, files) in readers {
+        for file in files {
+            versions.entry(&file.name).or_default().push((user_id, file.hash));
+        }
+    }
+    
+    versions.into_iter()
+        .filter_map(|(name, holders)| {
+            let mut counts: HashMap<u64, usize> = HashMap::new();
+            for (_, hash) in &holders {
+                *counts.entry(*hash).or_default() += 1;
+            }
+            if counts.len() < 2 {
+                return None;
+            }
+            let most = counts.values().copied().max().unwrap_or_default();
+            let common: Vec<u64> = counts.iter().filter(|(_, &count)| count == most).map(|(&hash, _)| hash).collect();
+            let differing = holders.iter()
+                .filter(|(_, hash)| common.len() > 1 || !common.contains(hash))
+                .map(|(user_id, _)| (*user_id).clone())
+                .collect();
+            Some(ChecksumMismatch { name: name.to_string(), differing })
+        })
+        .collect()
+}
+
+/// A comparison for the OSD, naming us "you"
+pub fn describe(mismatches: &[ChecksumMismatch], own_id: &str) -> String {
+    if mismatches.is_empty() {
+        return "Everyone's copies match".to_string();
+    }
+    
+    let mut lines = vec![format!("{} page(s) differ between readers:", mismatches.len())];
+    for mismatch in mismatches.iter().take(MAX_LISTED) {
+        let readers: Vec<&str> = mismatch.differing.iter()
+            .map(|user_id| if user_id == own_id { "you" } else { user_id.as_str() })
+            .collect();
+        lines.push(format!("{}: {}", mismatch.name, readers.join(", ")));
+    }
+    if mismatches.len() > MAX_LISTED {
+        lines.push(format!("{}and {} more", terminal::decoration("…"), mismatches.len() - MAX_LISTED));
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_compare_finds_odd_versions() {
+        let files = |hashes: &[(&str, u64)]| hashes.iter().map(|&(name, hash)| FileChecksum { name: name.to_string(), hash }).collect();
+        let readers = BTreeMap::from([
+            ("alice".to_string(), files(&[("p1.png", 1), ("p2.png", 2), ("p3.png", 3)])),
+            ("bob".to_string(), files(&[("p1.png", 1), ("p2.png", 20), ("p3.png", 30)])),
+            ("carol".to_string(), files(&[("p1.png", 1), ("p2.png", 2)])),
+        ]);
+        let mismatches = compare(&readers);
+        
+        // Bob's p2 is the odd one out; nobody agrees on p3
+        assert_eq!(mismatches, vec![
+            ChecksumMismatch { name: "p2.png".to_string(), differing: vec!["bob".to_string()] },
+            ChecksumMismatch { name: "p3.png".to_string(), differing: vec!["alice".to_string(), "bob".to_string()] },
+        ]);
+        assert_eq!(describe(&mismatches, "bob"), "2 page(s) differ between readers:\np2.png: you\np3.png: alice, you");
+        assert_eq!(describe(&[], "bob"), "Everyone's copies match");
+    }
+    
+    #[test]
+    fn test_hash_file() {
+        let dir = std::env::temp_dir().join(format!("syncread_test_checksums_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (a, b) = (dir.join("a.png"), dir.join("b.png"));
+        std::fs::write(&a, b"page").unwrap();
+        std::fs::write(&b, b"page").unwrap();
+        assert_eq!(hash_file(&a).unwrap(), hash_file(&b).unwrap());
+        
+        std::fs::write(&b, b"page, rescanned").unwrap();
+        assert_ne!(hash_file(&a).unwrap(), hash_file(&b).unwrap());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,177 @@
+use super::protocol::SyncMessage;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use std::borrow::Cow;
+use std::io::{Read, Write};
+
+/// Wire compression a client can offer in its `Hello`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    /// Raw DEFLATE (RFC 1951), the algorithm inside gzip and zlib
+    Deflate,
+}
+
+/// Everything this build can compress and decompress, in order of preference
+pub const SUPPORTED: &[Compression] = &[Compression::Deflate];
+
+/// Messages shorter than this are sent as plain JSON; state updates gain nothing
+const COMPRESSION_THRESHOLD: usize = 256;
+
+/// Compressed lines start with this, which no JSON object does
+const COMPRESSED_PREFIX: char = '~';
+
+/// Refuse to inflate a single message beyond this, so a bad peer can't exhaust memory
+pub const MAX_MESSAGE_SIZE: usize = 4 * 1024 * 1024;
+
+/// Pick the first compression we support from a peer's offer
+pub fn negotiate(offered: &[Compression]) -> Option<Compression> {
+    offered.iter().copied().find(|c| SUPPORTED.contains(c))
+}
+
+/// Serialize a message as one newline-terminated line, compressed if it pays off
+pub fn encode_message(message: &SyncMessage, compression: Option<Compression>) -> Result<String> {
+    let json = serde_json::to_string(message)?;
+    Ok(format!("{}\n", encode_line(json, compression)))
+}
+
+/// Parse a received line, compressed or not
+pub fn decode_message(line: &str, compression: Option<Compression>) -> Result<SyncMessage> {
+    Ok(serde_json::from_str(&decode_line(line, compression)?)?)
+}
+
+/// Compress a JSON line when it's large enough and actually shrinks
+pub fn encode_line(json: String, compression: Option<Compression>) -> String {
+    match compression {
+        Some(Compression::Deflate) if json.len() >= COMPRESSION_THRESHOLD => {
+            let encoded = format!("{}{}", COMPRESSED_PREFIX, base64_encode(&deflate(json.as_bytes())));
+            if encoded.len() < json.len() { encoded } else { json }
+        }
+        _ => json,
+    }
+}
+
+/// Compress a binary payload when it's large enough and actually shrinks
+pub fn compress(data: &[u8], compression: Option<Compression>) -> Option<Vec<u8>> {
+    match compression {
+        Some(Compression::Deflate) if data.len() >= COMPRESSION_THRESHOLD => {
+            Some(deflate(data)).filter(|compressed| compressed.len() < data.len())
+        }
+        _ => None,
+    }
+}
+
+/// Undo `compress`
+pub fn decompress(data: &[u8], compression: Compression) -> Result<Vec<u8>> {
+    match compression {
+        Compression::Deflate => inflate(data),
+    }
+}
+
+/// Recover the JSON text of a line; plain JSON passes through untouched
+///
+/// Compressed lines are only inflated on a connection that agreed on
+/// `compression`, so nobody can make us inflate before the `Welcome`.
+pub fn decode_line(line: &str, compression: Option<Compression>) -> Result<Cow<'_, str>> {
+    let Some(encoded) = line.strip_prefix(COMPRESSED_PREFIX) else {
+        return Ok(Cow::Borrowed(line));
+    };
+    anyhow::ensure!(compression.is_some(), "Compressed message, but compression wasn't agreed");
+    
+    let compressed = base64_decode(encoded).context("Invalid base64 in compressed message")?;
+    let json = inflate(&compressed).context("Invalid compressed message")?;
+    Ok(Cow::Owned(String::from_utf8(json).context("Compressed message is not UTF-8")?))
+}
+
+/// Compress with raw DEFLATE
+fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+    // Writing to a Vec can't fail
+    encoder.write_all(data).expect("deflate into memory");
+    encoder.finish().expect("deflate into memory")
+}
+
+/// Decompress raw DEFLATE data, refusing to grow past `MAX_MESSAGE_SIZE`
+fn inflate(data: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    DeflateDecoder::new(data).take(MAX_MESSAGE_SIZE as u64 + 1).read_to_end(&mut out)?;
+    anyhow::ensure!(out.len() <= MAX_MESSAGE_SIZE, "message too large");
+    Ok(out)
+}
+
+/// Standard base64 with padding, so compressed bytes fit on a text line
+pub fn base64_encode(data: &[u8]) -> String {
+    BASE64.encode(data)
+}
+
+pub fn base64_decode(text: &str) -> Result<Vec<u8>> {
+    Ok(BASE64.decode(text)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::protocol::{SyncEvent, UserState};
+    
+    #[test]
+    fn test_deflate_round_trip() {
+        let samples: [&[u8]; 4] = [
+            b"",
+            b"a",
+            b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa",
+            "{\"user_id\":\"alice\",\"file\":\"ch1/p001.png\"},{\"user_id\":\"bob\",\"file\":\"ch1/p002.png\"} ✓".as_bytes(),
+        ];
+        for sample in samples {
+            assert_eq!(inflate(&deflate(sample)).unwrap(), sample);
+        }
+        
+        // Long input with matches at all distances
+        let long: Vec<u8> = (0..100_000u64).map(|i| (i * i % 251) as u8 ^ (i / 1000) as u8).collect();
+        assert_eq!(inflate(&deflate(&long)).unwrap(), long);
+        
+        // A small bomb stops at the limit instead of filling memory
+        let bomb = deflate(&vec![0; MAX_MESSAGE_SIZE + 1]);
+        assert!(inflate(&bomb).is_err());
+    }
+    
+    #[test]
+    fn test_inflate_stored_and_dynamic_blocks() {
+        // Stored block holding "hi", as written by other encoders
+        assert_eq!(inflate(&[0x01, 0x02, 0x00, 0xfd, 0xff, b'h', b'i']).unwrap(), b"hi");
+        
+        // zlib's output for this text at level 9, which uses dynamic codes
+        let text = "babbabaacaabcaabbbacacbaaaabcbaaaaaaaaaaabbaabababaaaaaabbaaaabaaaaaaaaabbacbcba";
+        let dynamic = [
+            0x4d, 0x8b, 0x81, 0x09, 0x00, 0x30, 0x08, 0xc3, 0x6e, 0x4d, 0xfa, 0xff, 0x0f, 0xab, 0x32, 0xc6,
+            0x2c, 0x52, 0x62, 0x50, 0x14, 0x21, 0xe0, 0x6c, 0x29, 0xa4, 0x87, 0xe1, 0xad, 0x3b, 0x15, 0xb8,
+            0x79, 0x08, 0xfe, 0x3a, 0xf3, 0x70, 0x00,
+        ];
+        assert_eq!(inflate(&dynamic).unwrap(), text.as_bytes());
+    }
+    
+    #[test]
+    fn test_lines_compress_only_when_worth_it() {
+        let mut state = UserState::new("alice".to_string());
+        state.current_file_name = Some("a-rather-long-chapter-file-name.png".repeat(30));
+        let big = SyncMessage::state_update(state, 7);
+        let small = SyncMessage::new(SyncEvent::StatsRequest, 1);
+        
+        let line = encode_message(&big, Some(Compression::Deflate)).unwrap();
+        assert!(line.starts_with(COMPRESSED_PREFIX) && line.ends_with('\n'));
+        assert!(!line.trim_end().contains('\n'));
+        assert_eq!(decode_message(line.trim(), Some(Compression::Deflate)).unwrap().sequence, 7);
+        assert!(decode_message(line.trim(), None).is_err());
+        
+        assert!(encode_message(&small, Some(Compression::Deflate)).unwrap().starts_with('{'));
+        assert!(encode_message(&big, None).unwrap().starts_with('{'));
+        assert!(decode_line("~not base64!", Some(Compression::Deflate)).is_err());
+        
+        assert_eq!(negotiate(&[Compression::Deflate]), Some(Compression::Deflate));
+        assert_eq!(negotiate(&[]), None);
+        assert_eq!(base64_decode(&base64_encode(b"syncread")).unwrap(), b"syncread");
+    }
+}
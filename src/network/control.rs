@@ -0,0 +1,156 @@
+//! Commands from other programs for a running client (`--control-socket`)
+//!
+//! Window-manager keybinds, scripts or a phone app bridge connect to a Unix
+//! socket (a named pipe on Windows) and send one command per line, in the
+//! same words headless clients take: `next`, `prev`, `goto 42`,
+//! `follow alice`, `chat hi`. Every line is answered with `ok` or
+//! `error: <reason>`, except `status`, which is answered with the session
+//! state as one line of JSON.
+
+use super::headless::{parse_command, HeadlessCommand};
+use super::protocol::SessionState;
+use anyhow::{Context, Result};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::{mpsc, RwLock};
+use tracing::{debug, info, warn};
+
+/// Line a control connection sends to get the session state back
+pub const STATUS_COMMAND: &str = "status";
+
+/// Listen on `path`, passing each command a connection sends to `commands`
+///
+/// Fails right away if the socket can't be created; connections are then
+/// served in the background for as long as the client runs.
+pub async fn spawn(path: &Path, commands: mpsc::UnboundedSender<HeadlessCommand>, session_state: Arc<RwLock<SessionState>>) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        
+        // A socket left behind by a client that crashed would block the bind
+        let _ = std::fs::remove_file(path);
+        let listener = tokio::net::UnixListener::bind(path)
+            .with_context(|| format!("Failed to create control socket {:?}", path))?;
+        // Anyone who can connect can steer the session; keep it to our user
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+            .with_context(|| format!("Failed to restrict access to {:?}", path))?;
+        info!("Accepting commands on {:?}", path);
+        
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        tokio::spawn(serve_connection(stream, commands.clone(), session_state.clone()));
+                    }
+                    Err(e) => warn!("Failed to accept control connection: {}", e),
+                }
+            }
+        });
+    }
+    
+    #[cfg(windows)]
+    {
+        use tokio::net::windows::named_pipe::ServerOptions;
+        
+        let address = crate::mpv::ipc_address(path);
+        let mut server = ServerOptions::new()
+            .first_pipe_instance(true)
+            .create(&address)
+            .with_context(|| format!("Failed to create control pipe {}", address))?;
+        info!("Accepting commands on {}", address);
+        
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = server.connect().await {
+                    warn!("Failed to accept control connection: {}", e);
+                    continue;
+                }
+                // Open the next instance before serving this one, so clients never find the pipe missing
+                let connected = server;
+                server = match ServerOptions::new().create(&address) {
+                    Ok(next) => next,
+                    Err(e) => {
+                        warn!("Control pipe closed: {}", e);
+                        return;
+                    }
+                };
+                tokio::spawn(serve_connection(connected, commands.clone(), session_state.clone()));
+            }
+        });
+    }
+    
+    Ok(())
+}
+
+/// Answer one connection's lines until it hangs up
+async fn serve_connection<S: AsyncRead + AsyncWrite>(stream: S, commands: mpsc::UnboundedSender<HeadlessCommand>, session_state: Arc<RwLock<SessionState>>) {
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut lines = BufReader::new(reader).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let reply = if line.trim() == STATUS_COMMAND {
+            match serde_json::to_string(&*session_state.read().await) {
+                Ok(json) => json,
+                Err(e) => format!("error: {}", e),
+            }
+        } else {
+            match parse_command(&line) {
+                Ok(Some(command)) => {
+                    debug!("Control command: {:?}", command);
+                    if commands.send(command).is_err() {
+                        return; // The session is over
+                    }
+                    "ok".to_string()
+                }
+                Ok(None) => continue,
+                Err(e) => format!("error: {:#}", e),
+            }
+        };
+        if writer.write_all(format!("{}\n", reply).as_bytes()).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Send `status` to a client's control socket and return the session state it answers with
+pub async fn request_status(path: &Path) -> Result<SessionState> {
+    #[cfg(unix)]
+    let stream = tokio::net::UnixStream::connect(path).await
+        .with_context(|| format!("Failed to connect to control socket {:?}", path))?;
+    #[cfg(windows)]
+    let stream = tokio::net::windows::named_pipe::ClientOptions::new()
+        .open(crate::mpv::ipc_address(path))
+        .with_context(|| format!("Failed to connect to control pipe for {:?}", path))?;
+    
+    let (reader, mut writer) = tokio::io::split(stream);
+    writer.write_all(format!("{}\n", STATUS_COMMAND).as_bytes()).await?;
+    let mut line = String::new();
+    BufReader::new(reader).read_line(&mut line).await?;
+    serde_json::from_str(&line).with_context(|| format!("Unexpected reply from the control socket: {}", line.trim()))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncBufReadExt;
+    
+    #[tokio::test]
+    async fn test_commands_and_status() {
+        let path = std::env::temp_dir().join(format!("syncread_test_control_{}.socket", std::process::id()));
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let session = Arc::new(RwLock::new(SessionState::new()));
+        spawn(&path, tx, session).await.unwrap();
+        
+        let stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut replies = BufReader::new(reader).lines();
+        writer.write_all(b"goto 42\nfly away\n").await.unwrap();
+        assert_eq!(replies.next_line().await.unwrap().as_deref(), Some("ok"));
+        assert_eq!(replies.next_line().await.unwrap().as_deref(), Some("error: unknown command 'fly'"));
+        assert_eq!(rx.recv().await, Some(HeadlessCommand::Page(42)));
+        
+        let status = request_status(&path).await.unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert!(status.users.is_empty());
+    }
+}
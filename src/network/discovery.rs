@@ -0,0 +1,113 @@
+//! mDNS / DNS-SD support for finding servers on the local network
+//!
+//! Servers register `_syncread._tcp.local` with `mdns-sd`, which answers
+//! queries with the usual PTR/SRV/TXT/A record set; clients browse for it
+//! and collect the servers resolved in time.
+
+use anyhow::{Context, Result};
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use tokio::time::{timeout_at, Duration, Instant};
+use tracing::{debug, info};
+
+/// DNS-SD service type advertised by syncread servers
+pub const SERVICE_TYPE: &str = "_syncread._tcp.local.";
+
+/// A server found on the LAN
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiscoveredServer {
+    pub name: String,
+    pub addr: SocketAddr,
+}
+
+/// The mDNS responder thread, stopped when this is dropped
+struct Daemon(ServiceDaemon);
+
+impl Daemon {
+    fn start() -> Result<Self> {
+        Ok(Self(ServiceDaemon::new().context("Failed to start mDNS")?))
+    }
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = self.0.shutdown();
+    }
+}
+
+/// Answer mDNS queries for our service until the task is dropped
+pub async fn advertise(port: u16) -> Result<()> {
+    let daemon = Daemon::start()?;
+    let host = local_hostname();
+    let instance = format!("{} ({})", host, port);
+    let version = [("version", env!("CARGO_PKG_VERSION"))];
+    
+    // Addresses are filled in from each interface, and kept up to date
+    let service = ServiceInfo::new(SERVICE_TYPE, &instance, &format!("{}.local.", host.replace('.', "-")), "", port, &version[..])
+        .context("Invalid mDNS service")?
+        .enable_addr_auto();
+    daemon.0.register(service).context("Failed to register the mDNS service")?;
+    info!("Advertising '{}' on the LAN via mDNS", instance);
+    
+    std::future::pending::<()>().await;
+    Ok(())
+}
+
+/// Browse for servers and collect the ones that answer within `wait`
+pub async fn discover(wait: Duration) -> Result<Vec<DiscoveredServer>> {
+    let daemon = Daemon::start()?;
+    let events = daemon.0.browse(SERVICE_TYPE).context("Failed to send mDNS query")?;
+    
+    let deadline = Instant::now() + wait;
+    let mut found: BTreeMap<String, DiscoveredServer> = BTreeMap::new();
+    while let Ok(Ok(event)) = timeout_at(deadline, events.recv_async()).await {
+        if let ServiceEvent::ServiceResolved(info) = event {
+            debug!("mDNS resolved {}", info.get_fullname());
+            if let Some(server) = discovered(&info) {
+                found.insert(server.name.clone(), server);
+            }
+        }
+    }
+    
+    Ok(found.into_values().collect())
+}
+
+/// The server a resolved service points at, preferring an IPv4 address
+fn discovered(info: &ServiceInfo) -> Option<DiscoveredServer> {
+    let addresses = info.get_addresses();
+    let ip = addresses.iter().find(|ip| ip.is_ipv4()).or_else(|| addresses.iter().next())?;
+    let fullname = info.get_fullname();
+    let name = fullname.strip_suffix(&format!(".{}", SERVICE_TYPE)).unwrap_or(fullname);
+    Some(DiscoveredServer { name: name.to_string(), addr: SocketAddr::new(*ip, info.get_port()) })
+}
+
+fn local_hostname() -> String {
+    std::env::var("HOSTNAME")
+        .or_else(|_| std::env::var("COMPUTERNAME"))
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|h| h.trim().to_string())
+        .filter(|h| !h.is_empty())
+        .unwrap_or_else(|| "syncread".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr};
+    
+    #[test]
+    fn test_discovered_server() {
+        let ips: &[IpAddr] = &["fe80::1".parse().unwrap(), IpAddr::V4(Ipv4Addr::new(192, 168, 1, 20))];
+        let info = ServiceInfo::new(SERVICE_TYPE, "study-room (8080)", "reader-pc.local.", ips, 8080, None).unwrap();
+        assert_eq!(discovered(&info), Some(DiscoveredServer {
+            name: "study-room (8080)".to_string(),
+            addr: "192.168.1.20:8080".parse().unwrap(),
+        }));
+        
+        // Nothing to connect to until an address is known
+        let info = ServiceInfo::new(SERVICE_TYPE, "host (9000)", "host.local.", "", 9000, None).unwrap();
+        assert_eq!(discovered(&info), None);
+    }
+}
@@ -0,0 +1,190 @@
+//! End-to-end encryption of session payloads (`--passphrase`)
+//!
+//! Readers sharing a passphrase derive the same key and seal every event
+//! the server has no need to read (positions, notes, chat...) into an
+//! opaque `SyncEvent::Sealed`. The server still sees who connects and
+//! when, and relays the ciphertext like any other message.
+//!
+//! The key comes from the passphrase and a random salt the server picks
+//! for the session, through Argon2id. Each event is sealed with
+//! XChaCha20-Poly1305 under a random nonce, with the sender and sequence
+//! number as associated data, so the server can neither read, alter nor
+//! re-attribute events.
+
+use super::compression::{base64_decode, base64_encode};
+use super::protocol::{SyncEvent, SyncMessage, UserId};
+use anyhow::{Context, Result};
+use argon2::Argon2;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use std::sync::Arc;
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+
+/// A new session's salt, as carried in `SessionState`
+pub fn new_salt() -> Result<String> {
+    let mut salt = [0u8; SALT_LEN];
+    getrandom::getrandom(&mut salt).map_err(|e| anyhow::anyhow!("No randomness for a session salt: {}", e))?;
+    Ok(base64_encode(&salt))
+}
+
+/// Key derived from a session passphrase
+#[derive(Clone)]
+pub struct SessionKey {
+    cipher: XChaCha20Poly1305,
+}
+
+impl std::fmt::Debug for SessionKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SessionKey(..)")
+    }
+}
+
+impl SessionKey {
+    /// Stretch `passphrase` with the session's base64 `salt`; slow on purpose
+    pub fn derive(passphrase: &str, salt: &str) -> Result<Self> {
+        Self::derive_with(Argon2::default(), passphrase, salt)
+    }
+    
+    fn derive_with(argon2: Argon2, passphrase: &str, salt: &str) -> Result<Self> {
+        let salt = base64_decode(salt).context("malformed session salt")?;
+        let mut key = [0u8; 32];
+        argon2.hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+            .map_err(|e| anyhow::anyhow!("Failed to derive the session key: {}", e))?;
+        Ok(Self { cipher: XChaCha20Poly1305::new(&key.into()) })
+    }
+    
+    /// Encrypt and sign `plaintext` as sent by `user_id` under `sequence`, as base64
+    pub fn seal(&self, user_id: &str, sequence: u64, plaintext: &[u8]) -> Result<String> {
+        let mut nonce = [0u8; NONCE_LEN];
+        getrandom::getrandom(&mut nonce).map_err(|e| anyhow::anyhow!("No randomness for an encryption nonce: {}", e))?;
+        
+        let aad = Self::associated_data(user_id, sequence);
+        let ciphertext = self.cipher.encrypt(XNonce::from_slice(&nonce), Payload { msg: plaintext, aad: &aad })
+            .map_err(|_| anyhow::anyhow!("Failed to encrypt"))?;
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        Ok(base64_encode(&sealed))
+    }
+    
+    /// Check and decrypt what `seal` produced for the same sender and sequence
+    pub fn open(&self, user_id: &str, sequence: u64, sealed: &str) -> Result<Vec<u8>> {
+        let sealed = base64_decode(sealed)?;
+        anyhow::ensure!(sealed.len() >= NONCE_LEN, "sealed payload too short");
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let aad = Self::associated_data(user_id, sequence);
+        self.cipher.decrypt(XNonce::from_slice(nonce), Payload { msg: ciphertext, aad: &aad })
+            .map_err(|_| anyhow::anyhow!("wrong passphrase or tampered payload"))
+    }
+    
+    fn associated_data(user_id: &str, sequence: u64) -> Vec<u8> {
+        let mut aad = user_id.as_bytes().to_vec();
+        aad.push(0);
+        aad.extend_from_slice(&sequence.to_be_bytes());
+        aad
+    }
+}
+
+/// Seals what we send and opens what others sent, for one passphrase
+#[derive(Debug, Clone)]
+pub struct Encryption {
+    key: Arc<SessionKey>,
+    salt: String, // The session's, which the key was derived with
+    user_id: UserId, // Who our sealed messages are from
+}
+
+impl Encryption {
+    /// Derive the session key, which takes a moment on purpose
+    pub fn new(passphrase: &str, salt: &str, user_id: UserId) -> Result<Self> {
+        Ok(Self::with_key(SessionKey::derive(passphrase, salt)?, salt, user_id))
+    }
+    
+    fn with_key(key: SessionKey, salt: &str, user_id: UserId) -> Self {
+        Self { key: Arc::new(key), salt: salt.to_string(), user_id }
+    }
+    
+    /// The session salt this key belongs to
+    pub fn salt(&self) -> &str {
+        &self.salt
+    }
+    
+    /// Wrap a message's event in `Sealed` if it is private
+    pub fn seal(&self, message: SyncMessage) -> Result<SyncMessage> {
+        if !message.event.is_private() {
+            return Ok(message);
+        }
+        let reliable = message.event.is_reliable();
+        let payload = self.key.seal(&self.user_id, message.sequence, &serde_json::to_vec(&message.event)?)?;
+        let event = SyncEvent::Sealed { user_id: self.user_id.clone(), reliable, payload };
+        Ok(SyncMessage::new(event, message.sequence))
+    }
+    
+    /// Unwrap a `Sealed` event, refusing private events that arrive in the clear
+    ///
+    /// A readable page turn or chat message can only come from the server
+    /// or someone without the passphrase, so it is not to be trusted.
+    pub fn open(&self, message: SyncMessage) -> Result<SyncMessage> {
+        let SyncEvent::Sealed { user_id, payload, .. } = &message.event else {
+            anyhow::ensure!(!message.event.is_private(), "unencrypted message {} in an encrypted session", message.sequence);
+            return Ok(message);
+        };
+        let plaintext = self.key.open(user_id, message.sequence, payload)
+            .with_context(|| format!("can't decrypt a message from {}", user_id))?;
+        let event: SyncEvent = serde_json::from_slice(&plaintext)?;
+        anyhow::ensure!(event.is_private(), "{} sealed a message that should have been readable", user_id);
+        Ok(SyncMessage { event, ..message })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::protocol::UserState;
+    
+    /// Far cheaper than the real parameters, so tests don't crawl in debug builds
+    fn cheap_key(passphrase: &str, salt: &str) -> SessionKey {
+        let params = argon2::Params::new(argon2::Params::MIN_M_COST, 1, 1, None).unwrap();
+        SessionKey::derive_with(Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params), passphrase, salt).unwrap()
+    }
+    
+    #[test]
+    fn test_seal_and_open() {
+        let salt = new_salt().unwrap();
+        assert_ne!(new_salt().unwrap(), salt);
+        let key = cheap_key("correct horse", &salt);
+        let text = "a note only the other readers should see".as_bytes();
+        let sealed = key.seal("alice", 7, text).unwrap();
+        assert_eq!(key.open("alice", 7, &sealed).unwrap(), text);
+        assert_ne!(key.seal("alice", 7, text).unwrap(), sealed); // Fresh nonce each time
+        
+        // Another sender, sequence, passphrase or session doesn't verify
+        assert!(key.open("mallory", 7, &sealed).is_err());
+        assert!(key.open("alice", 8, &sealed).is_err());
+        assert!(cheap_key("battery staple", &salt).open("alice", 7, &sealed).is_err());
+        assert!(cheap_key("correct horse", &new_salt().unwrap()).open("alice", 7, &sealed).is_err());
+        assert!(SessionKey::derive("correct horse", "not base64!").is_err());
+    }
+    
+    #[test]
+    fn test_seal_message() {
+        let salt = new_salt().unwrap();
+        let alice = Encryption::with_key(cheap_key("correct horse", &salt), &salt, "alice".into());
+        
+        let update = SyncMessage::state_update(UserState::new("alice".into()), 3);
+        let sealed = alice.seal(update.clone()).unwrap();
+        assert!(matches!(sealed.event, SyncEvent::Sealed { reliable: false, .. }));
+        assert!(!serde_json::to_string(&sealed).unwrap().contains("playlist_position"));
+        let opened = alice.open(sealed).unwrap();
+        assert!(matches!(opened.event, SyncEvent::StateUpdate { ref user_state } if user_state.user_id == "alice"));
+        
+        let chat = SyncMessage::new(SyncEvent::Chat { user_id: "alice".into(), text: "hi".into() }, 4);
+        assert!(matches!(alice.seal(chat.clone()).unwrap().event, SyncEvent::Sealed { reliable: true, .. }));
+        
+        // What the server needs stays readable, and private events in the clear are refused
+        let heartbeat = SyncMessage::heartbeat("alice".into(), None, 5);
+        assert!(matches!(alice.seal(heartbeat.clone()).unwrap().event, SyncEvent::Heartbeat { .. }));
+        assert!(alice.open(heartbeat).is_ok());
+        assert!(alice.open(chat).is_err());
+    }
+}
@@ -0,0 +1,144 @@
+//! What happened in a session, kept by the server in SQLite (`--event-log`)
+//!
+//! One row per join, leave, page turn and message, with the session time
+//! it happened, so reading pace and attendance can be worked out after the
+//! session with any SQLite tool.
+
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection};
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Kinds of event written to the log
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    Join,
+    Leave,
+    Page, // Turned to `page`; `detail` is the file name
+    Note, // Left a note on `page`
+    Link, // Shared a link
+    Reaction, // Reacted with an emoji
+    Chat, // Said something; `detail` is the message
+}
+
+impl EventKind {
+    /// Name stored in the `kind` column
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EventKind::Join => "join",
+            EventKind::Leave => "leave",
+            EventKind::Page => "page",
+            EventKind::Note => "note",
+            EventKind::Link => "link",
+            EventKind::Reaction => "reaction",
+            EventKind::Chat => "chat",
+        }
+    }
+}
+
+/// One logged event, as read back
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoggedEvent {
+    pub at_ms: u64, // Session time, ms since the Unix epoch
+    pub user_id: String,
+    pub kind: String,
+    pub page: Option<i32>,
+    pub detail: Option<String>,
+}
+
+/// Append-only event table in an SQLite database
+pub struct EventLog {
+    db: Mutex<Connection>, // Shared by every connection task
+}
+
+impl EventLog {
+    /// Open or create the log, keeping events from earlier sessions
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory {:?}", parent))?;
+        }
+        
+        let db = Connection::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+        Self::set_up(db).with_context(|| format!("Failed to set up {:?}", path))
+    }
+    
+    /// A log kept only in memory, for servers that summarize sessions without keeping the events
+    pub fn in_memory() -> Result<Self> {
+        Self::set_up(Connection::open_in_memory()?)
+    }
+    
+    fn set_up(db: Connection) -> Result<Self> {
+        db.execute_batch(
+            "CREATE TABLE IF NOT EXISTS events (
+                id INTEGER PRIMARY KEY,
+                at_ms INTEGER NOT NULL,
+                user_id TEXT NOT NULL,
+                kind TEXT NOT NULL,
+                page INTEGER,
+                detail TEXT
+            );
+            CREATE INDEX IF NOT EXISTS events_by_user ON events (user_id, kind, at_ms);",
+        )?;
+        Ok(Self { db: Mutex::new(db) })
+    }
+    
+    /// Write one event at `at_ms`
+    pub fn record(&self, at_ms: u64, user_id: &str, kind: EventKind, page: Option<i32>, detail: Option<&str>) -> Result<()> {
+        self.db.lock().unwrap().execute(
+            "INSERT INTO events (at_ms, user_id, kind, page, detail) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![at_ms as i64, user_id, kind.as_str(), page, detail],
+        ).context("Failed to write to the event log")?;
+        Ok(())
+    }
+    
+    /// Every event from `since_ms` on, oldest first
+    pub fn events_since(&self, since_ms: u64) -> Result<Vec<LoggedEvent>> {
+        let db = self.db.lock().unwrap();
+        let mut query = db.prepare(
+            "SELECT at_ms, user_id, kind, page, detail FROM events WHERE at_ms >= ?1 ORDER BY at_ms, id",
+        )?;
+        let events = query.query_map(params![since_ms as i64], |row| {
+            Ok(LoggedEvent {
+                at_ms: row.get::<_, i64>(0)? as u64,
+                user_id: row.get(1)?,
+                kind: row.get(2)?,
+                page: row.get(3)?,
+                detail: row.get(4)?,
+            })
+        })?;
+        events.collect::<rusqlite::Result<_>>().context("Failed to read the event log")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_record_and_read_back() {
+        let path = std::env::temp_dir().join(format!("syncread_test_events_{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        
+        let log = EventLog::open(&path).unwrap();
+        log.record(1_000, "alice", EventKind::Join, Some(0), None).unwrap();
+        log.record(2_000, "alice", EventKind::Page, Some(1), Some("p2.png")).unwrap();
+        log.record(3_000, "bob", EventKind::Note, Some(1), Some("nice panel")).unwrap();
+        
+        // Reopening keeps what was there
+        let log = EventLog::open(&path).unwrap();
+        let events = log.events_since(2_000).unwrap();
+        let _ = std::fs::remove_file(&path);
+        
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0], LoggedEvent {
+            at_ms: 2_000,
+            user_id: "alice".to_string(),
+            kind: "page".to_string(),
+            page: Some(1),
+            detail: Some("p2.png".to_string()),
+        });
+        assert_eq!(events[1].kind, "note");
+    }
+}
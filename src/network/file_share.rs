@@ -0,0 +1,231 @@
+use crate::media::MediaSource;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::ffi::OsStr;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, info, warn};
+
+/// Longest request line accepted from a downloader
+const MAX_REQUEST_SIZE: u64 = 4096;
+
+/// Largest item downloaded unless configured otherwise
+pub const DEFAULT_MAX_DOWNLOAD_SIZE: u64 = 4 << 30;
+
+/// First line on a transfer connection: the playlist item wanted, by file name
+#[derive(Debug, Serialize, Deserialize)]
+struct FileRequest {
+    name: String,
+}
+
+/// The sharer's answer; `size` bytes of file follow it unless there is an `error`
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct FileReply {
+    size: u64,
+    error: Option<String>,
+}
+
+/// Playlist entry names, as files are matched between readers
+pub fn item_names(files: &[MediaSource]) -> Vec<String> {
+    files.iter().map(|file| file.display_name().unwrap_or_default()).collect()
+}
+
+/// Items in `theirs` missing from `ours`, with the index each belongs at
+///
+/// Inserting them in the order returned gives the same playlist as theirs
+/// when ours is theirs with some items left out.
+pub fn missing_items(ours: &[String], theirs: &[String]) -> Vec<(usize, String)> {
+    theirs.iter()
+        .enumerate()
+        .filter(|(_, name)| !name.is_empty() && !ours.contains(name))
+        .map(|(index, name)| (index, name.clone()))
+        .collect()
+}
+
+/// Send the local files of a playlist to whoever asks for one by name
+///
+/// Each download gets its own connection, separate from the session, so a
+/// large chapter doesn't hold up position updates.
+pub async fn serve(listener: TcpListener, files: Vec<MediaSource>) {
+    let files = Arc::new(files);
+    loop {
+        let (stream, addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("Failed to accept a download: {}", e);
+                continue;
+            }
+        };
+        
+        let files = files.clone();
+        tokio::spawn(async move {
+            if let Err(e) = send_file(stream, &files).await {
+                debug!("Download by {} failed: {}", addr, e);
+            }
+        });
+    }
+}
+
+async fn send_file(stream: TcpStream, files: &[MediaSource]) -> Result<()> {
+    let peer = stream.peer_addr()?;
+    let (reader, mut writer) = stream.into_split();
+    let mut line = String::new();
+    BufReader::new(reader.take(MAX_REQUEST_SIZE)).read_line(&mut line).await?;
+    let request: FileRequest = serde_json::from_str(&line).context("Bad download request")?;
+    
+    // Only playlist items are shared, never arbitrary paths
+    let path = files.iter()
+        .filter_map(MediaSource::as_path)
+        .find(|path| path.file_name() == Some(OsStr::new(&request.name)));
+    let opened = match path {
+        Some(path) => tokio::fs::File::open(path).await.map_err(anyhow::Error::from),
+        None => Err(anyhow::anyhow!("{} is not in the shared playlist", request.name)),
+    };
+    let mut file = match opened {
+        Ok(file) => file,
+        Err(e) => {
+            let reply = FileReply { error: Some(e.to_string()), ..Default::default() };
+            writer.write_all(format!("{}\n", serde_json::to_string(&reply)?).as_bytes()).await?;
+            return Err(e);
+        }
+    };
+    
+    let size = file.metadata().await?.len();
+    info!("Sending {} ({} KiB) to {}", request.name, size / 1024, peer);
+    let reply = FileReply { size, error: None };
+    writer.write_all(format!("{}\n", serde_json::to_string(&reply)?).as_bytes()).await?;
+    tokio::io::copy(&mut file, &mut writer).await?;
+    writer.shutdown().await?;
+    Ok(())
+}
+
+/// Download a playlist item into `dir`, reporting `(received, total)` bytes as it arrives
+///
+/// Items downloaded before are reused without asking again. Items larger
+/// than `max_size` are refused, since the sharer's word is all we have.
+pub async fn fetch(addr: SocketAddr, name: &str, dir: &Path, max_size: u64, progress: impl FnMut(u64, u64)) -> Result<PathBuf> {
+    // Names come from other readers; they must not point outside `dir`
+    anyhow::ensure!(
+        !name.is_empty() && Path::new(name).file_name() == Some(OsStr::new(name)),
+        "refusing to download {:?}", name
+    );
+    let path = dir.join(name);
+    if path.exists() {
+        return Ok(path);
+    }
+    
+    let stream = TcpStream::connect(addr).await
+        .with_context(|| format!("Failed to connect to {}", addr))?;
+    let (reader, mut writer) = stream.into_split();
+    let request = FileRequest { name: name.to_string() };
+    writer.write_all(format!("{}\n", serde_json::to_string(&request)?).as_bytes()).await?;
+    
+    let mut reader = BufReader::new(reader);
+    let mut line = String::new();
+    reader.read_line(&mut line).await?;
+    let reply: FileReply = serde_json::from_str(&line).context("Bad reply from the sharer")?;
+    if let Some(error) = reply.error {
+        anyhow::bail!(error);
+    }
+    anyhow::ensure!(reply.size <= max_size, "{} is {} MiB, over the {} MiB limit", name, reply.size >> 20, max_size >> 20);
+    
+    tokio::fs::create_dir_all(dir).await
+        .with_context(|| format!("Failed to create directory {:?}", dir))?;
+    let partial = dir.join(format!("{}.part", name));
+    let result = receive(reader.take(reply.size), &partial, reply.size, progress).await;
+    let result = match result {
+        Ok(()) => tokio::fs::rename(&partial, &path).await.map_err(anyhow::Error::from),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        let _ = tokio::fs::remove_file(&partial).await; // Don't leave half a file behind
+        return Err(e);
+    }
+    Ok(path)
+}
+
+/// Write `size` bytes from `reader` to `partial`
+async fn receive(mut reader: impl AsyncRead + Unpin, partial: &Path, size: u64, mut progress: impl FnMut(u64, u64)) -> Result<()> {
+    let mut file = tokio::fs::File::create(partial).await
+        .with_context(|| format!("Failed to create {:?}", partial))?;
+    
+    let mut received = 0;
+    let mut buffer = vec![0; 64 * 1024];
+    while received < size {
+        let len = reader.read(&mut buffer).await?;
+        anyhow::ensure!(len > 0, "connection closed after {} of {} bytes", received, size);
+        file.write_all(&buffer[..len]).await?;
+        received += len as u64;
+        progress(received, size);
+    }
+    file.flush().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_missing_items() {
+        let names = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let theirs = names(&["ch1.cbz", "ch2.cbz", "ch3.cbz", "ch4.cbz"]);
+        let ours = names(&["ch1.cbz", "ch3.cbz"]);
+        assert_eq!(missing_items(&ours, &theirs), vec![(1, "ch2.cbz".to_string()), (3, "ch4.cbz".to_string())]);
+        assert!(missing_items(&theirs, &ours).is_empty());
+    }
+    
+    #[tokio::test]
+    async fn test_fetch_from_sharer() {
+        let dir = std::env::temp_dir().join(format!("syncread-share-{}", std::process::id()));
+        let shared = dir.join("shared");
+        std::fs::create_dir_all(&shared).unwrap();
+        std::fs::write(shared.join("p2.png"), vec![7u8; 200_000]).unwrap();
+        
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, vec![MediaSource::Local(shared.join("p2.png"))]));
+        
+        let downloads = dir.join("downloads");
+        let mut last = (0, 0);
+        let path = fetch(addr, "p2.png", &downloads, DEFAULT_MAX_DOWNLOAD_SIZE, |received, total| last = (received, total)).await.unwrap();
+        assert_eq!(std::fs::read(&path).unwrap().len(), 200_000);
+        assert_eq!(last, (200_000, 200_000));
+        
+        assert!(fetch(addr, "secret.txt", &downloads, DEFAULT_MAX_DOWNLOAD_SIZE, |_, _| {}).await.is_err());
+        assert!(fetch(addr, "../p2.png", &downloads, DEFAULT_MAX_DOWNLOAD_SIZE, |_, _| {}).await.is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    
+    #[tokio::test]
+    async fn test_failed_fetch_leaves_nothing_behind() {
+        let dir = std::env::temp_dir().join(format!("syncread-share-failed-{}", std::process::id()));
+        let shared = dir.join("shared");
+        std::fs::create_dir_all(&shared).unwrap();
+        std::fs::write(shared.join("big.cbz"), vec![7u8; 200_000]).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, vec![MediaSource::Local(shared.join("big.cbz"))]));
+        
+        let downloads = dir.join("downloads");
+        assert!(fetch(addr, "big.cbz", &downloads, 100_000, |_, _| {}).await.is_err());
+        assert!(!downloads.join("big.cbz.part").exists());
+        
+        // A sharer that stops short of the size it promised
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0; 64];
+            let _ = stream.read(&mut request).await;
+            stream.write_all(b"{\"size\":1000,\"error\":null}\n0123456789").await.unwrap();
+        });
+        assert!(fetch(addr, "short.cbz", &downloads, DEFAULT_MAX_DOWNLOAD_SIZE, |_, _| {}).await.is_err());
+        assert!(!downloads.join("short.cbz.part").exists());
+        assert!(!downloads.join("short.cbz").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
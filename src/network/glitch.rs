@@ -0,0 +1,143 @@
+//! Filtering of bogus playlist positions reported while MPV switches files
+//!
+//! During a transition MPV can briefly report the first entry, or an entry
+//! far behind the one being opened. Broadcasting those would drag everyone
+//! else along, so big backward jumps are only believed once they persist.
+
+use serde::Deserialize;
+use tracing::{debug, info};
+
+/// Thresholds for telling MPV transition glitches from real page turns
+///
+/// The defaults suit chapters of a few dozen pages. For a long omnibus,
+/// where jumping back a hundred pages is normal, a larger `backward_jump`
+/// or fewer `confirmations` reacts faster.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GlitchFilter {
+    /// Check positions at all; when off, any position in the playlist is sent
+    pub enabled: bool,
+    /// Jumps of up to this many entries, either way, are always accepted
+    pub small_jump: i32,
+    /// Backward jumps beyond this many entries wait for confirmation
+    pub backward_jump: i32,
+    /// Consecutive readings a large backward jump must show up in before it's accepted
+    pub confirmations: u8,
+}
+
+impl Default for GlitchFilter {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            small_jump: 3,
+            backward_jump: 10,
+            confirmations: 2,
+        }
+    }
+}
+
+impl GlitchFilter {
+    /// A filter that lets every valid position through
+    pub fn disabled() -> Self {
+        Self { enabled: false, ..Self::default() }
+    }
+    
+    /// Whether `new_position` should be sent, updating the last accepted position and any jump awaiting confirmation
+    pub fn accept(
+        &self,
+        last_pos: &mut Option<i32>,
+        pending: &mut Option<(i32, u8)>,
+        new_position: i32,
+        playlist_length: usize,
+    ) -> bool {
+        // Reject jumping to invalid positions
+        if new_position < 0 || new_position >= playlist_length as i32 {
+            debug!("Rejected invalid position: {} (playlist length: {})", new_position, playlist_length);
+            *pending = None; // Clear pending for invalid positions
+            return false;
+        }
+        
+        // If we don't have a last known position, accept any reasonable position
+        let Some(last) = *last_pos else {
+            *last_pos = Some(new_position);
+            *pending = None; // Clear any pending position
+            return true;
+        };
+        
+        let position_diff = (new_position - last).abs();
+        
+        // Always allow small jumps, and larger ones if they seem intentional (forward progress)
+        if !self.enabled || position_diff <= self.small_jump || new_position > last {
+            *last_pos = Some(new_position);
+            *pending = None; // Clear any pending position
+            return true;
+        }
+        
+        // Handle large backward jumps with retry mechanism
+        if position_diff > self.backward_jump {
+            // Check if this is a glitch (jumping from middle/end back to start)
+            if last > 5 && new_position <= 1 {
+                debug!("Rejected obvious glitch: position {} -> {} (likely MPV transition)", last, new_position);
+                *pending = None; // Clear pending for obvious glitches
+                return false;
+            }
+            
+            // Handle legitimate large backward jumps with retry
+            let count = match *pending {
+                Some((pending_pos, count)) if pending_pos == new_position => count.saturating_add(1),
+                _ => 1,
+            };
+            debug!("Large backward jump {} -> {} (reading {} of {})", last, new_position, count, self.confirmations);
+            
+            if count >= self.confirmations {
+                *last_pos = Some(new_position);
+                *pending = None;
+                info!("Accepted legitimate large backward jump: {} -> {}", last, new_position);
+                return true;
+            }
+            *pending = Some((new_position, count));
+            return false; // Don't send update yet, wait for confirmation
+        }
+        
+        // Accept moderate backward jumps (user might have gone back a few pages)
+        *last_pos = Some(new_position);
+        *pending = None; // Clear any pending position
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    /// Feed positions through a filter, returning which were accepted
+    fn run(filter: &GlitchFilter, start: i32, positions: &[i32]) -> Vec<bool> {
+        let (mut last, mut pending) = (Some(start), None);
+        positions.iter().map(|&position| filter.accept(&mut last, &mut pending, position, 100)).collect()
+    }
+    
+    #[test]
+    fn test_default_filter() {
+        let filter = GlitchFilter::default();
+        assert_eq!(run(&filter, 50, &[52, 80, 75, 100, -1]), vec![true, true, true, false, false]);
+        
+        // Back to the start mid-playlist is a transition glitch
+        assert_eq!(run(&filter, 50, &[0, 0, 0]), vec![false, false, false]);
+        
+        // Far back is believed on the second reading
+        assert_eq!(run(&filter, 50, &[20, 20, 20]), vec![false, true, true]);
+        assert_eq!(run(&filter, 50, &[20, 30, 30]), vec![false, false, true]);
+    }
+    
+    #[test]
+    fn test_configured_filter() {
+        let omnibus = GlitchFilter { backward_jump: 40, confirmations: 3, ..GlitchFilter::default() };
+        assert_eq!(run(&omnibus, 50, &[20]), vec![true]);
+        assert_eq!(run(&omnibus, 50, &[5, 5, 5]), vec![false, false, true]);
+        
+        let trusting = GlitchFilter { confirmations: 1, ..GlitchFilter::default() };
+        assert_eq!(run(&trusting, 50, &[20]), vec![true]);
+        
+        assert_eq!(run(&GlitchFilter::disabled(), 50, &[0, 99, 100]), vec![true, true, false]);
+    }
+}
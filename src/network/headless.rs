@@ -0,0 +1,219 @@
+use super::appearance::parse_reaction;
+use anyhow::{Context, Result};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio::time::Duration;
+use tracing::{info, warn};
+
+/// Where a headless client takes its commands from
+#[derive(Debug, Clone)]
+pub enum HeadlessInput {
+    /// One command per line on stdin
+    Stdin,
+    /// A script file, run top to bottom; the client leaves at the end
+    Script(PathBuf),
+    /// Lines sent by any client connecting to this TCP address
+    Control(SocketAddr),
+}
+
+/// What a headless client can be told to do, standing in for keypresses in MPV
+#[derive(Debug, Clone, PartialEq)]
+pub enum HeadlessCommand {
+    /// Go to a page (1-based, like everywhere users see page numbers)
+    Page(i32),
+    Next,
+    Prev,
+    /// Jump to a playback time in seconds
+    Seek(f64),
+    Pause,
+    Play,
+    /// Leave a note on the current page
+    Note(String),
+    /// Share a link with the session
+    Link(String),
+    /// React with an emoji, or a reaction name like "like"
+    React(String),
+    /// Say something to the session
+    Chat(String),
+    /// Only follow this reader's page turns (a user id, or their place among the others); `None` follows everyone again
+    Follow(Option<String>),
+    /// Go to where another reader is (a user id, or their place among the others)
+    JumpTo(String),
+    /// Leave the session
+    Quit,
+}
+
+impl HeadlessCommand {
+    /// The `script-message syncread` action that does the same in MPV, for commands that have one
+    pub fn sync_action(&self) -> Option<Vec<String>> {
+        let (name, arg) = match self {
+            HeadlessCommand::Note(text) => ("note", Some(text)),
+            HeadlessCommand::Link(url) => ("share-link", Some(url)),
+            HeadlessCommand::React(emoji) => ("react", Some(emoji)),
+            HeadlessCommand::Chat(text) => ("chat", Some(text)),
+            HeadlessCommand::Follow(who) => ("follow", who.as_ref()),
+            HeadlessCommand::JumpTo(who) => ("jump-to", Some(who)),
+            _ => return None,
+        };
+        Some(std::iter::once(name.to_string()).chain(arg.cloned()).collect())
+    }
+}
+
+/// A parsed input line: either a command or a scripted delay
+#[derive(Debug, Clone, PartialEq)]
+enum InputLine {
+    Command(HeadlessCommand),
+    Wait(Duration),
+}
+
+/// Parse one input line; blank lines and `#` comments yield `None`
+fn parse_line(line: &str) -> Result<Option<InputLine>> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+    
+    let (name, arg) = match line.split_once(char::is_whitespace) {
+        Some((name, arg)) => (name, arg.trim()),
+        None => (line, ""),
+    };
+    
+    let number = |what: &str| -> Result<f64> {
+        arg.parse::<f64>()
+            .ok()
+            .filter(|n| n.is_finite() && *n >= 0.0)
+            .with_context(|| format!("'{}' needs a {}, got '{}'", name, what, arg))
+    };
+    
+    let command = match name {
+        "page" | "goto" => HeadlessCommand::Page(number("page number")? as i32),
+        "next" => HeadlessCommand::Next,
+        "prev" => HeadlessCommand::Prev,
+        "seek" => HeadlessCommand::Seek(number("time in seconds")?),
+        "pause" => HeadlessCommand::Pause,
+        "play" => HeadlessCommand::Play,
+        "note" if !arg.is_empty() => HeadlessCommand::Note(arg.to_string()),
+        "note" => anyhow::bail!("'note' needs some text"),
+        "link" if !arg.is_empty() => HeadlessCommand::Link(arg.to_string()),
+        "link" => anyhow::bail!("'link' needs a URL"),
+        "react" => HeadlessCommand::React(parse_reaction(arg)?),
+        "chat" if !arg.is_empty() => HeadlessCommand::Chat(arg.to_string()),
+        "chat" => anyhow::bail!("'chat' needs a message"),
+        "follow" => HeadlessCommand::Follow((!arg.is_empty()).then(|| arg.to_string())),
+        "jump" if !arg.is_empty() => HeadlessCommand::JumpTo(arg.to_string()),
+        "jump" => anyhow::bail!("'jump' needs a user"),
+        "quit" => HeadlessCommand::Quit,
+        "wait" => return Ok(Some(InputLine::Wait(Duration::from_secs_f64(number("duration in seconds")?)))),
+        other => anyhow::bail!("unknown command '{}'", other),
+    };
+    Ok(Some(InputLine::Command(command)))
+}
+
+/// Parse a command sent over a control connection, where there is nothing to `wait` for
+pub fn parse_command(line: &str) -> Result<Option<HeadlessCommand>> {
+    match parse_line(line)? {
+        Some(InputLine::Command(command)) => Ok(Some(command)),
+        Some(InputLine::Wait(_)) => anyhow::bail!("'wait' only works in scripts"),
+        None => Ok(None),
+    }
+}
+
+/// Start reading commands from `input`; the receiver closes when a script or stdin ends
+pub async fn spawn_input(input: HeadlessInput) -> Result<mpsc::UnboundedReceiver<HeadlessCommand>> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    
+    match input {
+        HeadlessInput::Stdin => {
+            tokio::spawn(read_commands(BufReader::new(tokio::io::stdin()), tx));
+        }
+        HeadlessInput::Script(path) => {
+            let file = tokio::fs::File::open(&path).await
+                .with_context(|| format!("Failed to open script {:?}", path))?;
+            tokio::spawn(read_commands(BufReader::new(file), tx));
+        }
+        HeadlessInput::Control(addr) => {
+            let listener = TcpListener::bind(addr).await
+                .with_context(|| format!("Failed to listen for control connections on {}", addr))?;
+            info!("Accepting headless commands on {}", addr);
+            
+            tokio::spawn(async move {
+                loop {
+                    match listener.accept().await {
+                        Ok((stream, peer)) => {
+                            info!("Control connection from {}", peer);
+                            tokio::spawn(read_commands(BufReader::new(stream), tx.clone()));
+                        }
+                        Err(e) => warn!("Failed to accept control connection: {}", e),
+                    }
+                }
+            });
+        }
+    }
+    
+    Ok(rx)
+}
+
+/// Forward commands from a line-based reader, sleeping through `wait` lines
+async fn read_commands<R: AsyncBufRead + Unpin>(reader: R, tx: mpsc::UnboundedSender<HeadlessCommand>) {
+    let mut lines = reader.lines();
+    loop {
+        let line = match lines.next_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(e) => {
+                warn!("Failed to read headless input: {}", e);
+                break;
+            }
+        };
+        
+        match parse_line(&line) {
+            Ok(Some(InputLine::Command(command))) => {
+                if tx.send(command).is_err() {
+                    break; // Client is gone
+                }
+            }
+            Ok(Some(InputLine::Wait(delay))) => tokio::time::sleep(delay).await,
+            Ok(None) => {}
+            Err(e) => warn!("Ignoring headless input line '{}': {}", line.trim(), e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_parse_line() {
+        assert_eq!(parse_line("page 12").unwrap(), Some(InputLine::Command(HeadlessCommand::Page(12))));
+        assert_eq!(parse_line("  next ").unwrap(), Some(InputLine::Command(HeadlessCommand::Next)));
+        assert_eq!(parse_line("seek 90.5").unwrap(), Some(InputLine::Command(HeadlessCommand::Seek(90.5))));
+        assert_eq!(parse_line("note look  here").unwrap(), Some(InputLine::Command(HeadlessCommand::Note("look  here".into()))));
+        assert_eq!(parse_line("link https://example.org").unwrap(), Some(InputLine::Command(HeadlessCommand::Link("https://example.org".into()))));
+        assert_eq!(parse_line("react wow").unwrap(), Some(InputLine::Command(HeadlessCommand::React("😮".into()))));
+        assert_eq!(parse_line("wait 1.5").unwrap(), Some(InputLine::Wait(Duration::from_millis(1500))));
+        assert_eq!(parse_line("goto 42").unwrap(), Some(InputLine::Command(HeadlessCommand::Page(42))));
+        assert_eq!(parse_line("chat hi all").unwrap(), Some(InputLine::Command(HeadlessCommand::Chat("hi all".into()))));
+        assert_eq!(parse_line("follow alice").unwrap(), Some(InputLine::Command(HeadlessCommand::Follow(Some("alice".into())))));
+        assert_eq!(parse_line("follow").unwrap(), Some(InputLine::Command(HeadlessCommand::Follow(None))));
+        assert_eq!(parse_line("jump 2").unwrap(), Some(InputLine::Command(HeadlessCommand::JumpTo("2".into()))));
+        assert!(parse_line("jump").is_err());
+        assert_eq!(parse_line("# turn pages").unwrap(), None);
+        assert_eq!(parse_line("").unwrap(), None);
+        
+        assert_eq!(HeadlessCommand::Follow(None).sync_action(), Some(vec!["follow".to_string()]));
+        assert_eq!(HeadlessCommand::Chat("hi".into()).sync_action(), Some(vec!["chat".to_string(), "hi".to_string()]));
+        assert_eq!(HeadlessCommand::Next.sync_action(), None);
+        
+        assert!(parse_line("page").is_err());
+        assert!(parse_line("seek -3").is_err());
+        assert!(parse_line("note").is_err());
+        assert!(parse_line("react").is_err());
+        assert!(parse_line("chat").is_err());
+        assert!(parse_command("wait 1").is_err());
+        assert!(parse_line("fly 3").is_err());
+    }
+}
@@ -0,0 +1,225 @@
+//! Commands of your own run on session events (`[client.hooks]`)
+//!
+//! Each hook is a shell command, run without waiting for it to finish, so a
+//! slow one never holds up syncing. What happened is passed both as
+//! `SYNCREAD_*` environment variables and as one line of JSON on stdin,
+//! for lights, timers, loggers or anything else worth wiring up.
+
+use super::protocol::{unix_millis, SessionState, UserState};
+use serde::{Deserialize, Serialize};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tracing::{debug, warn};
+
+/// Something in the session a hook can run on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookEvent {
+    Join, // Someone else joined
+    EveryoneSynced, // Everyone came onto the same page
+    PageChange, // Someone (maybe us) turned the page
+    Finish, // Someone (maybe us) reached the end of the playlist
+}
+
+impl HookEvent {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HookEvent::Join => "join",
+            HookEvent::EveryoneSynced => "everyone-synced",
+            HookEvent::PageChange => "page-change",
+            HookEvent::Finish => "finish",
+        }
+    }
+}
+
+/// The `[client.hooks]` table: a shell command for each event, all optional
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Hooks {
+    pub on_join: Option<String>,
+    pub on_everyone_synced: Option<String>,
+    pub on_page_change: Option<String>,
+    pub on_finish: Option<String>,
+}
+
+impl Hooks {
+    /// Command to run on `event`, if one is set
+    pub fn command(&self, event: HookEvent) -> Option<&str> {
+        let command = match event {
+            HookEvent::Join => &self.on_join,
+            HookEvent::EveryoneSynced => &self.on_everyone_synced,
+            HookEvent::PageChange => &self.on_page_change,
+            HookEvent::Finish => &self.on_finish,
+        };
+        command.as_deref().filter(|command| !command.trim().is_empty())
+    }
+}
+
+/// What a hook is told about its event, as the JSON on its stdin
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HookData {
+    pub event: &'static str,
+    /// Who joined, turned the page or finished; for `everyone-synced`, whoever got there last
+    pub user_id: String,
+    /// 1-based, as shown to readers
+    pub page: i32,
+    pub file_name: Option<String>,
+    pub users: usize,
+    pub finished: usize,
+    pub at_ms: u64,
+}
+
+impl HookData {
+    pub fn new(event: HookEvent, user: &UserState, session: &SessionState) -> Self {
+        Self {
+            event: event.as_str(),
+            user_id: user.user_id.clone(),
+            page: user.playlist_position + 1,
+            file_name: user.current_file_name.clone(),
+            users: session.users.len(),
+            finished: session.finished_count(),
+            at_ms: unix_millis(),
+        }
+    }
+    
+    /// The same, as `SYNCREAD_*` environment variables
+    pub fn env(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("SYNCREAD_EVENT", self.event.to_string()),
+            ("SYNCREAD_USER", self.user_id.clone()),
+            ("SYNCREAD_PAGE", self.page.to_string()),
+            ("SYNCREAD_FILE", self.file_name.clone().unwrap_or_default()),
+            ("SYNCREAD_USERS", self.users.to_string()),
+            ("SYNCREAD_FINISHED", self.finished.to_string()),
+        ]
+    }
+}
+
+/// Runs hooks as the session changes, remembering whether everyone was last on the same page
+#[derive(Debug, Default)]
+pub struct HookRunner {
+    hooks: Hooks,
+    synced: AtomicBool,
+}
+
+impl HookRunner {
+    pub fn new(hooks: Hooks) -> Self {
+        Self { hooks, synced: AtomicBool::new(false) }
+    }
+    
+    /// Run the hook for `event`, if there is one
+    pub fn fire(&self, event: HookEvent, user: &UserState, session: &SessionState) {
+        if let Some(command) = self.hooks.command(event) {
+            spawn(command.to_string(), HookData::new(event, user, session));
+        }
+    }
+    
+    /// Call with the session after `user`'s state went in, and their state before it
+    ///
+    /// Runs `on_page_change` if their page moved, `on_finish` if they just
+    /// finished, and `on_everyone_synced` when this brought two or more
+    /// readers onto the same page.
+    pub fn observe(&self, session: &SessionState, previous: Option<&UserState>, user: &UserState) {
+        if let Some(previous) = previous {
+            if previous.playlist_position != user.playlist_position {
+                self.fire(HookEvent::PageChange, user, session);
+            }
+            if user.finished && !previous.finished {
+                self.fire(HookEvent::Finish, user, session);
+            }
+        }
+        if self.check_synced(session) {
+            self.fire(HookEvent::EveryoneSynced, user, session);
+        }
+    }
+    
+    /// Track whether everyone is on the same page, returning true only when they just got there
+    pub fn check_synced(&self, session: &SessionState) -> bool {
+        let synced = session.users.len() > 1 && session.check_sync_status(0);
+        let was_synced = self.synced.swap(synced, Ordering::Relaxed);
+        synced && !was_synced
+    }
+}
+
+/// Start `command` in the background, handing it `data`
+fn spawn(command: String, data: HookData) {
+    #[cfg(unix)]
+    let mut cmd = {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(&command);
+        cmd
+    };
+    #[cfg(windows)]
+    let mut cmd = {
+        let mut cmd = Command::new("cmd");
+        cmd.arg("/C").arg(&command);
+        cmd
+    };
+    cmd.envs(data.env()).stdin(Stdio::piped());
+    
+    tokio::spawn(async move {
+        debug!("Running {} hook: {}", data.event, command);
+        let mut child = match cmd.spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                warn!("Failed to run {} hook: {}", data.event, e);
+                return;
+            }
+        };
+        if let Some(mut stdin) = child.stdin.take() {
+            let json = serde_json::to_string(&data).unwrap_or_default();
+            // A hook that ignores its stdin may exit before reading it
+            let _ = stdin.write_all(format!("{}\n", json).as_bytes()).await;
+        }
+        match child.wait().await {
+            Ok(status) if !status.success() => warn!("{} hook exited with {}", data.event, status),
+            Ok(_) => {}
+            Err(e) => warn!("Failed to wait for {} hook: {}", data.event, e),
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn user(user_id: &str, position: i32) -> UserState {
+        let mut user = UserState::new(user_id.to_string());
+        user.playlist_position = position;
+        user
+    }
+    
+    #[test]
+    fn test_everyone_synced_fires_once_per_meeting() {
+        let runner = HookRunner::default();
+        let mut session = SessionState::new();
+        session.update_user(user("alice", 3));
+        assert!(!runner.check_synced(&session)); // Alone isn't together
+        
+        session.update_user(user("bob", 2));
+        assert!(!runner.check_synced(&session));
+        session.update_user(user("bob", 3));
+        assert!(runner.check_synced(&session));
+        assert!(!runner.check_synced(&session));
+        
+        session.update_user(user("alice", 4));
+        assert!(!runner.check_synced(&session));
+        session.update_user(user("bob", 4));
+        assert!(runner.check_synced(&session));
+    }
+    
+    #[test]
+    fn test_hook_data() {
+        let mut session = SessionState::new();
+        session.update_user(user("alice", 3));
+        let data = HookData::new(HookEvent::PageChange, &user("alice", 3), &session);
+        assert_eq!(data.page, 4);
+        assert!(data.env().contains(&("SYNCREAD_EVENT", "page-change".to_string())));
+        
+        let hooks: Hooks = toml::from_str("on_finish = \"notify-send done\"\non_join = \" \"\n").unwrap();
+        assert_eq!(hooks.command(HookEvent::Finish), Some("notify-send done"));
+        assert_eq!(hooks.command(HookEvent::Join), None);
+        assert!(toml::from_str::<Hooks>("on_start = \"x\"\n").is_err());
+    }
+}
@@ -0,0 +1,140 @@
+use super::protocol::SessionState;
+use anyhow::{Context, Result};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::RwLock;
+use tokio::time::{timeout, Duration};
+use tracing::{debug, info, warn};
+
+/// How long a client may take to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Request heads larger than this are refused
+const MAX_HEADER_LINES: usize = 100;
+
+/// Serve read-only session status over HTTP until the server stops
+///
+/// `GET /session` returns the whole `SessionState`, `GET /users` just the
+/// users sorted by id. Responses allow any origin so browser overlays and
+/// dashboards can poll them.
+pub async fn serve(addr: SocketAddr, session_state: Arc<RwLock<SessionState>>) -> Result<()> {
+    let listener = TcpListener::bind(addr).await
+        .with_context(|| format!("Failed to bind HTTP status API to {}", addr))?;
+    info!("HTTP status API on http://{}/session", addr);
+    
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let session_state = session_state.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, &session_state).await {
+                debug!("HTTP request from {} failed: {}", peer, e);
+            }
+        });
+    }
+}
+
+/// Answer a single request, then close the connection
+async fn handle_connection(stream: TcpStream, session_state: &RwLock<SessionState>) -> Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    
+    let request_line = timeout(REQUEST_TIMEOUT, async {
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line).await?;
+        
+        // Headers are not needed; read past them so the client sees a clean close
+        let mut header = String::new();
+        for _ in 0..MAX_HEADER_LINES {
+            header.clear();
+            if reader.read_line(&mut header).await? == 0 || header.trim().is_empty() {
+                return Ok(request_line);
+            }
+        }
+        anyhow::bail!("request header too large")
+    }).await.context("Timed out reading HTTP request")??;
+    
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    
+    let (status, body) = {
+        let session = session_state.read().await;
+        route(method, target, &session)
+    };
+    if status != 200 {
+        warn!("HTTP status API: {} {} -> {}", method, target, status);
+    }
+    
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nAccess-Control-Allow-Origin: *\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason_phrase(status),
+        body.len(),
+        body
+    );
+    writer.write_all(response.as_bytes()).await?;
+    writer.shutdown().await?;
+    Ok(())
+}
+
+/// Status code and JSON body for a request
+fn route(method: &str, target: &str, session: &SessionState) -> (u16, String) {
+    // Query strings (e.g. cache busters from overlays) are ignored
+    let path = target.split('?').next().unwrap_or("");
+    
+    if method != "GET" {
+        return (405, error_body("only GET is supported"));
+    }
+    
+    let body = match path.trim_end_matches('/') {
+        "/session" => serde_json::to_string(session),
+        "/users" => serde_json::to_string(&session.get_users_sorted()),
+        _ => return (404, error_body("try /session or /users")),
+    };
+    
+    match body {
+        Ok(body) => (200, body),
+        Err(e) => (500, error_body(&e.to_string())),
+    }
+}
+
+fn error_body(message: &str) -> String {
+    serde_json::json!({ "error": message }).to_string()
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Internal Server Error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::protocol::UserState;
+    
+    #[test]
+    fn test_route() {
+        let mut session = SessionState::new();
+        session.update_user(UserState::new("bob".to_string()));
+        session.update_user(UserState::new("alice".to_string()));
+        
+        let (status, body) = route("GET", "/users?t=1", &session);
+        assert_eq!(status, 200);
+        let users: Vec<UserState> = serde_json::from_str(&body).unwrap();
+        assert_eq!(users[0].user_id, "alice");
+        assert_eq!(users[1].user_id, "bob");
+        
+        let (status, body) = route("GET", "/session/", &session);
+        assert_eq!(status, 200);
+        let restored: SessionState = serde_json::from_str(&body).unwrap();
+        assert_eq!(restored.users.len(), 2);
+        
+        assert_eq!(route("GET", "/", &session).0, 404);
+        assert_eq!(route("POST", "/session", &session).0, 405);
+    }
+}
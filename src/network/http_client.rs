@@ -0,0 +1,130 @@
+//! Just enough of an HTTP client for webhooks, the chat bridge and UPnP routers
+//!
+//! Requests go through one shared `reqwest` client, so both `http://` and
+//! `https://` (rustls, with the bundled web PKI roots) work.
+
+use anyhow::{Context, Result};
+use reqwest::{Client, Method, RequestBuilder, Url};
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::OnceLock;
+
+/// Responses larger than this are refused
+const MAX_RESPONSE_SIZE: usize = 4 * 1024 * 1024;
+
+/// An `http://` or `https://` URL
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpUrl {
+    url: Url,
+}
+
+impl HttpUrl {
+    pub fn parse(url: &str) -> Result<Self> {
+        let parsed = Url::parse(url).with_context(|| format!("{} is not a URL", url))?;
+        anyhow::ensure!(matches!(parsed.scheme(), "http" | "https"), "{} is not an http:// or https:// URL", url);
+        anyhow::ensure!(parsed.host().is_some(), "No host in {}", url);
+        Ok(Self { url: parsed })
+    }
+    
+    /// The same server with another path (and query)
+    pub fn with_path(&self, path: &str) -> Self {
+        let (path, query) = path.split_once('?').map_or((path, None), |(path, query)| (path, Some(query)));
+        let mut url = self.url.clone();
+        url.set_path(&format!("{}{}", self.url.path().trim_end_matches('/'), path));
+        url.set_query(query);
+        Self { url }
+    }
+    
+    /// The host, if it's an address rather than a name
+    pub fn ip(&self) -> Option<IpAddr> {
+        // IPv6 hosts come in brackets
+        self.url.host_str()?.trim_start_matches('[').trim_end_matches(']').parse().ok()
+    }
+}
+
+impl fmt::Display for HttpUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.url.as_str())
+    }
+}
+
+/// Status code and body of a response
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub body: String,
+}
+
+impl Response {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+fn client() -> &'static Client {
+    static CLIENT: OnceLock<Client> = OnceLock::new();
+    CLIENT.get_or_init(|| Client::builder().user_agent("syncread").build().expect("HTTP client with default settings"))
+}
+
+/// Send one request with an optional `Authorization` header and JSON body, and read the whole response
+pub async fn request(method: &str, url: &HttpUrl, authorization: Option<&str>, json: Option<&str>) -> Result<Response> {
+    let method = Method::from_bytes(method.as_bytes()).with_context(|| format!("Bad HTTP method {}", method))?;
+    let mut request = client().request(method, url.url.clone());
+    if let Some(authorization) = authorization {
+        request = request.header(reqwest::header::AUTHORIZATION, authorization);
+    }
+    if let Some(json) = json {
+        request = request.header(reqwest::header::CONTENT_TYPE, "application/json").body(json.to_string());
+    }
+    send(request, url).await
+}
+
+/// POST a SOAP `action` with its XML envelope, and read the whole response
+pub async fn soap(url: &HttpUrl, action: &str, xml: &str) -> Result<Response> {
+    let request = client().post(url.url.clone())
+        .header(reqwest::header::CONTENT_TYPE, "text/xml; charset=\"utf-8\"")
+        .header("SOAPAction", action)
+        .body(xml.to_string());
+    send(request, url).await
+}
+
+async fn send(request: RequestBuilder, url: &HttpUrl) -> Result<Response> {
+    let mut response = request.send().await.with_context(|| format!("Failed to reach {}", url))?;
+    let status = response.status().as_u16();
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        body.extend_from_slice(&chunk);
+        anyhow::ensure!(body.len() <= MAX_RESPONSE_SIZE, "Response from {} is too large", url);
+    }
+    Ok(Response { status, body: String::from_utf8_lossy(&body).into_owned() })
+}
+
+/// Percent-encode everything but unreserved characters, for a path segment or query value
+pub fn encode_component(value: &str) -> String {
+    value.bytes().map(|b| match b {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+        _ => format!("%{:02X}", b),
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_parse_url() {
+        assert_eq!(HttpUrl::parse("https://discord.com/api/webhooks/1?wait=true").unwrap().to_string(), "https://discord.com/api/webhooks/1?wait=true");
+        assert_eq!(HttpUrl::parse("http://[::1]:8000/x").unwrap().to_string(), "http://[::1]:8000/x");
+        assert_eq!(HttpUrl::parse("http://localhost:8008/").unwrap().with_path("/_matrix/x?y=1").to_string(), "http://localhost:8008/_matrix/x?y=1");
+        assert_eq!(HttpUrl::parse("https://example.org/matrix").unwrap().with_path("/_matrix/x").to_string(), "https://example.org/matrix/_matrix/x");
+        assert_eq!(HttpUrl::parse("http://[::1]:8000/x").unwrap().ip(), Some("::1".parse().unwrap()));
+        assert_eq!(HttpUrl::parse("http://192.168.1.1/ctl").unwrap().ip(), Some("192.168.1.1".parse().unwrap()));
+        assert_eq!(HttpUrl::parse("http://router.local/ctl").unwrap().ip(), None);
+        
+        assert!(HttpUrl::parse("ftp://relay.local/hook").is_err());
+        assert!(HttpUrl::parse("relay.local/hook").is_err());
+        assert!(HttpUrl::parse("http://relay.local:port/").is_err());
+        
+        assert_eq!(encode_component("!room:example.org"), "%21room%3Aexample.org");
+    }
+}
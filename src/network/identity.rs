@@ -0,0 +1,236 @@
+//! Keys that prove a user is who they say (`--identity`, `--pin-keys`)
+//!
+//! Each client can keep an Ed25519 key and sign its user id, together with
+//! the nonce the server challenged its connection with, into the `Hello`.
+//! A server with a pin file remembers the key each user id first
+//! proved itself with and turns away anyone claiming that id without it,
+//! in this session and later ones.
+
+use super::compression::{base64_decode, base64_encode};
+use super::protocol::UserId;
+use crate::storage;
+use anyhow::{Context, Result};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// This machine's key pair
+pub struct Identity {
+    key: SigningKey,
+}
+
+impl Identity {
+    /// Where the key is kept unless told otherwise
+    pub fn default_path() -> PathBuf {
+        storage::data_dir().join("identity.key")
+    }
+    
+    /// Read the key at `path`, generating one the first time
+    pub fn load_or_create(path: &Path) -> Result<Self> {
+        if path.exists() {
+            let hex = fs::read_to_string(path).with_context(|| format!("Failed to read identity key {:?}", path))?;
+            let seed = parse_hex(hex.trim()).with_context(|| format!("{:?} is not an identity key", path))?;
+            return Ok(Self::from_seed(seed));
+        }
+        
+        let mut seed = [0u8; 32];
+        getrandom::getrandom(&mut seed).map_err(|e| anyhow::anyhow!("No randomness for an identity key: {}", e))?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).with_context(|| format!("Failed to create directory {:?}", parent))?;
+        }
+        let hex: String = seed.iter().map(|b| format!("{:02x}", b)).collect();
+        fs::write(path, hex + "\n").with_context(|| format!("Failed to write identity key {:?}", path))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        }
+        let identity = Self::from_seed(seed);
+        info!("Created identity key {:?} ({})", path, identity.public_key());
+        Ok(identity)
+    }
+    
+    fn from_seed(seed: [u8; 32]) -> Self {
+        Self { key: SigningKey::from_bytes(&seed) }
+    }
+    
+    /// The public half, as servers pin it
+    pub fn public_key(&self) -> String {
+        base64_encode(self.key.verifying_key().as_bytes())
+    }
+    
+    /// Sign `user_id` and the server's `challenge` for a `Hello`
+    pub fn prove(&self, user_id: &str, challenge: &str) -> IdentityProof {
+        let signature = self.key.sign(&IdentityProof::signed_bytes(user_id, challenge));
+        IdentityProof {
+            user_id: user_id.to_string(),
+            public_key: self.public_key(),
+            signature: base64_encode(&signature.to_bytes()),
+        }
+    }
+}
+
+/// A fresh nonce for a connection's `Challenge`, so a proof only counts on the connection it was made for
+pub fn challenge() -> Result<String> {
+    let mut nonce = [0u8; 32];
+    getrandom::getrandom(&mut nonce).map_err(|e| anyhow::anyhow!("No randomness for a challenge: {}", e))?;
+    Ok(base64_encode(&nonce))
+}
+
+/// A user id signed with its owner's key, sent in `Hello`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct IdentityProof {
+    pub user_id: UserId,
+    pub public_key: String, // Base64
+    pub signature: String, // Base64
+}
+
+impl IdentityProof {
+    fn signed_bytes(user_id: &str, challenge: &str) -> Vec<u8> {
+        let mut bytes = b"syncread-hello-v2\0".to_vec();
+        bytes.extend_from_slice(user_id.as_bytes());
+        bytes.push(0);
+        bytes.extend_from_slice(challenge.as_bytes());
+        bytes
+    }
+    
+    /// Check the signature, and that it answers `challenge`
+    pub fn verify(&self, challenge: &str) -> Result<()> {
+        let public: [u8; 32] = base64_decode(&self.public_key)?.try_into()
+            .map_err(|_| anyhow::anyhow!("malformed public key"))?;
+        let public = VerifyingKey::from_bytes(&public).context("malformed public key")?;
+        let signature: [u8; 64] = base64_decode(&self.signature)?.try_into()
+            .map_err(|_| anyhow::anyhow!("malformed signature"))?;
+        public.verify_strict(&Self::signed_bytes(&self.user_id, challenge), &Signature::from_bytes(&signature))
+            .context("bad signature")?;
+        Ok(())
+    }
+}
+
+fn parse_hex(hex: &str) -> Option<[u8; 32]> {
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+    let bytes: Option<Vec<u8>> = (0..64).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok()).collect();
+    bytes?.try_into().ok()
+}
+
+/// The key each user id has signed in with, optionally backed by a file ("<key> <user id>" per line)
+#[derive(Debug, Clone, Default)]
+pub struct KeyPins {
+    keys: BTreeMap<UserId, String>,
+    path: Option<PathBuf>,
+    known_only: bool, // Turn away user ids that aren't pinned yet
+}
+
+impl KeyPins {
+    /// Load a pin file, creating an empty set if it doesn't exist yet
+    pub fn load(path: PathBuf, known_only: bool) -> Result<Self> {
+        let mut keys = BTreeMap::new();
+        if path.exists() {
+            let content = fs::read_to_string(&path).with_context(|| format!("Failed to read key pins {:?}", path))?;
+            for line in content.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+                let (key, user_id) = line.split_once(' ').with_context(|| format!("Expected \"<key> <user id>\" in {:?}: {}", path, line))?;
+                keys.insert(user_id.trim().to_string(), key.to_string());
+            }
+        }
+        
+        info!("Loaded {} pinned keys from {:?}", keys.len(), path);
+        Ok(Self { keys, path: Some(path), known_only })
+    }
+    
+    /// Whether `user_id` may join with the key it proved (if any), pinning keys seen for the first time
+    ///
+    /// The error is the reason given to the client.
+    pub fn admit(&mut self, user_id: &str, proof: Option<&IdentityProof>) -> Result<(), String> {
+        let key = proof.filter(|proof| proof.user_id == user_id).map(|proof| proof.public_key.as_str());
+        match (self.keys.get(user_id), key) {
+            (Some(pinned), Some(key)) if pinned == key => Ok(()),
+            (Some(_), Some(_)) => Err(format!("{} signs in with a different key", user_id)),
+            (Some(_), None) => Err(format!("{} has to sign in with their key (--identity)", user_id)),
+            (None, _) if self.known_only => Err(format!("{} is not on this server's list of known users", user_id)),
+            (None, None) => Ok(()),
+            (None, Some(key)) => {
+                info!("Pinned {} to key {}", user_id, key);
+                self.keys.insert(user_id.to_string(), key.to_string());
+                if let Err(e) = self.save() {
+                    warn!("{:#}", e);
+                }
+                Ok(())
+            }
+        }
+    }
+    
+    fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        
+        let mut content = String::from("# SyncRead key pins: a user's public key, then their user id\n");
+        for (user_id, key) in &self.keys {
+            content.push_str(&format!("{} {}\n", key, user_id));
+        }
+        
+        fs::write(path, content)
+            .with_context(|| format!("Failed to write key pins {:?}", path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn identity(byte: u8) -> Identity {
+        Identity::from_seed([byte; 32])
+    }
+    
+    #[test]
+    fn test_proof() {
+        let (ours, theirs) = (challenge().unwrap(), challenge().unwrap());
+        assert_ne!(ours, theirs);
+        
+        let proof = identity(1).prove("alice", &ours);
+        assert!(proof.verify(&ours).is_ok());
+        // Replayed on another connection
+        assert!(proof.verify(&theirs).is_err());
+        assert!(IdentityProof { user_id: "mallory".into(), ..proof.clone() }.verify(&ours).is_err());
+        assert!(IdentityProof { public_key: identity(2).public_key(), ..proof.clone() }.verify(&ours).is_err());
+    }
+    
+    #[test]
+    fn test_pins() {
+        let (alice, mallory) = (identity(1), identity(2));
+        let mut pins = KeyPins::default();
+        
+        // First come, first pinned
+        assert!(pins.admit("alice", Some(&alice.prove("alice", "nonce"))).is_ok());
+        assert!(pins.admit("alice", Some(&alice.prove("alice", "nonce"))).is_ok());
+        assert!(pins.admit("alice", Some(&mallory.prove("alice", "nonce"))).is_err());
+        assert!(pins.admit("alice", None).is_err());
+        // A proof for another id doesn't count
+        assert!(pins.admit("alice", Some(&alice.prove("bob", "nonce"))).is_err());
+        
+        // Unpinned users without a key still get in, unless only known users may
+        assert!(pins.admit("bob", None).is_ok());
+        pins.known_only = true;
+        assert!(pins.admit("carol", Some(&mallory.prove("carol", "nonce"))).is_err());
+        assert!(pins.admit("alice", Some(&alice.prove("alice", "nonce"))).is_ok());
+    }
+    
+    #[test]
+    fn test_pin_file() {
+        let path = std::env::temp_dir().join(format!("syncread_test_pins_{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let alice = identity(1);
+        
+        let mut pins = KeyPins::load(path.clone(), false).unwrap();
+        pins.admit("bob (matrix)", Some(&alice.prove("bob (matrix)", "nonce"))).unwrap();
+        let mut reloaded = KeyPins::load(path.clone(), true).unwrap();
+        let _ = fs::remove_file(&path);
+        assert!(reloaded.admit("bob (matrix)", Some(&alice.prove("bob (matrix)", "nonce"))).is_ok());
+        assert!(reloaded.admit("bob (matrix)", None).is_err());
+    }
+}
@@ -0,0 +1,259 @@
+//! Keys pressed in the client's terminal: `f` follow, `j` jump to, `c` chat, `q` quit
+//!
+//! The terminal is otherwise only a display, so these save switching to
+//! MPV for things that take typing. Commands go down the same channel as
+//! the control socket's; prompts are typed on the line under the display.
+
+use super::headless::HeadlessCommand;
+use crate::terminal::{self, RawMode};
+use crossterm::cursor::MoveToColumn;
+use crossterm::style::Print;
+use crossterm::terminal::{Clear, ClearType};
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+use tracing::debug;
+
+/// The keys, for the display's footer
+pub const HELP: &str = "Keys: f follow, j jump to, c chat, q quit";
+
+/// One keypress, as far as prompts care
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Escape,
+    Interrupt, // Ctrl+C, which raw mode hands us instead of a signal
+}
+
+/// What a prompt is asking for
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Asking {
+    Chat,
+    JumpTo,
+    Follow,
+}
+
+impl Asking {
+    fn label(self) -> String {
+        match self {
+            Asking::Chat => format!("{} Say: ", terminal::decoration("💬")),
+            Asking::JumpTo => "Jump to (name or number): ".to_string(),
+            Asking::Follow => "Follow (name or number, empty for everyone): ".to_string(),
+        }
+    }
+    
+    /// The command for what was typed, or None to do nothing
+    fn finish(self, text: &str) -> Option<HeadlessCommand> {
+        let text = text.trim();
+        match self {
+            Asking::Follow => Some(HeadlessCommand::Follow((!text.is_empty()).then(|| text.to_string()))),
+            _ if text.is_empty() => None,
+            Asking::Chat => Some(HeadlessCommand::Chat(text.to_string())),
+            Asking::JumpTo => Some(HeadlessCommand::JumpTo(text.to_string())),
+        }
+    }
+}
+
+/// Turns keys into commands, holding on to whatever is being typed into a prompt
+#[derive(Debug, Default)]
+pub struct KeyboardInput {
+    prompt: Option<(Asking, String)>,
+}
+
+impl KeyboardInput {
+    pub fn press(&mut self, key: Key) -> Option<HeadlessCommand> {
+        if key == Key::Interrupt {
+            return Some(HeadlessCommand::Quit);
+        }
+        let Some((asking, text)) = &mut self.prompt else {
+            let asking = match key {
+                Key::Char('q') => return Some(HeadlessCommand::Quit),
+                Key::Char('c') => Asking::Chat,
+                Key::Char('j') => Asking::JumpTo,
+                Key::Char('f') => Asking::Follow,
+                _ => return None,
+            };
+            self.prompt = Some((asking, String::new()));
+            return None;
+        };
+        
+        match key {
+            Key::Char(c) if !c.is_control() => text.push(c),
+            Key::Char(_) => {}
+            Key::Backspace => {
+                text.pop();
+            }
+            Key::Escape => self.prompt = None,
+            Key::Interrupt => {}
+            Key::Enter => {
+                let command = asking.finish(text);
+                self.prompt = None;
+                return command;
+            }
+        }
+        None
+    }
+    
+    /// The prompt as typed so far, if one is open
+    pub fn prompt_line(&self) -> Option<String> {
+        self.prompt.as_ref().map(|(asking, text)| format!("{}{}", asking.label(), text))
+    }
+}
+
+/// Keys read from the terminal while the client runs; dropping it gives the terminal back
+pub struct Keyboard {
+    input: Arc<Mutex<KeyboardInput>>,
+    _raw: RawMode,
+}
+
+impl Keyboard {
+    /// Start reading keys into `commands`, or None if stdin isn't a terminal
+    pub fn spawn(commands: mpsc::UnboundedSender<HeadlessCommand>) -> Option<Self> {
+        let raw = RawMode::enable()?;
+        let input = Arc::new(Mutex::new(KeyboardInput::default()));
+        let reader_input = input.clone();
+        
+        // Reads block, so they get a thread of their own; it goes with the process
+        std::thread::spawn(move || {
+            let mut keys = Keys::default();
+            let mut stdin = std::io::stdin();
+            let mut byte = [0u8; 1];
+            while let Ok(1) = stdin.read(&mut byte) {
+                let Some(key) = keys.decode(byte[0]) else {
+                    continue;
+                };
+                let (command, prompt) = {
+                    let mut input = reader_input.lock().unwrap();
+                    (input.press(key), input.prompt_line())
+                };
+                redraw_prompt(prompt.as_deref());
+                if let Some(command) = command {
+                    debug!("Terminal command: {:?}", command);
+                    if commands.send(command).is_err() {
+                        break; // The session is over
+                    }
+                }
+            }
+        });
+        
+        Some(Self { input, _raw: raw })
+    }
+    
+    /// What the keys typed so far, for drawing the prompt under the display
+    pub fn input(&self) -> Arc<Mutex<KeyboardInput>> {
+        self.input.clone()
+    }
+}
+
+/// Rewrite the line the cursor is on with the prompt, or blank it once the prompt is gone
+fn redraw_prompt(prompt: Option<&str>) {
+    let mut stdout = std::io::stdout();
+    let line = prompt.unwrap_or("");
+    if terminal::ansi_enabled() {
+        let _ = crossterm::queue!(stdout, MoveToColumn(0), Clear(ClearType::CurrentLine), Print(line));
+    } else {
+        // Spaces cover a character just deleted
+        let _ = write!(stdout, "\r{}  \r{}", line, line);
+    }
+    let _ = stdout.flush();
+}
+
+/// Assembles keys from the bytes a terminal sends
+#[derive(Debug, Default)]
+struct Keys {
+    pending: Vec<u8>, // The start of a multi-byte character
+    escape: Escape,
+}
+
+/// Where we are in an escape sequence (arrow keys and the like), which are skipped
+#[derive(Debug, Default, PartialEq)]
+enum Escape {
+    #[default]
+    None,
+    Started, // Escape was pressed, or a sequence begins
+    Sequence, // Until a letter or `~` ends it
+}
+
+impl Keys {
+    fn decode(&mut self, byte: u8) -> Option<Key> {
+        match self.escape {
+            Escape::Started if byte == b'[' || byte == b'O' => {
+                self.escape = Escape::Sequence;
+                return None;
+            }
+            Escape::Sequence => {
+                if byte.is_ascii_alphabetic() || byte == b'~' {
+                    self.escape = Escape::None;
+                }
+                return None;
+            }
+            _ => self.escape = Escape::None,
+        }
+        
+        match byte {
+            0x1b => {
+                self.escape = Escape::Started;
+                Some(Key::Escape)
+            }
+            0x03 => Some(Key::Interrupt),
+            b'\r' | b'\n' => Some(Key::Enter),
+            0x08 | 0x7f => Some(Key::Backspace),
+            _ => {
+                self.pending.push(byte);
+                match std::str::from_utf8(&self.pending) {
+                    Ok(text) => {
+                        let key = text.chars().next().map(Key::Char);
+                        self.pending.clear();
+                        key
+                    }
+                    Err(e) if e.error_len().is_none() => None, // More bytes to come
+                    Err(_) => {
+                        self.pending.clear();
+                        None
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn press_all(input: &mut KeyboardInput, keys: &str) -> Vec<HeadlessCommand> {
+        keys.chars()
+            .filter_map(|c| input.press(if c == '\n' { Key::Enter } else { Key::Char(c) }))
+            .collect()
+    }
+    
+    #[test]
+    fn test_prompts() {
+        let mut input = KeyboardInput::default();
+        assert_eq!(press_all(&mut input, "cHi all\n"), [HeadlessCommand::Chat("Hi all".into())]);
+        assert_eq!(press_all(&mut input, "j2\nf\n"), [HeadlessCommand::JumpTo("2".into()), HeadlessCommand::Follow(None)]);
+        
+        // Keys typed into a prompt aren't commands, and Escape drops the prompt
+        press_all(&mut input, "cqx");
+        assert_eq!(input.prompt_line().as_deref(), Some("💬 Say: qx"));
+        input.press(Key::Backspace);
+        assert_eq!(input.prompt_line().as_deref(), Some("💬 Say: q"));
+        input.press(Key::Escape);
+        assert_eq!(input.prompt_line(), None);
+        assert_eq!(press_all(&mut input, "c\nxq"), [HeadlessCommand::Quit]);
+        
+        // Ctrl+C quits even from a prompt
+        press_all(&mut input, "cq");
+        assert_eq!(input.press(Key::Interrupt), Some(HeadlessCommand::Quit));
+    }
+    
+    #[test]
+    fn test_decode_keys() {
+        let mut keys = Keys::default();
+        let decoded: Vec<Key> = "né\x1b[A\x1bq\r\x03".bytes().filter_map(|byte| keys.decode(byte)).collect();
+        // The up arrow only counts as Escape, and a key after Escape still counts
+        assert_eq!(decoded, [Key::Char('n'), Key::Char('é'), Key::Escape, Key::Escape, Key::Char('q'), Key::Enter, Key::Interrupt]);
+    }
+}
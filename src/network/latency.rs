@@ -0,0 +1,133 @@
+//! Round-trip times measured with heartbeat pings
+//!
+//! A ping carries the sender's clock and the pong echoes it back, so the
+//! round trip is measured on one clock and skew between machines doesn't matter.
+//! The pong also carries the other end's clock, which gives the offset
+//! between the two clocks the same way NTP does.
+
+use tokio::time::Duration;
+
+/// How often each end of a connection pings the other
+pub const PING_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Weight of each new sample in the smoothed round trip (as in TCP's SRTT)
+const SMOOTHING: f64 = 0.125;
+
+/// Samples above this come from a stalled connection, not the link's usual speed
+const MAX_SAMPLE_MS: u64 = 30_000;
+
+/// Smoothed round-trip time of one connection
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RoundTrip {
+    smoothed_ms: Option<f64>,
+}
+
+impl RoundTrip {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Account for a pong echoing our `sent_at_ms` arriving at `now_ms`
+    pub fn observe(&mut self, sent_at_ms: u64, now_ms: u64) {
+        let Some(sample) = now_ms.checked_sub(sent_at_ms).filter(|&ms| ms <= MAX_SAMPLE_MS) else {
+            return;
+        };
+        let sample = sample as f64;
+        self.smoothed_ms = Some(match self.smoothed_ms {
+            Some(smoothed) => smoothed + SMOOTHING * (sample - smoothed),
+            None => sample,
+        });
+    }
+    
+    /// Round trip in milliseconds, once a pong has arrived
+    pub fn get(&self) -> Option<u64> {
+        self.smoothed_ms.map(|ms| ms.round() as u64)
+    }
+}
+
+/// Pings exchanged while connecting to estimate the offset to the server's clock
+pub const CLOCK_SAMPLES: usize = 5;
+
+/// Offset between our clock and another machine's, from timed pings
+///
+/// The other end read its clock somewhere between our ping leaving and the
+/// pong arriving, assumed to be halfway. The sample with the quickest round
+/// trip is kept, since queueing delay is what makes that assumption wrong.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ClockOffset {
+    best: Option<(u64, i64)>, // (round trip, offset) of the quickest sample
+}
+
+impl ClockOffset {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Account for a pong to a ping sent at `sent_at_ms`, stamped `remote_ms` by the other end, arriving at `now_ms`
+    pub fn observe(&mut self, sent_at_ms: u64, remote_ms: u64, now_ms: u64) {
+        let Some(round_trip) = now_ms.checked_sub(sent_at_ms).filter(|&ms| ms <= MAX_SAMPLE_MS) else {
+            return;
+        };
+        if self.best.is_some_and(|(best, _)| best <= round_trip) {
+            return;
+        }
+        let midpoint = sent_at_ms + round_trip / 2;
+        self.best = Some((round_trip, remote_ms as i64 - midpoint as i64));
+    }
+    
+    /// Milliseconds the other clock is ahead of ours, once a pong has arrived
+    pub fn get(&self) -> Option<i64> {
+        self.best.map(|(_, offset)| offset)
+    }
+    
+    /// Most the estimate can be off by: half the round trip it came from
+    pub fn uncertainty(&self) -> Option<u64> {
+        self.best.map(|(round_trip, _)| round_trip / 2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_round_trip_smoothing() {
+        let mut rtt = RoundTrip::new();
+        assert_eq!(rtt.get(), None);
+        
+        rtt.observe(1_000, 1_040);
+        assert_eq!(rtt.get(), Some(40));
+        
+        // One slow pong moves the estimate only a little
+        rtt.observe(2_000, 2_360);
+        assert_eq!(rtt.get(), Some(80));
+        
+        rtt.observe(3_000, 2_990); // Clock stepped back
+        rtt.observe(4_000, 60_000); // Stalled
+        assert_eq!(rtt.get(), Some(80));
+    }
+    
+    #[test]
+    fn test_clock_offset_prefers_quickest_sample() {
+        let mut offset = ClockOffset::new();
+        assert_eq!(offset.get(), None);
+        
+        // Their clock is 10s ahead; the first pong sat in a queue on the way back
+        offset.observe(1_000, 11_020, 1_300);
+        assert_eq!(offset.get(), Some(9_870));
+        
+        offset.observe(2_000, 12_020, 2_040);
+        assert_eq!(offset.get(), Some(10_000));
+        assert_eq!(offset.uncertainty(), Some(20));
+        
+        // Slower samples don't replace it, whichever way they're skewed
+        offset.observe(3_000, 13_150, 3_200);
+        offset.observe(4_000, 14_000, 3_900);
+        assert_eq!(offset.get(), Some(10_000));
+        
+        // A machine behind us gives a negative offset
+        let mut behind = ClockOffset::new();
+        behind.observe(50_000, 20_005, 50_010);
+        assert_eq!(behind.get(), Some(-30_000));
+    }
+}
@@ -1,7 +1,48 @@
+pub mod admin;
+pub mod appearance;
+pub mod bridge;
+pub mod cadence;
+pub mod checksums;
+pub mod compression;
+pub mod control;
+pub mod discovery;
+pub mod encryption;
+pub mod event_log;
+pub mod file_share;
+pub mod glitch;
+pub mod headless;
+pub mod hooks;
+pub mod http_api;
+pub mod http_client;
+pub mod identity;
+pub mod keyboard;
+pub mod latency;
+pub mod moderation;
+pub mod p2p;
+pub mod page_map;
+pub mod port_mapping;
+pub mod properties;
 pub mod protocol;
+pub mod rate_limit;
+pub mod reliable;
+pub mod slideshow;
+pub mod stats;
+pub mod summary;
 pub mod sync_client;
 pub mod sync_server;
+pub mod systemd;
+pub mod template;
+pub mod text_width;
+pub mod time_sync;
+pub mod webhook;
+pub mod wire;
 
-pub use protocol::{SyncMessage, SyncEvent, UserState};
+pub use admin::{request_session, request_stats, send_admin_command};
+pub use discovery::DiscoveredServer;
+pub use headless::{HeadlessCommand, HeadlessInput};
+pub use moderation::{AllowList, BanList, DuplicateIds};
+pub use p2p::PeerNode;
+pub use protocol::{AdminCommand, SyncMessage, SyncEvent, UserState};
+pub use stats::{ReadingStats, StatsTracker};
 pub use sync_client::SyncClient;
 pub use sync_server::SyncServer;
@@ -0,0 +1,171 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeSet;
+use std::fs;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+/// Banned user ids and IP addresses, optionally backed by a file (one entry per line)
+#[derive(Debug, Clone, Default)]
+pub struct BanList {
+    entries: BTreeSet<String>,
+    path: Option<PathBuf>,
+}
+
+impl BanList {
+    /// Load a ban list file, creating an empty list if it doesn't exist yet
+    pub fn load(path: PathBuf) -> Result<Self> {
+        let entries = if path.exists() {
+            fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read ban list {:?}", path))?
+                .lines()
+                .map(|line| line.trim())
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(|line| line.to_string())
+                .collect()
+        } else {
+            BTreeSet::new()
+        };
+        
+        info!("Loaded {} bans from {:?}", entries.len(), path);
+        Ok(Self { entries, path: Some(path) })
+    }
+    
+    /// Check whether a user id or its connection address is banned
+    pub fn is_banned(&self, user_id: &str, ip: IpAddr) -> bool {
+        self.entries.contains(user_id) || self.entries.contains(&ip.to_string())
+    }
+    
+    /// Add a user id or IP address; returns false if it was already banned
+    pub fn ban(&mut self, target: &str) -> Result<bool> {
+        let added = self.entries.insert(target.to_string());
+        if added {
+            self.save()?;
+        }
+        Ok(added)
+    }
+    
+    /// Remove a ban; returns false if the target wasn't banned
+    pub fn unban(&mut self, target: &str) -> Result<bool> {
+        let removed = self.entries.remove(target);
+        if removed {
+            self.save()?;
+        }
+        Ok(removed)
+    }
+    
+    fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        
+        let mut content = String::from("# SyncRead ban list: one user id or IP address per line\n");
+        for entry in &self.entries {
+            content.push_str(entry);
+            content.push('\n');
+        }
+        
+        fs::write(path, content)
+            .with_context(|| format!("Failed to write ban list {:?}", path))
+    }
+}
+
+/// What the server does when someone joins under a user id that is already connected
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum DuplicateIds {
+    /// Disconnect the earlier connection, e.g. one a client left behind when its network dropped
+    #[default]
+    Replace,
+    /// Turn the newcomer away
+    Reject,
+    /// Let the newcomer in as "alice (2)"
+    Rename,
+}
+
+/// The first of "alice (2)", "alice (3)", ... that isn't `taken`
+pub fn free_user_id(user_id: &str, taken: impl Fn(&str) -> bool) -> String {
+    (2..)
+        .map(|n| format!("{} ({})", user_id, n))
+        .find(|candidate| !taken(candidate))
+        .unwrap()
+}
+
+/// Whether an admin token is the expected one
+///
+/// Both are hashed and the digests compared in constant time, so neither how
+/// much of a guess was right nor the token's length shows in the reply time.
+pub fn token_matches(expected: &str, given: &str) -> bool {
+    use subtle::ConstantTimeEq;
+    Sha256::digest(expected).ct_eq(&Sha256::digest(given)).into()
+}
+
+/// The only user ids allowed to join, from a TOML file (`users = ["alice", "bob"]`)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AllowList {
+    users: BTreeSet<String>,
+}
+
+impl AllowList {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read allowlist {:?}", path))?;
+        let list: Self = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse allowlist {:?}", path))?;
+        
+        info!("Allowing {} users from {:?}", list.users.len(), path);
+        Ok(list)
+    }
+    
+    pub fn allows(&self, user_id: &str) -> bool {
+        self.users.contains(user_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_ban_by_user_or_ip() {
+        let mut bans = BanList::default();
+        let ip: IpAddr = "10.0.0.5".parse().unwrap();
+        
+        assert!(!bans.is_banned("mallory", ip));
+        assert!(bans.ban("mallory").unwrap());
+        assert!(!bans.ban("mallory").unwrap());
+        assert!(bans.is_banned("mallory", "127.0.0.1".parse().unwrap()));
+        
+        bans.ban("10.0.0.5").unwrap();
+        assert!(bans.is_banned("someone-else", ip));
+        
+        assert!(bans.unban("10.0.0.5").unwrap());
+        assert!(!bans.is_banned("someone-else", ip));
+    }
+    
+    #[test]
+    fn test_free_user_id() {
+        let taken = ["alice", "alice (2)"];
+        assert_eq!(free_user_id("alice", |id| taken.contains(&id)), "alice (3)");
+        assert_eq!(free_user_id("bob", |id| taken.contains(&id)), "bob (2)");
+    }
+    
+    #[test]
+    fn test_token_matches() {
+        assert!(token_matches("s3cret", "s3cret"));
+        assert!(!token_matches("s3cret", "s3cre"));
+        assert!(!token_matches("s3cret", "s3cret!"));
+        assert!(!token_matches("s3cret", ""));
+    }
+    
+    #[test]
+    fn test_allow_list() {
+        let list: AllowList = toml::from_str("users = [\"alice\", \"bob (matrix)\"]\n").unwrap();
+        assert!(list.allows("alice"));
+        assert!(list.allows("bob (matrix)"));
+        assert!(!list.allows("mallory"));
+        assert!(toml::from_str::<AllowList>("user = [\"alice\"]\n").is_err());
+    }
+}
@@ -0,0 +1,437 @@
+use super::identity;
+use super::protocol::{PeerAddress, SequenceTracker, SyncEvent, SyncMessage, UserId, UserState};
+use super::reliable::DuplicateFilter;
+use super::wire::{self, Encoding, FrameReader, WireFormat};
+use crate::terminal;
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, Mutex, RwLock};
+use tokio::time::Duration;
+use tracing::{debug, info, warn};
+
+type PeerSender = mpsc::UnboundedSender<SyncMessage>;
+
+/// How long to wait before dialing a peer from the command line again
+const PEER_RETRY_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Bookmarks and notes kept for replay to peers who link up later
+const MAX_HISTORY: usize = 500;
+
+/// An open connection to another peer
+struct Link {
+    id: u64, // Tells a replaced link apart from its replacement when it closes
+    tx: PeerSender,
+    addr: SocketAddr, // Where the peer accepts connections
+    canonical: bool, // Dialed by whichever of the two has the smaller user id
+}
+
+/// Shared state handed to every link and the local client connection
+#[derive(Clone)]
+struct Mesh {
+    user_id: UserId,
+    port: u16, // Where we accept peers
+    links: Arc<RwLock<HashMap<UserId, Link>>>,
+    local_tx: PeerSender, // Queued until our own client connects
+    local_state: Arc<RwLock<Option<(UserState, u64)>>>, // Our client's latest state and its sequence
+    history: Arc<RwLock<Vec<SyncMessage>>>, // Our client's bookmarks, notes and links
+    sequences: Arc<RwLock<SequenceTracker>>, // Latest state update per peer
+    sequence_counter: Arc<AtomicU64>,
+}
+
+/// A serverless session, syncing our client directly with every other peer
+///
+/// Each peer links to every other one (a full mesh) and passes on only
+/// what its own client sends, so nothing is relayed twice. Our client
+/// connects to the node over loopback as if it were a server, which keeps
+/// MPV control exactly as in client mode. Linking to any one peer is
+/// enough: peers tell each other who they know, and whichever of two
+/// peers has the smaller user id dials the other.
+pub struct PeerNode {
+    user_id: UserId,
+    bind: SocketAddr,
+    peers: Vec<SocketAddr>,
+}
+
+impl PeerNode {
+    pub fn new(user_id: UserId, bind: SocketAddr) -> Self {
+        Self {
+            user_id,
+            bind,
+            peers: Vec::new(),
+        }
+    }
+    
+    /// Link to this peer on start, retrying until it answers
+    pub fn add_peer(&mut self, addr: SocketAddr) {
+        self.peers.push(addr);
+    }
+    
+    /// Start accepting peers and return the address our client should connect to
+    pub async fn start(self) -> Result<SocketAddr> {
+        let peer_listener = TcpListener::bind(self.bind).await
+            .with_context(|| format!("Failed to bind to {}", self.bind))?;
+        let local_listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+        let local_addr = local_listener.local_addr()?;
+        
+        let (local_tx, local_rx) = mpsc::unbounded_channel();
+        let mesh = Mesh {
+            user_id: self.user_id,
+            port: peer_listener.local_addr()?.port(),
+            links: Arc::new(RwLock::new(HashMap::new())),
+            local_tx,
+            local_state: Arc::new(RwLock::new(None)),
+            history: Arc::new(RwLock::new(Vec::new())),
+            sequences: Arc::new(RwLock::new(SequenceTracker::new())),
+            sequence_counter: Arc::new(AtomicU64::new(0)),
+        };
+        info!("Accepting peers on {}", self.bind);
+        
+        let accepting = mesh.clone();
+        tokio::spawn(async move {
+            loop {
+                match peer_listener.accept().await {
+                    Ok((stream, addr)) => {
+                        let mesh = accepting.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) = mesh.run_link(stream, addr, false).await {
+                                warn!("Link with {} failed: {}", addr, e);
+                            }
+                        });
+                    }
+                    Err(e) => warn!("Failed to accept a peer: {}", e),
+                }
+            }
+        });
+        
+        for addr in self.peers {
+            tokio::spawn(mesh.clone().keep_dialing(addr));
+        }
+        
+        // Only our own client ever connects here
+        let local_rx = Arc::new(Mutex::new(local_rx));
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = local_listener.accept().await {
+                if let Err(e) = mesh.serve_local(stream, local_rx.clone()).await {
+                    warn!("Local client connection failed: {}", e);
+                }
+            }
+        });
+        
+        Ok(local_addr)
+    }
+}
+
+impl Mesh {
+    fn next_sequence(&self) -> u64 {
+        self.sequence_counter.fetch_add(1, Ordering::Relaxed) + 1
+    }
+    
+    /// Stay linked to a peer given on the command line, redialing when the link drops
+    async fn keep_dialing(self, addr: SocketAddr) {
+        let mut peer: Option<UserId> = None;
+        loop {
+            // The peer may have linked to us first, or through gossip
+            let linked = match &peer {
+                Some(uid) => self.links.read().await.contains_key(uid),
+                None => false,
+            };
+            
+            if !linked {
+                match TcpStream::connect(addr).await {
+                    Ok(stream) => match self.run_link(stream, addr, true).await {
+                        Ok(uid) => peer = uid,
+                        Err(e) => warn!("Link with {} failed: {}", addr, e),
+                    },
+                    Err(e) => debug!("Couldn't reach peer {}: {}", addr, e),
+                }
+            }
+            tokio::time::sleep(PEER_RETRY_INTERVAL).await;
+        }
+    }
+    
+    /// Dial a peer we heard about from another one
+    fn dial(&self, peer: PeerAddress) {
+        let mesh = self.clone();
+        tokio::spawn(async move {
+            info!("Linking with {} at {}", peer.user_id, peer.addr);
+            let result = match TcpStream::connect(peer.addr).await {
+                Ok(stream) => mesh.run_link(stream, peer.addr, true).await.map(|_| ()),
+                Err(e) => Err(e.into()),
+            };
+            if let Err(e) = result {
+                warn!("Couldn't link with {} at {}: {}", peer.user_id, peer.addr, e);
+            }
+        });
+    }
+    
+    /// Exchange hellos with a peer, then pass messages until either side goes away
+    ///
+    /// Returns the peer's user id once the link has closed, or `None` if the
+    /// other end turned out to be ourselves.
+    async fn run_link(&self, stream: TcpStream, remote: SocketAddr, dialed: bool) -> Result<Option<UserId>> {
+        let wire = WireFormat::default(); // Peers always talk JSON lines
+        stream.set_nodelay(true)?;
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = FrameReader::new(reader);
+        
+        let hello = SyncEvent::PeerHello { user_id: self.user_id.clone(), port: self.port };
+        writer.write_all(&wire::encode(&SyncMessage::new(hello, self.next_sequence()), wire)?).await?;
+        
+        let frame = reader.next_frame().await?.context("Peer closed the connection")?;
+        let SyncEvent::PeerHello { user_id, port } = frame.decode()?.event else {
+            anyhow::bail!("{} is not a syncread peer", remote);
+        };
+        if user_id == self.user_id {
+            debug!("{} is ourselves", remote);
+            return Ok(None);
+        }
+        
+        let (tx, mut rx) = mpsc::unbounded_channel::<SyncMessage>();
+        let id = self.next_sequence();
+        let link = Link {
+            id,
+            tx: tx.clone(),
+            addr: SocketAddr::new(remote.ip(), port),
+            canonical: dialed == (self.user_id < user_id),
+        };
+        if !self.register(&user_id, link).await {
+            debug!("Already linked with {}, closing the extra connection", user_id);
+            return Ok(Some(user_id));
+        }
+        info!("{}Linked with {} ({})", terminal::decoration("🤝 "), user_id, remote);
+        
+        self.introduce(&tx).await;
+        self.share_peers().await;
+        
+        // Dropping the link's sender (when it is replaced or removed) ends this task and closes the connection
+        let mut writer_task = tokio::spawn(async move {
+            while let Some(message) = rx.recv().await {
+                let Ok(bytes) = wire::encode(&message, wire) else {
+                    continue;
+                };
+                if writer.write_all(&bytes).await.is_err() {
+                    break;
+                }
+            }
+        });
+        drop(tx);
+        
+        loop {
+            let frame = tokio::select! {
+                read = reader.next_frame() => match read {
+                    Ok(Some(frame)) => frame,
+                    Ok(None) => break,
+                    Err(e) => {
+                        debug!("Failed to read from {}: {}", user_id, e);
+                        break;
+                    }
+                },
+                _ = &mut writer_task => break,
+            };
+            
+            match frame.decode() {
+                Ok(message) => self.handle_peer_message(&user_id, message).await,
+                Err(e) => warn!("Failed to parse message from {}: {} - '{}'", user_id, e, frame.describe()),
+            }
+        }
+        
+        writer_task.abort();
+        self.unlink(&user_id, id).await;
+        Ok(Some(user_id))
+    }
+    
+    /// Keep one link per peer; returns false if this one is a duplicate
+    ///
+    /// Two peers may dial each other at once. Both keep the link dialed by
+    /// the smaller user id, so they agree on which one to close.
+    async fn register(&self, user_id: &UserId, link: Link) -> bool {
+        let mut links = self.links.write().await;
+        if let Some(existing) = links.get(user_id) {
+            if existing.canonical || !link.canonical {
+                return false;
+            }
+        }
+        links.insert(user_id.clone(), link);
+        true
+    }
+    
+    /// Forget a closed link, unless it was already replaced by another one
+    async fn unlink(&self, user_id: &UserId, id: u64) {
+        {
+            let mut links = self.links.write().await;
+            if links.get(user_id).is_none_or(|link| link.id != id) {
+                return;
+            }
+            links.remove(user_id);
+        }
+        
+        info!("Lost the link with {}", user_id);
+        self.sequences.write().await.forget(user_id);
+        let _ = self.local_tx.send(SyncMessage::user_left(user_id.clone(), self.next_sequence()));
+        self.share_peers().await;
+    }
+    
+    /// Bring a newly linked peer up to date on our client
+    async fn introduce(&self, tx: &PeerSender) {
+        if let Some((state, sequence)) = self.local_state.read().await.clone() {
+            let _ = tx.send(SyncMessage::user_joined(state.user_id.clone(), state, sequence));
+        }
+        for message in self.history.read().await.iter() {
+            let _ = tx.send(message.clone());
+        }
+    }
+    
+    /// Tell every peer who we are linked with
+    async fn share_peers(&self) {
+        let links = self.links.read().await;
+        let peers: Vec<PeerAddress> = links.iter()
+            .map(|(user_id, link)| PeerAddress { user_id: user_id.clone(), addr: link.addr })
+            .collect();
+        
+        let message = SyncMessage::new(SyncEvent::Peers { peers }, self.next_sequence());
+        for link in links.values() {
+            let _ = link.tx.send(message.clone());
+        }
+    }
+    
+    /// Pass what a peer's client did on to ours
+    async fn handle_peer_message(&self, peer: &UserId, message: SyncMessage) {
+        debug!("Received from {}: {:?}", peer, message);
+        
+        match &message.event {
+            SyncEvent::Peers { peers } => {
+                let links = self.links.read().await;
+                for other in peers {
+                    if self.user_id < other.user_id && !links.contains_key(&other.user_id) {
+                        self.dial(other.clone());
+                    }
+                }
+                return;
+            }
+            // Peers only speak for themselves
+            SyncEvent::UserJoined { user_id, .. } if user_id != peer => return,
+            SyncEvent::StateUpdate { user_state } if &user_state.user_id != peer => return,
+            SyncEvent::UserJoined { .. } => {
+                self.sequences.write().await.reset(peer, message.sequence);
+            }
+            SyncEvent::StateUpdate { .. } if !self.sequences.write().await.accept(peer, message.sequence) => {
+                debug!("Dropping stale update from {} (sequence {})", peer, message.sequence);
+                return;
+            }
+            SyncEvent::PeerHello { .. } | SyncEvent::Challenge { .. } | SyncEvent::Hello { .. } | SyncEvent::Welcome { .. } | SyncEvent::Ack { .. }
+            | SyncEvent::Host { .. } | SyncEvent::HostOffer { .. } | SyncEvent::HostCandidates { .. } | SyncEvent::Admin { .. }
+            | SyncEvent::AdminResult { .. } | SyncEvent::StatsRequest | SyncEvent::Stats { .. }
+            | SyncEvent::SessionRequest | SyncEvent::Session { .. }
+            | SyncEvent::SessionFull { .. } | SyncEvent::Admitted | SyncEvent::FileOffer { .. }
+            | SyncEvent::FilesAvailable { .. } | SyncEvent::Slideshow { .. } | SyncEvent::SlideshowAdvance { .. }
+            | SyncEvent::Checksums { .. } | SyncEvent::ChecksumReport { .. } => return,
+            _ => {}
+        }
+        
+        let _ = self.local_tx.send(message);
+    }
+    
+    /// Stand in for the server towards our own client
+    async fn serve_local(&self, stream: TcpStream, local_rx: Arc<Mutex<mpsc::UnboundedReceiver<SyncMessage>>>) -> Result<()> {
+        let wire = WireFormat::default();
+        stream.set_nodelay(true)?;
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = FrameReader::new(reader);
+        let mut duplicates = DuplicateFilter::new();
+        let reply_tx = self.local_tx.clone();
+        
+        // Our client waits for one before its Hello; nobody here checks the proof
+        let _ = reply_tx.send(SyncMessage::new(SyncEvent::Challenge { nonce: identity::challenge()? }, self.next_sequence()));
+        
+        let mut writer_task = tokio::spawn(async move {
+            let mut local_rx = local_rx.lock().await;
+            while let Some(message) = local_rx.recv().await {
+                let Ok(bytes) = wire::encode(&message, wire) else {
+                    continue;
+                };
+                if writer.write_all(&bytes).await.is_err() {
+                    break;
+                }
+            }
+        });
+        
+        loop {
+            let frame = tokio::select! {
+                read = reader.next_frame() => match read? {
+                    Some(frame) => frame,
+                    None => break,
+                },
+                _ = &mut writer_task => break,
+            };
+            let message = match frame.decode() {
+                Ok(message) => message,
+                Err(e) => {
+                    warn!("Failed to parse message from our client: {} - '{}'", e, frame.describe());
+                    continue;
+                }
+            };
+            
+            // Links are reliable, so acks stop here
+            if message.event.is_reliable() {
+                let _ = reply_tx.send(SyncMessage::new(SyncEvent::Ack { sequence: message.sequence }, self.next_sequence()));
+                if !duplicates.first_time(message.sequence) {
+                    continue;
+                }
+            }
+            
+            match &message.event {
+                SyncEvent::Hello { .. } => {
+                    // Nothing to gain from compression or binary frames over loopback
+                    let welcome = SyncEvent::Welcome { compression: None, encoding: Encoding::Json, salt: None };
+                    let _ = reply_tx.send(SyncMessage::new(welcome, self.next_sequence()));
+                    continue;
+                }
+                SyncEvent::UserJoined { user_state, .. } | SyncEvent::StateUpdate { user_state } => {
+                    *self.local_state.write().await = Some((user_state.clone(), message.sequence));
+                }
+                SyncEvent::Bookmark { .. } | SyncEvent::Annotation { .. } | SyncEvent::Link { .. } => {
+                    let mut history = self.history.write().await;
+                    if history.len() >= MAX_HISTORY {
+                        history.remove(0);
+                    }
+                    history.push(message.clone());
+                }
+                SyncEvent::Admin { .. } => {
+                    let result = SyncEvent::AdminResult { ok: false, message: "There is no server to moderate in peer-to-peer mode".to_string() };
+                    let _ = reply_tx.send(SyncMessage::new(result, self.next_sequence()));
+                    continue;
+                }
+                SyncEvent::Slideshow { .. } => {
+                    let text = "Slideshows are run by a server; there is none in peer-to-peer mode".to_string();
+                    let _ = reply_tx.send(SyncMessage::announcement(text, self.next_sequence()));
+                    continue;
+                }
+                SyncEvent::Checksums { .. } => {
+                    let text = "Checksums are compared by a server; there is none in peer-to-peer mode".to_string();
+                    let _ = reply_tx.send(SyncMessage::announcement(text, self.next_sequence()));
+                    continue;
+                }
+                SyncEvent::Heartbeat { user_id, sent_at_ms, echo_ms: None, .. } => {
+                    let pong = SyncMessage::heartbeat(user_id.clone(), Some(*sent_at_ms), self.next_sequence());
+                    let _ = reply_tx.send(pong);
+                    continue;
+                }
+                SyncEvent::HostOffer { .. } | SyncEvent::StatsRequest | SyncEvent::SessionRequest | SyncEvent::Heartbeat { .. } => continue,
+                _ => {}
+            }
+            
+            for link in self.links.read().await.values() {
+                let _ = link.tx.send(message.clone());
+            }
+        }
+        
+        writer_task.abort();
+        info!("Our client left the session");
+        Ok(())
+    }
+}
@@ -0,0 +1,90 @@
+//! Turning another reader's page number into ours
+//!
+//! Readers rarely have identical playlists: one has the chapter in a
+//! different folder layout, another has an extra credits page. Positions
+//! are shared along with the file name, and each reader looks the name up
+//! in their own playlist, comparing names loosely so `Page_01.jpg` and
+//! `page 1.png` are the same page.
+
+/// A file name reduced to what identifies the page
+///
+/// Case, extension, separators and leading zeros are dropped, so
+/// `Chapter-03/Page_01.JPG` and `page 1.png` both become `page 1`.
+pub fn normalize(name: &str) -> String {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or(name);
+    let stem = match name.rsplit_once('.') {
+        Some((stem, _)) if !stem.is_empty() => stem,
+        _ => name,
+    };
+    
+    // Runs of letters and runs of digits are separate words
+    let mut words: Vec<String> = Vec::new();
+    let mut word = String::new();
+    for c in stem.chars().flat_map(char::to_lowercase) {
+        let breaks = word.chars().last().is_some_and(|last| last.is_ascii_digit() != c.is_ascii_digit());
+        if (!c.is_alphanumeric() || breaks) && !word.is_empty() {
+            words.push(std::mem::take(&mut word));
+        }
+        if c.is_alphanumeric() {
+            word.push(c);
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    
+    for word in &mut words {
+        if word.starts_with('0') && word.chars().all(|c| c.is_ascii_digit()) {
+            let trimmed = word.trim_start_matches('0');
+            *word = if trimmed.is_empty() { "0".to_string() } else { trimmed.to_string() };
+        }
+    }
+    words.join(" ")
+}
+
+/// Our index for the page someone else is on at `position`, showing `file_name`
+///
+/// The same index wins when our file there has the same name; otherwise the
+/// matching page nearest to `position`. Without any match (or a name) the
+/// number is taken as it is.
+pub fn local_position(ours: &[String], position: i32, file_name: Option<&str>) -> i32 {
+    let Some(wanted) = file_name.map(normalize).filter(|name| !name.is_empty()) else {
+        return position;
+    };
+    ours.iter()
+        .enumerate()
+        .filter(|(_, name)| normalize(name) == wanted)
+        .map(|(index, _)| index as i32)
+        .min_by_key(|index| (index - position).abs())
+        .unwrap_or(position)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("Page_01.JPG"), "page 1");
+        assert_eq!(normalize("Chapter-03/page 1.png"), "page 1");
+        assert_eq!(normalize("ch3p001.webp"), "ch 3 p 1");
+        assert_eq!(normalize("000.png"), "0");
+        assert_eq!(normalize(".hidden"), "hidden");
+    }
+    
+    #[test]
+    fn test_local_position() {
+        // We have a credits page the sender doesn't
+        let ours: Vec<String> = ["credits.png", "p01.png", "p02.png", "p03.png"].map(String::from).to_vec();
+        assert_eq!(local_position(&ours, 1, Some("P_2.jpg")), 2);
+        assert_eq!(local_position(&ours, 3, Some("p03.png")), 3);
+        
+        // Unknown names and old clients keep the raw number
+        assert_eq!(local_position(&ours, 2, Some("bonus.png")), 2);
+        assert_eq!(local_position(&ours, 2, None), 2);
+        
+        // With a name repeated across chapters, the nearest copy wins
+        let chapters: Vec<String> = ["01.png", "02.png", "01.png", "02.png"].map(String::from).to_vec();
+        assert_eq!(local_position(&chapters, 3, Some("02.png")), 3);
+    }
+}
@@ -0,0 +1,361 @@
+//! Automatic port forwarding on home routers via UPnP IGD, falling back to NAT-PMP
+//!
+//! Mappings are requested with a lease and renewed while the server runs, so
+//! they lapse on their own once the server exits.
+
+use super::http_client::{self, HttpUrl};
+use crate::terminal;
+use anyhow::{Context, Result};
+use reqwest::Url;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket as StdUdpSocket};
+use tokio::net::UdpSocket;
+use tokio::time::{sleep, timeout, timeout_at, Duration, Instant};
+use tracing::{debug, info, warn};
+
+/// Lease requested from the router; renewed at half this interval
+const LEASE_SECONDS: u32 = 3600;
+
+const SSDP_ADDR: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(239, 255, 255, 250), 1900);
+const SSDP_WAIT: Duration = Duration::from_secs(3);
+const HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+const NATPMP_PORT: u16 = 5351;
+
+const IGD_SERVICES: [&str; 2] = [
+    "urn:schemas-upnp-org:service:WANIPConnection:",
+    "urn:schemas-upnp-org:service:WANPPPConnection:",
+];
+
+/// Which protocol the router accepted
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MappingMethod {
+    Upnp,
+    NatPmp,
+}
+
+impl fmt::Display for MappingMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MappingMethod::Upnp => write!(f, "UPnP"),
+            MappingMethod::NatPmp => write!(f, "NAT-PMP"),
+        }
+    }
+}
+
+/// A port forwarded by the router
+#[derive(Debug, Clone, PartialEq)]
+pub struct PortMapping {
+    pub external: SocketAddr,
+    pub method: MappingMethod,
+}
+
+/// Map `port` and keep renewing the lease until the task is dropped
+pub async fn maintain(port: u16) {
+    let mut announced = None;
+    
+    loop {
+        let retry = match map_port(port).await {
+            Ok(mapping) => {
+                if announced.as_ref() != Some(&mapping) {
+                    info!("{}Port forwarded via {}: internet clients can connect to {}", terminal::decoration("🌐 "), mapping.method, mapping.external);
+                    announced = Some(mapping);
+                } else {
+                    debug!("Renewed port mapping for {}", port);
+                }
+                Duration::from_secs(LEASE_SECONDS as u64 / 2)
+            }
+            Err(e) => {
+                warn!("Automatic port forwarding failed: {:#}", e);
+                Duration::from_secs(300)
+            }
+        };
+        sleep(retry).await;
+    }
+}
+
+/// Ask the router to forward TCP `port`, trying UPnP first and then NAT-PMP
+pub async fn map_port(port: u16) -> Result<PortMapping> {
+    let upnp_error = match upnp_map(port).await {
+        Ok(mapping) => return Ok(mapping),
+        Err(e) => e,
+    };
+    debug!("UPnP mapping failed ({:#}), trying NAT-PMP", upnp_error);
+    
+    natpmp_map(port).await.map_err(|natpmp_error| {
+        anyhow::anyhow!("UPnP: {:#}; NAT-PMP: {:#}", upnp_error, natpmp_error)
+    })
+}
+
+// --- UPnP IGD --------------------------------------------------------------
+
+async fn upnp_map(port: u16) -> Result<PortMapping> {
+    let location = ssdp_search().await?;
+    let gateway = HttpUrl::parse(&location)?.ip()
+        .with_context(|| format!("gateway URL must use an IP address: {}", location))?;
+    
+    let description = http_request(&location, None).await
+        .context("Failed to fetch gateway description")?;
+    let (service_type, control_url) = find_control_url(&description, &location)
+        .context("Gateway does not offer a WAN connection service")?;
+    
+    let internal_ip = local_ip_towards(gateway)?;
+    let lease = LEASE_SECONDS.to_string();
+    let port_str = port.to_string();
+    let internal_client = internal_ip.to_string();
+    
+    soap_call(&control_url, &service_type, "AddPortMapping", &[
+        ("NewRemoteHost", ""),
+        ("NewExternalPort", &port_str),
+        ("NewProtocol", "TCP"),
+        ("NewInternalPort", &port_str),
+        ("NewInternalClient", &internal_client),
+        ("NewEnabled", "1"),
+        ("NewPortMappingDescription", "syncread"),
+        ("NewLeaseDuration", &lease),
+    ]).await?;
+    
+    let reply = soap_call(&control_url, &service_type, "GetExternalIPAddress", &[]).await?;
+    let external_ip: IpAddr = xml_tag(&reply, "NewExternalIPAddress")
+        .and_then(|ip| ip.trim().parse().ok())
+        .context("Gateway did not report its external address")?;
+    
+    Ok(PortMapping { external: SocketAddr::new(external_ip, port), method: MappingMethod::Upnp })
+}
+
+/// Multicast an M-SEARCH for an internet gateway and return its description URL
+async fn ssdp_search() -> Result<String> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    let request = "M-SEARCH * HTTP/1.1\r\n\
+        HOST: 239.255.255.250:1900\r\n\
+        ST: urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\n\
+        MAN: \"ssdp:discover\"\r\n\
+        MX: 2\r\n\r\n";
+    socket.send_to(request.as_bytes(), SSDP_ADDR).await?;
+    
+    let deadline = Instant::now() + SSDP_WAIT;
+    let mut buf = vec![0u8; 2048];
+    while let Ok(received) = timeout_at(deadline, socket.recv_from(&mut buf)).await {
+        let (len, _) = received?;
+        if let Some(location) = header_value(&String::from_utf8_lossy(&buf[..len]), "location") {
+            return Ok(location);
+        }
+    }
+    
+    anyhow::bail!("no UPnP gateway answered")
+}
+
+async fn soap_call(control_url: &str, service_type: &str, action: &str, args: &[(&str, &str)]) -> Result<String> {
+    let args: String = args.iter()
+        .map(|(name, value)| format!("<{0}>{1}</{0}>", name, value))
+        .collect();
+    let body = format!(
+        "<?xml version=\"1.0\"?>\
+        <s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" \
+        s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\">\
+        <s:Body><u:{action} xmlns:u=\"{service_type}\">{args}</u:{action}></s:Body></s:Envelope>"
+    );
+    let soap_action = format!("\"{}#{}\"", service_type, action);
+    
+    http_request(control_url, Some((&soap_action, &body))).await
+        .with_context(|| format!("{} rejected", action))
+}
+
+/// GET `url`, or POST a SOAP call when `soap` is given; returns the body
+async fn http_request(url: &str, soap: Option<(&str, &str)>) -> Result<String> {
+    let url = HttpUrl::parse(url)?;
+    let response = match soap {
+        Some((action, body)) => timeout(HTTP_TIMEOUT, http_client::soap(&url, action, body)).await,
+        None => timeout(HTTP_TIMEOUT, http_client::request("GET", &url, None, None)).await,
+    }.context("gateway timed out")??;
+    
+    if response.status != 200 {
+        let detail = xml_tag(&response.body, "errorDescription").unwrap_or_else(|| format!("HTTP {}", response.status));
+        anyhow::bail!("{}", detail);
+    }
+    Ok(response.body)
+}
+
+fn header_value(response: &str, name: &str) -> Option<String> {
+    response.lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.trim().to_string())
+}
+
+/// Text of the first `<tag>...</tag>` (namespace prefixes and attributes on the tag are ignored)
+fn xml_tag(xml: &str, tag: &str) -> Option<String> {
+    let mut rest = xml;
+    while let Some(open) = rest.find('<') {
+        rest = &rest[open + 1..];
+        let close = rest.find('>')?;
+        let name = rest[..close].split_whitespace().next().unwrap_or("");
+        let local = name.rsplit_once(':').map_or(name, |(_, local)| local);
+        rest = &rest[close + 1..];
+        if local == tag {
+            return Some(rest[..rest.find("</")?].to_string());
+        }
+    }
+    None
+}
+
+/// Find the WAN connection service in a device description; returns (serviceType, absolute controlURL)
+fn find_control_url(description: &str, location: &str) -> Option<(String, String)> {
+    let service = description.split("<service>")
+        .skip(1)
+        .find(|block| {
+            xml_tag(block, "serviceType").is_some_and(|t| IGD_SERVICES.iter().any(|s| t.starts_with(s)))
+        })?;
+    
+    let service_type = xml_tag(service, "serviceType")?;
+    let control = xml_tag(service, "controlURL")?;
+    
+    // Relative to URLBase if there is one, else to where the description came from
+    let base = xml_tag(description, "URLBase").unwrap_or_else(|| location.to_string());
+    let url = Url::parse(base.trim()).ok()?.join(control.trim()).ok()?;
+    
+    Some((service_type.trim().to_string(), url.to_string()))
+}
+
+/// Address of the interface that routes to `target` (no packets are sent)
+fn local_ip_towards(target: IpAddr) -> Result<IpAddr> {
+    let socket = StdUdpSocket::bind("0.0.0.0:0")?;
+    socket.connect((target, 9))?;
+    Ok(socket.local_addr()?.ip())
+}
+
+// --- NAT-PMP ---------------------------------------------------------------
+
+async fn natpmp_map(port: u16) -> Result<PortMapping> {
+    let gateway = default_gateway().context("could not determine the default gateway")?;
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    socket.connect((gateway, NATPMP_PORT)).await?;
+    
+    let reply = natpmp_request(&socket, &[0, 0], 12).await?;
+    let external_ip = Ipv4Addr::new(reply[8], reply[9], reply[10], reply[11]);
+    
+    let mut request = vec![0, 2, 0, 0];
+    request.extend_from_slice(&port.to_be_bytes());
+    request.extend_from_slice(&port.to_be_bytes());
+    request.extend_from_slice(&LEASE_SECONDS.to_be_bytes());
+    let reply = natpmp_request(&socket, &request, 16).await?;
+    let external_port = u16::from_be_bytes([reply[10], reply[11]]);
+    
+    Ok(PortMapping {
+        external: SocketAddr::new(IpAddr::V4(external_ip), external_port),
+        method: MappingMethod::NatPmp,
+    })
+}
+
+/// Send a request with the RFC 6886 retry schedule and validate the reply header
+async fn natpmp_request(socket: &UdpSocket, request: &[u8], reply_len: usize) -> Result<Vec<u8>> {
+    let mut wait = Duration::from_millis(250);
+    let mut buf = [0u8; 16];
+    
+    for _ in 0..4 {
+        socket.send(request).await?;
+        if let Ok(received) = timeout(wait, socket.recv(&mut buf)).await {
+            let len = received?;
+            if len < reply_len || buf[1] != request[1] | 0x80 {
+                continue;
+            }
+            let result = u16::from_be_bytes([buf[2], buf[3]]);
+            if result != 0 {
+                anyhow::bail!("gateway refused with result code {}", result);
+            }
+            return Ok(buf[..len].to_vec());
+        }
+        wait *= 2;
+    }
+    
+    anyhow::bail!("no NAT-PMP gateway answered")
+}
+
+#[cfg(target_os = "linux")]
+fn default_gateway() -> Option<Ipv4Addr> {
+    parse_proc_route(&std::fs::read_to_string("/proc/net/route").ok()?)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn default_gateway() -> Option<Ipv4Addr> {
+    // Most home routers sit at .1 of the local /24
+    let IpAddr::V4(local) = local_ip_towards(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))).ok()? else {
+        return None;
+    };
+    let [a, b, c, _] = local.octets();
+    Some(Ipv4Addr::new(a, b, c, 1))
+}
+
+/// Default route gateway from /proc/net/route (addresses are little-endian hex)
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_proc_route(table: &str) -> Option<Ipv4Addr> {
+    table.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.get(1) != Some(&"00000000") {
+            return None;
+        }
+        let gateway = u32::from_str_radix(fields.get(2)?, 16).ok()?;
+        (gateway != 0).then(|| Ipv4Addr::from(gateway.swap_bytes()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    const DESCRIPTION: &str = r#"<?xml version="1.0"?>
+<root><URLBase>http://192.168.1.1:5000</URLBase><device><serviceList>
+<service><serviceType>urn:schemas-upnp-org:service:Layer3Forwarding:1</serviceType><controlURL>/l3f</controlURL></service>
+<service><serviceType>urn:schemas-upnp-org:service:WANIPConnection:1</serviceType><controlURL>/ctl/IPConn</controlURL></service>
+</serviceList></device></root>"#;
+    
+    #[test]
+    fn test_find_control_url() {
+        let (service, url) = find_control_url(DESCRIPTION, "http://192.168.1.1:5000/rootDesc.xml").unwrap();
+        assert_eq!(service, "urn:schemas-upnp-org:service:WANIPConnection:1");
+        assert_eq!(url, "http://192.168.1.1:5000/ctl/IPConn");
+        
+        let no_base = DESCRIPTION.replace("<URLBase>http://192.168.1.1:5000</URLBase>", "");
+        let (_, url) = find_control_url(&no_base, "http://10.0.0.1:1900/desc.xml").unwrap();
+        assert_eq!(url, "http://10.0.0.1:1900/ctl/IPConn");
+    }
+    
+    #[test]
+    fn test_ssdp_location_and_soap_reply() {
+        let ssdp = "HTTP/1.1 200 OK\r\nCACHE-CONTROL: max-age=120\r\nLocation: http://192.168.1.1:5000/rootDesc.xml\r\n\r\n";
+        assert_eq!(header_value(ssdp, "location").as_deref(), Some("http://192.168.1.1:5000/rootDesc.xml"));
+        
+        let reply = "<s:Body><u:GetExternalIPAddressResponse><NewExternalIPAddress>203.0.113.9</NewExternalIPAddress></u:GetExternalIPAddressResponse></s:Body>";
+        assert_eq!(xml_tag(reply, "NewExternalIPAddress").as_deref(), Some("203.0.113.9"));
+        
+        // Only the tag itself, not others ending the same way
+        let description = "<fooURLBase>http://evil/</fooURLBase><m:URLBase a=\"1\">http://192.168.1.1/</m:URLBase>";
+        assert_eq!(xml_tag(description, "URLBase").as_deref(), Some("http://192.168.1.1/"));
+        assert_eq!(xml_tag("<fooURLBase>http://evil/</fooURLBase>", "URLBase"), None);
+    }
+    
+    #[tokio::test]
+    async fn test_gateway_response_is_capped() {
+        use tokio::io::AsyncWriteExt;
+        
+        // A gateway that never stops sending
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/rootDesc.xml", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let _ = stream.write_all(b"HTTP/1.0 200 OK\r\n\r\n").await;
+            let chunk = vec![b'x'; 64 * 1024];
+            while stream.write_all(&chunk).await.is_ok() {}
+        });
+        
+        let error = http_request(&url, None).await.unwrap_err();
+        assert!(format!("{:#}", error).contains("too large"), "{:#}", error);
+    }
+    
+    #[test]
+    fn test_parse_proc_route() {
+        let table = "Iface\tDestination\tGateway \tFlags\n\
+            eth0\t0001A8C0\t00000000\t0001\n\
+            eth0\t00000000\t0101A8C0\t0003\n";
+        assert_eq!(parse_proc_route(table), Some(Ipv4Addr::new(192, 168, 1, 1)));
+    }
+}
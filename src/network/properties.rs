@@ -0,0 +1,122 @@
+//! Which viewer properties are shared with the session
+//!
+//! Pause and playback time have their own sync (`--shared-pause`,
+//! `--sync-time`); any other MPV property listed is read on every update
+//! and, when it changes, set to the same value for everyone syncing it.
+
+use anyhow::Result;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Shared through `--shared-pause`
+pub const PAUSE: &str = "pause";
+
+/// Shared through `--sync-time`
+pub const PLAYBACK_TIME: &str = "playback-time";
+
+/// Page turns, which everyone syncing it follows
+pub const PLAYLIST_POS: &str = "playlist-pos";
+
+/// Turning sideways scans upright, shared through `--sync-rotation`
+pub const VIDEO_ROTATE: &str = "video-rotate";
+
+/// The `[client.properties]` table: what to share and what to keep to yourself
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PropertySync {
+    /// MPV property names to share, e.g. `speed`, `sid` or `video-zoom`
+    pub sync: Vec<String>,
+    /// Never shared, even when listed in `sync`, e.g. `volume` or `fullscreen`
+    pub local: Vec<String>,
+}
+
+impl PropertySync {
+    /// Check every name looks like an MPV property
+    pub fn validate(&self) -> Result<()> {
+        for name in self.sync.iter().chain(&self.local) {
+            let valid = !name.is_empty()
+                && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '-' | '/'));
+            anyhow::ensure!(valid, "'{}' is not an MPV property name", name);
+        }
+        Ok(())
+    }
+    
+    /// Whether `name` is shared with the session
+    pub fn syncs(&self, name: &str) -> bool {
+        self.sync.iter().any(|n| n == name) && !self.local.iter().any(|n| n == name)
+    }
+    
+    /// Shared properties without their own sync mode, in the order listed
+    pub fn generic(&self) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        for name in &self.sync {
+            if name != PAUSE && name != PLAYBACK_TIME && self.syncs(name) && !names.contains(name) {
+                names.push(name.clone());
+            }
+        }
+        names
+    }
+}
+
+/// Last value of each synced property, as read from the viewer or set from someone else's change
+#[derive(Debug, Default)]
+pub struct PropertyTracker {
+    values: HashMap<String, Value>,
+}
+
+impl PropertyTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Record a value read from the viewer, returning whether it changed since last time
+    ///
+    /// The first reading of a property is not a change.
+    pub fn observe(&mut self, name: &str, value: Value) -> bool {
+        self.values.insert(name.to_string(), value.clone()).is_some_and(|previous| previous != value)
+    }
+    
+    /// Record a value set from someone else's change, so reading it back isn't shared again
+    pub fn applied(&mut self, name: &str, value: Value) {
+        self.values.insert(name.to_string(), value);
+    }
+    
+    /// Forget everything, e.g. after the viewer restarted with its defaults
+    pub fn clear(&mut self) {
+        self.values.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    
+    #[test]
+    fn test_property_selection() {
+        let properties = PropertySync {
+            sync: ["pause", "sid", "volume", "sid", "video-zoom"].map(String::from).to_vec(),
+            local: vec!["volume".to_string()],
+        };
+        assert!(properties.validate().is_ok());
+        assert!(properties.syncs("pause"));
+        assert!(!properties.syncs("volume"));
+        assert_eq!(properties.generic(), vec!["sid".to_string(), "video-zoom".to_string()]);
+        
+        let typo = PropertySync { sync: vec!["Video Zoom".to_string()], local: Vec::new() };
+        assert!(typo.validate().is_err());
+    }
+    
+    #[test]
+    fn test_tracker_reports_local_changes_only() {
+        let mut tracker = PropertyTracker::new();
+        assert!(!tracker.observe("sid", json!(1)));
+        assert!(!tracker.observe("sid", json!(1)));
+        assert!(tracker.observe("sid", json!(2)));
+        
+        // What we set for someone else reads back unchanged
+        tracker.applied("sid", json!(3));
+        assert!(!tracker.observe("sid", json!(3)));
+    }
+}
@@ -1,24 +1,87 @@
+use super::appearance::UserColor;
+use super::compression::Compression;
+use super::identity::IdentityProof;
+use super::properties::PLAYLIST_POS;
+use super::stats::ReadingStats;
+use super::text_width;
+use super::wire::Encoding;
+use crate::media::{playlist_fingerprint, MediaSource};
+use crate::mpv::playlist::format_time;
+use crate::storage::Bookmark;
+use crate::terminal;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::Duration;
 
 /// Unique identifier for users in the sync session
 pub type UserId = String;
 
+/// How long a user goes without turning a page, pausing or playing before showing as idle
+pub const DEFAULT_IDLE_AFTER: Duration = Duration::from_secs(5 * 60);
+
+/// Columns a user's name and file name may take in the session list before being cut short
+const MAX_NAME_WIDTH: usize = 20;
+const MAX_FILE_NAME_WIDTH: usize = 40;
+
 /// Current state of a user's media playback
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct UserState {
     pub user_id: UserId,
     pub playlist_position: i32,
-    pub current_file: Option<PathBuf>,
+    pub current_file: Option<MediaSource>,
     pub current_file_name: Option<String>, // For display purposes
     pub playback_time: f64,
     pub is_paused: bool,
     pub timestamp: u64, // Unix timestamp when this state was created
+    #[serde(default)]
+    pub timestamp_ms: u64, // Same instant in milliseconds, for latency compensation
+    #[serde(default)]
+    pub finished: bool, // Has reached the last item of the playlist
+    #[serde(default)]
+    pub color: Option<UserColor>, // Missing from older clients, who get `UserColor::for_user`
+    #[serde(default)]
+    pub icon: Option<String>, // Emoji avatar shown before the name
+    #[serde(default)]
+    pub last_active: u64, // Unix timestamp of the last page turn, pause, seek or playback; 0 from older clients
+    #[serde(default)]
+    pub rtt_ms: Option<u64>, // Round trip to the server, as the client measured it
+    #[serde(default)]
+    pub chapter: Option<ChapterPosition>, // Only sent in chapter mode, for files with chapters
+    #[serde(default)]
+    pub playlist_len: Option<usize>, // Entries in the sender's playlist; missing from older clients
+    #[serde(default)]
+    pub playlist_fingerprint: Option<String>, // `media::playlist_fingerprint` of that playlist
+}
+
+/// Where a user is among the chapters of the file they're watching
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ChapterPosition {
+    pub index: i32, // From 0, as MPV counts them
+    pub count: usize, // 0 when the chapter list couldn't be read
+    pub title: Option<String>,
+}
+
+impl ChapterPosition {
+    /// "ch. 3/12 The Heist", or just "ch. 3" when the list is unknown
+    pub fn label(&self) -> String {
+        let mut label = format!("ch. {}", self.index + 1);
+        if self.count > 0 {
+            label.push_str(&format!("/{}", self.count));
+        }
+        if let Some(title) = &self.title {
+            label.push(' ');
+            label.push_str(title);
+        }
+        label
+    }
 }
 
 impl UserState {
     pub fn new(user_id: UserId) -> Self {
+        let color = Some(UserColor::for_user(&user_id));
+        let timestamp = unix_millis() / 1000;
         Self {
             user_id,
             playlist_position: 0,
@@ -26,10 +89,29 @@ impl UserState {
             current_file_name: None,
             playback_time: 0.0,
             is_paused: true,
-            timestamp: std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_secs(),
+            timestamp,
+            timestamp_ms: unix_millis(),
+            finished: false,
+            color,
+            icon: None,
+            last_active: timestamp,
+            rtt_ms: None,
+            chapter: None,
+            playlist_len: None,
+            playlist_fingerprint: None,
+        }
+    }
+    
+    /// Color to draw this user in
+    pub fn color(&self) -> UserColor {
+        self.color.unwrap_or_else(|| UserColor::for_user(&self.user_id))
+    }
+    
+    /// The user id, after their avatar if they picked one
+    pub fn display_name(&self) -> String {
+        match &self.icon {
+            Some(icon) => format!("{} {}", icon, self.user_id),
+            None => self.user_id.clone(),
         }
     }
     
@@ -39,45 +121,364 @@ impl UserState {
         playlist_pos: i32, 
         playback_time: f64, 
         is_paused: bool,
-        current_file: Option<PathBuf>,
+        current_file: Option<MediaSource>,
     ) {
         self.playlist_position = playlist_pos;
         self.playback_time = playback_time;
         self.is_paused = is_paused;
         
         if let Some(ref file) = current_file {
-            self.current_file_name = file.file_name()
-                .and_then(|n| n.to_str())
-                .map(|s| s.to_string());
+            self.current_file_name = file.display_name();
         }
         
         self.current_file = current_file;
-        self.timestamp = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
+        self.timestamp_ms = unix_millis();
+        self.timestamp = self.timestamp_ms / 1000;
+    }
+    
+    /// Describe the playlist our position is in, for the server to check it against
+    pub fn set_playlist(&mut self, files: &[MediaSource]) {
+        self.playlist_len = Some(files.len());
+        self.playlist_fingerprint = Some(playlist_fingerprint(files));
+    }
+    
+    /// Keep `previous`'s last activity time unless the user has done something since
+    ///
+    /// A page turn, pause, resume or seek counts, and so does media playing.
+    pub fn carry_activity(&mut self, previous: &UserState) {
+        let unchanged = self.playlist_position == previous.playlist_position
+            && self.is_paused == previous.is_paused
+            && (self.playback_time - previous.playback_time).abs() < 0.01;
+        self.last_active = if unchanged { previous.last_active } else { self.timestamp };
+    }
+    
+    /// "idle 6m" once the user has done nothing for `idle_after`, as of `now` (Unix seconds)
+    pub fn idle_label(&self, idle_after: Duration, now: u64) -> Option<String> {
+        if self.last_active == 0 {
+            return None;
+        }
+        let idle = now.saturating_sub(self.last_active);
+        if idle < idle_after.as_secs() {
+            return None;
+        }
+        let minutes = idle / 60;
+        Some(match minutes {
+            0..=59 => format!("idle {}m", minutes),
+            _ => format!("idle {}h {}m", minutes / 60, minutes % 60),
+        })
+    }
+    
+    /// "p.4", or "p.4 ch.2" in chapter mode, for the in-player overlay
+    pub fn short_position(&self) -> String {
+        match &self.chapter {
+            Some(chapter) => format!("p.{} ch.{}", self.playlist_position + 1, chapter.index + 1),
+            None => format!("p.{}", self.playlist_position + 1),
+        }
     }
     
     /// Format for CLI display
-    pub fn format_for_display(&self) -> String {
+    ///
+    /// The name is padded to `name_width` columns so rows line up, and long
+    /// names and file names are cut short.
+    pub fn format_for_display(&self, name_width: usize) -> String {
         let file_name = self.current_file_name
             .as_deref()
-            .unwrap_or("(no file)");
-            
-        let status = if self.is_paused { "⏸" } else { "▶" };
+            .map(|name| text_width::truncate(name, MAX_FILE_NAME_WIDTH))
+            .unwrap_or_else(|| "(no file)".to_string());
+        let name = text_width::truncate(&self.display_name(), MAX_NAME_WIDTH);
         
-        format!("{}: {} {} (pos: {}, time: {:.1}s)", 
-                self.user_id, 
+        let status = terminal::decoration(if self.is_paused { "⏸" } else { "▶" });
+        let finished = if self.finished { format!(" {}", terminal::decoration("🏁")) } else { String::new() };
+        let rtt = self.rtt_ms.map(|ms| format!(", {} ms", ms)).unwrap_or_default();
+        let chapter = self.chapter.as_ref().map(|chapter| format!(", {}", chapter.label())).unwrap_or_default();
+        
+        format!("{} {} {} (pos: {}{}, time: {:.1}s{}){}", 
+                text_width::pad(&format!("{}:", name), name_width + 1), 
                 status,
                 file_name, 
                 self.playlist_position,
-                self.playback_time)
+                chapter,
+                self.playback_time,
+                rtt,
+                finished)
+    }
+}
+
+/// Longest note accepted on a page; longer text is cut off
+pub const MAX_ANNOTATION_LENGTH: usize = 280;
+
+/// Short note a user left on a page, shown to anyone who views that page
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Annotation {
+    pub user_id: UserId,
+    pub playlist_position: i32,
+    pub file_name: Option<String>, // Guards against readers whose playlists differ
+    pub text: String,
+    pub created_at: u64, // Unix timestamp
+}
+
+impl Annotation {
+    /// Note on the page `state` is viewing, truncated to `MAX_ANNOTATION_LENGTH` characters
+    pub fn new(state: &UserState, text: &str) -> Self {
+        Self {
+            user_id: state.user_id.clone(),
+            playlist_position: state.playlist_position,
+            file_name: state.current_file_name.clone(),
+            text: text.trim().chars().take(MAX_ANNOTATION_LENGTH).collect(),
+            created_at: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+        }
+    }
+    
+    /// Whether this note belongs on the given page
+    pub fn is_on_page(&self, playlist_position: i32, file_name: Option<&str>) -> bool {
+        self.playlist_position == playlist_position
+            && (self.file_name.is_none() || file_name.is_none() || self.file_name.as_deref() == file_name)
+    }
+}
+
+/// Longest link accepted for sharing
+pub const MAX_LINK_LENGTH: usize = 2048;
+
+/// Web page a user shared with the session, e.g. a wiki entry on the current chapter
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SharedLink {
+    pub user_id: UserId,
+    pub url: String,
+    pub shared_at: u64, // Unix timestamp
+}
+
+impl SharedLink {
+    /// Link to share, refusing anything but a single http(s) URL
+    pub fn new(user_id: UserId, url: &str) -> anyhow::Result<Self> {
+        let link = Self {
+            user_id,
+            url: url.trim().to_string(),
+            shared_at: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+        };
+        link.check()?;
+        Ok(link)
+    }
+    
+    /// Whether the link is safe to show and copy (checked again on receipt, as it came from another client)
+    pub fn check(&self) -> anyhow::Result<()> {
+        let url = &self.url;
+        anyhow::ensure!(url.starts_with("https://") || url.starts_with("http://"), "only http(s) links can be shared");
+        anyhow::ensure!(url.len() <= MAX_LINK_LENGTH, "link is longer than {} characters", MAX_LINK_LENGTH);
+        anyhow::ensure!(!url.chars().any(|c| c.is_whitespace() || c.is_control()), "link can't contain spaces");
+        Ok(())
+    }
+}
+
+/// Longest chat message; longer ones are cut off
+pub const MAX_CHAT_LENGTH: usize = 500;
+
+/// A chat message as it is sent: on one line, trimmed and cut to `MAX_CHAT_LENGTH` characters
+///
+/// `None` if nothing is left to send.
+pub fn chat_text(text: &str) -> Option<String> {
+    let line: String = text.trim()
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .take(MAX_CHAT_LENGTH)
+        .collect();
+    (!line.is_empty()).then_some(line)
+}
+
+/// A-B loop over part of the current file, as in MPV's `ab-loop-a`/`ab-loop-b`
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+pub struct AbLoop {
+    pub a: Option<f64>, // Seconds; None is MPV's "no"
+    pub b: Option<f64>,
+}
+
+impl AbLoop {
+    /// Read MPV's property values, which are a time or "no"
+    pub fn from_properties(a: &serde_json::Value, b: &serde_json::Value) -> Self {
+        Self { a: a.as_f64(), b: b.as_f64() }
+    }
+    
+    /// What pressing the loop key at `time` does, like MPV's own `ab-loop` command: set A, then B, then clear
+    pub fn cycle(self, time: f64) -> Self {
+        match (self.a, self.b) {
+            (None, _) => Self { a: Some(time), b: None },
+            (Some(a), None) => Self { a: Some(a.min(time)), b: Some(a.max(time)) },
+            (Some(_), Some(_)) => Self::default(),
+        }
+    }
+    
+    /// Both ends are set, so playback is looping
+    pub fn is_complete(&self) -> bool {
+        self.a.is_some() && self.b.is_some()
+    }
+    
+    /// "loop 01:23-01:45", "loop from 01:23" or "no loop"
+    pub fn describe(&self) -> String {
+        match (self.a, self.b) {
+            (Some(a), Some(b)) => format!("loop {}-{}", format_time(a), format_time(b)),
+            (Some(a), None) => format!("loop from {}", format_time(a)),
+            _ => "no loop".to_string(),
+        }
     }
 }
 
+/// The part of a page on screen: MPV's `video-zoom`, `video-pan-x` and `video-pan-y`
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+pub struct ViewTransform {
+    pub zoom: f64, // log2 scale, 0 is unzoomed
+    pub pan_x: f64,
+    pub pan_y: f64,
+}
+
+/// A change to the session's slideshow, which the server runs
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SlideshowCommand {
+    /// Show `count` pages from `from`, one every `interval_secs`
+    Start { interval_secs: u64, from: i32, count: usize },
+    /// Start at the server's interval, or pause or resume a running slideshow
+    Toggle { from: i32, count: usize },
+    Pause,
+    Resume,
+    Stop,
+}
+
+impl SlideshowCommand {
+    /// What `user_id` did, for the OSD
+    pub fn describe(&self, user_id: &str) -> String {
+        match self {
+            SlideshowCommand::Start { interval_secs, .. } => format!("{} started a slideshow ({}s per page)", user_id, interval_secs),
+            SlideshowCommand::Toggle { .. } => format!("{} toggled the slideshow", user_id),
+            SlideshowCommand::Pause => format!("{} paused the slideshow", user_id),
+            SlideshowCommand::Resume => format!("{} resumed the slideshow", user_id),
+            SlideshowCommand::Stop => format!("{} stopped the slideshow", user_id),
+        }
+    }
+}
+
+/// A change to the playlist, made by one reader for everyone
+///
+/// Entries are named as well as numbered, so readers whose playlists have
+/// drifted apart still edit the same item.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PlaylistEdit {
+    /// Add a file or URL at the end
+    Append { entry: String },
+    /// Take out the item at `index`
+    Remove { index: usize, name: String },
+    /// Move the item at `from` so it ends up at `to`
+    Move { from: usize, to: usize, name: String },
+}
+
+impl PlaylistEdit {
+    /// What `user_id` did, for the OSD
+    pub fn describe(&self, user_id: &str) -> String {
+        match self {
+            PlaylistEdit::Append { entry } => {
+                let name = MediaSource::parse(entry).display_name().unwrap_or_else(|| entry.clone());
+                format!("{} added {}", user_id, name)
+            }
+            PlaylistEdit::Remove { name, .. } => format!("{} removed {}", user_id, name),
+            PlaylistEdit::Move { to, name, .. } => format!("{} moved {} to p.{}", user_id, name, to + 1),
+        }
+    }
+    
+    /// Where the item the sender saw as `name` at `index` is in our playlist
+    pub fn locate(names: &[String], index: usize, name: &str) -> Option<usize> {
+        if names.get(index).is_some_and(|n| n == name) {
+            return Some(index);
+        }
+        names.iter().position(|n| n == name)
+    }
+}
+
+/// Hash of one playlist file's contents (`--checksums`)
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FileChecksum {
+    pub name: String, // As in the playlist, to match up with others' copies
+    pub hash: u64, // xxh3
+}
+
+/// A page readers have in different versions
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ChecksumMismatch {
+    pub name: String,
+    pub differing: Vec<UserId>, // Readers whose copy isn't the one most have
+}
+
+/// A user and the address they accept connections on (host candidates, p2p peers)
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PeerAddress {
+    pub user_id: UserId,
+    pub addr: SocketAddr,
+}
+
+/// How far the server's clock is ahead of ours, estimated while connecting
+static CLOCK_OFFSET_MS: AtomicI64 = AtomicI64::new(0);
+
+/// Use `offset_ms` to turn our clock into the session's (the server's) clock
+pub fn set_clock_offset(offset_ms: i64) {
+    CLOCK_OFFSET_MS.store(offset_ms, Ordering::Relaxed);
+}
+
+/// Offset applied by `unix_millis`, 0 until a server has been measured
+pub fn clock_offset() -> i64 {
+    CLOCK_OFFSET_MS.load(Ordering::Relaxed)
+}
+
+/// This machine's wall-clock time in milliseconds since the Unix epoch, uncorrected
+pub fn local_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+/// Session time in milliseconds since the Unix epoch
+///
+/// This is our clock corrected by the offset to the server's, so timestamps
+/// from readers on different machines can be compared.
+pub fn unix_millis() -> u64 {
+    local_millis().saturating_add_signed(clock_offset())
+}
+
 /// Events that can be synchronized between users
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum SyncEvent {
+    /// Server's first message on a connection: a nonce for the client to sign its identity proof with
+    ///
+    /// A proof signed for one connection is worthless on any other.
+    Challenge {
+        nonce: String, // Base64
+    },
+    
+    /// First message from a client, offering wire options (answered with `Welcome`, never broadcast)
+    ///
+    /// The client sends nothing else until the `Welcome`, after which both
+    /// sides use the agreed format.
+    Hello {
+        compression: Vec<Compression>,
+        #[serde(default)]
+        encodings: Vec<Encoding>,
+        #[serde(default)]
+        identity: Option<IdentityProof>, // Our user id signed with our key, for servers that pin keys
+    },
+    
+    /// Server's reply to `Hello`: the format both sides use from now on
+    Welcome {
+        compression: Option<Compression>,
+        #[serde(default)]
+        encoding: Encoding,
+        #[serde(default)]
+        salt: Option<String>, // The session's, for clients with a --passphrase
+    },
+    
     /// User joined the session
     UserJoined {
         user_id: UserId,
@@ -101,11 +502,313 @@ pub enum SyncEvent {
         value: Option<f64>,
     },
     
-    /// Heartbeat to keep connection alive
+    /// User seeked; receivers jump to `target` plus the time the message spent in flight
+    Seek {
+        user_id: UserId,
+        target: f64,
+        sent_at_ms: u64,
+    },
+    
+    /// User changed a viewer property everyone syncing `name` should take on
+    Property {
+        user_id: UserId,
+        name: String,
+        value: serde_json::Value,
+        #[serde(default)]
+        file_name: Option<String>, // For page turns, the file turned to, which each reader finds in their own playlist
+    },
+    
+    /// User set or cleared the A-B loop everyone plays
+    AbLoop {
+        user_id: UserId,
+        ab_loop: AbLoop,
+    },
+    
+    /// A presenter zoomed or panned; followers show the same crop
+    View {
+        user_id: UserId,
+        view: ViewTransform,
+    },
+    
+    /// Slideshow control: sent by a client, then passed on by the server as what it did
+    Slideshow {
+        user_id: UserId,
+        command: SlideshowCommand,
+    },
+    
+    /// The server's slideshow moved on; everyone turns to this page
+    SlideshowAdvance {
+        page: i32,
+    },
+    
+    /// User added, removed or moved a playlist entry; everyone makes the same change
+    PlaylistEdit {
+        user_id: UserId,
+        edit: PlaylistEdit,
+    },
+    
+    /// The reader who may edit the playlist: whoever has been in the session longest
+    Host {
+        user_id: UserId,
+    },
+    
+    /// User bookmarked a page of the playlist with the given fingerprint
+    Bookmark {
+        fingerprint: String,
+        bookmark: Bookmark,
+    },
+    
+    /// User left a note on a page
+    Annotation {
+        annotation: Annotation,
+    },
+    
+    /// User shared a link with the session
+    Link {
+        link: SharedLink,
+    },
+    
+    /// User said something to the session
+    Chat {
+        user_id: UserId,
+        text: String,
+    },
+    
+    /// User reacted with an emoji; only worth showing right away, so never resent
+    Reaction {
+        user_id: UserId,
+        emoji: String,
+    },
+    
+    /// A private event encrypted with the session passphrase; the server only relays it
+    ///
+    /// `reliable` is the wrapped event's `is_reliable`, so the server can
+    /// still ack it.
+    Sealed {
+        user_id: UserId,
+        reliable: bool,
+        payload: String, // Base64, see `encryption::SessionKey::seal`
+    },
+    
+    /// Ping on a connection (never broadcast); the other end answers with `echo_ms` set to our `sent_at_ms`
+    ///
+    /// The pong gives the pinger its round-trip time. Heartbeats from the
+    /// server carry the id of the client they are sent to.
     Heartbeat {
         user_id: UserId,
         timestamp: u64,
+        #[serde(default)]
+        sent_at_ms: u64,
+        #[serde(default)]
+        echo_ms: Option<u64>,
+    },
+    
+    /// Moderation command from an admin client (never broadcast)
+    Admin {
+        token: String,
+        command: AdminCommand,
+    },
+    
+    /// Server's reply to an admin command
+    AdminResult {
+        ok: bool,
+        message: String,
+    },
+    
+    /// Ask the server for everyone's reading stats (answered with `Stats`, never broadcast)
+    StatsRequest,
+    
+    /// Server's reading stats for every user seen this session
+    Stats {
+        users: HashMap<UserId, ReadingStats>,
+    },
+    
+    /// Ask the server for the whole session state (answered with `Session`, never broadcast)
+    SessionRequest,
+    
+    /// Server's session state, as `syncread status` shows it
+    Session {
+        session: SessionState,
+    },
+    
+    /// Notice from the server for everyone in the session
+    Announcement {
+        text: String,
+    },
+    
+    /// Confirms that the reliable message with this sequence number arrived (never broadcast)
+    Ack {
+        sequence: u64,
+    },
+    
+    /// Client offers to host the session if the server goes away, listening on `port` (never broadcast)
+    HostOffer {
+        port: u16,
+    },
+    
+    /// Clients able to take over if the server goes away, in the order they are tried
+    HostCandidates {
+        candidates: Vec<PeerAddress>,
+    },
+    
+    /// Client shares its playlist's files for download on `port`, listing every item in order (never broadcast)
+    FileOffer {
+        port: u16,
+        names: Vec<String>,
+    },
+    
+    /// Playlist items a reader shares, in their playlist order, and where to download them
+    FilesAvailable {
+        source: PeerAddress,
+        names: Vec<String>,
+    },
+    
+    /// Client's hashes of its playlist files, for the server to compare (never broadcast)
+    Checksums {
+        files: Vec<FileChecksum>,
+    },
+    
+    /// Server's comparison of everyone's checksums, sent whenever a reader's arrive
+    ChecksumReport {
+        mismatches: Vec<ChecksumMismatch>,
+    },
+    
+    /// The session already has as many readers as the server allows; sent instead of admitting a joining client
+    ///
+    /// `position` is the client's place in the waiting room, resent as the
+    /// queue moves. Without a waiting room it is `None` and the connection closes.
+    SessionFull {
+        position: Option<usize>,
+    },
+    
+    /// A place freed up for a client in the waiting room, which should send `UserJoined` again
+    Admitted,
+    
+    /// First message on a peer-to-peer link: who is on this end and the port they accept peers on
+    PeerHello {
+        user_id: UserId,
+        port: u16,
+    },
+    
+    /// Everyone a peer is linked to, so newcomers can connect to the whole group
+    Peers {
+        peers: Vec<PeerAddress>,
+    },
+    
+    /// Sent to a client right before the server closes its connection
+    Kicked {
+        user_id: UserId,
+        reason: Option<String>,
+    },
+}
+
+impl SyncEvent {
+    /// Whether the server must acknowledge this event, with the sender resending until it does
+    ///
+    /// Position and state updates are superseded by the next one a second
+    /// later, so only one-off events a user triggered are worth the resends.
+    pub fn is_reliable(&self) -> bool {
+        match self {
+            SyncEvent::Sealed { reliable, .. } => *reliable,
+            _ => matches!(self, SyncEvent::UserAction { .. } | SyncEvent::Seek { .. } | SyncEvent::Property { .. } | SyncEvent::AbLoop { .. } | SyncEvent::View { .. } | SyncEvent::Slideshow { .. } | SyncEvent::PlaylistEdit { .. } | SyncEvent::Bookmark { .. } | SyncEvent::Annotation { .. } | SyncEvent::Link { .. } | SyncEvent::Chat { .. }),
+        }
+    }
+    
+    /// Put `to` wherever this event names `from` as its sender, for a client the server renamed
+    ///
+    /// Sealed events keep their sender, which is part of what was signed.
+    pub fn rename_user(&mut self, from: &str, to: &str) {
+        let rename = |user_id: &mut UserId| {
+            if user_id == from {
+                *user_id = to.to_string();
+            }
+        };
+        match self {
+            SyncEvent::UserJoined { user_id, user_state } => {
+                rename(user_id);
+                rename(&mut user_state.user_id);
+            }
+            SyncEvent::StateUpdate { user_state } => rename(&mut user_state.user_id),
+            SyncEvent::UserLeft { user_id }
+            | SyncEvent::UserAction { user_id, .. }
+            | SyncEvent::Seek { user_id, .. }
+            | SyncEvent::Property { user_id, .. }
+            | SyncEvent::AbLoop { user_id, .. }
+            | SyncEvent::View { user_id, .. }
+            | SyncEvent::Slideshow { user_id, .. }
+            | SyncEvent::PlaylistEdit { user_id, .. }
+            | SyncEvent::Chat { user_id, .. }
+            | SyncEvent::Reaction { user_id, .. }
+            | SyncEvent::Heartbeat { user_id, .. } => rename(user_id),
+            SyncEvent::Bookmark { bookmark, .. } => rename(&mut bookmark.created_by),
+            SyncEvent::Annotation { annotation } => rename(&mut annotation.user_id),
+            SyncEvent::Link { link } => rename(&mut link.user_id),
+            _ => {}
+        }
+    }
+    
+    /// Whether every id this event names as its sender is `uid` (events naming no sender pass)
+    ///
+    /// The server checks this against the id a connection joined as, so
+    /// nobody can send updates, notes or leaves on someone else's behalf.
+    pub fn is_from(&self, uid: &str) -> bool {
+        match self {
+            SyncEvent::UserJoined { user_id, user_state } => user_id == uid && user_state.user_id == uid,
+            SyncEvent::StateUpdate { user_state } => user_state.user_id == uid,
+            SyncEvent::UserLeft { user_id }
+            | SyncEvent::UserAction { user_id, .. }
+            | SyncEvent::Seek { user_id, .. }
+            | SyncEvent::Property { user_id, .. }
+            | SyncEvent::AbLoop { user_id, .. }
+            | SyncEvent::View { user_id, .. }
+            | SyncEvent::Slideshow { user_id, .. }
+            | SyncEvent::PlaylistEdit { user_id, .. }
+            | SyncEvent::Chat { user_id, .. }
+            | SyncEvent::Reaction { user_id, .. }
+            | SyncEvent::Sealed { user_id, .. }
+            | SyncEvent::Heartbeat { user_id, .. } => user_id == uid,
+            SyncEvent::Annotation { annotation } => annotation.user_id == uid,
+            SyncEvent::Link { link } => link.user_id == uid,
+            // Not bookmarks: clients re-share every one they saved, other readers' too
+            _ => true,
+        }
+    }
+    
+    /// Whether a connection may send this before it has joined: the handshake, pings and one-off queries
+    pub fn allowed_before_join(&self) -> bool {
+        matches!(self, SyncEvent::Hello { .. } | SyncEvent::UserJoined { .. } | SyncEvent::Heartbeat { .. } | SyncEvent::StatsRequest | SyncEvent::SessionRequest | SyncEvent::Admin { .. })
+    }
+    
+    /// Whether this is about what readers read and say, which `--passphrase` hides from the server
+    ///
+    /// Everything else (joins, pings, admin and slideshow traffic...) the
+    /// server has to read to run the session.
+    pub fn is_private(&self) -> bool {
+        matches!(self, SyncEvent::StateUpdate { .. } | SyncEvent::UserAction { .. } | SyncEvent::Seek { .. } | SyncEvent::Property { .. } | SyncEvent::AbLoop { .. } | SyncEvent::View { .. } | SyncEvent::PlaylistEdit { .. } | SyncEvent::Bookmark { .. } | SyncEvent::Annotation { .. } | SyncEvent::Link { .. } | SyncEvent::Chat { .. } | SyncEvent::Reaction { .. })
+    }
+}
+
+/// Moderation actions an authenticated admin can request
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum AdminCommand {
+    /// Disconnect a user from the session
+    Kick {
+        user_id: UserId,
+        reason: Option<String>,
+    },
+    
+    /// Ban a user id or IP address (also kicks matching connections)
+    Ban {
+        target: String,
     },
+    
+    /// Lift a ban on a user id or IP address
+    Unban {
+        target: String,
+    },
+    
+    /// Write the session summary (if the server keeps one) and start a new session
+    EndSession,
 }
 
 /// Messages sent over the network
@@ -113,11 +816,21 @@ pub enum SyncEvent {
 pub struct SyncMessage {
     pub event: SyncEvent,
     pub sequence: u64, // For ordering messages
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_sequence: Option<u64>, // Stamped by the server on everything it relays
 }
 
 impl SyncMessage {
     pub fn new(event: SyncEvent, sequence: u64) -> Self {
-        Self { event, sequence }
+        Self { event, sequence, server_sequence: None }
+    }
+    
+    /// Where this message falls among everything its sender sent
+    ///
+    /// The server's stamp orders messages the same way for every client; older
+    /// servers and peers in p2p mode leave it out and the sender's own counts.
+    pub fn canonical_sequence(&self) -> u64 {
+        self.server_sequence.unwrap_or(self.sequence)
     }
     
     /// Create a state update message
@@ -125,6 +838,11 @@ impl SyncMessage {
         Self::new(SyncEvent::StateUpdate { user_state }, sequence)
     }
     
+    /// Create a hello message offering wire compression and encodings, signed if we have an identity key
+    pub fn hello(compression: Vec<Compression>, encodings: Vec<Encoding>, identity: Option<IdentityProof>, sequence: u64) -> Self {
+        Self::new(SyncEvent::Hello { compression, encodings, identity }, sequence)
+    }
+    
     /// Create a user joined message
     pub fn user_joined(user_id: UserId, user_state: UserState, sequence: u64) -> Self {
         Self::new(SyncEvent::UserJoined { user_id, user_state }, sequence)
@@ -135,22 +853,67 @@ impl SyncMessage {
         Self::new(SyncEvent::UserLeft { user_id }, sequence)
     }
     
-    /// Create a heartbeat message
-    pub fn heartbeat(user_id: UserId, sequence: u64) -> Self {
-        let timestamp = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-            
-        Self::new(SyncEvent::Heartbeat { user_id, timestamp }, sequence)
+    /// Create a user action message (e.g. "pause" / "play")
+    pub fn user_action(user_id: UserId, action: &str, value: Option<f64>, sequence: u64) -> Self {
+        Self::new(SyncEvent::UserAction { user_id, action: action.to_string(), value }, sequence)
+    }
+    
+    /// Create a seek message stamped with the current wall-clock time
+    pub fn seek(user_id: UserId, target: f64, sequence: u64) -> Self {
+        Self::new(SyncEvent::Seek { user_id, target, sent_at_ms: unix_millis() }, sequence)
+    }
+    
+    /// Create a message sharing a viewer property's new value
+    pub fn property(user_id: UserId, name: &str, value: serde_json::Value, sequence: u64) -> Self {
+        Self::new(SyncEvent::Property { user_id, name: name.to_string(), value, file_name: None }, sequence)
+    }
+    
+    /// Create a message sharing a page turn, with the file turned to for readers whose playlists differ
+    pub fn page_turn(user_id: UserId, position: i32, file_name: Option<String>, sequence: u64) -> Self {
+        let value = serde_json::Value::from(position);
+        Self::new(SyncEvent::Property { user_id, name: PLAYLIST_POS.to_string(), value, file_name }, sequence)
+    }
+    
+    /// Create a bookmark message for a playlist fingerprint
+    pub fn bookmark(fingerprint: String, bookmark: Bookmark, sequence: u64) -> Self {
+        Self::new(SyncEvent::Bookmark { fingerprint, bookmark }, sequence)
+    }
+    
+    /// Create an annotation message
+    pub fn annotation(annotation: Annotation, sequence: u64) -> Self {
+        Self::new(SyncEvent::Annotation { annotation }, sequence)
+    }
+    
+    /// Create a shared link message
+    pub fn link(link: SharedLink, sequence: u64) -> Self {
+        Self::new(SyncEvent::Link { link }, sequence)
+    }
+    
+    /// Create a server announcement
+    pub fn announcement(text: String, sequence: u64) -> Self {
+        Self::new(SyncEvent::Announcement { text }, sequence)
+    }
+    
+    /// Create a heartbeat: a ping, or the pong to a ping sent at `echo_ms`
+    pub fn heartbeat(user_id: UserId, echo_ms: Option<u64>, sequence: u64) -> Self {
+        let sent_at_ms = unix_millis();
+        Self::new(SyncEvent::Heartbeat { user_id, timestamp: sent_at_ms / 1000, sent_at_ms, echo_ms }, sequence)
     }
 }
 
 /// Manages the state of all users in a sync session
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SessionState {
     pub users: HashMap<UserId, UserState>,
     pub created_at: u64,
+    #[serde(default)]
+    pub annotations: Vec<Annotation>,
+    #[serde(default)]
+    pub last_link: Option<SharedLink>, // Most recent link anyone shared
+    #[serde(default)]
+    pub salt: Option<String>, // Random, for deriving the --passphrase key; the server picks it
+    #[serde(default)]
+    pub host: Option<UserId>, // Who may edit the playlist, once the server has said
 }
 
 impl SessionState {
@@ -161,6 +924,10 @@ impl SessionState {
                 .duration_since(std::time::UNIX_EPOCH)
                 .unwrap()
                 .as_secs(),
+            annotations: Vec::new(),
+            last_link: None,
+            salt: None,
+            host: None,
         }
     }
     
@@ -174,6 +941,72 @@ impl SessionState {
         self.users.remove(user_id);
     }
     
+    /// Why the server should refuse a state update, if it should
+    ///
+    /// Positions must fall inside the sender's playlist, and a playlist
+    /// fingerprint others have reported must come with the same length.
+    pub fn check_update(&self, update: &UserState) -> Result<(), String> {
+        // MPV reports -1 with nothing loaded
+        if update.playlist_position < -1 {
+            return Err(format!("invalid playlist position {}", update.playlist_position));
+        }
+        if !update.playback_time.is_finite() || update.playback_time < 0.0 {
+            return Err(format!("invalid playback time {}", update.playback_time));
+        }
+        if let Some(len) = update.playlist_len.filter(|&len| len > 0) {
+            if update.playlist_position >= len as i32 {
+                return Err(format!("p.{} is past the end of a {}-entry playlist", update.playlist_position + 1, len));
+            }
+        }
+        if let (Some(fingerprint), Some(len)) = (&update.playlist_fingerprint, update.playlist_len) {
+            let conflicting = self.users.values()
+                .filter(|user| user.user_id != update.user_id && user.playlist_fingerprint.as_ref() == Some(fingerprint))
+                .find_map(|user| user.playlist_len.filter(|&theirs| theirs != len));
+            if let Some(theirs) = conflicting {
+                return Err(format!("playlist {} has {} entries, not {}", fingerprint, theirs, len));
+            }
+        }
+        Ok(())
+    }
+    
+    /// Take the server's copy of the session, keeping our own entry as it is
+    ///
+    /// Returns who left without us hearing about it.
+    pub fn catch_up(&mut self, snapshot: SessionState, own_user_id: &str) -> Vec<UserId> {
+        let own = self.users.remove(own_user_id);
+        let gone = self.users.keys().filter(|user_id| !snapshot.users.contains_key(*user_id)).cloned().collect();
+        
+        self.users = snapshot.users;
+        if let Some(own) = own {
+            self.users.insert(own.user_id.clone(), own);
+        }
+        self.annotations = snapshot.annotations;
+        self.last_link = snapshot.last_link.or(self.last_link.take());
+        gone
+    }
+    
+    /// Store a page note; returns false if the same user already left the same note there
+    pub fn add_annotation(&mut self, annotation: Annotation) -> bool {
+        let duplicate = self.annotations.iter().any(|a| {
+            a.user_id == annotation.user_id
+                && a.playlist_position == annotation.playlist_position
+                && a.text == annotation.text
+        });
+        if duplicate {
+            return false;
+        }
+        
+        self.annotations.push(annotation);
+        true
+    }
+    
+    /// Notes left on a page, oldest first
+    pub fn annotations_for(&self, playlist_position: i32, file_name: Option<&str>) -> Vec<&Annotation> {
+        self.annotations.iter()
+            .filter(|a| a.is_on_page(playlist_position, file_name))
+            .collect()
+    }
+    
     /// Get all users sorted by user ID for consistent display
     pub fn get_users_sorted(&self) -> Vec<&UserState> {
         let mut users: Vec<&UserState> = self.users.values().collect();
@@ -183,12 +1016,160 @@ impl SessionState {
     
     /// Format all users for CLI display
     pub fn format_for_display(&self) -> Vec<String> {
+        let name_width = self.name_width();
         self.get_users_sorted()
             .into_iter()
-            .map(|user| user.format_for_display())
+            .map(|user| user.format_for_display(name_width))
             .collect()
     }
     
+    /// Columns the longest (shortened) user name takes, to line up `UserState::format_for_display`
+    pub fn name_width(&self) -> usize {
+        self.users.values()
+            .map(|user| text_width::width(&text_width::truncate(&user.display_name(), MAX_NAME_WIDTH)))
+            .max()
+            .unwrap_or(0)
+    }
+    
+    /// Plain table of every user, one row each under a header, for `syncread status`
+    ///
+    /// Columns are padded with spaces and nothing is colored, so the output
+    /// can be piped into other tools.
+    pub fn format_table(&self, idle_after: Duration, now: u64) -> String {
+        let mut rows = vec![["USER", "PAGE", "STATE", "TIME", "RTT", "FILE"].map(String::from)];
+        for user in self.get_users_sorted() {
+            let mut state = if user.finished {
+                "finished".to_string()
+            } else if user.is_paused {
+                "paused".to_string()
+            } else {
+                "playing".to_string()
+            };
+            if let Some(idle) = user.idle_label(idle_after, now) {
+                state.push_str(&format!(", {}", idle));
+            }
+            rows.push([
+                user.user_id.clone(),
+                user.playlist_position.saturating_add(1).to_string(),
+                state,
+                format_time(user.playback_time),
+                user.rtt_ms.map(|ms| format!("{} ms", ms)).unwrap_or_else(|| "-".to_string()),
+                user.current_file_name.clone().unwrap_or_else(|| "-".to_string()),
+            ]);
+        }
+        
+        let mut widths = [0; 6];
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(text_width::width(cell));
+            }
+        }
+        rows.iter()
+            .map(|row| {
+                let cells: Vec<String> = row.iter().zip(widths).map(|(cell, width)| text_width::pad(cell, width)).collect();
+                cells.join("  ").trim_end().to_string()
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+    
+    /// Compact "name: p.N" lines in each user's color for the in-player overlay (ASS markup), other users first
+    pub fn format_for_osd(&self, current_user_id: &str, idle_after: Duration) -> String {
+        let (me, others): (Vec<&UserState>, Vec<&UserState>) = self.get_users_sorted()
+            .into_iter()
+            .partition(|user| user.user_id == current_user_id);
+        
+        let now = unix_millis() / 1000;
+        others.iter()
+            .map(|user| {
+                let idle = user.idle_label(idle_after, now).map(|label| format!(" ({})", label)).unwrap_or_default();
+                format!("{}: {}{}", user.color().paint_ass(&user.display_name()), user.short_position(), idle)
+            })
+            .chain(me.iter().map(|user| format!("{}: {}", user.color().paint_ass("you"), user.short_position())))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+    
+    /// One-line bar across the playlist with a marker where each user is, e.g. `[──A───●───B──] p.5/12`
+    ///
+    /// Other users are marked with the first letter of their id, we with
+    /// `●`, and `+` marks several users sharing a spot. `colored` paints
+    /// markers in their user's color for the terminal.
+    pub fn format_progress_bar(&self, current_user_id: &str, playlist_len: usize, width: usize, colored: bool) -> String {
+        let width = width.max(2);
+        let column = |position: i32| {
+            if playlist_len <= 1 {
+                return 0;
+            }
+            let position = position.clamp(0, playlist_len as i32 - 1) as usize;
+            position * (width - 1) / (playlist_len - 1)
+        };
+        
+        let mut cells: Vec<Vec<&UserState>> = vec![Vec::new(); width];
+        for user in self.get_users_sorted() {
+            cells[column(user.playlist_position)].push(user);
+        }
+        
+        let bar: String = cells.iter()
+            .map(|users| match users.as_slice() {
+                [] => terminal::decoration("─").to_string(),
+                [user] => {
+                    let marker = if user.user_id == current_user_id {
+                        terminal::decoration("●").to_string()
+                    } else {
+                        user.user_id.chars().next().unwrap_or('?').to_uppercase().to_string()
+                    };
+                    if colored { user.color().paint(&marker) } else { marker }
+                }
+                _ => "+".to_string(),
+            })
+            .collect();
+        
+        let page = self.users.get(current_user_id)
+            .map(|me| format!(" p.{}/{}", me.playlist_position + 1, playlist_len))
+            .unwrap_or_default();
+        format!("[{}]{}", bar, page)
+    }
+    
+    /// The user furthest back in the playlist, other than `current_user_id`
+    pub fn slowest_other(&self, current_user_id: &str) -> Option<&UserState> {
+        self.get_users_sorted()
+            .into_iter()
+            .filter(|user| user.user_id != current_user_id)
+            .min_by_key(|user| user.playlist_position)
+    }
+    
+    /// Another user by id, or by their 1-based place among the others sorted by id (as on F1, F2, ...)
+    pub fn find_other(&self, current_user_id: &str, who: &str) -> Option<&UserState> {
+        let mut others = self.get_users_sorted()
+            .into_iter()
+            .filter(|user| user.user_id != current_user_id);
+        match who.parse::<usize>() {
+            Ok(n) => others.nth(n.checked_sub(1)?),
+            Err(_) => others.find(|user| user.user_id == who),
+        }
+    }
+    
+    /// The user at the middle playlist position of everyone but `current_user_id` (the lower one of an even count)
+    pub fn median_other(&self, current_user_id: &str) -> Option<&UserState> {
+        let mut others: Vec<&UserState> = self.get_users_sorted()
+            .into_iter()
+            .filter(|user| user.user_id != current_user_id)
+            .collect();
+        others.sort_by_key(|user| user.playlist_position);
+        others.get(others.len().saturating_sub(1) / 2).copied()
+    }
+    
+    /// Number of users who reached the end of the playlist
+    pub fn finished_count(&self) -> usize {
+        self.users.values().filter(|u| u.finished).count()
+    }
+    
+    /// True once every connected user has finished
+    pub fn all_finished(&self) -> bool {
+        !self.users.is_empty() && self.finished_count() == self.users.len()
+    }
+    
     /// Check if users are synchronized (within tolerance)
     pub fn check_sync_status(&self, position_tolerance: i32) -> bool {
         if self.users.len() < 2 {
@@ -210,9 +1191,12 @@ impl SessionState {
         let user_count = self.users.len();
         let in_sync = self.check_sync_status(1); // Allow 1 position difference
         
-        let status = if in_sync { "✅ In Sync" } else { "⚠️ Out of Sync" };
+        let status = if in_sync { format!("{} In Sync", terminal::decoration("✅")) } else { format!("{} Out of Sync", terminal::decoration("⚠️")) };
         
-        format!("{} users connected - {}", user_count, status)
+        match self.finished_count() {
+            0 => format!("{} users connected - {}", user_count, status),
+            finished => format!("{} users connected - {} - {}/{} finished", user_count, status, finished, user_count),
+        }
     }
 }
 
@@ -222,6 +1206,50 @@ impl Default for SessionState {
     }
 }
 
+/// Last sequence number seen from each user, to drop stale and duplicate updates
+///
+/// Every client numbers its messages from one counter, starting over when
+/// it reconnects, so a join resets the user's entry.
+#[derive(Debug, Default)]
+pub struct SequenceTracker {
+    last_seen: HashMap<UserId, u64>,
+}
+
+impl SequenceTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Record a message from a user; returns false if it is older than one already seen
+    pub fn accept(&mut self, user_id: &str, sequence: u64) -> bool {
+        match self.last_seen.get_mut(user_id) {
+            Some(last) if sequence <= *last => false,
+            Some(last) => {
+                *last = sequence;
+                true
+            }
+            None => {
+                self.last_seen.insert(user_id.to_string(), sequence);
+                true
+            }
+        }
+    }
+    
+    /// Start counting again from a user's (re)join
+    pub fn reset(&mut self, user_id: &str, sequence: u64) {
+        self.last_seen.insert(user_id.to_string(), sequence);
+    }
+    
+    pub fn forget(&mut self, user_id: &str) {
+        self.last_seen.remove(user_id);
+    }
+    
+    /// Forget everyone, e.g. on moving to a server that counts from scratch
+    pub fn clear(&mut self) {
+        self.last_seen.clear();
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -234,6 +1262,344 @@ mod tests {
         assert!(state.is_paused);
     }
     
+    #[test]
+    fn test_session_state_roundtrip() {
+        let mut session = SessionState::new();
+        let mut user = UserState::new("alice".to_string());
+        user.playlist_position = 7;
+        session.update_user(user.clone());
+        
+        let json = serde_json::to_string(&session).unwrap();
+        let restored: SessionState = serde_json::from_str(&json).unwrap();
+        
+        assert_eq!(restored.created_at, session.created_at);
+        assert_eq!(restored.users["alice"], user);
+    }
+    
+    #[test]
+    fn test_check_update() {
+        let files: Vec<MediaSource> = ["a.png", "b.png", "c.png"].iter().map(|name| MediaSource::Local(name.into())).collect();
+        let mut session = SessionState::new();
+        let mut alice = UserState::new("alice".to_string());
+        alice.set_playlist(&files);
+        session.update_user(alice.clone());
+        
+        let mut bob = UserState::new("bob".to_string());
+        bob.set_playlist(&files);
+        bob.playlist_position = 2;
+        assert_eq!(session.check_update(&bob), Ok(()));
+        bob.playlist_position = -1;
+        assert_eq!(session.check_update(&bob), Ok(()));
+        bob.playlist_position = 3;
+        assert!(session.check_update(&bob).unwrap_err().contains("past the end"));
+        
+        // Same entries as alice's, but claiming more of them
+        bob.playlist_position = 0;
+        bob.playlist_len = Some(5);
+        assert!(session.check_update(&bob).unwrap_err().contains("has 3 entries"));
+        
+        // Older clients don't say, and only alice herself can change her playlist's length
+        alice.playlist_len = Some(4);
+        assert_eq!(session.check_update(&alice), Ok(()));
+        let mut old = UserState::new("carol".to_string());
+        old.playlist_position = 40;
+        assert_eq!(session.check_update(&old), Ok(()));
+        old.playback_time = f64::NAN;
+        assert!(session.check_update(&old).is_err());
+    }
+    
+    #[test]
+    fn test_canonical_sequence() {
+        let mut message = SyncMessage::state_update(UserState::new("alice".to_string()), 7);
+        assert_eq!(message.canonical_sequence(), 7);
+        message.server_sequence = Some(120);
+        assert_eq!(message.canonical_sequence(), 120);
+        
+        // Only there when the server stamped it
+        let json = serde_json::to_string(&SyncMessage::new(SyncEvent::Admitted, 1)).unwrap();
+        assert!(!json.contains("server_sequence"));
+    }
+    
+    #[test]
+    fn test_catch_up() {
+        let at = |user_id: &str, page: i32| {
+            let mut user = UserState::new(user_id.to_string());
+            user.playlist_position = page;
+            user
+        };
+        let mut ours = SessionState::new();
+        for user in [at("alice", 4), at("bob", 1), at("carol", 2)] {
+            ours.update_user(user);
+        }
+        
+        // Bob moved on and carol left while we were away; the server is behind on us
+        let mut snapshot = SessionState::new();
+        for user in [at("alice", 2), at("bob", 3), at("dave", 0)] {
+            snapshot.update_user(user);
+        }
+        
+        assert_eq!(ours.catch_up(snapshot, "alice"), vec!["carol".to_string()]);
+        let pages: HashMap<&str, i32> = ours.users.iter().map(|(id, user)| (id.as_str(), user.playlist_position)).collect();
+        assert_eq!(pages, HashMap::from([("alice", 4), ("bob", 3), ("dave", 0)]));
+    }
+    
+    #[test]
+    fn test_format_for_osd() {
+        let mut session = SessionState::new();
+        for (user, position) in [("me", 3), ("bob", 39), ("alice", 41)] {
+            let mut state = UserState::new(user.to_string());
+            state.playlist_position = position;
+            state.color = Some(UserColor::Green);
+            session.update_user(state);
+        }
+        session.users.get_mut("bob").unwrap().icon = Some("🐱".to_string());
+        
+        let green = |text: &str| UserColor::Green.paint_ass(text);
+        assert_eq!(
+            session.format_for_osd("me", DEFAULT_IDLE_AFTER),
+            format!("{}: p.42\n{}: p.40\n{}: p.4", green("alice"), green("🐱 bob"), green("you"))
+        );
+        
+        // States from clients that predate colors still get one
+        let old: UserState = serde_json::from_str(r#"{"user_id":"carol","playlist_position":0,"current_file":null,"current_file_name":null,"playback_time":0.0,"is_paused":true,"timestamp":0}"#).unwrap();
+        assert_eq!(old.color(), UserColor::for_user("carol"));
+    }
+    
+    #[test]
+    fn test_playlist_edit_locates_by_name() {
+        let names: Vec<String> = ["a.png", "b.png", "c.png"].map(String::from).to_vec();
+        assert_eq!(PlaylistEdit::locate(&names, 1, "b.png"), Some(1));
+        // The sender's playlist has an extra entry before this one
+        assert_eq!(PlaylistEdit::locate(&names, 3, "c.png"), Some(2));
+        assert_eq!(PlaylistEdit::locate(&names, 0, "d.png"), None);
+        
+        let edit = PlaylistEdit::Append { entry: "/home/alice/scans/extra.png".to_string() };
+        assert_eq!(edit.describe("alice"), "alice added extra.png");
+    }
+    
+    #[test]
+    fn test_ab_loop_cycle() {
+        let start = AbLoop::from_properties(&serde_json::json!("no"), &serde_json::json!("no"));
+        assert_eq!(start, AbLoop::default());
+        
+        let from = start.cycle(83.0);
+        assert_eq!(from.describe(), "loop from 01:23");
+        assert!(!from.is_complete());
+        
+        // B before A is taken as the other end
+        let both = from.cycle(60.0);
+        assert_eq!(both, AbLoop { a: Some(60.0), b: Some(83.0) });
+        assert_eq!(both.describe(), "loop 01:00-01:23");
+        assert_eq!(both.cycle(90.0), AbLoop::default());
+    }
+    
+    #[test]
+    fn test_chapter_labels() {
+        let mut state = UserState::new("alice".to_string());
+        state.chapter = Some(ChapterPosition { index: 2, count: 12, title: Some("The Heist".to_string()) });
+        assert_eq!(state.short_position(), "p.1 ch.3");
+        assert!(state.format_for_display(5).contains("(pos: 0, ch. 3/12 The Heist, time: 0.0s)"));
+        
+        let unknown = ChapterPosition { index: 0, count: 0, title: None };
+        assert_eq!(unknown.label(), "ch. 1");
+    }
+    
+    #[test]
+    fn test_format_progress_bar() {
+        let mut session = SessionState::new();
+        for (user, position) in [("me", 4), ("bob", 0), ("alice", 10), ("carol", 10)] {
+            let mut state = UserState::new(user.to_string());
+            state.playlist_position = position;
+            session.update_user(state);
+        }
+        
+        assert_eq!(session.format_progress_bar("me", 11, 11, false), "[B───●─────+] p.5/11");
+        // Squeezed into fewer columns than pages
+        assert_eq!(session.format_progress_bar("me", 11, 6, false), "[B─●──+] p.5/11");
+    }
+    
+    #[test]
+    fn test_slowest_other() {
+        let mut session = SessionState::new();
+        assert!(session.slowest_other("me").is_none());
+        
+        for (user, position) in [("me", 1), ("carol", 6), ("bob", 3), ("alice", 3)] {
+            let mut state = UserState::new(user.to_string());
+            state.playlist_position = position;
+            session.update_user(state);
+        }
+        assert_eq!(session.slowest_other("me").unwrap().user_id, "alice");
+        let median = |me: &str| session.median_other(me).map(|user| user.playlist_position);
+        assert_eq!(median("me"), Some(3));
+        assert_eq!(median("nobody"), Some(3)); // 1, 3, 3, 6
+        assert!(SessionState::new().median_other("me").is_none());
+        
+        assert_eq!(session.find_other("me", "2").unwrap().user_id, "bob");
+        assert_eq!(session.find_other("me", "carol").unwrap().playlist_position, 6);
+        assert!(session.find_other("me", "0").is_none());
+        assert!(session.find_other("me", "4").is_none());
+        assert!(session.find_other("me", "me").is_none());
+    }
+    
+    #[test]
+    fn test_annotations_per_page() {
+        let mut session = SessionState::new();
+        let mut alice = UserState::new("alice".to_string());
+        alice.playlist_position = 4;
+        alice.current_file_name = Some("p5.png".to_string());
+        
+        assert!(session.add_annotation(Annotation::new(&alice, "  look at the background ")));
+        assert!(!session.add_annotation(Annotation::new(&alice, "look at the background")));
+        
+        let notes = session.annotations_for(4, Some("p5.png"));
+        assert_eq!(notes.len(), 1);
+        assert_eq!(notes[0].text, "look at the background");
+        assert!(session.annotations_for(4, Some("other.png")).is_empty());
+        assert!(session.annotations_for(3, Some("p5.png")).is_empty());
+        
+        let long = "x".repeat(MAX_ANNOTATION_LENGTH + 10);
+        assert_eq!(Annotation::new(&alice, &long).text.len(), MAX_ANNOTATION_LENGTH);
+    }
+    
+    #[test]
+    fn test_shared_link_checks() {
+        assert_eq!(SharedLink::new("alice".into(), " https://example.org/wiki/Ch_3 ").unwrap().url, "https://example.org/wiki/Ch_3");
+        assert!(SharedLink::new("alice".into(), "file:///etc/passwd").is_err());
+        assert!(SharedLink::new("alice".into(), "javascript:alert(1)").is_err());
+        assert!(SharedLink::new("alice".into(), "https://example.org/a b").is_err());
+        assert!(SharedLink::new("alice".into(), &format!("https://{}", "x".repeat(MAX_LINK_LENGTH))).is_err());
+    }
+    
+    #[test]
+    fn test_format_table() {
+        let mut session = SessionState::new();
+        let mut bob = UserState::new("bob".to_string());
+        bob.playlist_position = 11;
+        bob.current_file_name = Some("p12.png".to_string());
+        bob.rtt_ms = Some(42);
+        bob.last_active = 1_000;
+        session.update_user(bob);
+        let mut alice = UserState::new("alice".to_string());
+        alice.is_paused = false;
+        alice.playback_time = 83.0;
+        alice.last_active = 1_000;
+        session.update_user(alice);
+        
+        assert_eq!(session.format_table(DEFAULT_IDLE_AFTER, 1_400), [
+            "USER   PAGE  STATE             TIME   RTT    FILE",
+            "alice  1     playing, idle 6m  01:23  -      -",
+            "bob    12    paused, idle 6m   00:00  42 ms  p12.png",
+        ].join("\n"));
+    }
+    
+    #[test]
+    fn test_wide_names_line_up() {
+        let mut session = SessionState::new();
+        let mut cat = UserState::new("猫".to_string());
+        cat.current_file_name = Some("第一章-".repeat(10) + ".png");
+        session.update_user(cat);
+        let mut alice = UserState::new("alice".to_string());
+        alice.icon = Some("🐱".to_string());
+        session.update_user(alice);
+        
+        let lines = session.format_for_display();
+        assert!(lines[0].starts_with("🐱 alice: ⏸"));
+        assert!(lines[1].starts_with("猫:       ⏸ 第一章-第一章-"));
+        assert!(lines[1].contains("…"));
+        let status_column = |line: &str| text_width::width(&line[..line.find('⏸').unwrap()]);
+        assert_eq!(status_column(&lines[0]), status_column(&lines[1]));
+        
+        let table = session.format_table(DEFAULT_IDLE_AFTER, 0);
+        assert!(table.lines().nth(1).unwrap().starts_with("alice  1 "));
+        assert!(table.lines().nth(2).unwrap().starts_with("猫     1 "));
+    }
+    
+    #[test]
+    fn test_idle_after_no_activity() {
+        let mut before = UserState::new("bob".to_string());
+        before.last_active = 1_000;
+        
+        let mut still = before.clone();
+        still.timestamp = 1_400;
+        still.carry_activity(&before);
+        assert_eq!(still.last_active, 1_000);
+        assert_eq!(still.idle_label(DEFAULT_IDLE_AFTER, 1_200), None);
+        assert_eq!(still.idle_label(DEFAULT_IDLE_AFTER, 1_400).as_deref(), Some("idle 6m"));
+        assert_eq!(still.idle_label(DEFAULT_IDLE_AFTER, 1_000 + 65 * 60).as_deref(), Some("idle 1h 5m"));
+        
+        let mut turned = still.clone();
+        turned.playlist_position += 1;
+        turned.carry_activity(&still);
+        assert_eq!(turned.last_active, 1_400);
+        
+        let old = UserState { last_active: 0, ..before };
+        assert_eq!(old.idle_label(DEFAULT_IDLE_AFTER, u64::MAX), None);
+    }
+    
+    #[test]
+    fn test_finished_count() {
+        let mut session = SessionState::new();
+        assert!(!session.all_finished());
+        
+        let mut alice = UserState::new("alice".to_string());
+        alice.finished = true;
+        session.update_user(alice);
+        session.update_user(UserState::new("bob".to_string()));
+        
+        assert_eq!(session.finished_count(), 1);
+        assert!(!session.all_finished());
+        assert!(session.get_sync_summary().ends_with("1/2 finished"));
+        
+        session.remove_user(&"bob".to_string());
+        assert!(session.all_finished());
+    }
+    
+    #[test]
+    fn test_sequence_tracker() {
+        let mut tracker = SequenceTracker::new();
+        assert!(tracker.accept("alice", 5));
+        assert!(tracker.accept("alice", 7));
+        assert!(!tracker.accept("alice", 7)); // Duplicate
+        assert!(!tracker.accept("alice", 6)); // Arrived late
+        assert!(tracker.accept("bob", 1));
+        
+        // Reconnected clients count from the start again
+        tracker.reset("alice", 1);
+        assert!(tracker.accept("alice", 2));
+        
+        tracker.forget("bob");
+        assert!(tracker.accept("bob", 1));
+    }
+    
+    #[test]
+    fn test_rename_user() {
+        let mut joined = SyncMessage::user_joined("alice".into(), UserState::new("alice".into()), 1).event;
+        joined.rename_user("alice", "alice (2)");
+        assert!(matches!(joined, SyncEvent::UserJoined { ref user_id, ref user_state } if user_id == "alice (2)" && user_state.user_id == "alice (2)"));
+        
+        // Only the sender is renamed
+        let mut kicked = SyncEvent::Kicked { user_id: "alice".into(), reason: None };
+        kicked.rename_user("alice", "alice (2)");
+        assert!(matches!(kicked, SyncEvent::Kicked { ref user_id, .. } if user_id == "alice"));
+    }
+    
+    #[test]
+    fn test_is_from() {
+        let update = SyncMessage::state_update(UserState::new("alice".into()), 1).event;
+        assert!(update.is_from("alice"));
+        assert!(!update.is_from("bob"));
+        
+        // Both ids in a join have to match
+        let mut joined = SyncEvent::UserJoined { user_id: "alice".into(), user_state: UserState::new("bob".into()) };
+        assert!(!joined.is_from("alice"));
+        joined.rename_user("bob", "alice");
+        assert!(joined.is_from("alice"));
+        
+        assert!(!SyncEvent::UserLeft { user_id: "alice".into() }.is_from("bob"));
+        assert!(SyncEvent::SessionRequest.is_from("bob"));
+        assert!(!SyncEvent::Chat { user_id: "alice".into(), text: "hi".into() }.allowed_before_join());
+    }
+    
     #[test]
     fn test_session_state_sync_check() {
         let mut session = SessionState::new();
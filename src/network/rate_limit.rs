@@ -0,0 +1,85 @@
+use tokio::time::Instant;
+
+/// Messages a client may send in a burst before being throttled
+pub const BURST: f64 = 40.0;
+
+/// Messages per second a client may keep sending (a busy client sends a few)
+pub const RATE: f64 = 20.0;
+
+/// Largest message accepted from a client; session snapshots only flow the other way
+pub const MAX_CLIENT_MESSAGE_SIZE: usize = 64 * 1024;
+
+/// Strikes that get a connection closed; each dropped message is one, and one wears off per second
+const MAX_STRIKES: f64 = 100.0;
+
+/// What to do with a message from a client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Allow,
+    /// Drop the message; the client is sending too fast
+    Throttle,
+    /// The client kept flooding after being throttled
+    Disconnect,
+}
+
+/// Token bucket limiting how fast one connection's messages are handled
+#[derive(Debug)]
+pub struct RateLimiter {
+    tokens: f64,
+    strikes: f64,
+    last: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(now: Instant) -> Self {
+        Self { tokens: BURST, strikes: 0.0, last: now }
+    }
+    
+    /// Account for one incoming message
+    pub fn check(&mut self, now: Instant) -> Verdict {
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.last = now;
+        self.tokens = (self.tokens + elapsed * RATE).min(BURST);
+        self.strikes = (self.strikes - elapsed).max(0.0);
+        
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Verdict::Allow;
+        }
+        self.strike()
+    }
+    
+    /// Count a dropped message against the connection
+    fn strike(&mut self) -> Verdict {
+        self.strikes += 1.0;
+        if self.strikes > MAX_STRIKES {
+            Verdict::Disconnect
+        } else {
+            Verdict::Throttle
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::Duration;
+    
+    #[test]
+    fn test_throttles_then_disconnects_floods() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(start);
+        for _ in 0..BURST as usize {
+            assert_eq!(limiter.check(start), Verdict::Allow);
+        }
+        assert_eq!(limiter.check(start), Verdict::Throttle);
+        
+        // Back to a normal pace, the bucket refills
+        let later = start + Duration::from_secs(1);
+        assert_eq!(limiter.check(later), Verdict::Allow);
+        
+        let verdicts: Vec<_> = (0..200).map(|_| limiter.check(later)).collect();
+        assert!(verdicts.contains(&Verdict::Throttle));
+        assert_eq!(verdicts.last(), Some(&Verdict::Disconnect));
+    }
+}
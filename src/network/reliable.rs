@@ -0,0 +1,183 @@
+use super::protocol::{SyncEvent, SyncMessage};
+use std::collections::{BTreeMap, VecDeque};
+use tokio::time::{Duration, Instant};
+
+/// How long to wait for an ack before sending a reliable message again
+pub const ACK_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Resends before giving up on a message and warning about the connection
+pub const MAX_RESENDS: u32 = 3;
+
+/// Sequence numbers remembered per connection for spotting resent duplicates
+const DUPLICATE_WINDOW: usize = 256;
+
+/// Short name of a reliable message for connection warnings ("pause", "note", ...)
+pub fn describe(message: &SyncMessage) -> String {
+    match &message.event {
+        SyncEvent::UserAction { action, .. } => action.clone(),
+        SyncEvent::Seek { target, .. } => format!("seek to {:.1}s", target),
+        SyncEvent::Bookmark { bookmark, .. } => format!("bookmark on p.{}", bookmark.playlist_position + 1),
+        SyncEvent::Annotation { annotation } => format!("note on p.{}", annotation.playlist_position + 1),
+        SyncEvent::Link { .. } => "link".to_string(),
+        _ => format!("message {}", message.sequence),
+    }
+}
+
+/// A reliable message still waiting for its ack
+#[derive(Debug)]
+struct Pending {
+    message: SyncMessage,
+    sent_at: Instant,
+    resends: u32,
+}
+
+/// Reliable messages sent but not yet acknowledged, keyed by sequence number
+#[derive(Debug, Default)]
+pub struct PendingAcks {
+    pending: BTreeMap<u64, Pending>,
+}
+
+impl PendingAcks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Start waiting for an ack if the message needs one
+    pub fn track(&mut self, message: &SyncMessage, now: Instant) {
+        if message.event.is_reliable() {
+            self.pending.insert(message.sequence, Pending { message: message.clone(), sent_at: now, resends: 0 });
+        }
+    }
+    
+    /// Stop waiting for a message; returns false for unknown or repeated acks
+    pub fn ack(&mut self, sequence: u64) -> bool {
+        self.pending.remove(&sequence).is_some()
+    }
+    
+    /// Messages due to be sent again, and the ones given up on after `MAX_RESENDS`
+    pub fn poll(&mut self, now: Instant) -> (Vec<SyncMessage>, Vec<SyncMessage>) {
+        let mut resend = Vec::new();
+        let mut expired = Vec::new();
+        
+        self.pending.retain(|_, pending| {
+            if now.duration_since(pending.sent_at) < ACK_TIMEOUT {
+                return true;
+            }
+            if pending.resends >= MAX_RESENDS {
+                expired.push(pending.message.clone());
+                return false;
+            }
+            pending.resends += 1;
+            pending.sent_at = now;
+            resend.push(pending.message.clone());
+            true
+        });
+        
+        (resend, expired)
+    }
+    
+    /// Everything still unacknowledged, oldest first, to send on a new connection
+    ///
+    /// Doesn't count as a resend: the old connection may never have delivered them.
+    pub fn resend_all(&mut self, now: Instant) -> Vec<SyncMessage> {
+        self.pending.values_mut()
+            .map(|pending| {
+                pending.sent_at = now;
+                pending.message.clone()
+            })
+            .collect()
+    }
+    
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+/// Recently seen reliable sequence numbers from one connection
+#[derive(Debug, Default)]
+pub struct DuplicateFilter {
+    seen: VecDeque<u64>,
+}
+
+impl DuplicateFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// True the first time a sequence number shows up, false for resends
+    pub fn first_time(&mut self, sequence: u64) -> bool {
+        if self.seen.contains(&sequence) {
+            return false;
+        }
+        if self.seen.len() >= DUPLICATE_WINDOW {
+            self.seen.pop_front();
+        }
+        self.seen.push_back(sequence);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::protocol::UserState;
+    
+    #[test]
+    fn test_resend_until_acked_or_expired() {
+        let start = Instant::now();
+        let mut pending = PendingAcks::new();
+        pending.track(&SyncMessage::user_action("alice".into(), "pause", None, 4), start);
+        pending.track(&SyncMessage::user_action("alice".into(), "play", None, 5), start);
+        pending.track(&SyncMessage::state_update(UserState::new("alice".into()), 6), start); // Lossy, not tracked
+        assert_eq!(pending.len(), 2);
+        
+        assert!(pending.poll(start + Duration::from_secs(1)).0.is_empty());
+        assert!(pending.ack(4));
+        assert!(!pending.ack(4));
+        
+        let mut now = start;
+        for _ in 0..MAX_RESENDS {
+            now += ACK_TIMEOUT;
+            let (resend, expired) = pending.poll(now);
+            assert_eq!(resend.iter().map(|m| m.sequence).collect::<Vec<_>>(), vec![5]);
+            assert!(expired.is_empty());
+        }
+        
+        let (resend, expired) = pending.poll(now + ACK_TIMEOUT);
+        assert!(resend.is_empty());
+        assert_eq!(expired.len(), 1);
+        assert!(pending.is_empty());
+    }
+    
+    #[test]
+    fn test_resend_all_after_reconnecting() {
+        let start = Instant::now();
+        let mut pending = PendingAcks::new();
+        pending.track(&SyncMessage::user_action("alice".into(), "play", None, 9), start);
+        pending.track(&SyncMessage::user_action("alice".into(), "pause", None, 7), start);
+        
+        let now = start + ACK_TIMEOUT;
+        let sequences: Vec<u64> = pending.resend_all(now).iter().map(|m| m.sequence).collect();
+        assert_eq!(sequences, vec![7, 9]);
+        
+        // Not due again yet, and still the full number of resends left
+        assert!(pending.poll(now + Duration::from_secs(1)).0.is_empty());
+        let mut later = now;
+        for _ in 0..MAX_RESENDS {
+            later += ACK_TIMEOUT;
+            assert_eq!(pending.poll(later).0.len(), 2);
+        }
+    }
+    
+    #[test]
+    fn test_duplicate_filter() {
+        let mut filter = DuplicateFilter::new();
+        assert!(filter.first_time(3));
+        assert!(filter.first_time(4));
+        assert!(!filter.first_time(3));
+    }
+}
@@ -0,0 +1,96 @@
+//! Slideshows the server runs for the whole session
+//!
+//! A reader starts one from their page; the server then tells everyone
+//! which page to show each time the interval passes, through the end of
+//! the starter's playlist. Pausing keeps the time left on the current page.
+
+use tokio::time::{Duration, Instant};
+
+/// Seconds per page when the server isn't given `--slideshow-interval`
+pub const DEFAULT_INTERVAL_SECS: u64 = 20;
+
+/// A running or paused slideshow
+#[derive(Debug, Clone)]
+pub struct Slideshow {
+    interval: Duration,
+    page: i32,
+    last_page: i32,
+    left: Duration, // On the current page, as of `since`
+    since: Option<Instant>, // When the countdown last started; None while paused
+}
+
+impl Slideshow {
+    /// Start on `from` and run through `count` pages of the starter's playlist
+    pub fn start(interval: Duration, from: i32, count: usize, now: Instant) -> Self {
+        Self {
+            interval,
+            page: from,
+            last_page: count as i32 - 1,
+            left: interval,
+            since: Some(now),
+        }
+    }
+    
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+    
+    pub fn is_paused(&self) -> bool {
+        self.since.is_none()
+    }
+    
+    pub fn pause(&mut self, now: Instant) {
+        if let Some(since) = self.since.take() {
+            self.left = self.left.saturating_sub(now - since);
+        }
+    }
+    
+    pub fn resume(&mut self, now: Instant) {
+        if self.since.is_none() {
+            self.since = Some(now);
+        }
+    }
+    
+    /// The page to turn to, if the current one's time is up
+    pub fn advance(&mut self, now: Instant) -> Option<i32> {
+        let since = self.since?;
+        if now - since < self.left || self.is_finished() {
+            return None;
+        }
+        self.page += 1;
+        self.left = self.interval;
+        self.since = Some(now);
+        Some(self.page)
+    }
+    
+    /// Whether the last page is showing
+    pub fn is_finished(&self) -> bool {
+        self.page >= self.last_page
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_slideshow_advances_and_pauses() {
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+        let mut slideshow = Slideshow::start(Duration::from_secs(10), 1, 4, start);
+        
+        assert_eq!(slideshow.advance(at(9)), None);
+        assert_eq!(slideshow.advance(at(10)), Some(2));
+        
+        // Paused 4s into page 3, so 6s are left once resumed
+        slideshow.pause(at(14));
+        assert_eq!(slideshow.advance(at(30)), None);
+        slideshow.resume(at(30));
+        assert_eq!(slideshow.advance(at(35)), None);
+        assert_eq!(slideshow.advance(at(36)), Some(3));
+        
+        // Page 4 is the last
+        assert!(slideshow.is_finished());
+        assert_eq!(slideshow.advance(at(60)), None);
+    }
+}
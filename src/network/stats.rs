@@ -0,0 +1,157 @@
+use super::protocol::{UserId, UserState};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// Longest gap between two reports that still counts as reading time (s)
+///
+/// Anything longer means the reader dropped off for a while (disconnect,
+/// suspended laptop) and the time shouldn't pile up on one page.
+const MAX_REPORT_GAP: f64 = 30.0;
+
+/// Time one reader spent on each playlist index
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ReadingStats {
+    pub page_seconds: BTreeMap<i32, f64>,
+    pub pages_turned: u32,
+}
+
+impl ReadingStats {
+    /// Add reading time to a page
+    pub fn record(&mut self, page: i32, seconds: f64) {
+        *self.page_seconds.entry(page).or_default() += seconds;
+    }
+    
+    /// Total reading time in seconds
+    pub fn total_seconds(&self) -> f64 {
+        self.page_seconds.values().sum()
+    }
+    
+    /// Pages (or files) turned per hour of reading
+    pub fn pages_per_hour(&self) -> Option<f64> {
+        let hours = self.total_seconds() / 3600.0;
+        (hours > 0.0 && self.pages_turned > 0).then(|| self.pages_turned as f64 / hours)
+    }
+    
+    /// Average time spent on each visited page
+    pub fn average_page_seconds(&self) -> Option<f64> {
+        (!self.page_seconds.is_empty()).then(|| self.total_seconds() / self.page_seconds.len() as f64)
+    }
+    
+    /// The page read the longest, as (index, seconds)
+    pub fn longest_page(&self) -> Option<(i32, f64)> {
+        self.page_seconds.iter()
+            .map(|(&page, &seconds)| (page, seconds))
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+    }
+    
+    /// One-line summary ("12 pages in 8m 30s, 84.7 pages/hour, 42.5s per page, longest p.7 (3m 02s)")
+    pub fn format_summary(&self) -> String {
+        let pages = self.page_seconds.len();
+        let page_word = if pages == 1 { "page" } else { "pages" };
+        let mut parts = vec![format!("{} {} in {}", pages, page_word, format_duration(self.total_seconds()))];
+        
+        if let Some(rate) = self.pages_per_hour() {
+            parts.push(format!("{:.1} pages/hour", rate));
+        }
+        if let Some(average) = self.average_page_seconds() {
+            parts.push(format!("{:.1}s per page", average));
+        }
+        if let Some((page, seconds)) = self.longest_page() {
+            parts.push(format!("longest p.{} ({})", page + 1, format_duration(seconds)));
+        }
+        
+        parts.join(", ")
+    }
+}
+
+/// Builds reading stats from the stream of state reports for each user
+#[derive(Debug, Default)]
+pub struct StatsTracker {
+    users: HashMap<UserId, ReadingStats>,
+    last_report: HashMap<UserId, (i32, u64)>, // (page, timestamp_ms) of each user's previous report
+}
+
+impl StatsTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Credit the time since a user's previous report to the page they were on
+    pub fn observe(&mut self, state: &UserState) {
+        let stats = self.users.entry(state.user_id.clone()).or_default();
+        
+        if let Some((page, at_ms)) = self.last_report.get(&state.user_id).copied() {
+            let elapsed = state.timestamp_ms.saturating_sub(at_ms) as f64 / 1000.0;
+            if elapsed <= MAX_REPORT_GAP {
+                stats.record(page, elapsed);
+            }
+            if page != state.playlist_position {
+                stats.pages_turned += 1;
+            }
+        }
+        
+        self.last_report.insert(state.user_id.clone(), (state.playlist_position, state.timestamp_ms));
+    }
+    
+    /// Stop timing a user who left, keeping what they read so far
+    pub fn user_left(&mut self, user_id: &str) {
+        self.last_report.remove(user_id);
+    }
+    
+    /// Stats for one user
+    pub fn get(&self, user_id: &str) -> Option<&ReadingStats> {
+        self.users.get(user_id)
+    }
+    
+    /// Stats for everyone seen this session
+    pub fn all(&self) -> &HashMap<UserId, ReadingStats> {
+        &self.users
+    }
+}
+
+/// Compact human duration: "42s", "3m 02s", "1h 05m"
+pub fn format_duration(seconds: f64) -> String {
+    let total = seconds.round() as u64;
+    match total {
+        0..=59 => format!("{}s", total),
+        60..=3599 => format!("{}m {:02}s", total / 60, total % 60),
+        _ => format!("{}h {:02}m", total / 3600, total % 3600 / 60),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn report(page: i32, at_ms: u64) -> UserState {
+        let mut state = UserState::new("alice".to_string());
+        state.playlist_position = page;
+        state.timestamp_ms = at_ms;
+        state
+    }
+    
+    #[test]
+    fn test_tracker_credits_previous_page() {
+        let mut tracker = StatsTracker::new();
+        tracker.observe(&report(0, 0));
+        tracker.observe(&report(0, 10_000));
+        tracker.observe(&report(1, 20_000));
+        tracker.observe(&report(1, 25_000));
+        tracker.observe(&report(2, 500_000)); // Long gap: not counted
+        
+        let stats = tracker.get("alice").unwrap();
+        assert_eq!(stats.page_seconds[&0], 20.0);
+        assert_eq!(stats.page_seconds[&1], 5.0);
+        assert_eq!(stats.pages_turned, 2);
+        assert_eq!(stats.longest_page(), Some((0, 20.0)));
+        assert_eq!(stats.average_page_seconds(), Some(12.5));
+        assert_eq!(stats.pages_per_hour(), Some(288.0));
+    }
+    
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(42.4), "42s");
+        assert_eq!(format_duration(182.0), "3m 02s");
+        assert_eq!(format_duration(3900.0), "1h 05m");
+    }
+}
@@ -0,0 +1,227 @@
+//! What a session amounted to, written out when it ends (`--summary`)
+//!
+//! Built from the server's event log (joins, leaves and messages) and its
+//! reading stats (time per page), so it covers everyone seen since the
+//! session started, including readers who left early.
+
+use super::event_log::LoggedEvent;
+use super::protocol::UserId;
+use super::stats::{format_duration, ReadingStats};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+/// Event kinds that make up the transcript
+const TRANSCRIPT_KINDS: &[&str] = &["chat", "note", "link", "reaction"];
+
+/// One reader's part in the session
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Participant {
+    pub user_id: UserId,
+    pub joined_ms: Option<u64>, // First join, ms since the Unix epoch
+    pub left_ms: Option<u64>, // Last leave; None if still there at the end
+    pub pages_read: usize,
+    pub pages_turned: u32,
+    pub reading_seconds: f64,
+    pub pages_per_hour: Option<f64>,
+    pub seconds_per_page: Option<f64>,
+}
+
+/// A chat message, note, link or reaction, as it was sent
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TranscriptLine {
+    pub at_ms: u64,
+    pub user_id: UserId,
+    pub kind: String,
+    pub page: Option<i32>, // Numbered from 1
+    pub text: String,
+}
+
+/// Everything written out at the end of a session
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SessionSummary {
+    pub started_ms: u64,
+    pub ended_ms: u64,
+    pub duration_seconds: f64,
+    pub pages_covered: Vec<i32>, // Every page someone read, numbered from 1
+    pub participants: Vec<Participant>,
+    pub transcript: Vec<TranscriptLine>,
+}
+
+impl SessionSummary {
+    /// Summarize the session between `started_ms` and `ended_ms` from its logged events and reading stats
+    pub fn build(started_ms: u64, ended_ms: u64, events: &[LoggedEvent], stats: &HashMap<UserId, ReadingStats>) -> Self {
+        let mut participants: BTreeMap<UserId, Participant> = BTreeMap::new();
+        let mut pages_covered = BTreeSet::new();
+        let mut transcript = Vec::new();
+        
+        for event in events.iter().filter(|event| event.at_ms >= started_ms && event.at_ms <= ended_ms) {
+            let participant = participants.entry(event.user_id.clone()).or_default();
+            match event.kind.as_str() {
+                "join" => {
+                    participant.joined_ms.get_or_insert(event.at_ms);
+                    participant.left_ms = None;
+                }
+                "leave" => participant.left_ms = Some(event.at_ms),
+                kind if TRANSCRIPT_KINDS.contains(&kind) => transcript.push(TranscriptLine {
+                    at_ms: event.at_ms,
+                    user_id: event.user_id.clone(),
+                    kind: kind.to_string(),
+                    page: event.page.map(|page| page + 1),
+                    text: event.detail.clone().unwrap_or_default(),
+                }),
+                _ => {}
+            }
+        }
+        
+        for (user_id, reading) in stats {
+            let participant = participants.entry(user_id.clone()).or_default();
+            participant.pages_read = reading.page_seconds.len();
+            participant.pages_turned = reading.pages_turned;
+            participant.reading_seconds = reading.total_seconds();
+            participant.pages_per_hour = reading.pages_per_hour();
+            participant.seconds_per_page = reading.average_page_seconds();
+            pages_covered.extend(reading.page_seconds.keys().map(|page| page + 1));
+        }
+        for (user_id, participant) in &mut participants {
+            participant.user_id = user_id.clone();
+        }
+        
+        Self {
+            started_ms,
+            ended_ms,
+            duration_seconds: ended_ms.saturating_sub(started_ms) as f64 / 1000.0,
+            pages_covered: pages_covered.into_iter().collect(),
+            participants: participants.into_values().collect(),
+            transcript,
+        }
+    }
+    
+    /// Write the summary to `path`: JSON, or CSV if it ends in `.csv`
+    ///
+    /// A CSV summary is the participants table; the transcript goes next to
+    /// it, in `<name>.chat.csv`. Returns every file written.
+    pub fn write(&self, path: &Path) -> Result<Vec<PathBuf>> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory {:?}", parent))?;
+        }
+        
+        if !path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("csv")) {
+            let content = serde_json::to_string_pretty(self)?;
+            std::fs::write(path, content).with_context(|| format!("Failed to write {:?}", path))?;
+            return Ok(vec![path.to_path_buf()]);
+        }
+        
+        let transcript_path = path.with_extension("chat.csv");
+        std::fs::write(path, self.participants_csv()).with_context(|| format!("Failed to write {:?}", path))?;
+        std::fs::write(&transcript_path, self.transcript_csv())
+            .with_context(|| format!("Failed to write {:?}", transcript_path))?;
+        Ok(vec![path.to_path_buf(), transcript_path])
+    }
+    
+    /// One row per participant
+    pub fn participants_csv(&self) -> String {
+        let mut csv = String::from("user_id,joined_ms,left_ms,pages_read,pages_turned,reading_seconds,pages_per_hour,seconds_per_page\n");
+        for p in &self.participants {
+            let row = [
+                csv_field(&p.user_id),
+                optional(p.joined_ms),
+                optional(p.left_ms),
+                p.pages_read.to_string(),
+                p.pages_turned.to_string(),
+                format!("{:.1}", p.reading_seconds),
+                optional(p.pages_per_hour.map(|rate| format!("{:.1}", rate))),
+                optional(p.seconds_per_page.map(|seconds| format!("{:.1}", seconds))),
+            ];
+            csv.push_str(&row.join(","));
+            csv.push('\n');
+        }
+        csv
+    }
+    
+    /// One row per message, note, link or reaction, with how far into the session it came
+    pub fn transcript_csv(&self) -> String {
+        let mut csv = String::from("at_ms,elapsed,user_id,kind,page,text\n");
+        for line in &self.transcript {
+            let elapsed = line.at_ms.saturating_sub(self.started_ms) as f64 / 1000.0;
+            let row = [
+                line.at_ms.to_string(),
+                format_duration(elapsed),
+                csv_field(&line.user_id),
+                line.kind.clone(),
+                optional(line.page),
+                csv_field(&line.text),
+            ];
+            csv.push_str(&row.join(","));
+            csv.push('\n');
+        }
+        csv
+    }
+    
+    /// "3 readers over 1h 05m, 42 pages covered, 12 messages"
+    pub fn describe(&self) -> String {
+        let readers = self.participants.len();
+        format!(
+            "{} {} over {}, {} pages covered, {} messages",
+            readers,
+            if readers == 1 { "reader" } else { "readers" },
+            format_duration(self.duration_seconds),
+            self.pages_covered.len(),
+            self.transcript.len(),
+        )
+    }
+}
+
+/// A value, or an empty field
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+/// Quote a field if it holds a comma, quote or line break
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn event(at_ms: u64, user_id: &str, kind: &str, page: Option<i32>, detail: Option<&str>) -> LoggedEvent {
+        LoggedEvent { at_ms, user_id: user_id.to_string(), kind: kind.to_string(), page, detail: detail.map(String::from) }
+    }
+    
+    #[test]
+    fn test_build_summary() {
+        let events = vec![
+            event(500, "carol", "join", Some(0), None), // From an earlier session
+            event(1_000, "alice", "join", Some(0), None),
+            event(2_000, "bob", "join", Some(0), None),
+            event(3_000, "bob", "note", Some(1), Some("look, \"this\" panel")),
+            event(4_000, "bob", "leave", None, None),
+        ];
+        let mut alice = ReadingStats::default();
+        alice.record(0, 60.0);
+        alice.record(2, 30.0);
+        alice.pages_turned = 2;
+        let stats = HashMap::from([("alice".to_string(), alice)]);
+        
+        let summary = SessionSummary::build(1_000, 61_000, &events, &stats);
+        assert_eq!(summary.duration_seconds, 60.0);
+        assert_eq!(summary.pages_covered, vec![1, 3]);
+        assert_eq!(summary.participants.len(), 2);
+        assert_eq!(summary.participants[0].user_id, "alice");
+        assert_eq!(summary.participants[0].pages_read, 2);
+        assert_eq!(summary.participants[1].left_ms, Some(4_000));
+        assert_eq!(summary.transcript[0].page, Some(2));
+        assert_eq!(summary.describe(), "2 readers over 1m 00s, 2 pages covered, 1 messages");
+        
+        let transcript = summary.transcript_csv();
+        assert_eq!(transcript.lines().nth(1), Some("3000,2s,bob,note,2,\"look, \"\"this\"\" panel\""));
+    }
+}
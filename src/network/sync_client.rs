@@ -1,374 +1,2356 @@
-use super::protocol::{SyncMessage, SyncEvent, UserId, UserState, SessionState};
-use crate::mpv::MpvController;
+use super::appearance::{self, UserColor};
+use super::cadence::{self, UpdateCadence};
+use super::checksums;
+use super::compression;
+use super::control;
+use super::encryption::Encryption;
+use super::file_share;
+use super::glitch::GlitchFilter;
+use super::page_map;
+use super::protocol::{self, chat_text, unix_millis, AbLoop, Annotation, ChapterPosition, DEFAULT_IDLE_AFTER, FileChecksum, PeerAddress, SequenceTracker, SharedLink, SyncMessage, SyncEvent, UserId, UserState, SessionState, PlaylistEdit, SlideshowCommand, ViewTransform};
+use super::headless::HeadlessCommand;
+use super::hooks::{HookEvent, HookRunner, Hooks};
+use super::identity::Identity;
+use super::keyboard::{self, Keyboard, KeyboardInput};
+use super::latency::{self, ClockOffset, RoundTrip};
+use super::properties::{self, PropertyTracker};
+use super::reliable::{self, PendingAcks};
+use super::stats::StatsTracker;
+use super::sync_server::SyncServer;
+use super::template::LineTemplate;
+use super::text_width;
+use super::time_sync;
+use super::wire::{self, Encoding, FrameReader, WireFormat};
+use crate::clipboard;
+use crate::error::{self, ConnectionError, MediaError, ProtocolError};
+use crate::media::MediaSource;
+use crate::mpv::{hud, screenshot_path, MpvEvent};
+use crate::player::{Chapter, MediaPlayer};
+use crate::storage::{self, Bookmark, BookmarkStore, ResumeStore};
+use crate::terminal;
 use anyhow::{Context, Result};
-use std::net::SocketAddr;
-use std::path::PathBuf;
-use tokio::net::TcpStream;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-use tokio::sync::{mpsc, RwLock, broadcast};
-use tokio::time::{interval, Duration};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::io::{AsyncRead, AsyncWriteExt};
+use tokio::sync::{mpsc, oneshot, RwLock, broadcast};
+use tokio::task::JoinHandle;
+use tokio::time::{interval, Duration, Instant};
 use tracing::{debug, error, info, warn};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 
+/// Minimum time between catch-up seeks in time sync mode
+const CATCH_UP_COOLDOWN: Duration = Duration::from_secs(3);
+
+/// How long the in-player status summary stays on screen (ms)
+const STATUS_TEXT_DURATION: i64 = 4000;
+
+/// How long a reaction stays on screen (ms); ones arriving meanwhile are shown alongside it
+const REACTION_TEXT_DURATION: i64 = 2500;
+
+/// How long notes on a page stay on screen when it is opened (ms)
+const NOTE_TEXT_DURATION: i64 = 6000;
+
+/// How long to wait for the server's `Welcome` before carrying on with plain JSON
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long each shutdown step may take before we stop waiting
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Give up restarting MPV after this many crashes within `RESTART_WINDOW`
+const MAX_RESTARTS: usize = 3;
+const RESTART_WINDOW: Duration = Duration::from_secs(60);
+
+/// How long to keep trying each host candidate after losing the server
+const HOST_ELECTION_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long `--pause-on-disconnect` keeps trying the same server
+const RECONNECT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Columns in the minimal mode playlist bar
+const PROGRESS_BAR_WIDTH: usize = 50;
+
+/// Bookmark store shared by the update task and the incoming message handler
+type SharedBookmarks = Arc<RwLock<BookmarkStore>>;
+
+/// Commands applied to the local MPV by the state update task
+#[derive(Debug)]
+enum PlayerCommand {
+    Pause,
+    Play,
+    Seek { target: f64, sent_at_ms: u64, round_trip_ms: Option<u64> }, // Sender's and our round trip to the server
+    ShowText(String),
+    ShowReaction(String),
+    InsertFile { index: usize, file: MediaSource },
+    SetProperty { name: String, value: Value }, // Someone else changed a synced property
+    SetAbLoop(AbLoop),
+    SetView(ViewTransform), // The presenter zoomed or panned
+    EditPlaylist { edit: PlaylistEdit, ours: bool }, // Someone (maybe us) added, removed or moved an entry
+    Screenshot { moment: u64 }, // Someone took a group screenshot at this Unix time
+    Follow(Option<UserId>), // We picked someone whose page turns to follow, or went back to everyone's
+}
+
+/// Where to download each sharing reader's playlist items, and their names in order
+type FileSources = BTreeMap<UserId, (SocketAddr, Vec<String>)>;
+
+/// What the update task has to follow up on after a syncread action from MPV
+#[derive(Default)]
+struct ActionOutcome {
+    restarted: bool, // MPV was relaunched and lost its overlays
+    outgoing: Option<SyncEvent>, // Bookmark, note or link to share with the session
+    fetch_missing: bool, // Download playlist items others share that we don't have
+    local: Option<PlayerCommand>, // Applied to our own MPV, as if someone else had sent it
+}
+
+/// How the terminal shows the session, fixed for a run
+struct TerminalView {
+    minimal: bool,
+    playlist_len: usize,
+    idle_after: Duration,
+    line: Option<LineTemplate>, // Replaces each reader's usual line
+    keys: Option<Arc<std::sync::Mutex<KeyboardInput>>>, // For drawing the prompt being typed
+}
+
 /// Client that connects to sync server and synchronizes MPV state
 pub struct SyncClient {
     user_id: UserId,
-    sequence_counter: u64,
+    color: UserColor,
+    icon: Option<String>, // Emoji avatar shown before our name
+    sequence_counter: Arc<AtomicU64>, // Shared with the state update task
     session_state: Arc<RwLock<SessionState>>,
     last_known_position: Arc<RwLock<Option<i32>>>,
     pending_position: Arc<RwLock<Option<(i32, u8)>>>, // (position, retry_count)
+    glitch_filter: GlitchFilter, // Which position changes to believe
+    stats: Arc<RwLock<StatsTracker>>, // Our own reading time per page
+    sequences: Arc<RwLock<SequenceTracker>>, // Latest state update seen per user
+    resume: Option<(ResumeStore, String)>, // (store, playlist fingerprint)
+    bookmarks: Option<(SharedBookmarks, String)>, // (store, playlist fingerprint)
+    time_sync_threshold: Option<f64>, // Seconds behind the leader before seeking
+    wait_for_all: Option<i32>, // Pages we may get ahead of the slowest reader
+    shared_pause: bool,
+    synced_properties: Vec<String>, // Viewer properties shared beyond pause and time
+    chapter_mode: bool, // Report which chapter of a long file we're in
+    presenting: bool, // Share our zoom and pan with the session
+    follow_presenter: bool, // Show the crop a presenter shares
+    finish_pause: bool,
+    pause_on_disconnect: bool, // Hold MPV paused while we can't reach the session
+    osd_overlay: bool,
+    show_links: bool, // Also put links others share on the MPV OSD
+    idle_after: Duration, // Inactivity before others show as idle
+    update_interval: Duration, // Between polls of MPV and state updates
+    adaptive_updates: bool, // Vary update_interval with activity
+    hud: bool,
+    compression: bool, // Offer wire compression to the server
+    binary: bool, // Offer MessagePack framing to the server
+    wire: WireFormat, // What the server agreed to
+    host_port: Option<u16>, // Port to host on if we're elected after the server goes away
+    host_candidates: Arc<RwLock<Vec<PeerAddress>>>, // As last announced by the server
+    share_port: Option<u16>, // Port to serve our playlist's files on
+    checksums: Option<Vec<FileChecksum>>, // Hashes of our files, for the server to compare with everyone's
+    download_dir: PathBuf, // Where items fetched from others go
+    max_download_size: u64, // Bytes; larger items aren't fetched
+    screenshot_dir: PathBuf, // Where group screenshots go
+    file_sources: Arc<RwLock<FileSources>>,
+    playlist_names: Arc<RwLock<Vec<String>>>, // Our playlist, as matched against others'
+    round_trip: Arc<RwLock<RoundTrip>>, // Ping time to the server
+    following: Arc<RwLock<Option<UserId>>>, // Only this reader's page turns move us
+    control_socket: Option<PathBuf>, // Where to accept commands from other programs
+    terminal_keys: bool, // Take commands from keys pressed in the terminal
+    display_line: Option<LineTemplate>, // Replaces each reader's usual line in the terminal
+    hooks: Arc<HookRunner>, // Commands to run on session events
+    passphrase: Option<String>, // Turned into `e2e` with the salt the server hands out
+    e2e: Option<Encryption>, // Seals private events with the session passphrase
+    identity: Option<Arc<Identity>>, // Signs our user id into each Hello
 }
 
 impl SyncClient {
     /// Create a new sync client
     pub fn new(user_id: UserId) -> Self {
         Self {
+            color: UserColor::for_user(&user_id),
+            icon: None,
             user_id,
-            sequence_counter: 0,
+            sequence_counter: Arc::new(AtomicU64::new(0)),
             session_state: Arc::new(RwLock::new(SessionState::new())),
             last_known_position: Arc::new(RwLock::new(None)),
             pending_position: Arc::new(RwLock::new(None)),
+            glitch_filter: GlitchFilter::default(),
+            stats: Arc::new(RwLock::new(StatsTracker::new())),
+            sequences: Arc::new(RwLock::new(SequenceTracker::new())),
+            resume: None,
+            bookmarks: None,
+            time_sync_threshold: None,
+            wait_for_all: None,
+            shared_pause: false,
+            synced_properties: Vec::new(),
+            chapter_mode: false,
+            presenting: false,
+            follow_presenter: true,
+            finish_pause: false,
+            pause_on_disconnect: false,
+            osd_overlay: false,
+            show_links: false,
+            idle_after: DEFAULT_IDLE_AFTER,
+            update_interval: cadence::DEFAULT_UPDATE_INTERVAL,
+            adaptive_updates: false,
+            hud: false,
+            compression: true,
+            binary: false,
+            wire: WireFormat::default(),
+            host_port: None,
+            host_candidates: Arc::new(RwLock::new(Vec::new())),
+            share_port: None,
+            checksums: None,
+            download_dir: storage::data_dir().join("downloads"),
+            max_download_size: file_share::DEFAULT_MAX_DOWNLOAD_SIZE,
+            screenshot_dir: storage::data_dir().join("screenshots"),
+            file_sources: Arc::new(RwLock::new(BTreeMap::new())),
+            playlist_names: Arc::new(RwLock::new(Vec::new())),
+            round_trip: Arc::new(RwLock::new(RoundTrip::new())),
+            following: Arc::new(RwLock::new(None)),
+            control_socket: None,
+            terminal_keys: false,
+            display_line: None,
+            hooks: Arc::new(HookRunner::default()),
+            passphrase: None,
+            e2e: None,
+            identity: None,
         }
     }
     
-    /// Connect to sync server and start synchronization
+    /// Draw us in this color instead of the one picked from our user id
+    pub fn set_color(&mut self, color: UserColor) {
+        self.color = color;
+    }
+    
+    /// Show an emoji avatar before our name in everyone's displays
+    pub fn set_icon(&mut self, icon: Option<String>) {
+        self.icon = icon;
+    }
+    
+    /// Record this session's position in a resume store under the playlist fingerprint
+    pub fn set_resume_store(&mut self, store: ResumeStore, fingerprint: String) {
+        self.resume = Some((store, fingerprint));
+    }
+    
+    /// Keep shared bookmarks for this playlist in a bookmark store
+    pub fn set_bookmark_store(&mut self, store: BookmarkStore, fingerprint: String) {
+        self.bookmarks = Some((Arc::new(RwLock::new(store)), fingerprint));
+    }
+    
+    /// Seek forward whenever we fall more than `threshold` seconds behind another viewer of the same file
+    pub fn set_time_sync(&mut self, threshold: f64) {
+        self.time_sync_threshold = Some(threshold);
+    }
+    
+    /// Never get more than `tolerance` pages ahead of the slowest reader, turning MPV back if we do
+    pub fn set_wait_for_all(&mut self, tolerance: i32) {
+        self.wait_for_all = Some(tolerance.max(0));
+    }
+    
+    /// Broadcast local pause/play and apply everyone else's to our MPV
+    pub fn set_shared_pause(&mut self, shared_pause: bool) {
+        self.shared_pause = shared_pause;
+    }
+    
+    /// Share which chapter of the current file we're in, for long videos where pages say little
+    pub fn set_chapter_mode(&mut self, chapter_mode: bool) {
+        self.chapter_mode = chapter_mode;
+    }
+    
+    /// Share our zoom and pan so everyone sees the crop we're looking at
+    pub fn set_presenting(&mut self, presenting: bool) {
+        self.presenting = presenting;
+    }
+    
+    /// Show the crop a presenter shares instead of keeping our own
+    pub fn set_follow_presenter(&mut self, follow_presenter: bool) {
+        self.follow_presenter = follow_presenter;
+    }
+    
+    /// Share changes to these viewer properties and apply everyone else's (see `properties::PropertySync::generic`)
+    pub fn set_synced_properties(&mut self, names: Vec<String>) {
+        self.synced_properties = names;
+    }
+    
+    /// Pause and show an "N/M finished" banner whenever someone finishes before us
+    pub fn set_finish_pause(&mut self, finish_pause: bool) {
+        self.finish_pause = finish_pause;
+    }
+    
+    /// Pause while the server connection is lost, keep trying to get it back,
+    /// and resume once we're in the session again
+    pub fn set_pause_on_disconnect(&mut self, pause_on_disconnect: bool) {
+        self.pause_on_disconnect = pause_on_disconnect;
+    }
+    
+    /// Show where everyone is in a corner of the MPV window
+    pub fn set_osd_overlay(&mut self, osd_overlay: bool) {
+        self.osd_overlay = osd_overlay;
+    }
+    
+    /// The session as this client sees it, kept up to date while connected
+    pub fn session_state(&self) -> Arc<RwLock<SessionState>> {
+        self.session_state.clone()
+    }
+    
+    /// Show links others share on the MPV OSD as well as in the terminal
+    pub fn set_show_links(&mut self, show_links: bool) {
+        self.show_links = show_links;
+    }
+    
+    /// Mark others as idle in our displays once they've done nothing for this long
+    pub fn set_idle_after(&mut self, idle_after: Duration) {
+        self.idle_after = idle_after;
+    }
+    
+    /// Tune, or turn off, the filtering of bogus positions MPV reports while switching files
+    pub fn set_glitch_filter(&mut self, glitch_filter: GlitchFilter) {
+        self.glitch_filter = glitch_filter;
+    }
+    
+    /// Poll MPV and send our state this often (never faster than `MIN_UPDATE_INTERVAL`)
+    pub fn set_update_interval(&mut self, update_interval: Duration) {
+        self.update_interval = update_interval;
+    }
+    
+    /// Update faster right after page turns and slower while nothing changes
+    pub fn set_adaptive_updates(&mut self, adaptive: bool) {
+        self.adaptive_updates = adaptive;
+    }
+    
+    /// Feed session status to the bundled HUD script (MPV must have been launched with it)
+    pub fn set_hud(&mut self, hud: bool) {
+        self.hud = hud;
+    }
+    
+    /// Offer to compress large messages (on by default); the server decides whether to
+    pub fn set_compression(&mut self, compression: bool) {
+        self.compression = compression;
+    }
+    
+    /// Offer length-prefixed MessagePack instead of JSON lines; the server decides
+    pub fn set_binary(&mut self, binary: bool) {
+        self.binary = binary;
+    }
+    
+    /// Offer to take over hosting on this port if the server goes away
+    pub fn set_host_port(&mut self, port: u16) {
+        self.host_port = Some(port);
+    }
+    
+    /// Let others download our playlist's files on this port
+    pub fn set_share_port(&mut self, port: u16) {
+        self.share_port = Some(port);
+    }
+    
+    /// Have the server compare these hashes of our files with everyone else's
+    pub fn set_checksums(&mut self, files: Vec<FileChecksum>) {
+        self.checksums = Some(files);
+    }
+    
+    /// Save playlist items downloaded from others here
+    pub fn set_download_dir(&mut self, dir: PathBuf) {
+        self.download_dir = dir;
+    }
+    
+    /// Refuse to download items larger than this many bytes
+    pub fn set_max_download_size(&mut self, bytes: u64) {
+        self.max_download_size = bytes;
+    }
+    
+    /// Save group screenshots here
+    pub fn set_screenshot_dir(&mut self, dir: PathBuf) {
+        self.screenshot_dir = dir;
+    }
+    
+    /// Take commands from other programs on this Unix socket (a named pipe on Windows)
+    pub fn set_control_socket(&mut self, path: PathBuf) {
+        self.control_socket = Some(path);
+    }
+    
+    /// Take commands from keys pressed in the terminal (f, j, c, q) when it is one
+    pub fn set_terminal_keys(&mut self, terminal_keys: bool) {
+        self.terminal_keys = terminal_keys;
+    }
+    
+    /// Lay out each reader's line in the terminal with this template
+    pub fn set_display_line(&mut self, template: LineTemplate) {
+        self.display_line = Some(template);
+    }
+    
+    /// Run these commands on joins, page turns, everyone meeting on a page and finishing
+    pub fn set_hooks(&mut self, hooks: Hooks) {
+        self.hooks = Arc::new(HookRunner::new(hooks));
+    }
+    
+    /// Encrypt page turns, notes, chat and the like so only readers with the same passphrase can read them
+    ///
+    /// The key is derived once the server's `Welcome` brings the session's salt.
+    pub fn set_passphrase(&mut self, passphrase: &str) {
+        self.passphrase = Some(passphrase.to_string());
+    }
+    
+    /// Prove our user id with this key, so servers that pin keys know it's really us
+    pub fn set_identity(&mut self, identity: Identity) {
+        self.identity = Some(Arc::new(identity));
+    }
+    
+    /// Connect to sync server and start synchronization, following and steering `mpv_controller`
     pub async fn connect_and_sync(
         &mut self,
         server_addr: SocketAddr,
-        mut mpv_controller: MpvController,
-        playlist_files: Vec<PathBuf>,
+        mut mpv_controller: Box<dyn MediaPlayer>,
+        playlist_files: Vec<MediaSource>,
         minimal: bool,
-    ) -> Result<()> {
+    ) -> error::Result<()> {
         info!("Connecting to sync server at {}", server_addr);
         
-        let stream = TcpStream::connect(server_addr).await
-            .with_context(|| format!("Failed to connect to {}", server_addr))?;
-            
+        let stream = Self::connect_to(server_addr).await?;
         let (reader, mut writer) = stream.into_split();
-        let mut reader = BufReader::new(reader);
+        let mut reader = FrameReader::new(reader);
         
         // Create channels for communication
         let (outgoing_tx, mut outgoing_rx) = mpsc::unbounded_channel::<SyncMessage>();
         
         info!("Connected to sync server as user: {}", self.user_id);
         
-        // Send initial user joined message
-        let initial_state = self.get_current_state(&mut mpv_controller, &playlist_files).await?;
-        let join_message = SyncMessage::user_joined(
-            self.user_id.clone(), 
-            initial_state.clone(), 
-            self.next_sequence()
-        );
+        self.negotiate_wire(&mut reader, &mut writer).await.map_err(ConnectionError::Lost)?;
+        self.sync_clock(&mut reader, &mut writer).await.map_err(ConnectionError::Lost)?;
         
-        self.send_message(&mut writer, join_message).await?;
+        *self.playlist_names.write().await = file_share::item_names(&playlist_files);
+        if let Some(port) = self.share_port {
+            let addr = SocketAddr::from((Ipv4Addr::UNSPECIFIED, port));
+            let listener = TcpListener::bind(addr).await
+                .map_err(|source| ConnectionError::Bind { addr, source })?;
+            info!("Sharing playlist files on port {}", port);
+            tokio::spawn(file_share::serve(listener, playlist_files.clone()));
+        }
+        
+        // Send initial user joined message
+        let initial_state = Self::get_current_state(&mut *mpv_controller, &playlist_files, &self.user_id, self.color, self.icon.clone()).await
+            .map_err(MediaError::Player)?;
+        self.join_session(&mut writer, initial_state.clone()).await.map_err(ConnectionError::Lost)?;
         
         // Add our own state to the session and set initial position
         self.session_state.write().await.update_user(initial_state.clone());
         *self.last_known_position.write().await = Some(initial_state.playlist_position);
         
+        // Commands from other programs and the terminal are carried out by the update task, like keys pressed in MPV
+        let (control_tx, control_rx) = mpsc::unbounded_channel::<HeadlessCommand>();
+        let keyboard = if self.terminal_keys { Keyboard::spawn(control_tx.clone()) } else { None };
+        if let Some(path) = &self.control_socket {
+            control::spawn(path, control_tx, self.session_state.clone()).await.map_err(ConnectionError::Control)?;
+        }
+        
         // Create broadcast channel for UI updates
         let (ui_update_tx, ui_update_rx) = broadcast::channel(100);
         
         // Start the display loop
         let session_state_for_display = self.session_state.clone();
         let user_id_for_display = self.user_id.clone();
-        tokio::spawn(async move {
-            Self::display_loop(session_state_for_display, user_id_for_display, minimal, ui_update_rx).await;
+        let view = TerminalView {
+            minimal,
+            playlist_len: playlist_files.len(),
+            idle_after: self.idle_after,
+            line: self.display_line.clone(),
+            keys: keyboard.as_ref().map(Keyboard::input),
+        };
+        let display_task = tokio::spawn(async move {
+            Self::display_loop(session_state_for_display, user_id_for_display, view, ui_update_rx).await;
         });
         
-        // Start periodic state updates
-        let outgoing_tx_clone = outgoing_tx.clone();
-        let user_id_clone = self.user_id.clone();
-        let session_state_for_updates = self.session_state.clone();
-        let last_known_position_clone = self.last_known_position.clone();
-        let pending_position_clone = self.pending_position.clone();
-        let ui_update_tx_clone = ui_update_tx.clone();
-        let mut sequence_counter = self.sequence_counter;
+        // Poll MPV and apply what others do in it
+        let (player_tx, player_rx) = mpsc::unbounded_channel::<PlayerCommand>();
+        let (quit_tx, quit_rx) = oneshot::channel::<()>();
+        let task = UpdateTask::new(self, mpv_controller, playlist_files, player_tx.clone(), outgoing_tx.clone(), ui_update_tx.clone());
+        let mut update_task = tokio::spawn(task.run(player_rx, control_rx, quit_rx));
         
-        tokio::spawn(async move {
-            let mut interval = interval(Duration::from_millis(1000)); // Update every second
-            
+        // Handle outgoing messages, resending reliable ones until the server acks them
+        let user_id_for_cleanup = self.user_id.clone();
+        let wire = self.wire;
+        let e2e = self.e2e.clone();
+        let (ack_tx, mut ack_rx) = mpsc::unbounded_channel::<u64>();
+        let (writer_tx, mut writer_rx) = mpsc::unbounded_channel::<(OwnedWriteHalf, WireFormat)>();
+        let health_tx = player_tx.clone();
+        let outgoing_task = tokio::spawn(async move {
+            let mut writer = Some(writer);
+            let mut wire = wire;
+            let mut pending = PendingAcks::new();
+            let mut missed_update = None; // Our latest state while there's no connection to send it on
+            let mut resend_interval = interval(Duration::from_secs(1));
             loop {
-                interval.tick().await;
-                
-                match Self::get_current_state_with_user_id(&mut mpv_controller, &playlist_files, &user_id_clone).await {
-                    Ok(state) => {
-                        // Validate position change to prevent MPV transition glitches
-                        let should_send_update = Self::validate_position_change(
-                            &last_known_position_clone,
-                            &pending_position_clone,
-                            state.playlist_position,
-                            playlist_files.len()
-                        ).await;
-                        
-                        if should_send_update {
-                            // Update our local session state
-                            session_state_for_updates.write().await.update_user(state.clone());
-                            
-                            sequence_counter += 1;
-                            let update_message = SyncMessage::state_update(state, sequence_counter);
-                            
-                            if let Err(e) = outgoing_tx_clone.send(update_message) {
-                                error!("Failed to send state update: {}", e);
-                                break;
+                // While reconnecting there is no writer; reliable messages wait in `pending`
+                // and only the newest state update is kept
+                let result = tokio::select! {
+                    message = outgoing_rx.recv() => {
+                        let Some(message) = message else {
+                            break;
+                        };
+                        pending.track(&message, Instant::now());
+                        match writer.as_mut() {
+                            Some(writer) => Self::send_message_static(writer, message, wire, e2e.as_ref()).await,
+                            None => {
+                                if matches!(message.event, SyncEvent::StateUpdate { .. }) {
+                                    missed_update = Some(message);
+                                }
+                                Ok(())
                             }
-                            
-                            // Trigger UI update since our state changed
-                            let _ = ui_update_tx_clone.send(());
-                        } else {
-                            debug!("Skipped potentially invalid position update: {}", state.playlist_position);
                         }
                     }
-                    Err(e) => {
-                        warn!("Failed to get MPV state: {}", e);
+                    Some(sequence) = ack_rx.recv() => {
+                        pending.ack(sequence);
+                        Ok(())
                     }
-                }
-            }
-        });
-        
-        // Handle outgoing messages
-        let user_id_for_cleanup = self.user_id.clone();
-        tokio::spawn(async move {
-            while let Some(message) = outgoing_rx.recv().await {
-                if let Err(e) = Self::send_message_static(&mut writer, message).await {
+                    Some((new_writer, new_wire)) = writer_rx.recv() => {
+                        wire = new_wire;
+                        let writer = writer.insert(new_writer);
+                        Self::catch_up(writer, &mut pending, missed_update.take(), wire, e2e.as_ref()).await
+                    }
+                    _ = resend_interval.tick() => match writer.as_mut() {
+                        Some(writer) => Self::resend_unacked(writer, &mut pending, wire, e2e.as_ref(), &health_tx).await,
+                        None => Ok(()),
+                    },
+                };
+                if let Err(e) = result {
                     error!("Failed to send message: {}", e);
-                    break;
+                    writer = None;
                 }
             }
             
-            // Send leave message when shutting down
-            let leave_message = SyncMessage::user_left(user_id_for_cleanup, 999999);
-            let _ = Self::send_message_static(&mut writer, leave_message).await;
+            // Send leave message once every sender is gone, i.e. when shutting down
+            if let Some(mut writer) = writer {
+                let leave_message = SyncMessage::user_left(user_id_for_cleanup, 999999);
+                let _ = Self::send_message_static(&mut writer, leave_message, wire, e2e.as_ref()).await;
+                let _ = writer.shutdown().await;
+            }
         });
         
-        // Handle incoming messages  
+        // Handle incoming messages until the server, the user or MPV ends the session
         let ui_update_tx_for_incoming = ui_update_tx.clone();
-        let mut line = String::new();
-        while let Ok(bytes_read) = reader.read_line(&mut line).await {
-            if bytes_read == 0 {
-                info!("Server connection closed");
-                break;
-            }
+        let shutdown = shutdown_signal();
+        tokio::pin!(shutdown);
+        let mut server_addr = server_addr;
+        let mut ping = interval(latency::PING_INTERVAL);
+        let mut outcome = Ok(());
+        loop {
+            let lost = tokio::select! {
+                read = reader.next_frame() => match read {
+                    Ok(Some(frame)) => Ok(frame),
+                    Ok(None) => Err("Server connection closed".to_string()),
+                    Err(e) => Err(format!("Lost connection to server: {}", e)),
+                },
+                _ = ping.tick() => {
+                    let _ = outgoing_tx.send(SyncMessage::heartbeat(self.user_id.clone(), None, self.next_sequence()));
+                    continue;
+                }
+                _ = &mut shutdown => {
+                    info!("Interrupted, leaving the session");
+                    break;
+                }
+                _ = &mut update_task => {
+                    info!("Leaving the session");
+                    break;
+                }
+            };
             
-            let trimmed = line.trim();
-            if trimmed.is_empty() {
-                line.clear();
-                continue;
-            }
+            // Carry on with whoever takes over hosting, if anyone can
+            let frame = match lost {
+                Ok(frame) => frame,
+                Err(reason) => {
+                    warn!("{}", reason);
+                    let stop = async {
+                        tokio::select! {
+                            _ = &mut shutdown => {}
+                            _ = &mut update_task => {}
+                        }
+                    };
+                    let Some((new_reader, new_writer, new_addr)) = self.reconnect(server_addr, &player_tx, stop).await else {
+                        break;
+                    };
+                    (reader, server_addr) = (new_reader, new_addr);
+                    let _ = writer_tx.send((new_writer, self.wire));
+                    continue;
+                }
+            };
             
-            match serde_json::from_str::<SyncMessage>(trimmed) {
+            match frame.decode().and_then(|message| self.unseal(message)) {
+                Ok(SyncMessage { event: SyncEvent::Ack { sequence }, .. }) => {
+                    let _ = ack_tx.send(sequence);
+                }
+                Ok(SyncMessage { event: SyncEvent::Heartbeat { sent_at_ms, echo_ms, .. }, .. }) => {
+                    if let Some(pong) = self.answer_heartbeat(sent_at_ms, echo_ms).await {
+                        let _ = outgoing_tx.send(pong);
+                    }
+                }
+                Ok(SyncMessage { event: SyncEvent::SessionFull { position }, .. }) => {
+                    let Some(position) = position else {
+                        outcome = Err(ProtocolError::SessionFull.into());
+                        break;
+                    };
+                    let text = format!("The session is full, you are #{} in the waiting room", position);
+                    warn!("{}", text);
+                    let _ = player_tx.send(PlayerCommand::ShowText(text));
+                }
+                Ok(SyncMessage { event: SyncEvent::Kicked { user_id, reason }, .. }) if user_id == self.user_id => {
+                    // Rejoining would only be turned away again, or push out whoever replaced us
+                    let reason = reason.unwrap_or_else(|| "no reason given".to_string());
+                    let _ = player_tx.send(PlayerCommand::ShowText(format!("Removed from the session: {}", reason)));
+                    outcome = Err(ProtocolError::Removed(reason).into());
+                    break;
+                }
+                Ok(SyncMessage { event: SyncEvent::Admitted, .. }) => {
+                    info!("A place opened up, joining the session");
+                    let _ = player_tx.send(PlayerCommand::ShowText("Joined the session".to_string()));
+                    for message in self.join_messages(self.own_state().await).await {
+                        let _ = outgoing_tx.send(message);
+                    }
+                }
                 Ok(message) => {
                     debug!("Received from server: {:?}", message);
-                    self.handle_incoming_message(message).await;
+                    self.handle_incoming_message(message, &player_tx).await;
                     // Trigger UI update since someone else's state changed
                     let _ = ui_update_tx_for_incoming.send(());
                 }
                 Err(e) => {
-                    warn!("Failed to parse server message: {} - '{}'", e, trimmed);
+                    warn!("Failed to parse server message: {} - '{}'", e, frame.describe());
                 }
             }
-            
-            line.clear();
         }
         
+        // Quit MPV politely, then let the outgoing task drain and send UserLeft
+        if !update_task.is_finished() {
+            let _ = quit_tx.send(());
+            if tokio::time::timeout(SHUTDOWN_TIMEOUT, &mut update_task).await.is_err() {
+                update_task.abort();
+            }
+        }
+        drop(outgoing_tx);
+        if tokio::time::timeout(SHUTDOWN_TIMEOUT, outgoing_task).await.is_err() {
+            warn!("Timed out notifying the server that we left");
+        }
+        display_task.abort();
+        drop(keyboard);
+        
+        if let Some(stats) = self.stats.read().await.get(&self.user_id) {
+            println!("{}You read {}", terminal::decoration("📊 "), stats.format_summary());
+        }
+        
+        outcome
+    }
+    
+    /// Open a connection to the server
+    async fn connect_to(server_addr: SocketAddr) -> error::Result<TcpStream> {
+        let stream = TcpStream::connect(server_addr).await
+            .map_err(|source| ConnectionError::Connect { addr: server_addr, source })?;
+        let _ = stream.set_nodelay(true); // Updates are small and late ones are stale; don't batch them
+        Ok(stream)
+    }
+    
+    /// Record the round trip a pong measured, or return the pong to answer a ping with
+    async fn answer_heartbeat(&self, sent_at_ms: u64, echo_ms: Option<u64>) -> Option<SyncMessage> {
+        match echo_ms {
+            Some(echo_ms) => {
+                let mut round_trip = self.round_trip.write().await;
+                round_trip.observe(echo_ms, unix_millis());
+                debug!("Round trip to the server: {:?} ms", round_trip.get());
+                None
+            }
+            None => Some(SyncMessage::heartbeat(self.user_id.clone(), Some(sent_at_ms), self.next_sequence())),
+        }
+    }
+    
+    /// Announce ourselves to a freshly connected server
+    async fn join_session(&mut self, writer: &mut OwnedWriteHalf, state: UserState) -> Result<()> {
+        for message in self.join_messages(state).await {
+            self.send_message(writer, message).await?;
+        }
         Ok(())
     }
     
-    /// Get current state from MPV controller
-    async fn get_current_state(
-        &self,
-        mpv: &mut MpvController,
-        playlist_files: &[PathBuf],
-    ) -> Result<UserState> {
-        // Add longer delays between requests to give MPV time to respond properly
-        let playlist_pos = mpv.get_playlist_pos().await.unwrap_or(0);
-        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-        
-        let playback_time = mpv.get_position().await.unwrap_or(0.0);
-        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-        
-        let is_paused = mpv.is_paused().await.unwrap_or(true);
+    /// `UserJoined`, which the server reads, so with a passphrase our state follows it sealed
+    fn user_joined(&self, state: UserState) -> Vec<SyncMessage> {
+        if self.e2e.is_none() {
+            return vec![SyncMessage::user_joined(self.user_id.clone(), state, self.next_sequence())];
+        }
+        let mut placeholder = UserState::new(self.user_id.clone());
+        placeholder.color = state.color;
+        placeholder.icon = state.icon.clone();
+        vec![
+            SyncMessage::user_joined(self.user_id.clone(), placeholder, self.next_sequence()),
+            SyncMessage::state_update(state, self.next_sequence()),
+        ]
+    }
+    
+    /// Open a message sealed with our passphrase, refusing private ones sent in the clear
+    fn unseal(&self, message: SyncMessage) -> Result<SyncMessage> {
+        match &self.e2e {
+            Some(e2e) => e2e.open(message),
+            None if matches!(message.event, SyncEvent::Sealed { .. }) => {
+                anyhow::bail!("encrypted message; join with the session's --passphrase to read it")
+            }
+            None => Ok(message),
+        }
+    }
+    
+    /// What joining takes: `UserJoined`, then our bookmarks, so readers who
+    /// joined without them catch up, and an offer to host if we can
+    async fn join_messages(&self, state: UserState) -> Vec<SyncMessage> {
+        let mut messages = self.user_joined(state);
         
-        let current_file = if playlist_pos >= 0 && (playlist_pos as usize) < playlist_files.len() {
-            Some(playlist_files[playlist_pos as usize].clone())
-        } else {
-            None
-        };
+        if let Some((store, fingerprint)) = self.bookmarks.clone() {
+            let saved = store.read().await.get(&fingerprint).to_vec();
+            for bookmark in saved {
+                messages.push(SyncMessage::bookmark(fingerprint.clone(), bookmark, self.next_sequence()));
+            }
+        }
         
-        let mut state = UserState::new(self.user_id.clone());
-        state.update_from_mpv(playlist_pos, playback_time, is_paused, current_file);
+        if let Some(port) = self.host_port {
+            messages.push(SyncMessage::new(SyncEvent::HostOffer { port }, self.next_sequence()));
+        }
+        if let Some(port) = self.share_port {
+            let names = self.playlist_names.read().await.clone();
+            messages.push(SyncMessage::new(SyncEvent::FileOffer { port, names }, self.next_sequence()));
+        }
+        if let Some(files) = self.checksums.clone() {
+            messages.push(SyncMessage::new(SyncEvent::Checksums { files }, self.next_sequence()));
+        }
+        messages
+    }
+    
+    /// Our own state as last recorded in the session
+    async fn own_state(&self) -> UserState {
+        self.session_state.read().await.users.get(&self.user_id).cloned()
+            .unwrap_or_else(|| UserState::new(self.user_id.clone()))
+    }
+    
+    /// Get back into the session after losing the connection to `lost`
+    ///
+    /// Whoever takes over hosting is tried first. With `--pause-on-disconnect`
+    /// MPV is held paused meanwhile, and `lost` itself is retried for a while
+    /// in case it only blipped, unless `stop` finishes first.
+    async fn reconnect(
+        &mut self,
+        lost: SocketAddr,
+        player_tx: &mpsc::UnboundedSender<PlayerCommand>,
+        stop: impl Future<Output = ()>,
+    ) -> Option<(FrameReader<OwnedReadHalf>, OwnedWriteHalf, SocketAddr)> {
+        let held = self.pause_on_disconnect && !self.own_state().await.is_paused;
+        if self.pause_on_disconnect {
+            if held {
+                let _ = player_tx.send(PlayerCommand::Pause);
+            }
+            let _ = player_tx.send(PlayerCommand::ShowText("Lost the connection to the session - paused until it's back".to_string()));
+        }
         
-        Ok(state)
+        let mut rejoined = self.rejoin(lost).await;
+        if rejoined.is_none() && self.pause_on_disconnect {
+            rejoined = tokio::select! {
+                reconnected = self.rejoin_at(lost, RECONNECT_TIMEOUT) => reconnected.map(|(reader, writer)| (reader, writer, lost)),
+                _ = stop => None,
+            };
+        }
+        let (reader, writer, addr) = rejoined?;
+        let _ = player_tx.send(PlayerCommand::ShowText(format!("Reconnected to the session via {}", addr)));
+        self.sequences.write().await.clear(); // A new host counts from scratch
+        if held {
+            let _ = player_tx.send(PlayerCommand::Play);
+        }
+        Some((reader, writer, addr))
     }
     
-    /// Static version for use in spawned tasks with proper user_id
-    async fn get_current_state_with_user_id(
-        mpv: &mut MpvController,
-        playlist_files: &[PathBuf],
-        user_id: &str,
-    ) -> Result<UserState> {
-        // Add longer delays between requests to give MPV time to respond properly
-        let playlist_pos = mpv.get_playlist_pos().await.unwrap_or(0);
-        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+    /// Find the new host after losing the server and join the session there
+    ///
+    /// Candidates are tried in the order the server announced, each for up
+    /// to `HOST_ELECTION_TIMEOUT`, so everyone settles on the first one
+    /// still around. Reaching our own turn means we are that one: we start
+    /// a server seeded with our view of the session and connect to it.
+    async fn rejoin(&mut self, lost: SocketAddr) -> Option<(FrameReader<OwnedReadHalf>, OwnedWriteHalf, SocketAddr)> {
+        let candidates = self.host_candidates.read().await.clone();
+        if candidates.is_empty() {
+            return None;
+        }
         
-        let playback_time = mpv.get_position().await.unwrap_or(0.0);
-        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+        for candidate in candidates {
+            let addr = if candidate.user_id == self.user_id {
+                let port = self.host_port?;
+                if let Err(e) = self.start_host(port).await {
+                    error!("Failed to take over hosting: {}", e);
+                    continue;
+                }
+                SocketAddr::from((Ipv4Addr::LOCALHOST, port))
+            } else if candidate.addr.ip().is_loopback() && !lost.ip().is_loopback() {
+                continue; // Only reachable from the old host's machine
+            } else {
+                info!("Looking for {} as the new host at {}", candidate.user_id, candidate.addr);
+                candidate.addr
+            };
+            
+            if let Some((reader, writer)) = self.rejoin_at(addr, HOST_ELECTION_TIMEOUT).await {
+                info!("Rejoined the session hosted by {} at {}", candidate.user_id, addr);
+                return Some((reader, writer, addr));
+            }
+        }
         
-        let is_paused = mpv.is_paused().await.unwrap_or(true);
+        error!("No one could take over hosting the session");
+        None
+    }
+    
+    /// Connect to `addr`, trying for up to `limit`, and join the session there
+    async fn rejoin_at(&mut self, addr: SocketAddr, limit: Duration) -> Option<(FrameReader<OwnedReadHalf>, OwnedWriteHalf)> {
+        let stream = Self::connect_with_retry(addr, limit).await?;
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = FrameReader::new(reader);
         
-        let current_file = if playlist_pos >= 0 && (playlist_pos as usize) < playlist_files.len() {
-            Some(playlist_files[playlist_pos as usize].clone())
-        } else {
-            None
+        let joined = async {
+            self.negotiate_wire(&mut reader, &mut writer).await?;
+            self.sync_clock(&mut reader, &mut writer).await?;
+            let state = self.own_state().await;
+            self.join_session(&mut writer, state).await
         };
+        match joined.await {
+            Ok(()) => Some((reader, writer)),
+            Err(e) => {
+                warn!("Failed to rejoin via {}: {}", addr, e);
+                None
+            }
+        }
+    }
+    
+    /// Host the session from this process after the server went away
+    async fn start_host(&mut self, port: u16) -> Result<()> {
+        let addr = SocketAddr::from((Ipv4Addr::UNSPECIFIED, port));
+        info!("Taking over hosting the session on {}", addr);
         
-        // Debug logging to help diagnose position issues
-        if let Some(ref file) = current_file {
-            if let Some(filename) = file.file_name().and_then(|n| n.to_str()) {
-                debug!("🔍 User {}: MPV reports pos={}, file={}, total_files={}", 
-                      user_id, playlist_pos, filename, playlist_files.len());
-                debug!("   📤 Sending to server: pos={}, file={}", 
-                       playlist_pos, filename);
+        let mut server = SyncServer::new();
+        server.set_advertise(false);
+        server.set_display(false);
+        server.set_compression(self.compression);
+        server.set_initial_session(self.session_state.read().await.clone());
+        tokio::spawn(async move {
+            if let Err(e) = server.start(&[addr]).await {
+                error!("Hosted server stopped: {}", e);
             }
-        } else {
-            debug!("🔍 User {}: MPV reports pos={}, file=None, total_files={}", 
-                  user_id, playlist_pos, playlist_files.len());
+        });
+        Ok(())
+    }
+    
+    /// Keep trying to connect until `limit` runs out
+    async fn connect_with_retry(addr: SocketAddr, limit: Duration) -> Option<TcpStream> {
+        let deadline = Instant::now() + limit;
+        loop {
+            match tokio::time::timeout_at(deadline, TcpStream::connect(addr)).await {
+                Ok(Ok(stream)) => {
+                    let _ = stream.set_nodelay(true);
+                    return Some(stream);
+                }
+                Ok(Err(e)) => debug!("Connecting to {} failed: {}", addr, e),
+                Err(_) => return None,
+            }
+            if Instant::now() + Duration::from_millis(500) >= deadline {
+                return None;
+            }
+            tokio::time::sleep(Duration::from_millis(500)).await;
         }
+    }
+    
+    /// Join the session without MPV or a display, driven by `commands`
+    ///
+    /// Every message from the server is printed to stdout as a JSON line so
+    /// bots and tests can follow the session. Shared pauses and seeks from
+    /// others apply to the simulated player just as they would to MPV. The
+    /// session ends when the command stream closes or sends `quit`.
+    pub async fn run_headless(
+        &mut self,
+        server_addr: SocketAddr,
+        playlist_files: Vec<MediaSource>,
+        mut commands: mpsc::UnboundedReceiver<HeadlessCommand>,
+    ) -> error::Result<()> {
+        info!("Connecting to sync server at {}", server_addr);
         
-        let mut state = UserState::new(user_id.to_string());
-        state.update_from_mpv(playlist_pos, playback_time, is_paused, current_file);
+        let stream = Self::connect_to(server_addr).await?;
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = FrameReader::new(reader);
+        
+        info!("Connected to sync server as headless user: {}", self.user_id);
+        
+        // Control socket commands join the others; the session still ends when those run out
+        if let Some(path) = &self.control_socket {
+            let (tx, merged) = mpsc::unbounded_channel();
+            control::spawn(path, tx.clone(), self.session_state.clone()).await.map_err(ConnectionError::Control)?;
+            let mut input = std::mem::replace(&mut commands, merged);
+            tokio::spawn(async move {
+                while let Some(command) = input.recv().await {
+                    if tx.send(command).is_err() {
+                        return;
+                    }
+                }
+                let _ = tx.send(HeadlessCommand::Quit);
+            });
+        }
+        self.negotiate_wire(&mut reader, &mut writer).await.map_err(ConnectionError::Lost)?;
+        self.sync_clock(&mut reader, &mut writer).await.map_err(ConnectionError::Lost)?;
+        
+        let last_index = playlist_files.len() as i32 - 1;
+        let file_at = |position: i32| playlist_files.get(position as usize).cloned();
+        
+        let mut state = UserState::new(self.user_id.clone());
+        state.color = Some(self.color);
+        state.icon = self.icon.clone();
+        state.update_from_mpv(0, 0.0, true, file_at(0));
+        for message in self.user_joined(state.clone()) {
+            self.send_message(&mut writer, message).await.map_err(ConnectionError::Lost)?;
+        }
+        self.session_state.write().await.update_user(state.clone());
+        
+        let (player_tx, mut player_rx) = mpsc::unbounded_channel::<PlayerCommand>();
+        let mut pending = PendingAcks::new();
+        let mut ping = interval(latency::PING_INTERVAL);
+        let mut interval = interval(self.update_interval.max(cadence::MIN_UPDATE_INTERVAL));
+        let mut last_tick = Instant::now();
+        let shutdown = shutdown_signal();
+        tokio::pin!(shutdown);
+        let mut outcome = Ok(());
+        
+        loop {
+            let mut outgoing = Vec::new();
+            let (mut position, mut paused) = (state.playlist_position, state.is_paused);
+            let mut time = state.playback_time + if paused { 0.0 } else { last_tick.elapsed().as_secs_f64() };
+            
+            tokio::select! {
+                read = reader.next_frame() => {
+                    let frame = match read {
+                        Ok(Some(frame)) => frame,
+                        Ok(None) => {
+                            info!("Server connection closed");
+                            break;
+                        }
+                        Err(e) => {
+                            warn!("Lost connection to server: {}", e);
+                            break;
+                        }
+                    };
+                    
+                    // Print JSON even when the server sends MessagePack, compresses or the sender encrypted
+                    let decoded = frame.decode().and_then(|message| self.unseal(message)).and_then(|message| {
+                        let json = if self.e2e.is_some() { serde_json::to_string(&message)? } else { frame.to_json()? };
+                        Ok((message, json))
+                    });
+                    match decoded {
+                        Ok((SyncMessage { event: SyncEvent::Ack { sequence }, .. }, _)) => {
+                            pending.ack(sequence);
+                        }
+                        Ok((SyncMessage { event: SyncEvent::Heartbeat { sent_at_ms, echo_ms, .. }, .. }, _)) => {
+                            if let Some(pong) = self.answer_heartbeat(sent_at_ms, echo_ms).await {
+                                self.send_message(&mut writer, pong).await.map_err(ConnectionError::Lost)?;
+                            }
+                        }
+                        Ok((SyncMessage { event: SyncEvent::SessionFull { position: None }, .. }, json)) => {
+                            println!("{}", json);
+                            outcome = Err(ProtocolError::SessionFull.into());
+                            break;
+                        }
+                        Ok((SyncMessage { event: SyncEvent::Kicked { user_id, reason }, .. }, json)) if user_id == self.user_id => {
+                            println!("{}", json);
+                            outcome = Err(ProtocolError::Removed(reason.unwrap_or_else(|| "no reason given".to_string())).into());
+                            break;
+                        }
+                        Ok((SyncMessage { event: SyncEvent::Admitted, .. }, json)) => {
+                            println!("{}", json);
+                            for message in self.user_joined(state.clone()) {
+                                self.send_message(&mut writer, message).await.map_err(ConnectionError::Lost)?;
+                            }
+                        }
+                        Ok((message, json)) => {
+                            println!("{}", json);
+                            self.handle_incoming_message(message, &player_tx).await;
+                        }
+                        Err(e) => warn!("Failed to parse server message: {} - '{}'", e, frame.describe()),
+                    }
+                    continue;
+                }
+                command = commands.recv() => {
+                    let Some(command) = command else {
+                        info!("Headless input ended, leaving the session");
+                        break;
+                    };
+                    debug!("Headless command: {:?}", command);
+                    
+                    match command {
+                        HeadlessCommand::Page(page) => position = page - 1,
+                        HeadlessCommand::Next => position += 1,
+                        HeadlessCommand::Prev => position -= 1,
+                        HeadlessCommand::Seek(target) => {
+                            time = target;
+                            if self.time_sync_threshold.is_some() {
+                                outgoing.push(SyncEvent::Seek { user_id: self.user_id.clone(), target, sent_at_ms: unix_millis() });
+                            }
+                        }
+                        HeadlessCommand::Pause | HeadlessCommand::Play => {
+                            paused = command == HeadlessCommand::Pause;
+                            if self.shared_pause && paused != state.is_paused {
+                                let action = if paused { "pause" } else { "play" };
+                                outgoing.push(SyncEvent::UserAction { user_id: self.user_id.clone(), action: action.to_string(), value: None });
+                            }
+                        }
+                        HeadlessCommand::Note(text) => {
+                            let annotation = Annotation::new(&state, &text);
+                            self.session_state.write().await.add_annotation(annotation.clone());
+                            outgoing.push(SyncEvent::Annotation { annotation });
+                        }
+                        HeadlessCommand::Link(url) => match SharedLink::new(self.user_id.clone(), &url) {
+                            Ok(link) => {
+                                self.session_state.write().await.last_link = Some(link.clone());
+                                outgoing.push(SyncEvent::Link { link });
+                            }
+                            Err(e) => warn!("Can't share link: {}", e),
+                        },
+                        HeadlessCommand::React(emoji) => {
+                            outgoing.push(SyncEvent::Reaction { user_id: self.user_id.clone(), emoji });
+                        }
+                        HeadlessCommand::Chat(text) => match chat_text(&text) {
+                            Some(text) => outgoing.push(SyncEvent::Chat { user_id: self.user_id.clone(), text }),
+                            None => warn!("Not sending an empty chat message"),
+                        },
+                        HeadlessCommand::Follow(None) => {
+                            info!("Following everyone's page turns");
+                            *self.following.write().await = None;
+                        }
+                        HeadlessCommand::Follow(Some(who)) => {
+                            let target = self.session_state.read().await.find_other(&self.user_id, &who)
+                                .map(|user| (user.user_id.clone(), user.playlist_position));
+                            match target {
+                                Some((user_id, page)) => {
+                                    info!("Following {}'s page turns", user_id);
+                                    *self.following.write().await = Some(user_id);
+                                    position = page;
+                                }
+                                None => warn!("No one called {} to follow", who),
+                            }
+                        }
+                        HeadlessCommand::JumpTo(who) => {
+                            let target = self.session_state.read().await.find_other(&self.user_id, &who)
+                                .map(|user| (user.user_id.clone(), user.playlist_position, time_sync::estimated_playback_time(user, unix_millis())));
+                            match target {
+                                Some((user_id, page, at)) => {
+                                    info!("Jumping to {} at p.{}", user_id, page + 1);
+                                    position = page;
+                                    time = at;
+                                }
+                                None => warn!("No one called {} to jump to", who),
+                            }
+                        }
+                        HeadlessCommand::Quit => {
+                            info!("Leaving the session");
+                            break;
+                        }
+                    }
+                }
+                Some(command) = player_rx.recv() => {
+                    match command {
+                        PlayerCommand::Pause => paused = true,
+                        PlayerCommand::Play => paused = false,
+                        PlayerCommand::Seek { target, sent_at_ms, round_trip_ms } => {
+                            time = target + if paused { 0.0 } else { time_sync::seek_delay(sent_at_ms, unix_millis(), round_trip_ms) };
+                        }
+                        PlayerCommand::ShowText(text) | PlayerCommand::ShowReaction(text) => info!("{}", text),
+                        PlayerCommand::InsertFile { .. } | PlayerCommand::EditPlaylist { .. } => {} // Headless clients have no playlist
+                        PlayerCommand::SetProperty { name, value } => match value.as_i64() {
+                            Some(page) if name == properties::PLAYLIST_POS => position = page as i32,
+                            _ => debug!("No {} to set headless", name),
+                        },
+                        PlayerCommand::Screenshot { .. } | PlayerCommand::SetView(_) => {} // Nothing on screen headless
+                        PlayerCommand::Follow(_) => {} // Headless commands set who we follow directly
+                        PlayerCommand::SetAbLoop(ab_loop) => {
+                            if let Some(a) = ab_loop.a.filter(|_| ab_loop.is_complete()) {
+                                time = a;
+                            }
+                        }
+                    }
+                }
+                _ = interval.tick() => {
+                    Self::resend_unacked(&mut writer, &mut pending, self.wire, self.e2e.as_ref(), &player_tx).await.map_err(ConnectionError::Lost)?;
+                }
+                _ = ping.tick() => {
+                    let message = SyncMessage::heartbeat(self.user_id.clone(), None, self.next_sequence());
+                    self.send_message(&mut writer, message).await.map_err(ConnectionError::Lost)?;
+                    continue;
+                }
+                _ = &mut shutdown => {
+                    info!("Interrupted, leaving the session");
+                    break;
+                }
+            }
+            
+            last_tick = Instant::now();
+            
+            position = if last_index >= 0 { position.clamp(0, last_index) } else { position.max(0) };
+            if let Some(tolerance) = self.wait_for_all {
+                let held_back = Self::wait_limit(&*self.session_state.read().await, &self.user_id, position, tolerance);
+                if let Some((limit, waiting_for)) = held_back {
+                    info!("{}", waiting_for);
+                    position = limit;
+                }
+            }
+            if position != state.playlist_position {
+                time = 0.0;
+            }
+            let previous = state.clone();
+            state.update_from_mpv(position, time, paused, file_at(position));
+            state.carry_activity(&previous);
+            state.rtt_ms = self.round_trip.read().await.get();
+            
+            if !state.finished && last_index > 0 && position == last_index {
+                state.finished = true;
+                outgoing.push(SyncEvent::UserAction { user_id: self.user_id.clone(), action: "finished".to_string(), value: None });
+            }
+            
+            Self::record_state(&self.session_state, &self.hooks, state.clone()).await;
+            outgoing.push(SyncEvent::StateUpdate { user_state: state.clone() });
+            
+            for event in outgoing {
+                let message = SyncMessage::new(event, self.next_sequence());
+                pending.track(&message, Instant::now());
+                self.send_message(&mut writer, message).await.map_err(ConnectionError::Lost)?;
+            }
+        }
+        
+        let leave_message = SyncMessage::user_left(self.user_id.clone(), self.next_sequence());
+        let _ = self.send_message(&mut writer, leave_message).await;
+        let _ = writer.shutdown().await;
+        
+        outcome
+    }
+    
+    /// Run a syncread action requested from inside MPV
+    async fn handle_script_action(
+        mpv: &mut dyn MediaPlayer,
+        session_state: &Arc<RwLock<SessionState>>,
+        bookmarks: Option<&(SharedBookmarks, String)>,
+        screenshot_dir: &Path,
+        state: &UserState,
+        playlist: &[MediaSource],
+        action: &[String],
+    ) -> ActionOutcome {
+        let mut outcome = ActionOutcome::default();
+        let result = match action[0].as_str() {
+            "realign" => match mpv.realign_spreads().await {
+                Ok(true) => {
+                    outcome.restarted = true;
+                    mpv.show_text("Spreads realigned", STATUS_TEXT_DURATION).await
+                }
+                Ok(false) => mpv.show_text("Spread mode is off (--spreads)", STATUS_TEXT_DURATION).await,
+                Err(e) => Err(e),
+            },
+            "status" => {
+                let text = {
+                    let session = session_state.read().await;
+                    format!("{}\n{}", session.get_sync_summary(), session.format_for_display().join("\n"))
+                };
+                mpv.show_text(&text, STATUS_TEXT_DURATION).await
+            }
+            "bookmark" | "bookmarks" | "goto-bookmark" => match bookmarks {
+                Some((store, fingerprint)) => {
+                    Self::handle_bookmark_action(mpv, store, fingerprint, &state.user_id, action, &mut outcome).await
+                }
+                None => mpv.show_text("Bookmarks are unavailable", STATUS_TEXT_DURATION).await,
+            },
+            "catch-up" => {
+                let target = {
+                    let session = session_state.read().await;
+                    let target = match action.get(1).map(String::as_str) {
+                        None | Some("median") => Ok(session.median_other(&state.user_id)),
+                        Some("behind") => Ok(session.slowest_other(&state.user_id)),
+                        Some(_) => Err(()),
+                    };
+                    target.map(|user| user.map(|user| Self::local_position(playlist, user)))
+                };
+                match target {
+                    Ok(Some(position)) if position == state.playlist_position => {
+                        mpv.show_text("Already with the group", STATUS_TEXT_DURATION).await
+                    }
+                    Ok(Some(position)) => {
+                        info!("Catching up: p.{} -> p.{}", state.playlist_position + 1, position + 1);
+                        match mpv.set_playlist_pos(position).await {
+                            Ok(()) => mpv.show_text(&format!("Caught up to p.{}", position + 1), STATUS_TEXT_DURATION).await,
+                            Err(e) => Err(e),
+                        }
+                    }
+                    Ok(None) => mpv.show_text("Nobody else is reading", STATUS_TEXT_DURATION).await,
+                    Err(()) => mpv.show_text("Usage: script-message syncread catch-up [median|behind]", STATUS_TEXT_DURATION).await,
+                }
+            }
+            "jump-to" => {
+                let target = {
+                    let session = session_state.read().await;
+                    action.get(1)
+                        .and_then(|who| session.find_other(&state.user_id, who))
+                        .map(|user| (user.display_name(), Self::local_position(playlist, user), time_sync::estimated_playback_time(user, unix_millis())))
+                };
+                match target {
+                    Some((name, position, time)) => {
+                        info!("Jumping to {} at p.{} ({:.1}s)", name, position + 1, time);
+                        let jumped = if position == state.playlist_position {
+                            mpv.seek_to(time).await
+                        } else {
+                            mpv.restore_position(position, time).await
+                        };
+                        match jumped {
+                            Ok(()) => mpv.show_text(&format!("Now with {} (p.{})", name, position + 1), STATUS_TEXT_DURATION).await,
+                            Err(e) => Err(e),
+                        }
+                    }
+                    None => mpv.show_text("Usage: script-message syncread jump-to <user or number>", STATUS_TEXT_DURATION).await,
+                }
+            }
+            "fetch-missing" => {
+                if mpv.spreads_enabled() {
+                    mpv.show_text("Can't add downloaded pages in spread mode", STATUS_TEXT_DURATION).await
+                } else {
+                    outcome.fetch_missing = true;
+                    Ok(())
+                }
+            }
+            "share-link" => match SharedLink::new(state.user_id.clone(), &action[1..].join(" ")) {
+                Ok(link) => {
+                    info!("Shared link: {}", link.url);
+                    session_state.write().await.last_link = Some(link.clone());
+                    outcome.outgoing = Some(SyncEvent::Link { link });
+                    mpv.show_text("Link shared", STATUS_TEXT_DURATION).await
+                }
+                Err(e) => mpv.show_text(&format!("Can't share link: {}", e), STATUS_TEXT_DURATION).await,
+            },
+            "react" => match action.get(1).map(|reaction| appearance::parse_reaction(reaction)) {
+                Some(Ok(emoji)) => {
+                    outcome.outgoing = Some(SyncEvent::Reaction { user_id: state.user_id.clone(), emoji });
+                    Ok(())
+                }
+                _ => mpv.show_text("Usage: script-message syncread react <emoji or like|laugh|wow|love|sad|fire>", STATUS_TEXT_DURATION).await,
+            },
+            "slideshow" => {
+                let (from, count) = (state.playlist_position, playlist.len());
+                let command = match action.get(1).map(String::as_str) {
+                    None | Some("toggle") => Some(SlideshowCommand::Toggle { from, count }),
+                    Some("pause") => Some(SlideshowCommand::Pause),
+                    Some("resume") => Some(SlideshowCommand::Resume),
+                    Some("stop") => Some(SlideshowCommand::Stop),
+                    Some(seconds) => seconds.parse::<u64>().ok()
+                        .filter(|&seconds| seconds > 0)
+                        .map(|interval_secs| SlideshowCommand::Start { interval_secs, from, count }),
+                };
+                match command {
+                    Some(command) => {
+                        outcome.outgoing = Some(SyncEvent::Slideshow { user_id: state.user_id.clone(), command });
+                        Ok(())
+                    }
+                    None => mpv.show_text("Usage: script-message syncread slideshow [seconds|pause|resume|stop]", STATUS_TEXT_DURATION).await,
+                }
+            }
+            "playlist-add" | "playlist-remove" | "playlist-move" if mpv.spreads_enabled() => {
+                mpv.show_text("Can't edit the playlist in spread mode", STATUS_TEXT_DURATION).await
+            }
+            "playlist-add" | "playlist-remove" | "playlist-move" => {
+                let host = session_state.read().await.host.clone();
+                match host {
+                    Some(host) if host != state.user_id => {
+                        mpv.show_text(&format!("Only the host, {}, can edit the playlist", host), STATUS_TEXT_DURATION).await
+                    }
+                    _ => match Self::playlist_edit(action, state.playlist_position, playlist) {
+                        Ok(edit) => {
+                            info!("{}", edit.describe("You"));
+                            outcome.outgoing = Some(SyncEvent::PlaylistEdit { user_id: state.user_id.clone(), edit: edit.clone() });
+                            outcome.local = Some(PlayerCommand::EditPlaylist { edit, ours: true });
+                            Ok(())
+                        }
+                        Err(usage) => mpv.show_text(&usage, STATUS_TEXT_DURATION).await,
+                    },
+                }
+            }
+            "copy-link" => {
+                let link = session_state.read().await.last_link.clone();
+                match link {
+                    Some(link) => match clipboard::copy(&link.url).await {
+                        Ok(()) => mpv.show_text(&format!("Copied {}", link.url), STATUS_TEXT_DURATION).await,
+                        Err(e) => {
+                            warn!("{}", e);
+                            mpv.show_text(&format!("{:#}", e), STATUS_TEXT_DURATION).await
+                        }
+                    },
+                    None => mpv.show_text("Nobody has shared a link yet", STATUS_TEXT_DURATION).await,
+                }
+            }
+            "screenshot" => {
+                let moment = unix_millis() / 1000;
+                match Self::take_screenshot(mpv, screenshot_dir, &state.user_id, moment).await {
+                    Ok(()) => {
+                        outcome.outgoing = Some(SyncEvent::UserAction {
+                            user_id: state.user_id.clone(),
+                            action: "screenshot".to_string(),
+                            value: Some(moment as f64),
+                        });
+                        mpv.show_text("📸 Screenshot for everyone", STATUS_TEXT_DURATION).await
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            "ab-loop" => {
+                let ab_loop = match action.get(1).map(String::as_str) {
+                    None => Self::read_ab_loop(mpv).await.map(|current| Some(current.cycle(state.playback_time))),
+                    Some("clear") => Ok(Some(AbLoop::default())),
+                    Some(_) => Ok(None),
+                };
+                match ab_loop {
+                    Ok(Some(ab_loop)) => match Self::apply_ab_loop(mpv, ab_loop).await {
+                        Ok(()) => {
+                            info!("Set {}", ab_loop.describe());
+                            outcome.outgoing = Some(SyncEvent::AbLoop { user_id: state.user_id.clone(), ab_loop });
+                            mpv.show_text(&format!("A-B {} for everyone", ab_loop.describe()), STATUS_TEXT_DURATION).await
+                        }
+                        Err(e) => Err(e),
+                    },
+                    Ok(None) => mpv.show_text("Usage: script-message syncread ab-loop [clear]", STATUS_TEXT_DURATION).await,
+                    Err(e) => Err(e),
+                }
+            }
+            "chat" => match chat_text(&action[1..].join(" ")) {
+                Some(text) => {
+                    info!("{} You: {}", terminal::decoration("💬"), text);
+                    let shown = format!("You: {}", text);
+                    outcome.outgoing = Some(SyncEvent::Chat { user_id: state.user_id.clone(), text });
+                    mpv.show_text(&shown, STATUS_TEXT_DURATION).await
+                }
+                None => mpv.show_text("Usage: script-message syncread chat <message>", STATUS_TEXT_DURATION).await,
+            },
+            "follow" => match action.get(1) {
+                None => {
+                    outcome.local = Some(PlayerCommand::Follow(None));
+                    Ok(())
+                }
+                Some(who) => {
+                    let target = {
+                        let session = session_state.read().await;
+                        session.find_other(&state.user_id, who).map(|user| (user.user_id.clone(), Self::local_position(playlist, user)))
+                    };
+                    match target {
+                        Some((user_id, position)) => {
+                            outcome.local = Some(PlayerCommand::Follow(Some(user_id)));
+                            mpv.set_playlist_pos(position).await
+                        }
+                        None => mpv.show_text(&format!("No one called {} to follow", who), STATUS_TEXT_DURATION).await,
+                    }
+                }
+            },
+            "note" => {
+                let text = action[1..].join(" ");
+                if text.trim().is_empty() {
+                    mpv.show_text("Usage: script-message syncread note <text>", STATUS_TEXT_DURATION).await
+                } else {
+                    let annotation = Annotation::new(state, &text);
+                    info!("Note on p.{}: {}", annotation.playlist_position + 1, annotation.text);
+                    session_state.write().await.add_annotation(annotation.clone());
+                    outcome.outgoing = Some(SyncEvent::Annotation { annotation });
+                    mpv.show_text(&format!("Note added to p.{}", state.playlist_position + 1), STATUS_TEXT_DURATION).await
+                }
+            }
+            other => {
+                warn!("Unknown syncread action from MPV: {}", other);
+                Ok(())
+            }
+        };
+        
+        if let Err(e) = result {
+            warn!("Failed to run action {:?}: {}", action, e);
+        }
+        outcome
+    }
+    
+    /// Mark the current page, list this playlist's bookmarks, or jump to one
+    ///
+    /// `bookmark [label]` marks the page, `goto-bookmark [n]` jumps to the
+    /// n-th listed bookmark or, without `n`, to the next one after this page.
+    async fn handle_bookmark_action(
+        mpv: &mut dyn MediaPlayer,
+        store: &SharedBookmarks,
+        fingerprint: &str,
+        user_id: &str,
+        action: &[String],
+        outcome: &mut ActionOutcome,
+    ) -> Result<()> {
+        let page = mpv.get_playlist_pos().await?;
+        
+        match action[0].as_str() {
+            "bookmark" => {
+                let label = Some(action[1..].join(" ")).filter(|label| !label.is_empty());
+                let bookmark = Bookmark::new(page, label, user_id.to_string());
+                if store.write().await.add(fingerprint, bookmark.clone())? {
+                    info!("Bookmarked {}", bookmark.format_for_display());
+                    mpv.show_text(&format!("Bookmarked p.{}", page + 1), STATUS_TEXT_DURATION).await?;
+                    outcome.outgoing = Some(SyncEvent::Bookmark { fingerprint: fingerprint.to_string(), bookmark });
+                } else {
+                    mpv.show_text(&format!("p.{} is already bookmarked", page + 1), STATUS_TEXT_DURATION).await?;
+                }
+            }
+            "bookmarks" => {
+                let text = {
+                    let store = store.read().await;
+                    let lines: Vec<String> = store.get(fingerprint).iter()
+                        .enumerate()
+                        .map(|(i, bookmark)| format!("{}. {}", i + 1, bookmark.format_for_display()))
+                        .collect();
+                    if lines.is_empty() {
+                        "No bookmarks yet (press b to add one)".to_string()
+                    } else {
+                        lines.join("\n")
+                    }
+                };
+                mpv.show_text(&text, STATUS_TEXT_DURATION).await?;
+            }
+            _ => {
+                let target = {
+                    let store = store.read().await;
+                    match action.get(1).and_then(|n| n.parse::<usize>().ok()) {
+                        Some(n) => store.get(fingerprint).get(n.wrapping_sub(1)).cloned(),
+                        None => store.next_after(fingerprint, page).cloned(),
+                    }
+                };
+                match target {
+                    Some(bookmark) => {
+                        mpv.set_playlist_pos(bookmark.playlist_position).await?;
+                        mpv.show_text(&bookmark.format_for_display(), STATUS_TEXT_DURATION).await?;
+                    }
+                    None => mpv.show_text("No such bookmark", STATUS_TEXT_DURATION).await?,
+                }
+            }
+        }
+        Ok(())
+    }
+    
+    /// Relaunch a crashed MPV and put it back on the last file, time and play state we saw
+    async fn recover_mpv(mpv: &mut dyn MediaPlayer, last_state: Option<&UserState>) -> Result<()> {
+        mpv.restart().await?;
+        
+        if let Some(state) = last_state {
+            mpv.restore_position(state.playlist_position, state.playback_time).await?;
+            if !state.is_paused {
+                mpv.play().await?;
+            }
+            info!("MPV restored to item {} at {:.1}s", state.playlist_position + 1, state.playback_time);
+        }
+        Ok(())
+    }
+    
+    /// Which chapter of the current file we're in, fetching the file's chapter list when we reach a new page
+    async fn read_chapter(mpv: &mut dyn MediaPlayer, chapters: &mut Option<(i32, Vec<Chapter>)>, page: i32) -> Option<ChapterPosition> {
+        // Until a file has loaded its list is empty, so keep asking
+        if chapters.as_ref().is_none_or(|(cached, list)| *cached != page || list.is_empty()) {
+            match mpv.get_chapters().await {
+                Ok(list) => *chapters = Some((page, list)),
+                Err(e) => debug!("Failed to read chapter list: {}", e),
+            }
+        }
+        let list = chapters.as_ref().filter(|(cached, list)| *cached == page && !list.is_empty()).map(|(_, list)| list)?;
+        
+        let index = match mpv.get_chapter().await {
+            Ok(index) => index?,
+            Err(e) => {
+                debug!("Failed to read chapter: {}", e);
+                return None;
+            }
+        };
+        Some(ChapterPosition {
+            index,
+            count: list.len(),
+            title: list.get(index as usize).and_then(|chapter| chapter.title.clone()).filter(|title| !title.is_empty()),
+        })
+    }
+    
+    /// Save a screenshot for the group moment `moment`, named after us and the page we're on
+    async fn take_screenshot(mpv: &mut dyn MediaPlayer, dir: &Path, user_id: &str, moment: u64) -> Result<()> {
+        std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        let path = screenshot_path(dir, moment, user_id, mpv.get_playlist_pos().await?);
+        mpv.screenshot(&path).await?;
+        info!("Saved screenshot {}", path.display());
+        Ok(())
+    }
+    
+    /// The A-B loop MPV is playing, however it was set
+    async fn read_ab_loop(mpv: &mut dyn MediaPlayer) -> Result<AbLoop> {
+        let a = mpv.get_property("ab-loop-a").await?;
+        let b = mpv.get_property("ab-loop-b").await?;
+        Ok(AbLoop::from_properties(&a, &b))
+    }
+    
+    /// Play an A-B loop, starting it from A so everyone loops in step
+    async fn apply_ab_loop(mpv: &mut dyn MediaPlayer, ab_loop: AbLoop) -> Result<()> {
+        let value = |end: Option<f64>| end.map_or_else(|| Value::from("no"), Value::from);
+        mpv.set_property("ab-loop-a", value(ab_loop.a)).await?;
+        mpv.set_property("ab-loop-b", value(ab_loop.b)).await?;
+        match ab_loop.a.filter(|_| ab_loop.is_complete()) {
+            Some(a) => mpv.seek_to(a).await,
+            None => Ok(()),
+        }
+    }
+    
+    /// Set a property to the value another viewer changed it to; pages go through the spread-aware page turn
+    async fn apply_property(mpv: &mut dyn MediaPlayer, name: &str, value: Value) -> Result<()> {
+        if name != properties::PLAYLIST_POS {
+            return mpv.set_property(name, value).await;
+        }
+        let page = value.as_i64().context("page number expected")?;
+        mpv.set_playlist_pos(page as i32).await
+    }
+    
+    /// Seek to a time another viewer jumped to, adding the message's transit time if we're playing
+    async fn apply_remote_seek(mpv: &mut dyn MediaPlayer, target: f64, sent_at_ms: u64, round_trip_ms: Option<u64>) -> Result<()> {
+        let delay = if mpv.is_paused().await? {
+            0.0
+        } else {
+            time_sync::seek_delay(sent_at_ms, unix_millis(), round_trip_ms)
+        };
+        
+        debug!("Remote seek to {:.2}s (+{:.3}s in flight)", target, delay);
+        mpv.seek_to(target + delay).await
+    }
+    
+    /// Whether the viewer said it was quit since the last look
+    fn mpv_closed(events: &mut Option<broadcast::Receiver<MpvEvent>>) -> bool {
+        let Some(events) = events else {
+            return false;
+        };
+        let mut closed = false;
+        loop {
+            match events.try_recv() {
+                Ok(event) => closed |= event.is_quit(),
+                Err(broadcast::error::TryRecvError::Lagged(_)) => continue,
+                Err(_) => return closed,
+            }
+        }
+    }
+    
+    /// Our state as MPV reports it, shown with our color and icon
+    async fn get_current_state(
+        mpv: &mut dyn MediaPlayer,
+        playlist_files: &[MediaSource],
+        user_id: &str,
+        color: UserColor,
+        icon: Option<String>,
+    ) -> Result<UserState> {
+        // Add longer delays between requests to give MPV time to respond properly
+        let playlist_pos = mpv.get_playlist_pos().await.unwrap_or(0);
+        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+        
+        let playback_time = mpv.get_position().await.unwrap_or(0.0);
+        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+        
+        let is_paused = mpv.is_paused().await.unwrap_or(true);
+        if mpv.hung_up() {
+            anyhow::bail!("MPV closed the IPC connection"); // The defaults above would send us back to p.1
+        }
+        
+        let current_file = if playlist_pos >= 0 && (playlist_pos as usize) < playlist_files.len() {
+            Some(playlist_files[playlist_pos as usize].clone())
+        } else {
+            None
+        };
+        
+        // Debug logging to help diagnose position issues
+        if let Some(ref file) = current_file {
+            if let Some(filename) = file.display_name() {
+                debug!("{}User {}: MPV reports pos={}, file={}, total_files={}", 
+                      terminal::decoration("🔍 "), user_id, playlist_pos, filename, playlist_files.len());
+                debug!("   {}Sending to server: pos={}, file={}", terminal::decoration("📤 "), 
+                       playlist_pos, filename);
+            }
+        } else {
+            debug!("{}User {}: MPV reports pos={}, file=None, total_files={}", 
+                  terminal::decoration("🔍 "), user_id, playlist_pos, playlist_files.len());
+        }
+        
+        let mut state = UserState::new(user_id.to_string());
+        state.color = Some(color);
+        state.icon = icon;
+        state.update_from_mpv(playlist_pos, playback_time, is_paused, current_file);
+        state.set_playlist(playlist_files);
+        
+        Ok(state)
+    }
+    
+    /// Handle incoming message from server
+    async fn handle_incoming_message(&self, message: SyncMessage, player_tx: &mpsc::UnboundedSender<PlayerCommand>) {
+        let sequence = message.canonical_sequence();
+        match message.event {
+            SyncEvent::UserJoined { user_id, user_state } => {
+                self.sequences.write().await.reset(&user_id, sequence);
+                let mut session = self.session_state.write().await;
+                session.update_user(user_state.clone());
+                if user_id != self.user_id {
+                    self.hooks.fire(HookEvent::Join, &user_state, &session);
+                }
+                self.hooks.check_synced(&session);
+            }
+            
+            SyncEvent::UserLeft { user_id } => {
+                self.sequences.write().await.forget(&user_id);
+                {
+                    let mut session = self.session_state.write().await;
+                    session.remove_user(&user_id);
+                    // Whoever was holding everyone up may have just left
+                    if self.hooks.check_synced(&session) {
+                        if let Some(me) = session.users.get(&self.user_id) {
+                            self.hooks.fire(HookEvent::EveryoneSynced, me, &session);
+                        }
+                    }
+                }
+                self.file_sources.write().await.remove(&user_id);
+                
+                let mut following = self.following.write().await;
+                if following.as_ref() == Some(&user_id) {
+                    *following = None;
+                    let text = format!("{} left; no longer following them", user_id);
+                    info!("{}", text);
+                    let _ = player_tx.send(PlayerCommand::ShowText(text));
+                }
+            }
+            
+            SyncEvent::FilesAvailable { source, names } => {
+                let missing = file_share::missing_items(&self.playlist_names.read().await, &names).len();
+                if missing > 0 {
+                    let text = format!("{} has {} playlist item(s) you don't; press D to download them", source.user_id, missing);
+                    info!("{}", text);
+                    let _ = player_tx.send(PlayerCommand::ShowText(text));
+                }
+                self.file_sources.write().await.insert(source.user_id, (source.addr, names));
+            }
+            
+            SyncEvent::StateUpdate { user_state } => {
+                // A late update must not roll someone's position back
+                if !self.sequences.write().await.accept(&user_state.user_id, sequence) {
+                    debug!("Dropping stale update from {} (sequence {})", user_state.user_id, sequence);
+                    return;
+                }
+                
+                // Stay on the page of whoever we follow, even with page turns unsynced
+                if self.following.read().await.as_ref() == Some(&user_state.user_id) {
+                    let theirs = page_map::local_position(&self.playlist_names.read().await, user_state.playlist_position, user_state.current_file_name.as_deref());
+                    let ours = self.session_state.read().await.users.get(&self.user_id).map(|user| user.playlist_position);
+                    if ours.is_some_and(|ours| ours != theirs) {
+                        let _ = player_tx.send(PlayerCommand::SetProperty { name: properties::PLAYLIST_POS.to_string(), value: Value::from(theirs) });
+                    }
+                }
+                Self::record_state(&self.session_state, &self.hooks, user_state).await;
+            }
+            
+            SyncEvent::Seek { user_id, target, sent_at_ms } => {
+                if self.time_sync_threshold.is_some() && user_id != self.user_id {
+                    info!("{} seeked to {:.1}s", user_id, target);
+                    let round_trip_ms = {
+                        let sender = self.session_state.read().await.users.get(&user_id).and_then(|user| user.rtt_ms);
+                        sender.zip(self.round_trip.read().await.get()).map(|(theirs, ours)| theirs + ours)
+                    };
+                    let _ = player_tx.send(PlayerCommand::Seek { target, sent_at_ms, round_trip_ms });
+                }
+            }
+            
+            SyncEvent::Property { user_id, name, mut value, file_name } => {
+                let ignored = name == properties::PLAYLIST_POS
+                    && self.following.read().await.as_ref().is_some_and(|followed| *followed != user_id);
+                if user_id != self.user_id && self.synced_properties.contains(&name) && !ignored {
+                    // Page numbers are the sender's; turn to the same file in ours
+                    if let Some(position) = value.as_i64().filter(|_| name == properties::PLAYLIST_POS) {
+                        let ours = self.playlist_names.read().await;
+                        value = Value::from(page_map::local_position(&ours, position as i32, file_name.as_deref()));
+                    }
+                    info!("{} set {} to {}", user_id, name, value);
+                    let _ = player_tx.send(PlayerCommand::SetProperty { name, value });
+                }
+            }
+            
+            SyncEvent::AbLoop { user_id, ab_loop } => {
+                if user_id != self.user_id {
+                    let text = format!("{} set {}", user_id, ab_loop.describe());
+                    info!("{}", text);
+                    let _ = player_tx.send(PlayerCommand::SetAbLoop(ab_loop));
+                    let _ = player_tx.send(PlayerCommand::ShowText(text));
+                }
+            }
+            
+            SyncEvent::View { user_id, view } => {
+                if user_id != self.user_id && self.follow_presenter {
+                    debug!("{} zoomed to {:.2} at ({:.2}, {:.2})", user_id, view.zoom, view.pan_x, view.pan_y);
+                    let _ = player_tx.send(PlayerCommand::SetView(view));
+                }
+            }
+            
+            SyncEvent::Slideshow { user_id, command } => {
+                let text = command.describe(&user_id);
+                info!("{}", text);
+                let _ = player_tx.send(PlayerCommand::ShowText(text));
+            }
+            
+            SyncEvent::PlaylistEdit { user_id, edit } => {
+                // The server checks too, but can't see edits in encrypted sessions
+                let host = self.session_state.read().await.host.clone();
+                if host.as_ref().is_some_and(|host| host != &user_id) {
+                    warn!("Ignoring a playlist edit from {}, who isn't the host", user_id);
+                } else if user_id != self.user_id {
+                    let text = edit.describe(&user_id);
+                    info!("{}", text);
+                    let _ = player_tx.send(PlayerCommand::EditPlaylist { edit, ours: false });
+                    let _ = player_tx.send(PlayerCommand::ShowText(text));
+                }
+            }
+            
+            SyncEvent::Host { user_id } => {
+                info!("{} is the host", user_id);
+                self.session_state.write().await.host = Some(user_id);
+            }
+            
+            SyncEvent::ChecksumReport { mismatches } => {
+                // Only readers who asked for the comparison hear about it
+                if self.checksums.is_some() {
+                    for mismatch in &mismatches {
+                        warn!("Different versions of {}: {}", mismatch.name, mismatch.differing.join(", "));
+                    }
+                    let text = checksums::describe(&mismatches, &self.user_id);
+                    info!("{}", text);
+                    let _ = player_tx.send(PlayerCommand::ShowText(text));
+                }
+            }
+            
+            SyncEvent::SlideshowAdvance { page } => {
+                if page < self.playlist_names.read().await.len() as i32 {
+                    let value = Value::from(page);
+                    let _ = player_tx.send(PlayerCommand::SetProperty { name: properties::PLAYLIST_POS.to_string(), value });
+                }
+            }
+            
+            SyncEvent::Bookmark { fingerprint, bookmark } => {
+                let Some((store, own_fingerprint)) = &self.bookmarks else {
+                    return;
+                };
+                match store.write().await.add(&fingerprint, bookmark.clone()) {
+                    Ok(true) => {
+                        info!("New bookmark: {}", bookmark.format_for_display());
+                        if fingerprint == *own_fingerprint && bookmark.created_by != self.user_id {
+                            let text = format!("Bookmark: {}", bookmark.format_for_display());
+                            let _ = player_tx.send(PlayerCommand::ShowText(text));
+                        }
+                    }
+                    Ok(false) => {}
+                    Err(e) => warn!("Failed to save bookmark: {}", e),
+                }
+            }
+            
+            SyncEvent::Annotation { annotation } => {
+                let mut session = self.session_state.write().await;
+                if session.add_annotation(annotation.clone()) {
+                    info!("{} left a note on p.{}: {}", annotation.user_id, annotation.playlist_position + 1, annotation.text);
+                }
+            }
+            
+            SyncEvent::Link { link } => {
+                if let Err(e) = link.check() {
+                    warn!("Ignoring link from {}: {}", link.user_id, e);
+                    return;
+                }
+                info!("{} shared a link: {}", link.user_id, link.url);
+                if self.show_links {
+                    let _ = player_tx.send(PlayerCommand::ShowText(format!("{} shared a link (press y to copy):\n{}", link.user_id, link.url)));
+                }
+                self.session_state.write().await.last_link = Some(link);
+            }
+            
+            SyncEvent::Reaction { user_id, emoji } => {
+                match appearance::parse_icon(&emoji) {
+                    Ok(emoji) => {
+                        info!("{} reacted {}", user_id, emoji);
+                        let _ = player_tx.send(PlayerCommand::ShowReaction(format!("{} {}", user_id, emoji)));
+                    }
+                    Err(e) => warn!("Ignoring reaction from {}: {}", user_id, e),
+                }
+            }
+            
+            SyncEvent::Chat { user_id, text } => {
+                if user_id != self.user_id {
+                    let line = format!("{}: {}", user_id, text);
+                    info!("{} {}", terminal::decoration("💬"), line);
+                    let _ = player_tx.send(PlayerCommand::ShowText(line));
+                }
+            }
+            
+            SyncEvent::Heartbeat { user_id, .. } => {
+                debug!("Heartbeat from {}", user_id);
+            }
+            
+            SyncEvent::UserAction { user_id, action, value } => {
+                info!("User {} performed action: {} {:?}", user_id, action, value);
+                
+                if action == "finished" && user_id != self.user_id {
+                    self.handle_user_finished(&user_id, player_tx).await;
+                }
+                
+                // Everyone saves their own view of the moment, named after when it was taken
+                if let Some(moment) = value.filter(|_| action == "screenshot" && user_id != self.user_id) {
+                    let _ = player_tx.send(PlayerCommand::Screenshot { moment: moment as u64 });
+                    let _ = player_tx.send(PlayerCommand::ShowText(format!("📸 {} took a screenshot for everyone", user_id)));
+                }
+                
+                if self.shared_pause && user_id != self.user_id {
+                    let command = match action.as_str() {
+                        "pause" => Some(PlayerCommand::Pause),
+                        "play" => Some(PlayerCommand::Play),
+                        _ => None,
+                    };
+                    if let Some(command) = command {
+                        let _ = player_tx.send(command);
+                    }
+                }
+            }
+            
+            SyncEvent::Announcement { text } => {
+                info!("{}", text);
+                let _ = player_tx.send(PlayerCommand::ShowText(text));
+            }
+            
+            SyncEvent::Kicked { user_id, reason } => {
+                if user_id == self.user_id {
+                    warn!("You were removed from the session: {}", reason.as_deref().unwrap_or("no reason given"));
+                }
+            }
+            
+            SyncEvent::Admin { .. } | SyncEvent::AdminResult { .. } | SyncEvent::Ack { .. } => {}
+            SyncEvent::Session { session } => {
+                // On joining and now and then, in place of any updates we missed; the
+                // server can't read encrypted sessions, so there its copy is no use
+                if self.e2e.is_some() {
+                    return;
+                }
+                let gone = self.session_state.write().await.catch_up(session, &self.user_id);
+                let mut sequences = self.sequences.write().await;
+                let mut file_sources = self.file_sources.write().await;
+                for user_id in gone {
+                    debug!("{} left while we were disconnected", user_id);
+                    sequences.forget(&user_id);
+                    file_sources.remove(&user_id);
+                }
+            }
+            SyncEvent::StatsRequest | SyncEvent::Stats { .. } | SyncEvent::SessionRequest => {}
+            SyncEvent::HostCandidates { candidates } => {
+                debug!("Host candidates: {:?}", candidates);
+                *self.host_candidates.write().await = candidates;
+            }
+            
+            // Handled by the connection loops
+            SyncEvent::SessionFull { .. } | SyncEvent::Admitted | SyncEvent::Sealed { .. } => {}
+            SyncEvent::FileOffer { .. } | SyncEvent::Checksums { .. } => {}
+            
+            SyncEvent::Challenge { .. } | SyncEvent::Hello { .. } | SyncEvent::Welcome { .. } | SyncEvent::HostOffer { .. }
+            | SyncEvent::PeerHello { .. } | SyncEvent::Peers { .. } => {}
+        }
+    }
+    
+    /// Download what the best-stocked sharer has and we don't, adding each item to MPV as it arrives
+    async fn fetch_missing(sources: FileSources, ours: Vec<String>, dir: PathBuf, max_size: u64, player_tx: mpsc::UnboundedSender<PlayerCommand>) {
+        let best = sources.iter()
+            .map(|(user, (addr, names))| (user, *addr, file_share::missing_items(&ours, names)))
+            .max_by_key(|(_, _, missing)| missing.len())
+            .filter(|(_, _, missing)| !missing.is_empty());
+        let Some((user, addr, missing)) = best else {
+            let text = if sources.is_empty() { "No one is sharing files (--share-port)" } else { "You have every shared playlist item" };
+            let _ = player_tx.send(PlayerCommand::ShowText(text.to_string()));
+            return;
+        };
+        
+        let total = missing.len();
+        for (count, (index, name)) in missing.into_iter().enumerate() {
+            let label = format!("Downloading {} from {} ({}/{})", name, user, count + 1, total);
+            info!("{}", label);
+            let mut shown = 0;
+            let result = file_share::fetch(addr, &name, &dir, max_size, |received, size| {
+                let percent = (received * 100).checked_div(size).unwrap_or(100);
+                if percent >= shown + 10 {
+                    shown = percent;
+                    let _ = player_tx.send(PlayerCommand::ShowText(format!("{}: {}%", label, percent)));
+                }
+            }).await;
+            
+            match result {
+                Ok(path) => {
+                    let _ = player_tx.send(PlayerCommand::InsertFile { index, file: MediaSource::Local(path) });
+                }
+                Err(e) => {
+                    warn!("Failed to download {}: {}", name, e);
+                    let _ = player_tx.send(PlayerCommand::ShowText(format!("Couldn't download {}: {}", name, e)));
+                    return;
+                }
+            }
+        }
+        
+        info!("Downloaded {} missing item(s) from {}", total, user);
+        let _ = player_tx.send(PlayerCommand::ShowText(format!("Downloaded {} missing item(s) from {}", total, user)));
+    }
+    
+    /// The edit a `playlist-add`, `playlist-remove` or `playlist-move` action asks for, or its usage
+    ///
+    /// Entries are numbered from 1 as on the OSD; removing defaults to the
+    /// page we're on.
+    fn playlist_edit(action: &[String], position: i32, playlist: &[MediaSource]) -> Result<PlaylistEdit, String> {
+        let entry = |arg: Option<&String>| {
+            arg.and_then(|n| n.parse::<usize>().ok())
+                .filter(|n| (1..=playlist.len()).contains(n))
+                .map(|n| (n - 1, playlist[n - 1].display_name().unwrap_or_default()))
+        };
+        match action[0].as_str() {
+            "playlist-add" => {
+                let entry = action[1..].join(" ");
+                match MediaSource::parse(&entry) {
+                    _ if entry.is_empty() => Err("Usage: script-message syncread playlist-add <path or URL>".to_string()),
+                    MediaSource::Local(path) => match path.canonicalize() {
+                        Ok(path) => Ok(PlaylistEdit::Append { entry: path.to_string_lossy().into_owned() }),
+                        Err(e) => Err(format!("Can't add {}: {}", entry, e)),
+                    },
+                    MediaSource::Remote(_) => Ok(PlaylistEdit::Append { entry }),
+                }
+            }
+            "playlist-remove" => {
+                let current = (position + 1).to_string();
+                match entry(Some(action.get(1).unwrap_or(&current))) {
+                    Some((index, name)) => Ok(PlaylistEdit::Remove { index, name }),
+                    None => Err(format!("Usage: script-message syncread playlist-remove [1-{}]", playlist.len())),
+                }
+            }
+            _ => match (entry(action.get(1)), entry(action.get(2))) {
+                (Some((from, name)), Some((to, _))) => Ok(PlaylistEdit::Move { from, to, name }),
+                _ => Err(format!("Usage: script-message syncread playlist-move <from> <to> (1-{})", playlist.len())),
+            },
+        }
+    }
+    
+    /// Where `user`'s page is in our playlist
+    fn local_position(playlist: &[MediaSource], user: &UserState) -> i32 {
+        let ours = file_share::item_names(playlist);
+        page_map::local_position(&ours, user.playlist_position, user.current_file_name.as_deref())
+    }
+    
+    /// Make a playlist edit in our MPV, finding the entry by name if our playlist differs from the sender's
+    async fn apply_playlist_edit(
+        mpv: &mut dyn MediaPlayer,
+        playlist_files: &mut Vec<MediaSource>,
+        playlist_names: &RwLock<Vec<String>>,
+        download_dir: &Path,
+        edit: &PlaylistEdit,
+        ours: bool,
+    ) -> Result<()> {
+        let mut names = playlist_names.write().await;
+        let result = match edit {
+            PlaylistEdit::Append { entry } if ours => {
+                let file = MediaSource::parse(entry);
+                mpv.insert_file(playlist_files.len(), file.clone()).await.map(|()| {
+                    names.push(file.display_name().unwrap_or_default());
+                    playlist_files.push(file);
+                })
+            }
+            PlaylistEdit::Append { entry } => match Self::find_entry(entry, playlist_files, download_dir) {
+                Some(file) => mpv.insert_file(playlist_files.len(), file.clone()).await.map(|()| {
+                    names.push(file.display_name().unwrap_or_default());
+                    playlist_files.push(file);
+                }),
+                None => Err(anyhow::anyhow!("you don't have {} next to your playlist or in your downloads", entry)),
+            },
+            PlaylistEdit::Remove { index, name } => match PlaylistEdit::locate(&names, *index, name) {
+                Some(index) => mpv.remove_file(index).await.map(|()| {
+                    names.remove(index);
+                    playlist_files.remove(index);
+                }),
+                None => Err(anyhow::anyhow!("{} isn't in your playlist", name)),
+            },
+            PlaylistEdit::Move { from, to, name } => match PlaylistEdit::locate(&names, *from, name) {
+                Some(from) => {
+                    let to = (*to).min(playlist_files.len() - 1);
+                    mpv.move_file(from, to).await.map(|()| {
+                        let name = names.remove(from);
+                        names.insert(to, name);
+                        let file = playlist_files.remove(from);
+                        playlist_files.insert(to, file);
+                    })
+                }
+                None => Err(anyhow::anyhow!("{} isn't in your playlist", name)),
+            },
+        };
+        
+        if let Err(e) = &result {
+            let _ = mpv.show_text(&format!("Playlist not changed: {}", e), STATUS_TEXT_DURATION).await;
+        }
+        result
+    }
+    
+    /// Our copy of a file someone else added, by name from our playlist's folders or the download dir
+    ///
+    /// Their path and URLs are never loaded as they are, so a peer can't
+    /// make our player open something we didn't already have to hand.
+    fn find_entry(entry: &str, playlist_files: &[MediaSource], download_dir: &Path) -> Option<MediaSource> {
+        let MediaSource::Local(path) = MediaSource::parse(entry) else {
+            return None;
+        };
+        let name = path.file_name()?;
+        playlist_files.iter()
+            .filter_map(|file| match file {
+                MediaSource::Local(path) => path.parent(),
+                MediaSource::Remote(_) => None,
+            })
+            .chain([download_dir])
+            .map(|dir| dir.join(name))
+            .find(|path| path.exists())
+            .map(MediaSource::Local)
+    }
+    
+    /// Page to go back to and a "waiting for" message if `position` is too far ahead of the slowest reader
+    fn wait_limit(session: &SessionState, user_id: &str, position: i32, tolerance: i32) -> Option<(i32, String)> {
+        let slowest = session.slowest_other(user_id)?;
+        let limit = slowest.playlist_position + tolerance;
+        (position > limit).then(|| {
+            (limit, format!("Waiting for {} (p.{}){}", slowest.display_name(), slowest.playlist_position + 1, terminal::decoration("…")))
+        })
+    }
+    
+    /// Put a user's state into the session, running any hooks it sets off
+    async fn record_state(session_state: &RwLock<SessionState>, hooks: &HookRunner, state: UserState) {
+        let mut session = session_state.write().await;
+        let previous = session.users.get(&state.user_id).cloned();
+        session.update_user(state.clone());
+        hooks.observe(&session, previous.as_ref(), &state);
+    }
+    
+    /// Tell the user someone finished, pausing us first if we're still reading and asked for it
+    async fn handle_user_finished(&self, user_id: &UserId, player_tx: &mpsc::UnboundedSender<PlayerCommand>) {
+        let (banner, straggling) = {
+            let mut session = self.session_state.write().await;
+            // The action arrives ahead of the state update that carries the flag
+            if let Some(user) = session.users.get_mut(user_id).filter(|user| !user.finished) {
+                user.finished = true;
+                let user = user.clone();
+                self.hooks.fire(HookEvent::Finish, &user, &session);
+            }
+            
+            let banner = format!("{} finished - {}/{} finished", user_id, session.finished_count(), session.users.len());
+            let straggling = !session.users.get(&self.user_id).is_some_and(|me| me.finished);
+            (banner, straggling)
+        };
+        
+        if self.finish_pause && straggling {
+            let _ = player_tx.send(PlayerCommand::Pause);
+        }
+        let _ = player_tx.send(PlayerCommand::ShowText(banner));
+    }
+    
+    /// Offer compression and MessagePack framing, and switch to whatever the server picks
+    ///
+    /// The `Hello` also carries our identity proof, if we have a key, signed
+    /// over the server's `Challenge`. Anything broadcast before the `Welcome`
+    /// predates our join and is skipped. Servers that don't know `Hello`
+    /// never answer, so after `HANDSHAKE_TIMEOUT` we carry on with plain JSON.
+    async fn negotiate_wire<R: AsyncRead + Unpin>(
+        &mut self,
+        reader: &mut FrameReader<R>,
+        writer: &mut tokio::net::tcp::OwnedWriteHalf,
+    ) -> Result<()> {
+        let compression = if self.compression { compression::SUPPORTED.to_vec() } else { Vec::new() };
+        let encodings = if self.binary { vec![Encoding::Msgpack] } else { Vec::new() };
+        if compression.is_empty() && encodings.is_empty() && self.identity.is_none() && self.passphrase.is_none() {
+            return Ok(());
+        }
+        
+        let mut identity = None;
+        if let Some(key) = &self.identity {
+            let challenge = tokio::time::timeout(HANDSHAKE_TIMEOUT, async {
+                while let Some(frame) = reader.next_frame().await? {
+                    if let Ok(SyncMessage { event: SyncEvent::Challenge { nonce }, .. }) = frame.decode() {
+                        return Ok(nonce);
+                    }
+                }
+                anyhow::bail!("Server closed the connection")
+            }).await;
+            match challenge {
+                Ok(nonce) => identity = Some(key.prove(&self.user_id, &nonce?)),
+                Err(_) => warn!("Server sent no challenge, joining without proving our identity"),
+            }
+        }
+        
+        let hello = SyncMessage::hello(compression, encodings, identity, self.next_sequence());
+        self.send_message(writer, hello).await?;
+        
+        let welcome = tokio::time::timeout(HANDSHAKE_TIMEOUT, async {
+            while let Some(frame) = reader.next_frame().await? {
+                if let Ok(SyncMessage { event: SyncEvent::Welcome { compression, encoding, salt }, .. }) = frame.decode() {
+                    return Ok((WireFormat { encoding, compression }, salt));
+                }
+            }
+            anyhow::bail!("Server closed the connection")
+        }).await;
         
-        Ok(state)
+        match welcome {
+            Ok(welcome) => {
+                let (format, salt) = welcome?;
+                self.wire = format;
+                reader.set_format(self.wire);
+                info!("Wire format: {:?}, compression: {:?}", self.wire.encoding, self.wire.compression);
+                self.unlock(salt).await?;
+            }
+            Err(_) if self.passphrase.is_some() => anyhow::bail!("Server didn't answer our Hello, so it can't host a --passphrase session"),
+            Err(_) => warn!("Server didn't answer our Hello, using plain JSON"),
+        }
+        Ok(())
     }
     
-    /// Static version for use in spawned tasks (deprecated - use get_current_state_with_user_id)
-    async fn get_current_state_static(
-        mpv: &mut MpvController,
-        playlist_files: &[PathBuf],
-    ) -> Result<UserState> {
-        let playlist_pos = mpv.get_playlist_pos().await.unwrap_or(0);
-        let playback_time = mpv.get_position().await.unwrap_or(0.0);
-        let is_paused = mpv.is_paused().await.unwrap_or(true);
-        
-        let current_file = if playlist_pos >= 0 && (playlist_pos as usize) < playlist_files.len() {
-            Some(playlist_files[playlist_pos as usize].clone())
-        } else {
-            None
+    /// Derive our `--passphrase` key with the session's salt, unless we already have it
+    async fn unlock(&mut self, salt: Option<String>) -> Result<()> {
+        let Some(passphrase) = self.passphrase.clone() else {
+            return Ok(());
         };
+        let salt = salt.context("The server has no salt for --passphrase; it may be too old, or in peer-to-peer mode")?;
+        if self.e2e.as_ref().is_some_and(|e2e| e2e.salt() == salt) {
+            return Ok(());
+        }
         
-        let mut state = UserState::new("temp".to_string()); // Will be overwritten
-        state.update_from_mpv(playlist_pos, playback_time, is_paused, current_file);
-        
-        Ok(state)
+        let (user_id, session_salt) = (self.user_id.clone(), salt.clone());
+        let e2e = tokio::task::spawn_blocking(move || Encryption::new(&passphrase, &session_salt, user_id)).await??;
+        self.e2e = Some(e2e);
+        // Kept in our copy of the session, so it carries over if we take over hosting
+        self.session_state.write().await.salt = Some(salt);
+        Ok(())
     }
     
-    /// Handle incoming message from server
-    async fn handle_incoming_message(&self, message: SyncMessage) {
-        match message.event {
-            SyncEvent::UserJoined { user_id: _, user_state } => {
-                self.session_state.write().await.update_user(user_state);
-            }
-            
-            SyncEvent::UserLeft { user_id } => {
-                self.session_state.write().await.remove_user(&user_id);
-            }
+    /// Estimate how far the server's clock is from ours with a few timed pings
+    ///
+    /// From then on `unix_millis` gives the server's time, so the timestamps
+    /// of readers on different machines line up. Servers that don't answer
+    /// pings before the join leave the clock as it was.
+    async fn sync_clock<R: AsyncRead + Unpin>(
+        &mut self,
+        reader: &mut FrameReader<R>,
+        writer: &mut tokio::net::tcp::OwnedWriteHalf,
+    ) -> Result<()> {
+        let mut offset = ClockOffset::new();
+        for _ in 0..latency::CLOCK_SAMPLES {
+            // Our own clock, uncorrected: the previous server's offset doesn't apply to this one
+            let sent_at_ms = protocol::local_millis();
+            let ping = SyncEvent::Heartbeat { user_id: self.user_id.clone(), timestamp: sent_at_ms / 1000, sent_at_ms, echo_ms: None };
+            self.send_message(writer, SyncMessage::new(ping, self.next_sequence())).await?;
             
-            SyncEvent::StateUpdate { user_state } => {
-                self.session_state.write().await.update_user(user_state);
-            }
+            let pong = tokio::time::timeout(HANDSHAKE_TIMEOUT, async {
+                while let Some(frame) = reader.next_frame().await? {
+                    if let Ok(SyncMessage { event: SyncEvent::Heartbeat { sent_at_ms: remote_ms, echo_ms: Some(echo), .. }, .. }) = frame.decode() {
+                        if echo == sent_at_ms {
+                            return Ok(remote_ms);
+                        }
+                    }
+                }
+                anyhow::bail!("Server closed the connection")
+            }).await;
             
-            SyncEvent::Heartbeat { user_id, .. } => {
-                debug!("Heartbeat from {}", user_id);
+            match pong {
+                Ok(remote_ms) => offset.observe(sent_at_ms, remote_ms?, protocol::local_millis()),
+                Err(_) => break,
             }
-            
-            SyncEvent::UserAction { user_id, action, value } => {
-                info!("User {} performed action: {} {:?}", user_id, action, value);
+        }
+        
+        match offset.get().zip(offset.uncertainty()) {
+            Some((offset_ms, uncertainty)) => {
+                protocol::set_clock_offset(offset_ms);
+                info!("Server clock is {:+} ms from ours ({}{} ms)", offset_ms, terminal::decoration("±"), uncertainty);
             }
+            None => warn!("Server didn't answer our pings, timestamps use our own clock"),
         }
+        Ok(())
     }
     
     /// Send a message to the server
     async fn send_message(&self, writer: &mut tokio::net::tcp::OwnedWriteHalf, message: SyncMessage) -> Result<()> {
-        let json = serde_json::to_string(&message)?;
-        writer.write_all(format!("{}\n", json).as_bytes()).await?;
-        writer.flush().await?;
-        Ok(())
+        Self::send_message_static(writer, message, self.wire, self.e2e.as_ref()).await
     }
     
     /// Static version for use in spawned tasks
-    async fn send_message_static(writer: &mut tokio::net::tcp::OwnedWriteHalf, message: SyncMessage) -> Result<()> {
-        let json = serde_json::to_string(&message)?;
-        writer.write_all(format!("{}\n", json).as_bytes()).await?;
+    async fn send_message_static(
+        writer: &mut tokio::net::tcp::OwnedWriteHalf,
+        message: SyncMessage,
+        wire: WireFormat,
+        e2e: Option<&Encryption>,
+    ) -> Result<()> {
+        let message = match e2e {
+            Some(e2e) => e2e.seal(message)?,
+            None => message,
+        };
+        writer.write_all(&wire::encode(&message, wire)?).await?;
         writer.flush().await?;
         Ok(())
     }
     
+    /// Bring a new connection up to date with what we sent while the old one was failing
+    ///
+    /// Unacked reliable messages go first, in their original order, then our
+    /// newest state. What we missed comes back with the session the server
+    /// sends on every join.
+    async fn catch_up(
+        writer: &mut tokio::net::tcp::OwnedWriteHalf,
+        pending: &mut PendingAcks,
+        missed_update: Option<SyncMessage>,
+        wire: WireFormat,
+        e2e: Option<&Encryption>,
+    ) -> Result<()> {
+        let unacked = pending.resend_all(Instant::now());
+        if !unacked.is_empty() {
+            info!("Resending {} unconfirmed message(s) after reconnecting", unacked.len());
+        }
+        for message in unacked.into_iter().chain(missed_update) {
+            Self::send_message_static(writer, message, wire, e2e).await?;
+        }
+        Ok(())
+    }
+    
+    /// Resend reliable messages the server hasn't acked, warning about any we gave up on
+    async fn resend_unacked(
+        writer: &mut tokio::net::tcp::OwnedWriteHalf,
+        pending: &mut PendingAcks,
+        wire: WireFormat,
+        e2e: Option<&Encryption>,
+        player_tx: &mpsc::UnboundedSender<PlayerCommand>,
+    ) -> Result<()> {
+        let (resend, expired) = pending.poll(Instant::now());
+        for message in resend {
+            debug!("Resending unconfirmed {}", reliable::describe(&message));
+            Self::send_message_static(writer, message, wire, e2e).await?;
+        }
+        
+        if !expired.is_empty() {
+            let lost: Vec<String> = expired.iter().map(reliable::describe).collect();
+            warn!("{} The server never confirmed: {} - the connection may be unhealthy", terminal::decoration("⚠️"), lost.join(", "));
+            let text = format!("Connection to the server looks unhealthy ({} not confirmed)", lost.join(", "));
+            let _ = player_tx.send(PlayerCommand::ShowText(text));
+        }
+        Ok(())
+    }
+    
     /// Get next sequence number
-    fn next_sequence(&mut self) -> u64 {
-        self.sequence_counter += 1;
-        self.sequence_counter
+    fn next_sequence(&self) -> u64 {
+        self.sequence_counter.fetch_add(1, Ordering::Relaxed) + 1
     }
     
     /// Display loop showing current session state for client
     async fn display_loop(
         session_state: Arc<RwLock<SessionState>>, 
         current_user_id: UserId, 
-        minimal: bool,
+        view: TerminalView,
         mut ui_update_rx: broadcast::Receiver<()>
     ) {
         // Initial display
-        Self::render_ui(&session_state, &current_user_id, minimal).await;
-
-        // Wait for UI update events
-        loop {
-            if let Ok(_) = ui_update_rx.recv().await {
-                Self::render_ui(&session_state, &current_user_id, minimal).await;
-            }
+        Self::render_ui(&session_state, &current_user_id, &view).await;
+        
+        // Wait for UI update events, until the session is over
+        while let Ok(()) | Err(broadcast::error::RecvError::Lagged(_)) = ui_update_rx.recv().await {
+            Self::render_ui(&session_state, &current_user_id, &view).await;
         }
     }
     
     /// Render the UI once
-    async fn render_ui(session_state: &Arc<RwLock<SessionState>>, current_user_id: &UserId, minimal: bool) {
+    async fn render_ui(session_state: &Arc<RwLock<SessionState>>, current_user_id: &UserId, view: &TerminalView) {
+        use std::fmt::Write as _;
+        use std::io::Write as _;
+        
+        let TerminalView { minimal, playlist_len, idle_after, .. } = *view;
         let state = session_state.read().await;
         let relative_info = Self::get_relative_position_info(&state, current_user_id);
-
-        // ANSI escape code to clear screen and move cursor to top-left
-        print!("\x1b[2J\x1b[1;1H");
-
+        
+        let mut frame = String::new();
+        
         if !state.users.is_empty() {
             if minimal {
-                // Minimal mode: only show relative position info
+                // Minimal mode: where everyone is along the playlist, then relative position info
+                if playlist_len > 1 {
+                    let _ = writeln!(frame, "{}", state.format_progress_bar(current_user_id, playlist_len, PROGRESS_BAR_WIDTH, terminal::ansi_enabled()));
+                }
                 if !relative_info.is_empty() {
-                    println!("{}", relative_info);
+                    let _ = writeln!(frame, "{}", relative_info);
                 } else {
-                    println!("📍 You are the only user connected");
+                    let _ = writeln!(frame, "{} You are the only user connected", terminal::decoration("📍"));
                 }
             } else {
                 // Full mode: show all users and relative info
                 let user_count = state.users.len();
-                let display_lines = state.format_for_display();
-                println!("🎬 SyncRead Client ({}) - {} users connected", current_user_id, user_count);
-                println!("{}", "=".repeat(60));
-
-                for line in display_lines {
-                    let is_current_user = line.starts_with(&format!("{}:", current_user_id));
-                    if is_current_user {
-                        println!("👤 {}", line);
-                    } else {
-                        println!("   {}", line);
-                    }
+                
+                // Others are numbered as on the F1-F4 jump-to keys
+                let now = unix_millis() / 1000;
+                let name_width = state.name_width();
+                let mut others = 0;
+                let rows: Vec<_> = state.get_users_sorted().into_iter()
+                    .map(|user| {
+                        let (marker, idle) = if user.user_id == *current_user_id {
+                            (terminal::decoration("👤").to_string(), None)
+                        } else {
+                            others += 1;
+                            let marker = if others <= 4 { format!("F{}", others) } else { "  ".to_string() };
+                            (marker, user.idle_label(idle_after, now))
+                        };
+                        let idle = idle.map(|label| format!(" {} {}", terminal::decoration("—"), label)).unwrap_or_default();
+                        let line = match &view.line {
+                            Some(template) => template.render(user),
+                            None => user.format_for_display(name_width),
+                        };
+                        (user, marker, line, idle)
+                    })
+                    .collect();
+                let rule = text_width::rule(rows.iter().map(|(_, marker, line, idle)| format!("{} {}{}", marker, line, idle)));
+                
+                let _ = writeln!(frame, "{}SyncRead Client ({}) - {} users connected", terminal::decoration("🎬 "), current_user_id, user_count);
+                let _ = writeln!(frame, "{}", rule);
+                for (user, marker, line, idle) in &rows {
+                    let _ = writeln!(frame, "{} {}{}", marker, terminal::paint(user.color(), line), idle);
                 }
-
-                println!("{}", "=".repeat(60));
+                let _ = writeln!(frame, "{}", rule);
                 if !relative_info.is_empty() {
-                    println!("{}", relative_info);
+                    let _ = writeln!(frame, "{}", relative_info);
+                }
+            }
+            
+            // Notes others left on the page we're viewing
+            if let Some(me) = state.users.get(current_user_id) {
+                for note in Self::format_notes(&state, me.playlist_position, me.current_file_name.as_deref()) {
+                    let _ = writeln!(frame, "{} {}", terminal::decoration("📝"), note);
                 }
             }
             
+            if let Some(link) = &state.last_link {
+                let _ = writeln!(frame, "{}{}: {}", terminal::decoration("🔗 "), link.user_id, link.url);
+            }
+            
             if !minimal {
-                println!("Press 'q' in MPV to quit, or Ctrl+C here");
+                let _ = match view.keys {
+                    Some(_) => writeln!(frame, "{}, or Ctrl+C", keyboard::HELP),
+                    None => writeln!(frame, "Press 'q' in MPV to quit, or Ctrl+C here"),
+                };
             }
         }
+        
+        // Keep what's being typed at the bottom
+        if let Some(prompt) = view.keys.as_ref().and_then(|keys| keys.lock().unwrap().prompt_line()) {
+            frame.push_str(&prompt);
+        }
+        
+        terminal::clear_screen();
+        let _ = write!(terminal::Stdout, "{}", frame);
+        let _ = terminal::Stdout.flush();
+    }
+    
+    /// "user: text" lines for the notes on a page
+    fn format_notes(session_state: &SessionState, playlist_position: i32, file_name: Option<&str>) -> Vec<String> {
+        session_state.annotations_for(playlist_position, file_name)
+            .into_iter()
+            .map(|note| format!("{}: {}", note.user_id, note.text))
+            .collect()
     }
     
     /// Get relative position information compared to other users
@@ -397,13 +2379,17 @@ impl SyncClient {
         let mut behind = Vec::new();
         
         for user in other_users {
-            let diff = current_pos - user.playlist_position;
+            // Within the same long video, chapters say more than pages
+            let (diff, unit) = match (&current_user.chapter, &user.chapter) {
+                (Some(ours), Some(theirs)) if current_pos == user.playlist_position => (ours.index - theirs.index, "chapter"),
+                _ => (current_pos - user.playlist_position, "page"),
+            };
             if diff == 0 {
                 same_page.push(&user.user_id);
             } else if diff > 0 {
-                ahead_of.push((&user.user_id, diff));
+                ahead_of.push((&user.user_id, diff, unit));
             } else {
-                behind.push((&user.user_id, -diff));
+                behind.push((&user.user_id, -diff, unit));
             }
         }
         
@@ -411,21 +2397,21 @@ impl SyncClient {
         
         if !same_page.is_empty() {
             if same_page.len() == 1 {
-                messages.push(format!("📍 You are on the same page as {}", same_page[0]));
+                messages.push(format!("{} You are on the same page as {}", terminal::decoration("📍"), same_page[0]));
             } else {
                 let names: Vec<String> = same_page.iter().map(|s| s.to_string()).collect();
-                messages.push(format!("📍 You are on the same page as {}", names.join(", ")));
+                messages.push(format!("{} You are on the same page as {}", terminal::decoration("📍"), names.join(", ")));
             }
         }
         
-        for (user_id, pages) in ahead_of {
-            let page_word = if pages == 1 { "page" } else { "pages" };
-            messages.push(format!("⬆️  You are {} {} ahead of {}", pages, page_word, user_id));
+        for (user_id, count, unit) in ahead_of {
+            let plural = if count == 1 { "" } else { "s" };
+            messages.push(format!("{}  You are {} {}{} ahead of {}", terminal::decoration("⬆️"), count, unit, plural, user_id));
         }
         
-        for (user_id, pages) in behind {
-            let page_word = if pages == 1 { "page" } else { "pages" };
-            messages.push(format!("⬇️  You are {} {} behind {}", pages, page_word, user_id));
+        for (user_id, count, unit) in behind {
+            let plural = if count == 1 { "" } else { "s" };
+            messages.push(format!("{}  You are {} {}{} behind {}", terminal::decoration("⬇️"), count, unit, plural, user_id));
         }
         
         messages.join("\n")
@@ -433,6 +2419,7 @@ impl SyncClient {
     
     /// Validate position change to prevent MPV transition glitches with retry mechanism
     async fn validate_position_change(
+        glitch_filter: &GlitchFilter,
         last_known_position: &Arc<RwLock<Option<i32>>>,
         pending_position: &Arc<RwLock<Option<(i32, u8)>>>,
         new_position: i32,
@@ -440,76 +2427,698 @@ impl SyncClient {
     ) -> bool {
         let mut last_pos = last_known_position.write().await;
         let mut pending = pending_position.write().await;
+        glitch_filter.accept(&mut last_pos, &mut pending, new_position, playlist_length)
+    }
+}
+
+/// Whether the update task can poll MPV
+enum MpvHealth {
+    Running,
+    Busy, // Restarted after a crash, or on its way out; try again at the next poll
+    Gone,
+}
+
+/// Polls MPV, shares what changed in it and applies what others did, until the session ends
+///
+/// Holds what it needs of the client, and what it remembers from one poll
+/// to the next.
+struct UpdateTask {
+    mpv: Box<dyn MediaPlayer>,
+    playlist_files: Vec<MediaSource>,
+    user_id: UserId,
+    color: UserColor,
+    icon: Option<String>,
+    session_state: Arc<RwLock<SessionState>>,
+    last_known_position: Arc<RwLock<Option<i32>>>,
+    pending_position: Arc<RwLock<Option<(i32, u8)>>>,
+    glitch_filter: GlitchFilter,
+    stats: Arc<RwLock<StatsTracker>>,
+    sequence_counter: Arc<AtomicU64>,
+    round_trip: Arc<RwLock<RoundTrip>>,
+    resume: Option<(ResumeStore, String)>,
+    time_sync_threshold: Option<f64>,
+    wait_for_all: Option<i32>,
+    shared_pause: bool,
+    synced_properties: Vec<String>,
+    chapter_mode: bool,
+    presenting: bool,
+    osd_overlay: bool,
+    hud: bool,
+    idle_after: Duration,
+    cadence: UpdateCadence,
+    bookmarks: Option<(SharedBookmarks, String)>,
+    file_sources: Arc<RwLock<FileSources>>,
+    playlist_names: Arc<RwLock<Vec<String>>>,
+    download_dir: PathBuf,
+    max_download_size: u64,
+    screenshot_dir: PathBuf,
+    following: Arc<RwLock<Option<UserId>>>,
+    hooks: Arc<HookRunner>,
+    player_tx: mpsc::UnboundedSender<PlayerCommand>, // Back to ourselves, for downloads and our own edits
+    outgoing_tx: mpsc::UnboundedSender<SyncMessage>,
+    ui_update_tx: broadcast::Sender<()>,
+    
+    last_resume_save: Option<(i32, Instant)>,
+    last_catch_up: Option<Instant>,
+    last_paused: Option<bool>,
+    last_sample: Option<(i32, f64, Instant)>, // (position, time, when) for seek detection
+    speed: f64,
+    last_good_state: Option<UserState>,
+    restarts: Vec<Instant>,
+    last_osd: Option<String>,
+    last_hud: Option<String>,
+    last_notes: Option<(i32, usize)>, // (page, note count) last shown
+    finished: bool,
+    fetch_task: Option<JoinHandle<()>>,
+    reactions: Vec<(String, Instant)>, // Still on screen
+    property_values: PropertyTracker,
+    chapters: Option<(i32, Vec<Chapter>)>, // Chapter list of the page we're on
+    last_view: Option<(ViewTransform, usize)>, // (view, readers) last presented to
+    control_actions: Vec<Vec<String>>, // From the control socket, run with MPV's at the next poll
+    hung_up_at: Option<Instant>,
+}
+
+impl UpdateTask {
+    fn new(
+        client: &mut SyncClient,
+        mpv: Box<dyn MediaPlayer>,
+        playlist_files: Vec<MediaSource>,
+        player_tx: mpsc::UnboundedSender<PlayerCommand>,
+        outgoing_tx: mpsc::UnboundedSender<SyncMessage>,
+        ui_update_tx: broadcast::Sender<()>,
+    ) -> Self {
+        Self {
+            mpv,
+            playlist_files,
+            user_id: client.user_id.clone(),
+            color: client.color,
+            icon: client.icon.clone(),
+            session_state: client.session_state.clone(),
+            last_known_position: client.last_known_position.clone(),
+            pending_position: client.pending_position.clone(),
+            glitch_filter: client.glitch_filter.clone(),
+            stats: client.stats.clone(),
+            sequence_counter: client.sequence_counter.clone(),
+            round_trip: client.round_trip.clone(),
+            resume: client.resume.take(),
+            time_sync_threshold: client.time_sync_threshold,
+            wait_for_all: client.wait_for_all,
+            shared_pause: client.shared_pause,
+            synced_properties: client.synced_properties.clone(),
+            chapter_mode: client.chapter_mode,
+            presenting: client.presenting,
+            osd_overlay: client.osd_overlay,
+            hud: client.hud,
+            idle_after: client.idle_after,
+            cadence: UpdateCadence::new(client.update_interval, client.adaptive_updates),
+            bookmarks: client.bookmarks.clone(),
+            file_sources: client.file_sources.clone(),
+            playlist_names: client.playlist_names.clone(),
+            download_dir: client.download_dir.clone(),
+            max_download_size: client.max_download_size,
+            screenshot_dir: client.screenshot_dir.clone(),
+            following: client.following.clone(),
+            hooks: client.hooks.clone(),
+            player_tx,
+            outgoing_tx,
+            ui_update_tx,
+            last_resume_save: None,
+            last_catch_up: None,
+            last_paused: None,
+            last_sample: None,
+            speed: 1.0,
+            last_good_state: None,
+            restarts: Vec::new(),
+            last_osd: None,
+            last_hud: None,
+            last_notes: None,
+            finished: false,
+            fetch_task: None,
+            reactions: Vec::new(),
+            property_values: PropertyTracker::new(),
+            chapters: None,
+            last_view: None,
+            control_actions: Vec::new(),
+            hung_up_at: None,
+        }
+    }
+    
+    /// Poll MPV at the pace the cadence sets, carrying out commands as they come in
+    async fn run(
+        mut self,
+        mut player_rx: mpsc::UnboundedReceiver<PlayerCommand>,
+        mut control_rx: mpsc::UnboundedReceiver<HeadlessCommand>,
+        mut quit_rx: oneshot::Receiver<()>,
+    ) {
+        let mut mpv_events = self.mpv.subscribe_events();
+        let mut period = Duration::ZERO; // Until the next update, as decided after the last
+        let next_update = tokio::time::sleep(period);
+        tokio::pin!(next_update);
         
-        // If we don't have a last known position, accept any reasonable position
-        let Some(last) = *last_pos else {
-            if new_position >= 0 && new_position < playlist_length as i32 {
-                *last_pos = Some(new_position);
-                *pending = None; // Clear any pending position
-                return true;
+        loop {
+            tokio::select! {
+                _ = &mut quit_rx => {
+                    self.mpv.quit().await;
+                    break;
+                }
+                Some(command) = player_rx.recv() => {
+                    self.apply(command).await;
+                    continue;
+                }
+                Some(command) = control_rx.recv() => {
+                    if !self.control(command).await {
+                        break;
+                    }
+                    // Share the change (or run the action) now rather than at the next poll
+                    self.cadence.wake(Instant::now());
+                    next_update.as_mut().reset(Instant::now());
+                    continue;
+                }
+                _ = &mut next_update => {
+                    next_update.as_mut().reset(Instant::now() + period);
+                }
+            }
+            
+            match self.check_mpv(&mut mpv_events).await {
+                MpvHealth::Running => {}
+                MpvHealth::Busy => continue,
+                MpvHealth::Gone => break,
+            }
+            
+            let state = SyncClient::get_current_state(&mut *self.mpv, &self.playlist_files, &self.user_id, self.color, self.icon.clone()).await;
+            match state {
+                Ok(state) => {
+                    // Quitting MPV we only attached to shows up here, and the state read while it quit is no good
+                    if SyncClient::mpv_closed(&mut mpv_events) {
+                        info!("MPV was closed");
+                        break;
+                    }
+                    period = self.cadence.next(state.playlist_position, state.is_paused, state.playback_time, Instant::now());
+                    next_update.as_mut().reset(Instant::now() + period);
+                    if !self.poll(state).await {
+                        break;
+                    }
+                }
+                Err(e) if self.mpv.hung_up() => debug!("MPV hung up while polling: {}", e),
+                Err(e) => {
+                    warn!("Failed to get MPV state: {}", e);
+                }
+            }
+        }
+    }
+    
+    fn next_sequence(&self) -> u64 {
+        self.sequence_counter.fetch_add(1, Ordering::Relaxed) + 1
+    }
+    
+    fn send(&self, message: SyncMessage) {
+        let _ = self.outgoing_tx.send(message);
+    }
+    
+    /// Carry out a command in MPV, recording the state we set so the next poll doesn't broadcast it back
+    async fn apply(&mut self, command: PlayerCommand) {
+        let mpv = &mut *self.mpv;
+        let result = match command {
+            PlayerCommand::Pause => mpv.pause().await,
+            PlayerCommand::Play => mpv.play().await,
+            PlayerCommand::Seek { target, sent_at_ms, round_trip_ms } => {
+                SyncClient::apply_remote_seek(mpv, target, sent_at_ms, round_trip_ms).await
+            }
+            PlayerCommand::ShowText(ref text) => {
+                mpv.show_text(text, STATUS_TEXT_DURATION).await
+            }
+            PlayerCommand::ShowReaction(ref text) => {
+                let shown_for = Duration::from_millis(REACTION_TEXT_DURATION as u64);
+                self.reactions.retain(|(_, at)| at.elapsed() < shown_for);
+                self.reactions.push((text.clone(), Instant::now()));
+                let line: Vec<&str> = self.reactions.iter().map(|(text, _)| text.as_str()).collect();
+                mpv.show_text(&line.join("   "), REACTION_TEXT_DURATION).await
+            }
+            PlayerCommand::InsertFile { index, ref file } => {
+                mpv.insert_file(index, file.clone()).await
+            }
+            PlayerCommand::SetProperty { ref name, ref value } => {
+                SyncClient::apply_property(mpv, name, value.clone()).await
+            }
+            PlayerCommand::SetAbLoop(ab_loop) => SyncClient::apply_ab_loop(mpv, ab_loop).await,
+            PlayerCommand::SetView(view) => mpv.set_view(view).await,
+            PlayerCommand::EditPlaylist { ref edit, ours } => {
+                SyncClient::apply_playlist_edit(mpv, &mut self.playlist_files, &self.playlist_names, &self.download_dir, edit, ours).await
+            }
+            PlayerCommand::Screenshot { moment } => {
+                SyncClient::take_screenshot(mpv, &self.screenshot_dir, &self.user_id, moment).await
+            }
+            PlayerCommand::Follow(ref user_id) => {
+                *self.following.write().await = user_id.clone();
+                let text = match user_id {
+                    Some(user_id) => format!("Following {}'s page turns", user_id),
+                    None => "Following everyone's page turns".to_string(),
+                };
+                info!("{}", text);
+                mpv.show_text(&text, STATUS_TEXT_DURATION).await
+            }
+        };
+        self.cadence.wake(Instant::now());
+        match result {
+            Ok(()) => match command {
+                PlayerCommand::Pause => self.last_paused = Some(true),
+                PlayerCommand::Play => self.last_paused = Some(false),
+                PlayerCommand::Seek { .. } | PlayerCommand::SetAbLoop(_) => {
+                    // Others' reports are stale until they land too; don't catch up to them
+                    self.last_sample = None;
+                    self.last_catch_up = Some(Instant::now());
+                }
+                PlayerCommand::ShowText(_) | PlayerCommand::ShowReaction(_) | PlayerCommand::Screenshot { .. } => {}
+                PlayerCommand::SetView(_) | PlayerCommand::Follow(_) => {}
+                PlayerCommand::EditPlaylist { .. } => {
+                    // A different item at our position isn't a seek
+                    self.last_sample = None;
+                }
+                PlayerCommand::InsertFile { index, file } => {
+                    self.playlist_names.write().await.insert(index, file.display_name().unwrap_or_default());
+                    self.playlist_files.insert(index, file);
+                }
+                PlayerCommand::SetProperty { name, value } => self.property_values.applied(&name, value),
+            },
+            Err(e) => warn!("Failed to apply {:?}: {}", command, e),
+        }
+    }
+    
+    /// Carry out a command from the terminal or control socket; false if it was to quit
+    async fn control(&mut self, command: HeadlessCommand) -> bool {
+        let last_index = (self.playlist_files.len() as i32 - 1).max(0);
+        let mpv = &mut *self.mpv;
+        let result = match command {
+            HeadlessCommand::Page(page) => mpv.set_playlist_pos((page - 1).clamp(0, last_index)).await,
+            HeadlessCommand::Next | HeadlessCommand::Prev => {
+                let step = if command == HeadlessCommand::Next { 1 } else { -1 };
+                match mpv.get_playlist_pos().await {
+                    Ok(position) => mpv.set_playlist_pos((position + step).clamp(0, last_index)).await,
+                    Err(e) => Err(e),
+                }
+            }
+            HeadlessCommand::Seek(target) => mpv.seek_to(target).await,
+            HeadlessCommand::Pause => mpv.pause().await,
+            HeadlessCommand::Play => mpv.play().await,
+            HeadlessCommand::Note(_) | HeadlessCommand::Link(_) | HeadlessCommand::React(_)
+            | HeadlessCommand::Chat(_) | HeadlessCommand::Follow(_) | HeadlessCommand::JumpTo(_) => {
+                self.control_actions.extend(command.sync_action());
+                Ok(())
+            }
+            HeadlessCommand::Quit => {
+                info!("Told to leave from the terminal or control socket");
+                mpv.quit().await;
+                return false;
             }
-            return false;
         };
+        if let Err(e) = result {
+            warn!("Failed to carry out control command: {}", e);
+        }
+        true
+    }
+    
+    /// See whether MPV is still there, relaunching it where we left off if it crashed
+    async fn check_mpv(&mut self, events: &mut Option<broadcast::Receiver<MpvEvent>>) -> MpvHealth {
+        // The user closed MPV, which ends the session
+        if self.mpv.exit_status().is_some_and(|s| s.success()) || SyncClient::mpv_closed(events) {
+            info!("MPV was closed");
+            return MpvHealth::Gone;
+        }
         
-        // Reject jumping to invalid positions
-        if new_position < 0 || new_position >= playlist_length as i32 {
-            debug!("Rejected invalid position: {} (playlist length: {})", new_position, playlist_length);
-            *pending = None; // Clear pending for invalid positions
-            return false;
+        if let Some(status) = self.mpv.exit_status().filter(|s| !s.success()) {
+            self.restarts.retain(|at| at.elapsed() < RESTART_WINDOW);
+            if self.restarts.len() >= MAX_RESTARTS {
+                error!("MPV crashed {} times in a minute ({}), not restarting it again", self.restarts.len() + 1, status);
+                return MpvHealth::Gone;
+            }
+            self.restarts.push(Instant::now());
+            
+            warn!("MPV exited unexpectedly ({}), restarting", status);
+            if let Err(e) = SyncClient::recover_mpv(&mut *self.mpv, self.last_good_state.as_ref()).await {
+                error!("Failed to restart MPV: {}", e);
+                return MpvHealth::Busy;
+            }
+            
+            // Position jumps caused by the restart are not local seeks or page turns
+            self.last_sample = None;
+            self.last_paused = None;
+            self.last_osd = None;
+            self.last_hud = None;
+            self.last_notes = None;
+            self.last_view = None;
+            self.hung_up_at = None;
+            self.speed = 1.0;
+            self.property_values.clear();
+            
+            self.send(SyncMessage::user_action(self.user_id.clone(), "mpv_restarted", None, self.next_sequence()));
+            return MpvHealth::Busy;
         }
         
-        let position_diff = (new_position - last).abs();
+        // Hung up without saying it quit: ours gets a moment to exit and show whether it crashed, one we attached to can't
+        if self.mpv.hung_up() {
+            if self.hung_up_at.get_or_insert_with(Instant::now).elapsed() >= SHUTDOWN_TIMEOUT {
+                info!("MPV closed its connection");
+                return MpvHealth::Gone;
+            }
+            return MpvHealth::Busy;
+        }
+        MpvHealth::Running
+    }
+    
+    /// Act on what MPV just reported: share it, keep up with the others and refresh what we show; false once the session is over
+    async fn poll(&mut self, mut state: UserState) -> bool {
+        state.rtt_ms = self.round_trip.read().await.get();
+        if self.chapter_mode {
+            state.chapter = SyncClient::read_chapter(&mut *self.mpv, &mut self.chapters, state.playlist_position).await;
+        }
+        if let Some(previous) = &self.last_good_state {
+            state.carry_activity(previous);
+        }
         
-        // Always allow small jumps (±3 positions)
-        if position_diff <= 3 {
-            *last_pos = Some(new_position);
-            *pending = None; // Clear any pending position
+        if self.hold_back(&state).await {
             return true;
         }
+        self.check_finished(&mut state);
+        self.run_actions(&mut state).await;
+        self.share_pause(&state);
+        self.share_seek(&state);
         
-        // Allow larger jumps if they seem intentional (forward progress)
-        if new_position > last {
-            *last_pos = Some(new_position);
-            *pending = None; // Clear any pending position
-            return true;
+        // Validate position change to prevent MPV transition glitches
+        let should_send_update = SyncClient::validate_position_change(
+            &self.glitch_filter,
+            &self.last_known_position,
+            &self.pending_position,
+            state.playlist_position,
+            self.playlist_files.len()
+        ).await;
+        
+        self.share_properties(&state, should_send_update).await;
+        if self.presenting {
+            self.share_view().await;
         }
         
-        // Handle large backward jumps with retry mechanism
-        if position_diff > 10 {
-            // Check if this is a glitch (jumping from middle/end back to start)
-            if last > 5 && new_position <= 1 {
-                debug!("Rejected obvious glitch: position {} -> {} (likely MPV transition)", last, new_position);
-                *pending = None; // Clear pending for obvious glitches
+        if should_send_update {
+            self.save_resume(&state);
+            if let Some(threshold) = self.time_sync_threshold {
+                self.keep_time(&state, threshold).await;
+            }
+            
+            self.last_good_state = Some(state.clone());
+            self.stats.write().await.observe(&state);
+            
+            // Update our local session state
+            SyncClient::record_state(&self.session_state, &self.hooks, state.clone()).await;
+            
+            let update_message = SyncMessage::state_update(state.clone(), self.next_sequence());
+            if let Err(e) = self.outgoing_tx.send(update_message) {
+                error!("Failed to send state update: {}", e);
                 return false;
             }
             
-            // Handle legitimate large backward jumps with retry
-            match pending.as_mut() {
-                Some((pending_pos, count)) if *pending_pos == new_position => {
-                    // Same position persists, increment retry count
-                    *count += 1;
-                    debug!("Large backward jump {} -> {} persists (attempt {})", last, new_position, *count);
-                    
-                    // Accept after 2 consistent readings (2 seconds total)
-                    if *count >= 2 {
-                        *last_pos = Some(new_position);
-                        *pending = None;
-                        info!("Accepted legitimate large backward jump: {} -> {}", last, new_position);
-                        return true;
+            // Trigger UI update since our state changed
+            let _ = self.ui_update_tx.send(());
+        } else {
+            debug!("Skipped potentially invalid position update: {}", state.playlist_position);
+        }
+        
+        if self.hud {
+            self.refresh_hud().await;
+        }
+        if self.osd_overlay {
+            self.refresh_overlay().await;
+        }
+        self.show_notes(&state).await;
+        true
+    }
+    
+    /// Turn back pages read past the slowest reader, without telling anyone we got there
+    async fn hold_back(&mut self, state: &UserState) -> bool {
+        let Some(tolerance) = self.wait_for_all else {
+            return false;
+        };
+        let held_back = {
+            let session = self.session_state.read().await;
+            SyncClient::wait_limit(&session, &state.user_id, state.playlist_position, tolerance)
+        };
+        let Some((limit, waiting_for)) = held_back else {
+            return false;
+        };
+        info!("{}", waiting_for);
+        if let Err(e) = self.mpv.set_playlist_pos(limit).await {
+            warn!("Failed to turn back to p.{}: {}", limit + 1, e);
+        }
+        if let Err(e) = self.mpv.show_text(&waiting_for, STATUS_TEXT_DURATION).await {
+            warn!("Failed to show wait message: {}", e);
+        }
+        true
+    }
+    
+    /// Reaching the last item marks us finished for the rest of the session
+    fn check_finished(&mut self, state: &mut UserState) {
+        let last_index = self.playlist_files.len() as i32 - 1;
+        if !self.finished && last_index > 0 && state.playlist_position == last_index {
+            self.finished = true;
+            info!("Reached the end of the playlist");
+            self.send(SyncMessage::user_action(self.user_id.clone(), "finished", None, self.next_sequence()));
+        }
+        state.finished = self.finished;
+    }
+    
+    /// Keys bound to `script-message syncread <action>` seen while polling, and actions from the control socket
+    async fn run_actions(&mut self, state: &mut UserState) {
+        let mut actions = self.mpv.take_script_actions();
+        actions.append(&mut self.control_actions);
+        for action in actions {
+            state.last_active = state.timestamp; // A key press is activity too
+            let outcome = SyncClient::handle_script_action(
+                &mut *self.mpv,
+                &self.session_state,
+                self.bookmarks.as_ref(),
+                &self.screenshot_dir,
+                state,
+                &self.playlist_files,
+                &action,
+            ).await;
+            if outcome.restarted {
+                // The new MPV has no overlays yet
+                self.last_osd = None;
+                self.last_hud = None;
+            }
+            if let Some(event) = outcome.outgoing {
+                if let SyncEvent::Reaction { emoji, .. } = &event {
+                    let _ = self.player_tx.send(PlayerCommand::ShowReaction(format!("You {}", emoji)));
+                }
+                self.send(SyncMessage::new(event, self.next_sequence()));
+            }
+            if let Some(command) = outcome.local {
+                let _ = self.player_tx.send(command);
+            }
+            if outcome.fetch_missing {
+                self.fetch_missing().await;
+            }
+        }
+    }
+    
+    /// Start downloading what others share and we don't have, unless we already are
+    async fn fetch_missing(&mut self) {
+        if self.fetch_task.as_ref().is_some_and(|task| !task.is_finished()) {
+            let _ = self.player_tx.send(PlayerCommand::ShowText("Already downloading".to_string()));
+            return;
+        }
+        let sources = self.file_sources.read().await.clone();
+        let ours = self.playlist_names.read().await.clone();
+        let task = SyncClient::fetch_missing(sources, ours, self.download_dir.clone(), self.max_download_size, self.player_tx.clone());
+        self.fetch_task = Some(tokio::spawn(task));
+    }
+    
+    /// Tell everyone about pauses/resumes made in our MPV
+    fn share_pause(&mut self, state: &UserState) {
+        if !self.shared_pause {
+            return;
+        }
+        if self.last_paused.is_some_and(|paused| paused != state.is_paused) {
+            let action = if state.is_paused { "pause" } else { "play" };
+            self.send(SyncMessage::user_action(self.user_id.clone(), action, None, self.next_sequence()));
+        }
+        self.last_paused = Some(state.is_paused);
+    }
+    
+    /// Share seeks made in our MPV with the other viewers
+    fn share_seek(&mut self, state: &UserState) {
+        if self.time_sync_threshold.is_none() {
+            return;
+        }
+        let seeked = self.last_sample.is_some_and(|(position, time, at)| {
+            position == state.playlist_position
+                && time_sync::is_seek(time, state.playback_time, at.elapsed().as_secs_f64())
+        });
+        if seeked {
+            self.last_catch_up = Some(Instant::now());
+            self.send(SyncMessage::seek(self.user_id.clone(), state.playback_time, self.next_sequence()));
+        }
+        self.last_sample = Some((state.playlist_position, state.playback_time, Instant::now()));
+    }
+    
+    /// Share changes made in our MPV to the synced properties
+    async fn share_properties(&mut self, state: &UserState, page_changed: bool) {
+        for name in &self.synced_properties {
+            let value = if name == properties::PLAYLIST_POS {
+                if !page_changed {
+                    continue;
+                }
+                Value::from(state.playlist_position)
+            } else {
+                match self.mpv.get_property(name).await {
+                    Ok(value) => value,
+                    Err(e) => {
+                        debug!("Failed to read {}: {}", name, e);
+                        continue;
                     }
                 }
-                _ => {
-                    // New large backward jump, start tracking it
-                    *pending = Some((new_position, 1));
-                    debug!("Tracking potential large backward jump: {} -> {} (attempt 1)", last, new_position);
+            };
+            if self.property_values.observe(name, value.clone()) {
+                let sequence = self.next_sequence();
+                let message = if name == properties::PLAYLIST_POS {
+                    SyncMessage::page_turn(self.user_id.clone(), state.playlist_position, state.current_file_name.clone(), sequence)
+                } else {
+                    SyncMessage::property(self.user_id.clone(), name, value, sequence)
+                };
+                self.send(message);
+            }
+        }
+    }
+    
+    /// Presenting: share our crop when it changes, and again whenever someone joins
+    async fn share_view(&mut self) {
+        match self.mpv.get_view().await {
+            Ok(view) => {
+                let readers = self.session_state.read().await.users.len();
+                if self.last_view.is_none_or(|(last, seen)| last != view || readers > seen) {
+                    let event = SyncEvent::View { user_id: self.user_id.clone(), view };
+                    self.send(SyncMessage::new(event, self.next_sequence()));
+                }
+                self.last_view = Some((view, readers));
+            }
+            Err(e) => debug!("Failed to read zoom and pan: {}", e),
+        }
+    }
+    
+    /// Persist position on page changes, and periodically while watching
+    fn save_resume(&mut self, state: &UserState) {
+        let Some((store, fingerprint)) = self.resume.as_mut() else {
+            return;
+        };
+        let due = self.last_resume_save.is_none_or(|(index, at)| {
+            index != state.playlist_position || at.elapsed() >= Duration::from_secs(5)
+        });
+        if due {
+            if let Err(e) = store.record(fingerprint, state.playlist_position, state.playback_time, self.finished) {
+                warn!("Failed to save resume position: {}", e);
+            }
+            self.last_resume_save = Some((state.playlist_position, Instant::now()));
+        }
+    }
+    
+    /// Catch up with viewers ahead of us, giving each seek time to land
+    async fn keep_time(&mut self, state: &UserState, threshold: f64) {
+        let settled = self.last_catch_up.is_none_or(|at| at.elapsed() >= CATCH_UP_COOLDOWN);
+        let target = {
+            let session = self.session_state.read().await;
+            time_sync::catch_up_target(state, session.users.values(), threshold, unix_millis())
+        };
+        if let Some(target) = target.filter(|_| settled) {
+            info!("Behind by {:.1}s, seeking to {:.1}s", target - state.playback_time, target);
+            if let Err(e) = self.mpv.seek_to(target).await {
+                warn!("Failed to catch up: {}", e);
+            }
+            self.last_catch_up = Some(Instant::now());
+            self.last_sample = None; // Our own catch-up is not a seek to share
+        } else if settled {
+            // Smaller drift is closed gently by playing slightly faster or slower
+            let drift = {
+                let session = self.session_state.read().await;
+                time_sync::drift(state, session.users.values(), unix_millis())
+            };
+            let new_speed = time_sync::nudge_speed(drift, self.speed);
+            if new_speed != self.speed {
+                debug!("Drift {:?}s, playback speed {} -> {}", drift, self.speed, new_speed);
+                match self.mpv.set_speed(new_speed).await {
+                    Ok(()) => self.speed = new_speed,
+                    Err(e) => warn!("Failed to adjust speed: {}", e),
+                }
+            }
+        }
+    }
+    
+    /// Push session status to the HUD script when it changed
+    async fn refresh_hud(&mut self) {
+        let payload = hud::hud_payload(&*self.session_state.read().await, &self.user_id, self.idle_after);
+        if self.last_hud.as_ref() != Some(&payload) {
+            match self.mpv.script_message_to(hud::HUD_SCRIPT_NAME, &["update", &payload]).await {
+                Ok(()) => self.last_hud = Some(payload),
+                Err(e) => warn!("Failed to update HUD: {}", e),
+            }
+        }
+    }
+    
+    /// Refresh the in-player overlay when anyone's position changed
+    async fn refresh_overlay(&mut self) {
+        let text = {
+            let session = self.session_state.read().await;
+            (session.users.len() > 1).then(|| session.format_for_osd(&self.user_id, self.idle_after))
+        };
+        if text != self.last_osd {
+            let result = match &text {
+                Some(text) => self.mpv.show_overlay(text).await,
+                None => self.mpv.hide_overlay().await,
+            };
+            match result {
+                Ok(()) => self.last_osd = text,
+                Err(e) => warn!("Failed to update MPV overlay: {}", e),
+            }
+        }
+    }
+    
+    /// Show notes when we open an annotated page, or someone annotates the one we're on
+    async fn show_notes(&mut self, state: &UserState) {
+        let notes = {
+            let session = self.session_state.read().await;
+            SyncClient::format_notes(&session, state.playlist_position, state.current_file_name.as_deref())
+        };
+        let shown = (state.playlist_position, notes.len());
+        if self.last_notes != Some(shown) {
+            if !notes.is_empty() {
+                let text = format!("Notes on p.{}:\n{}", state.playlist_position + 1, notes.join("\n"));
+                if let Err(e) = self.mpv.show_text(&text, NOTE_TEXT_DURATION).await {
+                    warn!("Failed to show notes: {}", e);
                 }
             }
-            return false; // Don't send update yet, wait for confirmation
+            self.last_notes = Some(shown);
         }
+    }
+}
+
+/// Resolves on Ctrl+C, or SIGTERM on Unix
+pub async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
         
-        // Accept moderate backward jumps (user might have gone back a few pages)
-        *last_pos = Some(new_position);
-        *pending = None; // Clear any pending position
-        true
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+            }
+            Err(_) => {
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
     }
 }
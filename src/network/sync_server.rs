@@ -1,21 +1,490 @@
-use super::protocol::{SessionState, SyncMessage, SyncEvent, UserId, UserState};
-use anyhow::{Context, Result};
-use std::collections::HashMap;
+use super::checksums;
+use super::compression;
+use super::discovery;
+use super::encryption;
+use super::event_log::{EventKind, EventLog};
+use super::http_api;
+use super::identity::{self, IdentityProof, KeyPins};
+use super::latency::{self, RoundTrip};
+use super::moderation::{self, AllowList, BanList, DuplicateIds};
+use super::port_mapping;
+use super::rate_limit::{self, RateLimiter, Verdict};
+use super::protocol::{unix_millis, AdminCommand, FileChecksum, PeerAddress, SequenceTracker, SessionState, SlideshowCommand, SyncMessage, SyncEvent, UserId, DEFAULT_IDLE_AFTER};
+use super::reliable::DuplicateFilter;
+use super::slideshow::{self, Slideshow};
+use super::stats::StatsTracker;
+use super::summary::SessionSummary;
+use super::text_width;
+use super::webhook::{Webhook, WebhookEvent};
+use super::wire::{self, FrameReader, WireFormat};
+use crate::error::{self, ConnectionError};
+use crate::storage::{load_json, save_json};
+use crate::terminal::{self, Screen};
+use anyhow::Result;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::{broadcast, mpsc, RwLock};
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::io::AsyncWriteExt;
+use tokio::time::{interval, Duration, Instant};
 use tracing::{debug, error, info, warn};
 use std::sync::Arc;
 
 type ClientSender = mpsc::UnboundedSender<SyncMessage>;
 
-/// Sync server that coordinates multiple clients
-pub struct SyncServer {
+/// How often the session is written to the state file
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long restored users stay listed while waiting for them to reconnect
+const RESTORED_USER_GRACE: Duration = Duration::from_secs(120);
+
+/// How often a running slideshow checks whether the page's time is up
+const SLIDESHOW_TICK: Duration = Duration::from_millis(250);
+
+/// How often everyone is sent the whole session, to heal any updates they missed
+pub const DEFAULT_RESYNC_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Longest `shutdown` waits for connections to pass on the notice
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(2);
+
+/// Bookmarks remembered for replay to late joiners; older ones are dropped first
+const MAX_BOOKMARKS: usize = 500;
+
+/// A connected client as seen by the server
+#[derive(Clone)]
+struct ClientHandle {
+    tx: ClientSender,
+    addr: SocketAddr,
+    joined: Instant, // Kept across reconnects, since the oldest reader is the host
+}
+
+/// A client waiting for a place in a full session
+struct Waiting {
+    user_id: UserId,
+    tx: ClientSender,
+    position: usize, // Place in the queue it was last told
+    admitted: bool, // Told to join again; its place is held until it does
+}
+
+/// A message on its way to every connection except the one that sent it
+#[derive(Debug, Clone)]
+struct Broadcast {
+    message: SyncMessage,
+    origin: Option<SocketAddr>, // None for messages the server makes itself
+}
+
+/// Shared server state handed to every connection task
+#[derive(Clone)]
+struct ServerContext {
     session_state: Arc<RwLock<SessionState>>,
-    clients: Arc<RwLock<HashMap<UserId, ClientSender>>>,
-    broadcast_tx: broadcast::Sender<SyncMessage>,
+    clients: Arc<RwLock<HashMap<UserId, ClientHandle>>>,
+    broadcast_tx: broadcast::Sender<Broadcast>,
     sequence_counter: Arc<RwLock<u64>>,
+    bans: Arc<RwLock<BanList>>,
+    allow_list: Option<Arc<AllowList>>, // Only these user ids may join
+    key_pins: Option<Arc<RwLock<KeyPins>>>, // User ids tied to the key they proved themselves with
+    duplicate_ids: DuplicateIds, // What to do when a connected user id joins again
+    admin_token: Option<String>,
+    compression: bool, // Whether clients may negotiate wire compression
+    bookmarks: Arc<RwLock<Vec<SyncMessage>>>, // Bookmark events, replayed to clients as they join
+    stats: Arc<RwLock<StatsTracker>>,
+    sequences: Arc<RwLock<SequenceTracker>>, // Latest state update per user
+    host_candidates: Arc<RwLock<BTreeMap<UserId, SocketAddr>>>, // Offers to host if we go away, tried in id order
+    file_sources: Arc<RwLock<BTreeMap<UserId, SyncMessage>>>, // `FilesAvailable` per sharing user, for newcomers
+    checksums: Arc<RwLock<BTreeMap<UserId, Vec<FileChecksum>>>>, // From readers comparing file contents
+    all_finished: Arc<RwLock<bool>>, // Whether "everyone has finished" was last announced
+    max_clients: Option<usize>,
+    waiting_room: bool, // Queue clients that find the session full instead of turning them away
+    waiting: Arc<RwLock<VecDeque<Waiting>>>,
+    round_trips: Arc<RwLock<HashMap<UserId, u64>>>, // Smoothed ping time to each client, in ms
+    slideshow: Arc<RwLock<Option<Slideshow>>>,
+    slideshow_interval: Duration, // Per page, for slideshows started without one
+    resync_interval: Option<Duration>, // Between full session broadcasts; None for never
+    event_log: Option<Arc<EventLog>>, // Joins, leaves, page turns and messages, with when they happened
+    summary_path: Option<PathBuf>, // Where to write the summary when the session ends
+    session_started_ms: Arc<RwLock<u64>>,
+    webhook: Option<Webhook>, // Where to post joins, leaves and the session finishing
+}
+
+impl ServerContext {
+    /// Add an event to the log, if the server keeps one
+    fn log_event(&self, user_id: &str, kind: EventKind, page: Option<i32>, detail: Option<&str>) {
+        if let Some(log) = &self.event_log {
+            if let Err(e) = log.record(unix_millis(), user_id, kind, page, detail) {
+                warn!("{:#}", e);
+            }
+        }
+    }
+    
+    /// Post an event to the webhook, if the server has one
+    fn notify(&self, event: WebhookEvent) {
+        if let Some(webhook) = &self.webhook {
+            webhook.send(event);
+        }
+    }
+    
+    /// Post that `user_id` joined or left, with how many are reading now
+    async fn notify_membership(&self, user_id: &str, joined: bool) {
+        let user_id = user_id.to_string();
+        let users = self.session_state.read().await.users.len();
+        self.notify(if joined { WebhookEvent::UserJoined { user_id, users } } else { WebhookEvent::UserLeft { user_id, users } });
+    }
+    
+    /// Write the summary of the session so far, if the server keeps one, and start a new session
+    ///
+    /// Returns the summary's description and where it went.
+    async fn end_session(&self) -> Result<Option<String>> {
+        let ended_ms = unix_millis();
+        let started_ms = *self.session_started_ms.read().await;
+        let written = match &self.summary_path {
+            Some(path) => {
+                let events = match &self.event_log {
+                    Some(log) => log.events_since(started_ms)?,
+                    None => Vec::new(),
+                };
+                let summary = SessionSummary::build(started_ms, ended_ms, &events, self.stats.read().await.all());
+                let files = summary.write(path)?;
+                let names: Vec<String> = files.iter().map(|file| file.display().to_string()).collect();
+                Some(format!("{} (written to {})", summary.describe(), names.join(" and ")))
+            }
+            None => None,
+        };
+        
+        *self.session_started_ms.write().await = ended_ms;
+        *self.stats.write().await = StatsTracker::new();
+        Ok(written)
+    }
+    
+    /// Remember a bookmark event for late joiners; returns false if it was already known
+    async fn record_bookmark(&self, message: &SyncMessage) -> bool {
+        let SyncEvent::Bookmark { fingerprint, bookmark } = &message.event else {
+            return false;
+        };
+        
+        let mut bookmarks = self.bookmarks.write().await;
+        let known = bookmarks.iter().any(|m| matches!(&m.event, SyncEvent::Bookmark { fingerprint: f, bookmark: b }
+            if f == fingerprint && b.playlist_position == bookmark.playlist_position && b.label == bookmark.label));
+        if known {
+            return false;
+        }
+        
+        if bookmarks.len() >= MAX_BOOKMARKS {
+            bookmarks.remove(0);
+        }
+        bookmarks.push(message.clone());
+        true
+    }
+    
+    /// Send a server-generated message to every client
+    fn broadcast(&self, message: SyncMessage) {
+        let _ = self.broadcast_tx.send(Broadcast { message, origin: None });
+    }
+    
+    /// Tell everyone who would take over hosting, in the order they are tried
+    async fn broadcast_host_candidates(&self) {
+        let candidates = self.host_candidates.read().await.iter()
+            .map(|(user_id, &addr)| PeerAddress { user_id: user_id.clone(), addr })
+            .collect();
+        self.broadcast(SyncMessage::new(SyncEvent::HostCandidates { candidates }, self.next_sequence().await));
+    }
+    
+    /// Forget a user's offer to host, telling everyone if they had made one
+    async fn withdraw_host_candidate(&self, uid: &UserId) {
+        if self.host_candidates.write().await.remove(uid).is_some() {
+            self.broadcast_host_candidates().await;
+        }
+    }
+    
+    /// The session's salt for `--passphrase` keys, picked the first time a client asks
+    async fn session_salt(&self) -> Option<String> {
+        let mut session = self.session_state.write().await;
+        if session.salt.is_none() {
+            match encryption::new_salt() {
+                Ok(salt) => session.salt = Some(salt),
+                Err(e) => error!("{:#}", e),
+            }
+        }
+        session.salt.clone()
+    }
+    
+    /// Next server-assigned sequence number
+    async fn next_sequence(&self) -> u64 {
+        let mut seq = self.sequence_counter.write().await;
+        *seq += 1;
+        *seq
+    }
+    
+    /// Check a joining user against `max_clients`; returns false if they were turned away or queued
+    async fn admit(&self, uid: &UserId, tx: &ClientSender) -> bool {
+        let Some(max) = self.max_clients else {
+            return true;
+        };
+        
+        let mut waiting = self.waiting.write().await;
+        let clients = self.clients.read().await;
+        if clients.contains_key(uid) {
+            return true; // Joining again on a new connection
+        }
+        if let Some(index) = waiting.iter().position(|w| &w.user_id == uid && w.admitted) {
+            waiting.remove(index);
+            return true;
+        }
+        
+        let held = waiting.iter().filter(|w| w.admitted).count();
+        let queued = waiting.iter().any(|w| !w.admitted);
+        if clients.len() + held < max && !queued {
+            return true;
+        }
+        
+        let position = if self.waiting_room {
+            if !waiting.iter().any(|w| &w.user_id == uid) {
+                let position = waiting.iter().filter(|w| !w.admitted).count() + 1;
+                waiting.push_back(Waiting { user_id: uid.clone(), tx: tx.clone(), position, admitted: false });
+            }
+            waiting.iter().find(|w| &w.user_id == uid).map(|w| w.position)
+        } else {
+            None
+        };
+        info!("Session full ({}/{}), {} {}", clients.len(), max, uid,
+              if position.is_some() { "is waiting" } else { "was turned away" });
+        let _ = tx.send(SyncMessage::new(SyncEvent::SessionFull { position }, self.next_sequence().await));
+        false
+    }
+    
+    /// Let clients in from the waiting room while there is space, and tell the rest where they are
+    async fn admit_waiting(&self) {
+        let Some(max) = self.max_clients else {
+            return;
+        };
+        
+        let mut waiting = self.waiting.write().await;
+        let taken = self.clients.read().await.len() + waiting.iter().filter(|w| w.admitted).count();
+        let mut free = max.saturating_sub(taken);
+        let mut position = 0;
+        for w in waiting.iter_mut().filter(|w| !w.admitted) {
+            if free > 0 {
+                info!("Admitting {} from the waiting room", w.user_id);
+                w.admitted = true;
+                free -= 1;
+                let _ = w.tx.send(SyncMessage::new(SyncEvent::Admitted, self.next_sequence().await));
+            } else {
+                position += 1;
+                if w.position != position {
+                    w.position = position;
+                    let full = SyncEvent::SessionFull { position: Some(position) };
+                    let _ = w.tx.send(SyncMessage::new(full, self.next_sequence().await));
+                }
+            }
+        }
+    }
+    
+    /// Forget a client that gave up waiting (or never rejoined once admitted)
+    async fn leave_waiting_room(&self, uid: &UserId) {
+        self.waiting.write().await.retain(|w| &w.user_id != uid);
+        self.admit_waiting().await;
+    }
+    
+    /// Make whoever has been here longest the host, telling everyone if that changed
+    async fn update_host(&self) -> Option<UserId> {
+        let host = self.clients.read().await.iter()
+            .min_by_key(|(_, handle)| handle.joined)
+            .map(|(user_id, _)| user_id.clone());
+        let changed = {
+            let mut session = self.session_state.write().await;
+            std::mem::replace(&mut session.host, host.clone()) != host
+        };
+        if let (true, Some(user_id)) = (changed, &host) {
+            info!("{} is now the host", user_id);
+            self.broadcast(SyncMessage::new(SyncEvent::Host { user_id: user_id.clone() }, self.next_sequence().await));
+        }
+        host
+    }
+    
+    /// Drop a user from the session and tell everyone they left
+    async fn remove_user(&self, uid: &UserId) {
+        let was_listed = self.session_state.read().await.users.contains_key(uid); // Not if it already sent UserLeft
+        self.clients.write().await.remove(uid);
+        self.session_state.write().await.remove_user(uid);
+        self.stats.write().await.user_left(uid);
+        self.sequences.write().await.forget(uid);
+        self.file_sources.write().await.remove(uid);
+        self.checksums.write().await.remove(uid);
+        self.round_trips.write().await.remove(uid);
+        if was_listed {
+            self.log_event(uid, EventKind::Leave, None, None);
+            self.notify_membership(uid, false).await;
+        }
+        
+        let leave_message = SyncMessage::user_left(uid.clone(), self.next_sequence().await);
+        self.broadcast(leave_message);
+        self.withdraw_host_candidate(uid).await;
+        self.update_host().await;
+        self.admit_waiting().await;
+    }
+    
+    /// Apply a client's slideshow command, returning what was done (None if there was nothing to do)
+    async fn control_slideshow(&self, command: SlideshowCommand) -> Option<SlideshowCommand> {
+        let now = Instant::now();
+        let mut slideshow = self.slideshow.write().await;
+        
+        let command = match command {
+            SlideshowCommand::Toggle { from, count } => match slideshow.as_ref() {
+                None => SlideshowCommand::Start { interval_secs: self.slideshow_interval.as_secs(), from, count },
+                Some(running) if running.is_paused() => SlideshowCommand::Resume,
+                Some(_) => SlideshowCommand::Pause,
+            },
+            command => command,
+        };
+        
+        match command {
+            SlideshowCommand::Start { interval_secs, from, count } => {
+                let interval = Duration::from_secs(interval_secs.max(1));
+                *slideshow = Some(Slideshow::start(interval, from, count, now));
+            }
+            SlideshowCommand::Pause => slideshow.as_mut()?.pause(now),
+            SlideshowCommand::Resume => slideshow.as_mut()?.resume(now),
+            SlideshowCommand::Stop => {
+                slideshow.take()?;
+            }
+            SlideshowCommand::Toggle { .. } => return None, // Resolved above
+        }
+        Some(command)
+    }
+    
+    /// Announce once when every connected user has reached the end of the playlist
+    async fn check_all_finished(&self) {
+        let (all_finished, count) = {
+            let session = self.session_state.read().await;
+            (session.all_finished(), session.users.len())
+        };
+        
+        let mut announced = self.all_finished.write().await;
+        if all_finished && !*announced {
+            let text = format!("Everyone has finished ({}/{})", count, count);
+            info!("{} {}", terminal::decoration("🏁"), text);
+            self.broadcast(SyncMessage::announcement(text, self.next_sequence().await));
+            self.notify(WebhookEvent::SessionFinished { users: count });
+        }
+        *announced = all_finished;
+    }
+    
+    /// Disconnect a user: announce their departure and close their connection
+    async fn kick(&self, uid: &UserId, reason: Option<String>) -> bool {
+        let Some(handle) = self.clients.read().await.get(uid).cloned() else {
+            return false;
+        };
+        
+        info!("Kicking {} ({}): {}", uid, handle.addr, reason.as_deref().unwrap_or("no reason given"));
+        self.remove_user(uid).await;
+        
+        let kicked = SyncEvent::Kicked { user_id: uid.clone(), reason };
+        let _ = handle.tx.send(SyncMessage::new(kicked, self.next_sequence().await));
+        true
+    }
+    
+    /// The user id a newcomer from `addr` claiming `uid` joins as, or None if it is turned away
+    async fn resolve_duplicate(&self, uid: &UserId, addr: SocketAddr, tx: &ClientSender) -> Option<UserId> {
+        let Some(existing) = self.clients.read().await.get(uid).filter(|handle| handle.addr != addr).cloned() else {
+            return Some(uid.clone());
+        };
+        
+        match self.duplicate_ids {
+            DuplicateIds::Replace => {
+                info!("{} joined again from {}, disconnecting {}", uid, addr, existing.addr);
+                let kicked = SyncEvent::Kicked {
+                    user_id: uid.clone(),
+                    reason: Some("you joined again from another connection".to_string()),
+                };
+                let _ = existing.tx.send(SyncMessage::new(kicked, self.next_sequence().await));
+                Some(uid.clone())
+            }
+            DuplicateIds::Reject => {
+                info!("Rejected {} from {}: already connected from {}", uid, addr, existing.addr);
+                let kicked = SyncEvent::Kicked {
+                    user_id: uid.clone(),
+                    reason: Some(format!("{} is already in the session", uid)),
+                };
+                let _ = tx.send(SyncMessage::new(kicked, self.next_sequence().await));
+                None
+            }
+            DuplicateIds::Rename => {
+                let renamed = {
+                    let clients = self.clients.read().await;
+                    moderation::free_user_id(uid, |id| clients.contains_key(id))
+                };
+                info!("{} from {} is already connected, joining as {}", uid, addr, renamed);
+                let text = format!("{} is already in the session, others see you as {}", uid, renamed);
+                let _ = tx.send(SyncMessage::announcement(text, self.next_sequence().await));
+                Some(renamed)
+            }
+        }
+    }
+    
+    /// Execute an admin command, returning (success, reply message)
+    async fn handle_admin(&self, token: &str, command: AdminCommand) -> (bool, String) {
+        match &self.admin_token {
+            Some(expected) if moderation::token_matches(expected, token) => {}
+            Some(_) => return (false, "invalid admin token".to_string()),
+            None => return (false, "admin commands are disabled on this server".to_string()),
+        }
+        
+        match command {
+            AdminCommand::Kick { user_id, reason } => {
+                if self.kick(&user_id, reason).await {
+                    (true, format!("kicked {}", user_id))
+                } else {
+                    (false, format!("no connected user named {}", user_id))
+                }
+            }
+            AdminCommand::Ban { target } => {
+                if let Err(e) = self.bans.write().await.ban(&target) {
+                    return (false, format!("failed to save ban list: {}", e));
+                }
+                
+                // Kick anyone currently connected under that id or address
+                let matching: Vec<UserId> = self.clients.read().await.iter()
+                    .filter(|(uid, handle)| **uid == target || handle.addr.ip().to_string() == target)
+                    .map(|(uid, _)| uid.clone())
+                    .collect();
+                for uid in &matching {
+                    self.kick(uid, Some("banned".to_string())).await;
+                }
+                
+                (true, format!("banned {} ({} connection(s) closed)", target, matching.len()))
+            }
+            AdminCommand::Unban { target } => match self.bans.write().await.unban(&target) {
+                Ok(true) => (true, format!("unbanned {}", target)),
+                Ok(false) => (false, format!("{} is not banned", target)),
+                Err(e) => (false, format!("failed to save ban list: {}", e)),
+            },
+            AdminCommand::EndSession => match self.end_session().await {
+                Ok(written) => {
+                    info!("Session ended by an admin");
+                    self.broadcast(SyncMessage::announcement("The session has ended".to_string(), self.next_sequence().await));
+                    self.notify(WebhookEvent::SessionEnded { summary: written.clone() });
+                    match written {
+                        Some(summary) => (true, format!("session ended: {}", summary)),
+                        None => (true, "session ended (no --summary file to write)".to_string()),
+                    }
+                }
+                Err(e) => (false, format!("failed to write the session summary: {:#}", e)),
+            },
+        }
+    }
+}
+
+/// Sync server that coordinates multiple clients
+pub struct SyncServer {
+    ctx: ServerContext,
+    state_file: Option<PathBuf>,
+    advertise: bool,
+    upnp: bool,
+    http_addr: Option<SocketAddr>,
+    display: bool,
+    idle_after: Duration, // Inactivity before users show as idle in the display
+    seeded_users: Vec<UserId>, // Users carried over by `set_initial_session`
 }
 
 impl SyncServer {
@@ -24,156 +493,863 @@ impl SyncServer {
         let (broadcast_tx, _) = broadcast::channel(1000);
         
         Self {
-            session_state: Arc::new(RwLock::new(SessionState::new())),
-            clients: Arc::new(RwLock::new(HashMap::new())),
-            broadcast_tx,
-            sequence_counter: Arc::new(RwLock::new(0)),
+            ctx: ServerContext {
+                session_state: Arc::new(RwLock::new(SessionState::new())),
+                clients: Arc::new(RwLock::new(HashMap::new())),
+                broadcast_tx,
+                sequence_counter: Arc::new(RwLock::new(0)),
+                bans: Arc::new(RwLock::new(BanList::default())),
+                allow_list: None,
+                key_pins: None,
+                duplicate_ids: DuplicateIds::default(),
+                admin_token: None,
+                compression: true,
+                bookmarks: Arc::new(RwLock::new(Vec::new())),
+                stats: Arc::new(RwLock::new(StatsTracker::new())),
+                sequences: Arc::new(RwLock::new(SequenceTracker::new())),
+                host_candidates: Arc::new(RwLock::new(BTreeMap::new())),
+                file_sources: Arc::new(RwLock::new(BTreeMap::new())),
+                checksums: Arc::new(RwLock::new(BTreeMap::new())),
+                all_finished: Arc::new(RwLock::new(false)),
+                max_clients: None,
+                waiting_room: false,
+                waiting: Arc::new(RwLock::new(VecDeque::new())),
+                round_trips: Arc::new(RwLock::new(HashMap::new())),
+                slideshow: Arc::new(RwLock::new(None)),
+                slideshow_interval: Duration::from_secs(slideshow::DEFAULT_INTERVAL_SECS),
+                resync_interval: Some(DEFAULT_RESYNC_INTERVAL),
+                event_log: None,
+                summary_path: None,
+                session_started_ms: Arc::new(RwLock::new(unix_millis())),
+                webhook: None,
+            },
+            state_file: None,
+            advertise: true,
+            upnp: false,
+            http_addr: None,
+            display: true,
+            idle_after: DEFAULT_IDLE_AFTER,
+            seeded_users: Vec::new(),
         }
     }
     
-    /// Start the server on the given address
-    pub async fn start(&self, addr: SocketAddr) -> Result<()> {
-        let listener = TcpListener::bind(addr).await
-            .with_context(|| format!("Failed to bind to {}", addr))?;
-            
-        info!("Sync server listening on {}", addr);
+    /// Snapshot the session to this file periodically and restore it on start
+    pub fn set_state_file(&mut self, path: PathBuf) {
+        self.state_file = Some(path);
+    }
+    
+    /// Record joins, leaves, page turns and messages in this SQLite event log
+    pub fn set_event_log(&mut self, log: Arc<EventLog>) {
+        self.ctx.event_log = Some(log);
+    }
+    
+    /// Write a summary of the session to `path` (JSON, or CSV for `.csv`) when it ends
+    ///
+    /// Without an event log, the server keeps one in memory for the summary's
+    /// participants and transcript.
+    pub fn set_summary_path(&mut self, path: PathBuf) -> Result<()> {
+        if self.ctx.event_log.is_none() {
+            self.ctx.event_log = Some(Arc::new(EventLog::in_memory()?));
+        }
+        self.ctx.summary_path = Some(path);
+        Ok(())
+    }
+    
+    /// POST joins, leaves and the session finishing or ending to this `http://` or `https://` URL as JSON
+    pub fn set_webhook(&mut self, url: &str) -> Result<()> {
+        self.ctx.webhook = Some(Webhook::start(url)?);
+        Ok(())
+    }
+    
+    /// End the session: write its summary if the server keeps one, returning its description
+    pub async fn end_session(&self) -> Result<Option<String>> {
+        self.ctx.end_session().await
+    }
+    
+    /// Tell everyone the server is going away and save the session, before the process exits
+    ///
+    /// Waits up to `SHUTDOWN_GRACE` for every connection to pass the notice on.
+    pub async fn shutdown(&self) {
+        info!("Telling {} client(s) the server is shutting down", self.ctx.clients.read().await.len());
+        self.ctx.broadcast(SyncMessage::announcement("The server is shutting down".to_string(), self.ctx.next_sequence().await));
+        
+        let deadline = Instant::now() + SHUTDOWN_GRACE;
+        while !self.ctx.broadcast_tx.is_empty() && Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        
+        if let Some(path) = &self.state_file {
+            if let Err(e) = save_json(path, &*self.ctx.session_state.read().await) {
+                warn!("Failed to write session snapshot: {}", e);
+            }
+        }
+    }
+    
+    /// Accept admin commands (kick/ban) from clients presenting this token
+    pub fn set_admin_token(&mut self, token: String) {
+        self.ctx.admin_token = Some(token);
+    }
+    
+    /// Let clients negotiate compression of large messages (on by default)
+    pub fn set_compression(&mut self, compression: bool) {
+        self.ctx.compression = compression;
+    }
+    
+    /// Turn away clients once this many have joined
+    pub fn set_max_clients(&mut self, max: usize) {
+        self.ctx.max_clients = Some(max);
+    }
+    
+    /// Queue clients that find the session full and let them in as others leave
+    pub fn set_waiting_room(&mut self, waiting_room: bool) {
+        self.ctx.waiting_room = waiting_room;
+    }
+    
+    /// Use a ban list (typically loaded from a file) to reject users and addresses
+    pub fn set_ban_list(&mut self, bans: BanList) {
+        self.ctx.bans = Arc::new(RwLock::new(bans));
+    }
+    
+    /// Only let in the user ids on this list
+    pub fn set_allow_list(&mut self, allow_list: AllowList) {
+        self.ctx.allow_list = Some(Arc::new(allow_list));
+    }
+    
+    /// Turn away users claiming a pinned user id without its key
+    pub fn set_key_pins(&mut self, pins: KeyPins) {
+        self.ctx.key_pins = Some(Arc::new(RwLock::new(pins)));
+    }
+    
+    /// Choose what happens when a user id that is already connected joins again
+    pub fn set_duplicate_ids(&mut self, duplicate_ids: DuplicateIds) {
+        self.ctx.duplicate_ids = duplicate_ids;
+    }
+    
+    /// Announce the server on the LAN via mDNS (on by default, skipped for loopback binds)
+    pub fn set_advertise(&mut self, advertise: bool) {
+        self.advertise = advertise;
+    }
+    
+    /// Ask the home router to forward the listening port (UPnP, then NAT-PMP)
+    pub fn set_upnp(&mut self, upnp: bool) {
+        self.upnp = upnp;
+    }
+    
+    /// Serve `GET /session` and `GET /users` as JSON on this address
+    pub fn set_http_addr(&mut self, addr: SocketAddr) {
+        self.http_addr = Some(addr);
+    }
+    
+    /// Redraw the session in the terminal (on by default; off when embedded in a client)
+    pub fn set_display(&mut self, display: bool) {
+        self.display = display;
+    }
+    
+    /// The live session, shared with the connection handlers
+    pub fn session_state(&self) -> Arc<RwLock<SessionState>> {
+        self.ctx.session_state.clone()
+    }
+    
+    /// Mark users as idle in the display once they've done nothing for this long
+    pub fn set_idle_after(&mut self, idle_after: Duration) {
+        self.idle_after = idle_after;
+    }
+    
+    /// Seconds per page for slideshows started with the slideshow key
+    pub fn set_slideshow_interval(&mut self, interval: Duration) {
+        self.ctx.slideshow_interval = interval;
+    }
+    
+    /// Send everyone the whole session this often (or never), so missed updates heal
+    pub fn set_resync_interval(&mut self, interval: Option<Duration>) {
+        self.ctx.resync_interval = interval;
+    }
+    
+    /// Start from an existing session, e.g. one taken over from a host that left
+    ///
+    /// Its users stay listed for a grace period while they reconnect.
+    pub fn set_initial_session(&mut self, session: SessionState) {
+        self.seeded_users = session.users.keys().cloned().collect();
+        self.ctx.session_state = Arc::new(RwLock::new(session));
+    }
+    
+    /// Start the server listening on every given address
+    pub async fn start(&self, addrs: &[SocketAddr]) -> error::Result<()> {
+        self.serve(Self::bind(addrs)?).await
+    }
+    
+    /// Listen on every given address, for `serve`
+    pub fn bind(addrs: &[SocketAddr]) -> error::Result<Vec<TcpListener>> {
+        Ok(addrs.iter().map(|&addr| bind_listener(addr)).collect::<Result<_, _>>()?)
+    }
+    
+    /// Run the server on listeners bound elsewhere, such as sockets passed in by systemd
+    pub async fn serve(&self, listeners: Vec<TcpListener>) -> error::Result<()> {
+        let addrs: Vec<SocketAddr> = listeners.iter().filter_map(|listener| listener.local_addr().ok()).collect();
+        for addr in &addrs {
+            info!("Sync server listening on {}", addr);
+        }
         info!("Clients can connect and start syncing playlist positions");
         
+        // LAN discovery and router mappings are IPv4-only
+        let public_v4 = addrs.iter().find(|a| a.is_ipv4() && !a.ip().is_loopback());
+        
+        if !self.seeded_users.is_empty() {
+            self.expire_restored_users(self.seeded_users.clone());
+        }
+        
+        if let Some(path) = self.state_file.clone() {
+            self.restore_session(&path).await;
+            
+            let session_state = self.ctx.session_state.clone();
+            tokio::spawn(async move {
+                Self::snapshot_loop(session_state, path).await;
+            });
+        }
+        
+        if let Some(addr) = public_v4.filter(|_| self.advertise) {
+            let port = addr.port();
+            tokio::spawn(async move {
+                if let Err(e) = discovery::advertise(port).await {
+                    warn!("LAN discovery disabled: {}", e);
+                }
+            });
+        }
+        
+        if self.upnp {
+            match public_v4 {
+                Some(addr) => {
+                    tokio::spawn(port_mapping::maintain(addr.port()));
+                }
+                None => warn!("--upnp needs a non-loopback IPv4 bind address (e.g. 0.0.0.0) to accept internet clients"),
+            }
+        }
+        
+        if let Some(addr) = self.http_addr {
+            let session_state = self.ctx.session_state.clone();
+            tokio::spawn(async move {
+                if let Err(e) = http_api::serve(addr, session_state).await {
+                    error!("HTTP status API stopped: {}", e);
+                }
+            });
+        }
+        
+        tokio::spawn(Self::slideshow_loop(self.ctx.clone()));
+        if let Some(period) = self.ctx.resync_interval {
+            tokio::spawn(Self::resync_loop(self.ctx.clone(), period));
+        }
+        
         // Start the display loop in background
-        let session_state = self.session_state.clone();
-        tokio::spawn(async move {
-            Self::display_loop(session_state).await;
-        });
+        if self.display {
+            let session_state = self.ctx.session_state.clone();
+            let idle_after = self.idle_after;
+            let round_trips = self.ctx.round_trips.clone();
+            tokio::spawn(async move {
+                Self::display_loop(session_state, round_trips, idle_after).await;
+            });
+        }
+        
+        // Accept client connections on every listener
+        let mut accept_tasks = Vec::new();
+        for listener in listeners {
+            let ctx = self.ctx.clone();
+            accept_tasks.push(tokio::spawn(Self::accept_loop(listener, ctx)));
+        }
+        for task in accept_tasks {
+            if let Err(e) = task.await {
+                error!("Listener stopped: {}", e);
+            }
+        }
         
-        // Accept client connections
+        Ok(())
+    }
+    
+    /// Turn everyone's page whenever the slideshow's interval passes, until its last page
+    async fn slideshow_loop(ctx: ServerContext) {
+        let mut tick = interval(SLIDESHOW_TICK);
+        loop {
+            tick.tick().await;
+            let advanced = {
+                let mut slideshow = ctx.slideshow.write().await;
+                let Some(running) = slideshow.as_mut() else {
+                    continue;
+                };
+                let page = running.advance(Instant::now());
+                if running.is_finished() {
+                    *slideshow = None;
+                }
+                page.map(|page| (page, slideshow.is_none()))
+            };
+            
+            if let Some((page, finished)) = advanced {
+                debug!("Slideshow on to p.{}", page + 1);
+                ctx.broadcast(SyncMessage::new(SyncEvent::SlideshowAdvance { page }, ctx.next_sequence().await));
+                if finished {
+                    info!("Slideshow finished");
+                    ctx.broadcast(SyncMessage::announcement("Slideshow finished".to_string(), ctx.next_sequence().await));
+                }
+            }
+        }
+    }
+    
+    /// Broadcast the whole session every `period`, as the copy clients should hold
+    async fn resync_loop(ctx: ServerContext, period: Duration) {
+        let mut tick = interval(period);
+        tick.tick().await; // Nobody has missed anything yet
+        loop {
+            tick.tick().await;
+            if ctx.clients.read().await.is_empty() {
+                continue;
+            }
+            let session = ctx.session_state.read().await.clone();
+            ctx.broadcast(SyncMessage::new(SyncEvent::Session { session }, ctx.next_sequence().await));
+        }
+    }
+    
+    async fn accept_loop(listener: TcpListener, ctx: ServerContext) {
         while let Ok((stream, client_addr)) = listener.accept().await {
             info!("New client connected from: {}", client_addr);
+            let _ = stream.set_nodelay(true); // Send each update as it comes rather than batching them
             
-            let session_state = self.session_state.clone();
-            let clients = self.clients.clone();
-            let broadcast_tx = self.broadcast_tx.clone();
-            let mut broadcast_rx = self.broadcast_tx.subscribe();
-            let sequence_counter = self.sequence_counter.clone();
+            let ctx = ctx.clone();
+            let mut broadcast_rx = ctx.broadcast_tx.subscribe();
             
             tokio::spawn(async move {
-                if let Err(e) = Self::handle_client(
-                    stream, 
-                    client_addr,
-                    session_state,
-                    clients,
-                    broadcast_tx,
-                    &mut broadcast_rx,
-                    sequence_counter,
-                ).await {
+                if let Err(e) = Self::handle_client(stream, client_addr, ctx, &mut broadcast_rx).await {
                     error!("Client {} error: {}", client_addr, e);
                 }
             });
         }
+    }
+    
+    /// Load a previous session snapshot, dropping users who don't come back in time
+    async fn restore_session(&self, path: &PathBuf) {
+        let restored: Option<SessionState> = match load_json(path) {
+            Ok(state) => state,
+            Err(e) => {
+                warn!("Ignoring unreadable session snapshot: {}", e);
+                return;
+            }
+        };
         
-        Ok(())
+        let Some(restored) = restored else {
+            return;
+        };
+        
+        let restored_ids: Vec<UserId> = restored.users.keys().cloned().collect();
+        info!("Restored session from {:?} with {} users", path, restored_ids.len());
+        *self.ctx.session_state.write().await = restored;
+        self.expire_restored_users(restored_ids);
+    }
+    
+    /// Remove restored users who haven't reconnected after `RESTORED_USER_GRACE`
+    fn expire_restored_users(&self, restored_ids: Vec<UserId>) {
+        let ctx = self.ctx.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(RESTORED_USER_GRACE).await;
+            
+            for uid in restored_ids {
+                if ctx.clients.read().await.contains_key(&uid) {
+                    continue; // Reconnected
+                }
+                
+                info!("Restored user {} did not reconnect, removing", uid);
+                ctx.remove_user(&uid).await;
+            }
+        });
+    }
+    
+    /// Periodically write the session to disk when it changes
+    async fn snapshot_loop(session_state: Arc<RwLock<SessionState>>, path: PathBuf) {
+        let mut interval = tokio::time::interval(SNAPSHOT_INTERVAL);
+        let mut last_saved = String::new();
+        
+        loop {
+            interval.tick().await;
+            
+            let state = session_state.read().await.clone();
+            let Ok(serialized) = serde_json::to_string(&state) else {
+                continue;
+            };
+            
+            if serialized == last_saved {
+                continue;
+            }
+            
+            match save_json(&path, &state) {
+                Ok(()) => {
+                    debug!("Session snapshot written to {:?}", path);
+                    last_saved = serialized;
+                }
+                Err(e) => warn!("Failed to write session snapshot: {}", e),
+            }
+        }
     }
     
     /// Handle a single client connection
     async fn handle_client(
         stream: TcpStream,
         client_addr: SocketAddr,
-        session_state: Arc<RwLock<SessionState>>,
-        clients: Arc<RwLock<HashMap<UserId, ClientSender>>>,
-        broadcast_tx: broadcast::Sender<SyncMessage>,
-        broadcast_rx: &mut broadcast::Receiver<SyncMessage>,
-        sequence_counter: Arc<RwLock<u64>>,
+        ctx: ServerContext,
+        broadcast_rx: &mut broadcast::Receiver<Broadcast>,
     ) -> Result<()> {
         let (reader, mut writer) = stream.into_split();
-        let mut reader = BufReader::new(reader);
+        let mut reader = FrameReader::with_limit(reader, rate_limit::MAX_CLIENT_MESSAGE_SIZE);
         
         let (client_tx, mut client_rx) = mpsc::unbounded_channel::<SyncMessage>();
         let mut user_id: Option<UserId> = None;
+        let mut waiting_as: Option<UserId> = None; // Set while in the waiting room
+        let mut proof: Option<IdentityProof> = None; // Checked when the Hello arrives
+        let mut renamed: Option<(UserId, UserId)> = None; // (claimed, given) when joined under another id
+        let mut wire = WireFormat::default(); // Switched once our Welcome is written
+        
+        // Goes out before anything else, so the client can sign it into its Hello
+        let challenge = identity::challenge()?;
+        let _ = client_tx.send(SyncMessage::new(SyncEvent::Challenge { nonce: challenge.clone() }, ctx.next_sequence().await));
         
         // Handle incoming messages from client
-        let broadcast_tx_clone = broadcast_tx.clone();
-        let session_state_clone = session_state.clone();
-        let clients_clone = clients.clone();
-        let sequence_counter_clone = sequence_counter.clone();
+        let ctx_clone = ctx.clone();
         
-        tokio::spawn(async move {
-            let mut line = String::new();
+        let mut reader_task = tokio::spawn(async move {
+            let ctx = ctx_clone;
+            let mut duplicates = DuplicateFilter::new();
+            let mut limiter = RateLimiter::new(Instant::now());
+            let mut throttled = false;
+            let mut ping = interval(latency::PING_INTERVAL);
+            let mut round_trip = RoundTrip::new();
             
-            while let Ok(bytes_read) = reader.read_line(&mut line).await {
-                if bytes_read == 0 {
-                    break; // Connection closed
-                }
+            loop {
+                let read = tokio::select! {
+                    read = reader.next_frame() => read,
+                    _ = ping.tick() => {
+                        // The pong comes back through this reader
+                        if let Some(uid) = &user_id {
+                            let _ = client_tx.send(SyncMessage::heartbeat(uid.clone(), None, ctx.next_sequence().await));
+                        }
+                        continue;
+                    }
+                };
+                let frame = match read {
+                    Ok(Some(frame)) => frame,
+                    Ok(None) => break, // Connection closed
+                    Err(e) => {
+                        debug!("Failed to read from {}: {}", client_addr, e);
+                        break;
+                    }
+                };
                 
-                let trimmed = line.trim();
-                if trimmed.is_empty() {
-                    line.clear();
-                    continue;
+                // Drop floods before they cost any work; unacked reliable ones get resent
+                match limiter.check(Instant::now()) {
+                    Verdict::Allow => throttled = false,
+                    Verdict::Throttle => {
+                        if !throttled {
+                            warn!("Throttling {} ({}): sending too fast", client_addr, user_id.as_deref().unwrap_or("not joined"));
+                            throttled = true;
+                        }
+                        continue;
+                    }
+                    Verdict::Disconnect => {
+                        warn!("Disconnecting {} ({}) for flooding", client_addr, user_id.as_deref().unwrap_or("not joined"));
+                        if let Some(uid) = &user_id {
+                            let kicked = SyncEvent::Kicked {
+                                user_id: uid.clone(),
+                                reason: Some("sending too many messages".to_string()),
+                            };
+                            let _ = client_tx.send(SyncMessage::new(kicked, ctx.next_sequence().await));
+                        }
+                        break;
+                    }
                 }
                 
-                match serde_json::from_str::<SyncMessage>(trimmed) {
-                    Ok(message) => {
+                match frame.decode() {
+                    Ok(mut message) => {
                         debug!("Received from {}: {:?}", client_addr, message);
+                        if let Some((claimed, given)) = &renamed {
+                            message.event.rename_user(claimed, given);
+                        }
+                        
+                        // Nothing reaches the session until the connection has joined, and then only under its own id
+                        match &user_id {
+                            None if !message.event.allowed_before_join() => {
+                                debug!("Ignoring {:?} from {}, which hasn't joined", message.event, client_addr);
+                                continue;
+                            }
+                            Some(uid) if !message.event.is_from(uid) => {
+                                warn!("Ignoring a message from {} ({}) sent as someone else", client_addr, uid);
+                                continue;
+                            }
+                            _ => {}
+                        }
+                        
+                        // Confirm reliable events, acting only on the first copy when the client resends
+                        if message.event.is_reliable() {
+                            let ack = SyncEvent::Ack { sequence: message.sequence };
+                            let _ = client_tx.send(SyncMessage::new(ack, ctx.next_sequence().await));
+                            if !duplicates.first_time(message.sequence) {
+                                debug!("Ignoring resent message {} from {}", message.sequence, client_addr);
+                                continue;
+                            }
+                        }
+                        
+                        // Clients in the waiting room aren't part of the session yet
+                        if waiting_as.is_some() && !matches!(message.event, SyncEvent::UserJoined { .. }) {
+                            continue;
+                        }
                         
                         // Update session state
                         match &message.event {
-                            SyncEvent::UserJoined { user_id: uid, user_state } => {
+                            SyncEvent::Hello { compression: offered, encodings, identity } => {
+                                if let (Some(identity), Some(_)) = (identity, &ctx.key_pins) {
+                                    match identity.verify(&challenge) {
+                                        Ok(()) => proof = Some(identity.clone()),
+                                        Err(e) => warn!("Ignoring identity proof from {}: {:#}", client_addr, e),
+                                    }
+                                }
+                                
+                                let compression = if ctx.compression { compression::negotiate(offered) } else { None };
+                                let encoding = wire::negotiate_encoding(encodings);
+                                debug!("{} offered {:?} / {:?}, using {:?} / {:?}", client_addr, offered, encodings, compression, encoding);
+                                
+                                // The client waits for our Welcome, so everything it sends next uses the new encoding
+                                reader.set_format(WireFormat { encoding, compression });
+                                let welcome = SyncEvent::Welcome { compression, encoding, salt: ctx.session_salt().await };
+                                let _ = client_tx.send(SyncMessage::new(welcome, ctx.next_sequence().await));
+                                continue;
+                            }
+                            SyncEvent::UserJoined { user_id: claimed, user_state } => {
+                                let uid = claimed;
                                 debug!("Processing UserJoined for: {}", uid);
+                                
+                                // Every check below is on the claimed id, so the state has to be that user's
+                                if &user_state.user_id != claimed {
+                                    warn!("Rejected a join from {} as {} carrying {}'s state", client_addr, uid, user_state.user_id);
+                                    let kicked = SyncEvent::Kicked {
+                                        user_id: uid.clone(),
+                                        reason: Some("the join's user ids don't match".to_string()),
+                                    };
+                                    let _ = client_tx.send(SyncMessage::new(kicked, ctx.next_sequence().await));
+                                    break;
+                                }
+                                
+                                if ctx.bans.read().await.is_banned(uid, client_addr.ip()) {
+                                    info!("Rejected banned user {} from {}", uid, client_addr);
+                                    let kicked = SyncEvent::Kicked {
+                                        user_id: uid.clone(),
+                                        reason: Some("banned".to_string()),
+                                    };
+                                    let _ = client_tx.send(SyncMessage::new(kicked, ctx.next_sequence().await));
+                                    break;
+                                }
+                                
+                                if ctx.allow_list.as_ref().is_some_and(|list| !list.allows(uid)) {
+                                    info!("Rejected {} from {}: not on the allowlist", uid, client_addr);
+                                    let kicked = SyncEvent::Kicked {
+                                        user_id: uid.clone(),
+                                        reason: Some(format!("{} is not on this server's allowlist", uid)),
+                                    };
+                                    let _ = client_tx.send(SyncMessage::new(kicked, ctx.next_sequence().await));
+                                    break;
+                                }
+                                
+                                if let Some(pins) = &ctx.key_pins {
+                                    if let Err(reason) = pins.write().await.admit(uid, proof.as_ref()) {
+                                        info!("Rejected {} from {}: {}", uid, client_addr, reason);
+                                        let kicked = SyncEvent::Kicked { user_id: uid.clone(), reason: Some(reason) };
+                                        let _ = client_tx.send(SyncMessage::new(kicked, ctx.next_sequence().await));
+                                        break;
+                                    }
+                                }
+                                
+                                let Some(uid) = ctx.resolve_duplicate(claimed, client_addr, &client_tx).await else {
+                                    break;
+                                };
+                                let mut user_state = user_state.clone();
+                                if &uid != claimed {
+                                    user_state.user_id = uid.clone();
+                                    renamed = Some((claimed.clone(), uid.clone()));
+                                }
+                                let (uid, user_state) = (&uid, &user_state);
+                                
+                                if !ctx.admit(uid, &client_tx).await {
+                                    if !ctx.waiting_room {
+                                        break;
+                                    }
+                                    waiting_as = Some(uid.clone());
+                                    continue;
+                                }
+                                waiting_as = None;
+                                
                                 user_id = Some(uid.clone());
-                                clients_clone.write().await.insert(uid.clone(), client_tx.clone());
-                                session_state_clone.write().await.update_user(user_state.clone());
+                                {
+                                    let mut clients = ctx.clients.write().await;
+                                    let joined = clients.get(uid).map_or_else(Instant::now, |old| old.joined);
+                                    clients.insert(uid.clone(), ClientHandle { tx: client_tx.clone(), addr: client_addr, joined });
+                                }
+                                ctx.session_state.write().await.update_user(user_state.clone());
+                                ctx.stats.write().await.observe(user_state);
+                                ctx.sequences.write().await.reset(uid, message.sequence);
+                                ctx.log_event(uid, EventKind::Join, Some(user_state.playlist_position), user_state.current_file_name.as_deref());
+                                ctx.notify_membership(uid, true).await;
+                                
+                                // Everyone already here, rather than as they next send an update
+                                let session = ctx.session_state.read().await.clone();
+                                let _ = client_tx.send(SyncMessage::new(SyncEvent::Session { session }, ctx.next_sequence().await));
+                                
+                                // Encrypted sessions ignore the snapshot, so say who the host is outright
+                                if let Some(host) = ctx.update_host().await {
+                                    let _ = client_tx.send(SyncMessage::new(SyncEvent::Host { user_id: host }, ctx.next_sequence().await));
+                                }
+                                
+                                // Bring the newcomer up to date on the session's bookmarks and notes
+                                for bookmark in ctx.bookmarks.read().await.iter() {
+                                    let _ = client_tx.send(bookmark.clone());
+                                }
+                                let annotations = ctx.session_state.read().await.annotations.clone();
+                                for annotation in annotations {
+                                    let _ = client_tx.send(SyncMessage::annotation(annotation, ctx.next_sequence().await));
+                                }
+                                
+                                let candidates: Vec<PeerAddress> = ctx.host_candidates.read().await.iter()
+                                    .map(|(user_id, &addr)| PeerAddress { user_id: user_id.clone(), addr })
+                                    .collect();
+                                if !candidates.is_empty() {
+                                    let event = SyncEvent::HostCandidates { candidates };
+                                    let _ = client_tx.send(SyncMessage::new(event, ctx.next_sequence().await));
+                                }
+                                for (sharer, offer) in ctx.file_sources.read().await.iter() {
+                                    if sharer != uid {
+                                        let _ = client_tx.send(offer.clone());
+                                    }
+                                }
                             }
                             SyncEvent::StateUpdate { user_state } => {
+                                if !ctx.sequences.write().await.accept(&user_state.user_id, message.sequence) {
+                                    debug!("Dropping stale update from {} (sequence {})", user_state.user_id, message.sequence);
+                                    continue;
+                                }
+                                if let Err(reason) = ctx.session_state.read().await.check_update(user_state) {
+                                    warn!("Refusing a state update from {}: {}", user_state.user_id, reason);
+                                    continue;
+                                }
                                 debug!("Processing StateUpdate for user: {}, pos: {}, file: {:?}", 
                                        user_state.user_id, user_state.playlist_position, user_state.current_file_name);
-                                session_state_clone.write().await.update_user(user_state.clone());
+                                let previous = {
+                                    let mut session = ctx.session_state.write().await;
+                                    let previous = session.users.get(&user_state.user_id).map(|user| user.playlist_position);
+                                    session.update_user(user_state.clone());
+                                    previous
+                                };
+                                if previous.is_some_and(|page| page != user_state.playlist_position) {
+                                    let file_name = user_state.current_file_name.as_deref();
+                                    ctx.log_event(&user_state.user_id, EventKind::Page, Some(user_state.playlist_position), file_name);
+                                }
+                                ctx.stats.write().await.observe(user_state);
+                                ctx.check_all_finished().await;
+                            }
+                            SyncEvent::Annotation { annotation } => {
+                                ctx.session_state.write().await.add_annotation(annotation.clone());
+                                ctx.log_event(&annotation.user_id, EventKind::Note, Some(annotation.playlist_position), Some(&annotation.text));
+                            }
+                            SyncEvent::Link { link } => {
+                                ctx.session_state.write().await.last_link = Some(link.clone());
+                                ctx.log_event(&link.user_id, EventKind::Link, None, Some(&link.url));
+                            }
+                            SyncEvent::Reaction { user_id: uid, emoji } => {
+                                ctx.log_event(uid, EventKind::Reaction, None, Some(emoji));
+                            }
+                            SyncEvent::Chat { user_id: uid, text } => {
+                                ctx.log_event(uid, EventKind::Chat, None, Some(text));
                             }
                             SyncEvent::UserLeft { user_id: uid } => {
                                 debug!("Processing UserLeft for: {}", uid);
-                                clients_clone.write().await.remove(uid);
-                                session_state_clone.write().await.remove_user(uid);
+                                user_id = None; // Nothing more counts from this connection
+                                if ctx.clients.read().await.get(uid).is_some_and(|handle| handle.addr != client_addr) {
+                                    continue; // Another connection has taken over this user id
+                                }
+                                ctx.clients.write().await.remove(uid);
+                                ctx.session_state.write().await.remove_user(uid);
+                                ctx.stats.write().await.user_left(uid);
+                                ctx.sequences.write().await.forget(uid);
+                                ctx.file_sources.write().await.remove(uid);
+                                ctx.checksums.write().await.remove(uid);
+                                ctx.round_trips.write().await.remove(uid);
+                                ctx.log_event(uid, EventKind::Leave, None, None);
+                                ctx.notify_membership(uid, false).await;
+                                ctx.withdraw_host_candidate(uid).await;
+                                ctx.update_host().await;
+                                ctx.check_all_finished().await;
+                                ctx.admit_waiting().await;
+                            }
+                            SyncEvent::HostOffer { port } => {
+                                // Reachable at the address it connected from, on the port it will listen on
+                                if let Some(uid) = &user_id {
+                                    let addr = SocketAddr::new(client_addr.ip(), *port);
+                                    info!("{} can take over hosting at {}", uid, addr);
+                                    ctx.host_candidates.write().await.insert(uid.clone(), addr);
+                                    ctx.broadcast_host_candidates().await;
+                                }
+                                continue;
+                            }
+                            SyncEvent::FileOffer { port, names } => {
+                                // Like host offers, downloads go to the address it connected from
+                                if let Some(uid) = &user_id {
+                                    let source = PeerAddress { user_id: uid.clone(), addr: SocketAddr::new(client_addr.ip(), *port) };
+                                    info!("{} shares {} playlist items at {}", uid, names.len(), source.addr);
+                                    let event = SyncEvent::FilesAvailable { source, names: names.clone() };
+                                    let offer = SyncMessage::new(event, ctx.next_sequence().await);
+                                    ctx.file_sources.write().await.insert(uid.clone(), offer.clone());
+                                    let _ = ctx.broadcast_tx.send(Broadcast { message: offer, origin: Some(client_addr) });
+                                }
+                                continue;
+                            }
+                            SyncEvent::Checksums { files } => {
+                                // Everyone hears the new comparison, since it may show their copy differs
+                                if let Some(uid) = &user_id {
+                                    let mismatches = {
+                                        let mut checksums = ctx.checksums.write().await;
+                                        checksums.insert(uid.clone(), files.clone());
+                                        checksums::compare(&checksums)
+                                    };
+                                    info!("{} sent checksums of {} files; {} differ between readers", uid, files.len(), mismatches.len());
+                                    let report = SyncMessage::new(SyncEvent::ChecksumReport { mismatches }, ctx.next_sequence().await);
+                                    let _ = ctx.broadcast_tx.send(Broadcast { message: report, origin: None });
+                                }
+                                continue;
+                            }
+                            SyncEvent::Heartbeat { sent_at_ms, echo_ms, .. } => {
+                                match echo_ms {
+                                    Some(sent_at_ms) => {
+                                        round_trip.observe(*sent_at_ms, unix_millis());
+                                        if let (Some(uid), Some(ms)) = (&user_id, round_trip.get()) {
+                                            ctx.round_trips.write().await.insert(uid.clone(), ms);
+                                        }
+                                    }
+                                    None => {
+                                        let uid = user_id.clone().unwrap_or_default();
+                                        let pong = SyncMessage::heartbeat(uid, Some(*sent_at_ms), ctx.next_sequence().await);
+                                        let _ = client_tx.send(pong);
+                                    }
+                                }
+                                continue;
+                            }
+                            SyncEvent::StatsRequest => {
+                                let users = ctx.stats.read().await.all().clone();
+                                let _ = client_tx.send(SyncMessage::new(SyncEvent::Stats { users }, ctx.next_sequence().await));
+                                continue;
+                            }
+                            SyncEvent::SessionRequest => {
+                                let session = ctx.session_state.read().await.clone();
+                                let _ = client_tx.send(SyncMessage::new(SyncEvent::Session { session }, ctx.next_sequence().await));
+                                continue;
+                            }
+                            SyncEvent::PlaylistEdit { user_id: uid, .. } => {
+                                let host = ctx.session_state.read().await.host.clone();
+                                if host.as_ref() != Some(uid) {
+                                    warn!("Dropping a playlist edit from {}, who isn't the host", uid);
+                                    let text = match host {
+                                        Some(host) => format!("Only the host, {}, can edit the playlist", host),
+                                        None => "Only the host can edit the playlist".to_string(),
+                                    };
+                                    let _ = client_tx.send(SyncMessage::announcement(text, ctx.next_sequence().await));
+                                    continue;
+                                }
+                            }
+                            SyncEvent::Stats { .. } | SyncEvent::Session { .. } | SyncEvent::Announcement { .. } | SyncEvent::Challenge { .. } | SyncEvent::Welcome { .. } | SyncEvent::Ack { .. }
+                            | SyncEvent::Host { .. } | SyncEvent::HostCandidates { .. } | SyncEvent::SessionFull { .. } | SyncEvent::Admitted
+                            | SyncEvent::FilesAvailable { .. } | SyncEvent::SlideshowAdvance { .. } | SyncEvent::ChecksumReport { .. }
+                            | SyncEvent::PeerHello { .. } | SyncEvent::Peers { .. } => {
+                                // Only the server (or peers in p2p mode) sends these
+                                continue;
+                            }
+                            // Clients re-share their saved bookmarks on join; only pass on new ones
+                            SyncEvent::Bookmark { .. } if !ctx.record_bookmark(&message).await => {
+                                continue;
+                            }
+                            SyncEvent::Slideshow { user_id: uid, command } => {
+                                // Everyone, the sender included, hears what the server made of it
+                                if let Some(command) = ctx.control_slideshow(*command).await {
+                                    info!("{}", command.describe(uid));
+                                    let event = SyncEvent::Slideshow { user_id: uid.clone(), command };
+                                    ctx.broadcast(SyncMessage::new(event, ctx.next_sequence().await));
+                                }
+                                continue;
+                            }
+                            SyncEvent::Admin { token, command } => {
+                                info!("Admin command from {}: {:?}", client_addr, command);
+                                let (ok, reply) = ctx.handle_admin(token, command.clone()).await;
+                                let result = SyncEvent::AdminResult { ok, message: reply };
+                                let _ = client_tx.send(SyncMessage::new(result, ctx.next_sequence().await));
+                                
+                                // Admin traffic carries the token, so it is never broadcast
+                                continue;
                             }
                             _ => {}
                         }
                         
-                        // Broadcast to all other clients
-                        if let Err(e) = broadcast_tx_clone.send(message) {
+                        // A join this connection was renamed on is only known now
+                        if let (Some((claimed, given)), SyncEvent::UserJoined { .. }) = (&renamed, &message.event) {
+                            message.event.rename_user(claimed, given);
+                        }
+                        
+                        // Broadcast to all other clients, in the order we took it in
+                        message.server_sequence = Some(ctx.next_sequence().await);
+                        if let Err(e) = ctx.broadcast_tx.send(Broadcast { message, origin: Some(client_addr) }) {
                             warn!("Failed to broadcast message: {}", e);
                         }
                     }
                     Err(e) => {
-                        warn!("Failed to parse message from {}: {} - '{}'", client_addr, e, trimmed);
+                        warn!("Failed to parse message from {}: {} - '{}'", client_addr, e, frame.describe());
                     }
                 }
-                
-                line.clear();
             }
             
             // Clean up when client disconnects
+            if let Some(uid) = waiting_as {
+                ctx.leave_waiting_room(&uid).await;
+            }
             if let Some(uid) = user_id {
                 info!("Client {} ({}) disconnected", client_addr, uid);
-                clients_clone.write().await.remove(&uid);
-                session_state_clone.write().await.remove_user(&uid);
-                
-                // Send user left message
-                let mut seq = sequence_counter_clone.write().await;
-                *seq += 1;
-                let leave_message = SyncMessage::user_left(uid, *seq);
-                let _ = broadcast_tx_clone.send(leave_message);
+                // Unless another connection has taken over this user id
+                let replaced = ctx.clients.read().await.get(&uid).is_some_and(|handle| handle.addr != client_addr);
+                if !replaced {
+                    ctx.remove_user(&uid).await;
+                }
             }
         });
         
         // Handle outgoing messages to client
         loop {
             tokio::select! {
+                // Replies like a kick go out before noticing the reader stopped
+                biased;
+                
                 // Receive message to send to this client
                 msg = client_rx.recv() => {
                     match msg {
                         Some(message) => {
-                            let json = serde_json::to_string(&message)?;
-                            if let Err(e) = writer.write_all(format!("{}
-", json).as_bytes()).await {
+                            let frame = match wire::encode(&message, wire) {
+                                Ok(frame) => frame,
+                                Err(e) => {
+                                    error!("Failed to encode a message for client {}: {}", client_addr, e);
+                                    break;
+                                }
+                            };
+                            if let Err(e) = writer.write_all(&frame).await {
                                 error!("Failed to write to client {}: {}", client_addr, e);
                                 break;
                             }
+                            
+                            // The Welcome itself goes out as plain JSON; everything after in the agreed format
+                            if let SyncEvent::Welcome { compression, encoding, .. } = message.event {
+                                wire = WireFormat { encoding, compression };
+                            }
+                            
+                            // Close the connection once the client has been told it was kicked or can't join
+                            if matches!(message.event, SyncEvent::Kicked { .. } | SyncEvent::SessionFull { position: None }) {
+                                let _ = writer.flush().await;
+                                reader_task.abort();
+                                break;
+                            }
                         }
                         None => break, // Channel closed
                     }
@@ -182,10 +1358,19 @@ impl SyncServer {
                 // Receive broadcast message to forward to client
                 msg = broadcast_rx.recv() => {
                     match msg {
-                        Ok(message) => {
-                            let json = serde_json::to_string(&message)?;
-                            if let Err(e) = writer.write_all(format!("{}
-", json).as_bytes()).await {
+                        Ok(Broadcast { message, origin }) => {
+                            // The sender already knows what it sent
+                            if origin == Some(client_addr) {
+                                continue;
+                            }
+                            let frame = match wire::encode(&message, wire) {
+                                Ok(frame) => frame,
+                                Err(e) => {
+                                    error!("Failed to encode a broadcast for client {}: {}", client_addr, e);
+                                    break;
+                                }
+                            };
+                            if let Err(e) = writer.write_all(&frame).await {
                                 error!("Failed to write broadcast to client {}: {}", client_addr, e);
                                 break;
                             }
@@ -196,6 +1381,9 @@ impl SyncServer {
                         }
                     }
                 }
+                
+                // Stop forwarding once the client has gone away
+                _ = &mut reader_task => break,
             }
         }
         
@@ -203,41 +1391,55 @@ impl SyncServer {
     }
     
     /// Display loop showing current session state, now with auto-refresh.
-    async fn display_loop(session_state: Arc<RwLock<SessionState>>) {
-        use tokio::time::{interval, Duration};
-
+    async fn display_loop(session_state: Arc<RwLock<SessionState>>, round_trips: Arc<RwLock<HashMap<UserId, u64>>>, idle_after: Duration) {
+        use std::fmt::Write as _;
+        
         let mut interval = interval(Duration::from_millis(500)); // Faster refresh
-
+        let mut screen = Screen::default();
+        
         loop {
             interval.tick().await;
-
+            
             let state = session_state.read().await;
-            let display_lines = state.format_for_display();
             let summary = state.get_sync_summary();
-
-            // ANSI escape code to clear screen and move cursor to top-left
-            print!("[2J[1;1H");
-
+            let mut frame = String::new();
+            
             if !state.users.is_empty() {
-                println!("🎬 SyncRead Server - {}", summary);
-                println!("{}", "=".repeat(60));
-
-                for line in display_lines {
-                    println!("{}", line);
+                let now = unix_millis() / 1000;
+                let name_width = state.name_width();
+                let round_trips = round_trips.read().await;
+                let rows: Vec<_> = state.get_users_sorted().into_iter()
+                    .map(|user| {
+                        // Our own measurement rather than the one the client reports
+                        let mut user = user.clone();
+                        if let Some(&ms) = round_trips.get(&user.user_id) {
+                            user.rtt_ms = Some(ms);
+                        }
+                        let line = user.format_for_display(name_width);
+                        let idle = user.idle_label(idle_after, now).map(|label| format!(" {} {}", terminal::decoration("—"), label)).unwrap_or_default();
+                        (user, line, idle)
+                    })
+                    .collect();
+                let rule = text_width::rule(rows.iter().map(|(_, line, idle)| format!("{}{}", line, idle)));
+                
+                let _ = writeln!(frame, "{}SyncRead Server - {}", terminal::decoration("🎬 "), summary);
+                let _ = writeln!(frame, "{}", rule);
+                for (user, line, idle) in &rows {
+                    let _ = writeln!(frame, "{}{}", terminal::paint(user.color(), line), idle);
                 }
-
-                println!("{}", "=".repeat(60));
+                let _ = writeln!(frame, "{}", rule);
             } else {
-                println!("🎬 SyncRead Server");
-                println!("{}", "=".repeat(60));
-                println!("Waiting for clients to connect...");
-                println!(
+                let _ = writeln!(frame, "{}SyncRead Server", terminal::decoration("🎬 "));
+                let _ = writeln!(frame, "{}", "=".repeat(text_width::RULE_WIDTH));
+                let _ = writeln!(frame, "Waiting for clients to connect...");
+                let _ = writeln!(
+                    frame,
                     "Run client with: syncread client --server <IP>:8080 --user-id <name> <files...>"
                 );
             }
-
-            println!("
-Press Ctrl+C to stop the server");
+            
+            let _ = writeln!(frame, "\nPress Ctrl+C to stop the server");
+            screen.show(frame);
         }
     }
 }
@@ -246,4 +1448,21 @@ impl Default for SyncServer {
     fn default() -> Self {
         Self::new()
     }
-}
\ No newline at end of file
+}
+
+/// Bind a listener; IPv6 sockets are made v6-only so `0.0.0.0` and `[::]` can share a port
+fn bind_listener(addr: SocketAddr) -> Result<TcpListener, ConnectionError> {
+    let bind = || -> std::io::Result<TcpListener> {
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+        if addr.is_ipv6() {
+            socket.set_only_v6(true)?;
+        }
+        #[cfg(unix)]
+        socket.set_reuse_address(true)?;
+        socket.bind(&addr.into())?;
+        socket.listen(1024)?;
+        socket.set_nonblocking(true)?;
+        TcpListener::from_std(socket.into())
+    };
+    bind().map_err(|source| ConnectionError::Bind { addr, source })
+}
@@ -0,0 +1,115 @@
+//! Running the server as a systemd service
+//!
+//! With a `.socket` unit, systemd binds the port and starts the server on
+//! the first connection, passing the listening sockets from fd 3 on and
+//! saying how many in `$LISTEN_FDS`. With `Type=notify`, the server reports
+//! when it is ready and when it is stopping on `$NOTIFY_SOCKET`.
+
+use anyhow::{Context, Result};
+use std::env;
+use std::net::TcpListener;
+use std::ops::Range;
+
+/// First descriptor systemd passes (`SD_LISTEN_FDS_START`)
+const LISTEN_FDS_START: i32 = 3;
+
+/// Listening sockets systemd passed to this process, if it started us through a socket unit
+pub fn listeners() -> Result<Vec<TcpListener>> {
+    let fds = passed_fds(env::var("LISTEN_PID").ok().as_deref(), env::var("LISTEN_FDS").ok().as_deref(), std::process::id())?;
+    fds.map(listener).collect()
+}
+
+/// Tell systemd how the service is doing (`READY=1`, `STOPPING=1`, ...), if it is listening
+pub fn notify(state: &str) {
+    #[cfg(unix)]
+    if let Some(path) = env::var_os("NOTIFY_SOCKET") {
+        if let Err(e) = send_notification(&path, state) {
+            tracing::warn!("Failed to notify systemd: {}", e);
+        }
+    }
+    
+    #[cfg(not(unix))]
+    let _ = state;
+}
+
+/// The descriptors passed to us, given `$LISTEN_PID` and `$LISTEN_FDS`
+///
+/// Variables meant for another process (a different `LISTEN_PID`) are ignored.
+fn passed_fds(listen_pid: Option<&str>, listen_fds: Option<&str>, pid: u32) -> Result<Range<i32>> {
+    let (Some(listen_pid), Some(listen_fds)) = (listen_pid, listen_fds) else {
+        return Ok(0..0);
+    };
+    if listen_pid.trim().parse::<u32>().ok() != Some(pid) {
+        return Ok(0..0);
+    }
+    let count: i32 = listen_fds.trim().parse().context("LISTEN_FDS isn't a number")?;
+    anyhow::ensure!(count >= 0, "LISTEN_FDS is negative");
+    Ok(LISTEN_FDS_START..LISTEN_FDS_START + count)
+}
+
+#[cfg(unix)]
+fn listener(fd: i32) -> Result<TcpListener> {
+    use socket2::{Socket, Type};
+    use std::os::fd::FromRawFd;
+    
+    // Safety: systemd hands these descriptors to us alone, and each is taken once
+    let socket = unsafe { Socket::from_raw_fd(fd) };
+    anyhow::ensure!(socket.r#type()? == Type::STREAM, "fd {} from systemd isn't a stream socket; use ListenStream= in the socket unit", fd);
+    socket.set_nonblocking(true)?;
+    Ok(socket.into())
+}
+
+#[cfg(not(unix))]
+fn listener(_fd: i32) -> Result<TcpListener> {
+    anyhow::bail!("socket activation is only supported on Unix")
+}
+
+#[cfg(unix)]
+fn send_notification(path: &std::ffi::OsStr, state: &str) -> std::io::Result<()> {
+    use std::os::unix::net::UnixDatagram;
+    
+    let socket = UnixDatagram::unbound()?;
+    // A leading '@' means an abstract socket, which has no path on disk
+    #[cfg(target_os = "linux")]
+    if let Some(name) = path.as_encoded_bytes().strip_prefix(b"@") {
+        use std::os::linux::net::SocketAddrExt;
+        let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+        socket.send_to_addr(state.as_bytes(), &addr)?;
+        return Ok(());
+    }
+    socket.send_to(state.as_bytes(), path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_passed_fds() {
+        assert_eq!(passed_fds(Some("42"), Some("2"), 42).unwrap(), 3..5);
+        assert_eq!(passed_fds(Some("42"), Some("0"), 42).unwrap(), 3..3);
+        
+        // Not started by systemd, or the variables were inherited from whoever was
+        assert!(passed_fds(None, None, 42).unwrap().is_empty());
+        assert!(passed_fds(Some("41"), Some("2"), 42).unwrap().is_empty());
+        
+        assert!(passed_fds(Some("42"), Some("two"), 42).is_err());
+    }
+    
+    #[cfg(unix)]
+    #[test]
+    fn test_notify() {
+        let dir = env::temp_dir().join(format!("syncread_test_notify_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("notify.sock");
+        let receiver = std::os::unix::net::UnixDatagram::bind(&path).unwrap();
+        
+        send_notification(path.as_os_str(), "READY=1").unwrap();
+        let mut buf = [0; 64];
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1");
+        
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,151 @@
+//! Layout of each reader's line in the terminal (`display_line` in config.toml)
+//!
+//! A template like `"{user} p.{page} {status}"` replaces the usual line,
+//! for example to keep it short enough to capture into a stream overlay.
+//! `{{` and `}}` stand for literal braces.
+
+use super::protocol::UserState;
+use crate::mpv::playlist::format_time;
+use crate::terminal;
+use anyhow::Result;
+use serde::Deserialize;
+
+/// The placeholders a template may use, for error messages
+const FIELDS: &str = "{user}, {page}, {position}, {file}, {time}, {status}, {chapter}, {rtt}";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    User,     // Display name, with the avatar
+    Page,     // 1-based, as shown everywhere else
+    Position, // 0-based playlist position
+    File,
+    Time,     // mm:ss into the file
+    Status,   // ⏸, ▶ or 🏁
+    Chapter,
+    Rtt,
+}
+
+impl Field {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "user" => Field::User,
+            "page" => Field::Page,
+            "position" => Field::Position,
+            "file" => Field::File,
+            "time" => Field::Time,
+            "status" => Field::Status,
+            "chapter" => Field::Chapter,
+            "rtt" => Field::Rtt,
+            _ => return None,
+        })
+    }
+    
+    fn render(self, user: &UserState) -> String {
+        match self {
+            Field::User => user.display_name(),
+            Field::Page => user.playlist_position.saturating_add(1).to_string(),
+            Field::Position => user.playlist_position.to_string(),
+            Field::File => user.current_file_name.clone().unwrap_or_default(),
+            Field::Time => format_time(user.playback_time),
+            Field::Status if user.finished => terminal::decoration("🏁").to_string(),
+            Field::Status => terminal::decoration(if user.is_paused { "⏸" } else { "▶" }).to_string(),
+            Field::Chapter => user.chapter.as_ref().map(|chapter| chapter.label()).unwrap_or_default(),
+            Field::Rtt => user.rtt_ms.map(|ms| format!("{} ms", ms)).unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Text(String),
+    Field(Field),
+}
+
+/// A parsed `display_line`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct LineTemplate {
+    parts: Vec<Part>,
+}
+
+impl LineTemplate {
+    pub fn parse(template: &str) -> Result<Self> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => anyhow::bail!("unclosed {{{} in {:?}", name, template),
+                        }
+                    }
+                    let field = Field::parse(&name)
+                        .ok_or_else(|| anyhow::anyhow!("unknown placeholder {{{}}} in {:?}, expected one of {}", name, template, FIELDS))?;
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(Part::Field(field));
+                }
+                '}' => anyhow::bail!("unmatched }} in {:?}, write }}}} for a brace", template),
+                _ => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        Ok(Self { parts })
+    }
+    
+    /// `user`'s line
+    pub fn render(&self, user: &UserState) -> String {
+        self.parts.iter()
+            .map(|part| match part {
+                Part::Text(text) => text.clone(),
+                Part::Field(field) => field.render(user),
+            })
+            .collect()
+    }
+}
+
+impl TryFrom<String> for LineTemplate {
+    type Error = anyhow::Error;
+    
+    fn try_from(template: String) -> Result<Self> {
+        Self::parse(&template)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_render() {
+        let mut user = UserState::new("alice".to_string());
+        user.playlist_position = 41;
+        user.playback_time = 83.0;
+        user.current_file_name = Some("p42.png".to_string());
+        
+        let template = LineTemplate::parse("{user} p.{page} {status} {{{file}}} {time}").unwrap();
+        assert_eq!(template.render(&user), "alice p.42 ⏸ {p42.png} 01:23");
+        user.is_paused = false;
+        assert_eq!(LineTemplate::parse("{status}{rtt}").unwrap().render(&user), "▶");
+        
+        assert!(LineTemplate::parse("{name}").is_err());
+        assert!(LineTemplate::parse("{user").is_err());
+        assert!(LineTemplate::parse("user}").is_err());
+    }
+}
@@ -0,0 +1,86 @@
+//! How many terminal columns text takes, for lining up tables
+//!
+//! CJK characters and most emoji take two columns, combining marks and
+//! joiners none, as `unicode-width` reckons them.
+
+use crate::terminal;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// Columns the header rules span unless a row is wider
+pub const RULE_WIDTH: usize = 60;
+
+/// Marks where text was cut short
+const ELLIPSIS: &str = "…";
+
+/// Columns one character takes
+fn char_width(c: char) -> usize {
+    UnicodeWidthChar::width(c).unwrap_or(0)
+}
+
+/// Columns `text` takes
+pub fn width(text: &str) -> usize {
+    UnicodeWidthStr::width(text)
+}
+
+/// `text` followed by spaces up to `columns`
+pub fn pad(text: &str, columns: usize) -> String {
+    format!("{}{}", text, " ".repeat(columns.saturating_sub(width(text))))
+}
+
+/// `text` cut to at most `columns`, ending in an ellipsis if anything was left out
+pub fn truncate(text: &str, columns: usize) -> String {
+    if width(text) <= columns {
+        return text.to_string();
+    }
+    
+    let mut kept = String::new();
+    let mut used = 0;
+    for c in text.chars() {
+        let c_width = char_width(c);
+        if used + c_width + 1 > columns {
+            break;
+        }
+        kept.push(c);
+        used += c_width;
+    }
+    if columns > 0 {
+        kept.push_str(terminal::decoration(ELLIPSIS));
+    }
+    kept
+}
+
+/// A rule of `=` under a header, as wide as the widest of `lines`
+pub fn rule(lines: impl IntoIterator<Item = impl AsRef<str>>) -> String {
+    let columns = lines.into_iter().map(|line| width(line.as_ref())).max().unwrap_or(0).max(RULE_WIDTH);
+    "=".repeat(columns)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_width() {
+        assert_eq!(width("alice"), 5);
+        assert_eq!(width("ページ 1"), 8);
+        assert_eq!(width("🐱 bob"), 6);
+        assert_eq!(width("👍🏽"), 2);
+        assert_eq!(width("e\u{301}"), 1); // Combining accent
+        assert_eq!(width("⏸ ▶"), 3);
+    }
+    
+    #[test]
+    fn test_pad_and_truncate() {
+        assert_eq!(pad("猫", 4), "猫  ");
+        assert_eq!(pad("toolong", 3), "toolong");
+        
+        assert_eq!(truncate("short", 10), "short");
+        assert_eq!(truncate("chapter-01.png", 8), "chapter…");
+        // A wide character that doesn't fit whole is left out
+        assert_eq!(truncate("第一章第二章", 6), "第一…");
+        assert_eq!(width(&truncate("第一章第二章", 6)), 5);
+        
+        assert_eq!(rule(["short"]).len(), RULE_WIDTH);
+        assert_eq!(rule(["漢".repeat(40)]).len(), 80);
+    }
+}
@@ -0,0 +1,192 @@
+//! Playback-time synchronization for video sessions
+//!
+//! Clients that fall behind the furthest-ahead viewer on the same file seek
+//! forward to catch up; nobody is ever pulled backwards.
+
+use super::protocol::UserState;
+
+/// Reports older than this are assumed to come from a skewed clock and get no compensation
+const MAX_COMPENSATION_MS: u64 = 5000;
+
+/// Where a playing user probably is now, given when their state was sampled
+pub fn estimated_playback_time(state: &UserState, now_ms: u64) -> f64 {
+    if state.is_paused {
+        return state.playback_time;
+    }
+    
+    let age_ms = now_ms.saturating_sub(state.timestamp_ms);
+    if age_ms > MAX_COMPENSATION_MS {
+        return state.playback_time;
+    }
+    
+    state.playback_time + age_ms as f64 / 1000.0
+}
+
+/// Drift (seconds) at which playback speed starts being nudged
+const NUDGE_START: f64 = 0.3;
+
+/// Drift at which a nudge is considered done and speed returns to normal
+const NUDGE_STOP: f64 = 0.1;
+
+/// Speed change applied while nudging (5% is hard to notice)
+const NUDGE_AMOUNT: f64 = 0.05;
+
+/// Jumps larger than playback alone could explain by this margin count as seeks
+const SEEK_DETECT_SLACK: f64 = 1.5;
+
+/// Whether moving from `previous` to `current` seconds over `elapsed` seconds must have been a seek
+pub fn is_seek(previous: f64, current: f64, elapsed: f64) -> bool {
+    (current - previous).abs() > elapsed + SEEK_DETECT_SLACK
+}
+
+/// Seconds a seek message spent in flight
+///
+/// `round_trip_ms` is the sender's round trip to the server plus ours, when
+/// both are known; the seek took half of that to arrive. Otherwise the
+/// sender's timestamp is trusted, which works because clients set their
+/// clocks by the server's while connecting.
+pub fn seek_delay(sent_at_ms: u64, now_ms: u64, round_trip_ms: Option<u64>) -> f64 {
+    if let Some(round_trip_ms) = round_trip_ms {
+        return (round_trip_ms.min(MAX_COMPENSATION_MS) / 2) as f64 / 1000.0;
+    }
+    
+    let age_ms = now_ms.saturating_sub(sent_at_ms);
+    if age_ms > MAX_COMPENSATION_MS {
+        return 0.0;
+    }
+    age_ms as f64 / 1000.0
+}
+
+/// How far `own` is ahead (positive) or behind (negative) the average playing viewer of the same file
+pub fn drift<'a>(own: &UserState, others: impl IntoIterator<Item = &'a UserState>, now_ms: u64) -> Option<f64> {
+    if own.is_paused {
+        return None;
+    }
+    
+    let times: Vec<f64> = others.into_iter()
+        .filter(|other| other.user_id != own.user_id && !other.is_paused)
+        .filter(|other| {
+            other.playlist_position == own.playlist_position
+                && other.current_file_name == own.current_file_name
+        })
+        .map(|other| estimated_playback_time(other, now_ms))
+        .collect();
+    
+    if times.is_empty() {
+        return None;
+    }
+    
+    let average = times.iter().sum::<f64>() / times.len() as f64;
+    Some(estimated_playback_time(own, now_ms) - average)
+}
+
+/// Speed to play at given the current drift, keeping a nudge going until the gap has nearly closed
+pub fn nudge_speed(drift: Option<f64>, current_speed: f64) -> f64 {
+    let Some(drift) = drift else {
+        return 1.0;
+    };
+    
+    if drift.abs() >= NUDGE_START {
+        // Ahead slows down, behind speeds up
+        1.0 - NUDGE_AMOUNT * drift.signum()
+    } else if drift.abs() <= NUDGE_STOP {
+        1.0
+    } else {
+        current_speed
+    }
+}
+
+/// Time to seek to if `own` lags another playing viewer of the same file by more than `threshold` seconds
+pub fn catch_up_target<'a>(
+    own: &UserState,
+    others: impl IntoIterator<Item = &'a UserState>,
+    threshold: f64,
+    now_ms: u64,
+) -> Option<f64> {
+    if own.is_paused {
+        return None;
+    }
+    
+    let own_time = estimated_playback_time(own, now_ms);
+    
+    others.into_iter()
+        .filter(|other| other.user_id != own.user_id && !other.is_paused)
+        .filter(|other| {
+            other.playlist_position == own.playlist_position
+                && other.current_file_name == own.current_file_name
+        })
+        .map(|other| estimated_playback_time(other, now_ms))
+        .filter(|&time| time - own_time > threshold)
+        .max_by(f64::total_cmp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn playing(user: &str, time: f64, sampled_ms: u64) -> UserState {
+        let mut state = UserState::new(user.to_string());
+        state.playback_time = time;
+        state.is_paused = false;
+        state.timestamp_ms = sampled_ms;
+        state
+    }
+    
+    #[test]
+    fn test_estimate_compensates_for_age() {
+        let state = playing("alice", 10.0, 1_000);
+        assert_eq!(estimated_playback_time(&state, 1_500), 10.5);
+        assert_eq!(estimated_playback_time(&state, 60_000), 10.0);
+        
+        let paused = UserState { is_paused: true, ..state };
+        assert_eq!(estimated_playback_time(&paused, 1_500), 10.0);
+    }
+    
+    #[test]
+    fn test_seek_detection_and_delay() {
+        assert!(!is_seek(10.0, 11.0, 1.0));
+        assert!(!is_seek(10.0, 10.0, 1.0));
+        assert!(is_seek(10.0, 40.0, 1.0));
+        assert!(is_seek(40.0, 10.0, 1.0));
+        
+        assert_eq!(seek_delay(1_000, 1_250, None), 0.25);
+        assert_eq!(seek_delay(1_000, 900, None), 0.0);
+        assert_eq!(seek_delay(1_000, 60_000, None), 0.0);
+        
+        // Measured round trips win over timestamps from a skewed clock
+        assert_eq!(seek_delay(1_000, 60_000, Some(120)), 0.06);
+    }
+    
+    #[test]
+    fn test_drift_and_nudge() {
+        let now = 10_000;
+        let me = playing("me", 100.5, now);
+        let others = [playing("alice", 100.0, now), playing("bob", 99.0, now - 1_000)];
+        
+        assert_eq!(drift(&me, &others, now), Some(0.5));
+        assert_eq!(drift(&me, [], now), None);
+        
+        assert_eq!(nudge_speed(Some(0.5), 1.0), 0.95);
+        assert_eq!(nudge_speed(Some(-0.5), 1.0), 1.05);
+        assert_eq!(nudge_speed(Some(0.2), 0.95), 0.95);
+        assert_eq!(nudge_speed(Some(0.2), 1.0), 1.0);
+        assert_eq!(nudge_speed(Some(0.05), 0.95), 1.0);
+        assert_eq!(nudge_speed(None, 1.05), 1.0);
+    }
+    
+    #[test]
+    fn test_catch_up_only_when_behind() {
+        let now = 10_000;
+        let me = playing("me", 100.0, now);
+        let ahead = playing("alice", 102.0, now - 500);
+        let close = playing("bob", 100.8, now);
+        let behind = playing("carol", 90.0, now);
+        
+        assert_eq!(catch_up_target(&me, [&close, &behind], 1.0, now), None);
+        assert_eq!(catch_up_target(&me, [&close, &ahead, &behind], 1.0, now), Some(102.5));
+        
+        let mut other_file = ahead.clone();
+        other_file.playlist_position = 3;
+        assert_eq!(catch_up_target(&me, [&other_file], 1.0, now), None);
+    }
+}
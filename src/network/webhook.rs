@@ -0,0 +1,119 @@
+//! JSON posted to an HTTP endpoint on significant session events (`--webhook`)
+//!
+//! For Discord or Slack, point it at a small relay that reformats the `text`
+//! field for their webhook.
+
+use super::http_client::{self, HttpUrl};
+use super::protocol::unix_millis;
+use anyhow::Result;
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tokio::time::{timeout, Duration};
+use tracing::{debug, warn};
+
+/// How long one delivery may take before it is given up on
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Something worth telling the endpoint about
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum WebhookEvent {
+    UserJoined { user_id: String, users: usize },
+    UserLeft { user_id: String, users: usize },
+    /// Every connected user reached the end of the playlist
+    SessionFinished { users: usize },
+    /// An admin ended the session; `summary` describes it when the server writes one
+    SessionEnded { summary: Option<String> },
+}
+
+impl WebhookEvent {
+    /// One line for a chat relay to post as is
+    pub fn text(&self) -> String {
+        match self {
+            WebhookEvent::UserJoined { user_id, users } => format!("{} joined ({} reading)", user_id, users),
+            WebhookEvent::UserLeft { user_id, users } => format!("{} left ({} reading)", user_id, users),
+            WebhookEvent::SessionFinished { users } => format!("Everyone has finished ({}/{})", users, users),
+            WebhookEvent::SessionEnded { summary: Some(summary) } => format!("Session ended: {}", summary),
+            WebhookEvent::SessionEnded { summary: None } => "Session ended".to_string(),
+        }
+    }
+}
+
+/// The body of each POST
+#[derive(Debug, Serialize)]
+struct Payload<'a> {
+    #[serde(flatten)]
+    event: &'a WebhookEvent,
+    text: String,
+    at_ms: u64,
+}
+
+/// Posts events to one URL in the background, in the order they happened
+#[derive(Debug, Clone)]
+pub struct Webhook {
+    tx: mpsc::UnboundedSender<WebhookEvent>,
+}
+
+impl Webhook {
+    /// Check `url` and start delivering to it
+    pub fn start(url: &str) -> Result<Self> {
+        let url = HttpUrl::parse(url)?;
+        let (tx, mut rx) = mpsc::unbounded_channel::<WebhookEvent>();
+        tokio::spawn(async move {
+            while let Some(event) = rx.recv().await {
+                match timeout(DELIVERY_TIMEOUT, post(&url, &event)).await {
+                    Ok(Ok(())) => debug!("Webhook delivered: {}", event.text()),
+                    Ok(Err(e)) => warn!("Webhook delivery failed: {:#}", e),
+                    Err(_) => warn!("Webhook delivery to {} timed out", url),
+                }
+            }
+        });
+        Ok(Self { tx })
+    }
+    
+    /// Queue `event` for delivery
+    pub fn send(&self, event: WebhookEvent) {
+        let _ = self.tx.send(event);
+    }
+}
+
+/// POST one event, failing unless the endpoint answers with a 2xx status
+async fn post(url: &HttpUrl, event: &WebhookEvent) -> Result<()> {
+    let body = serde_json::to_string(&Payload { event, text: event.text(), at_ms: unix_millis() })?;
+    let response = http_client::request("POST", url, None, Some(&body)).await?;
+    anyhow::ensure!(response.is_success(), "{} answered {}", url, response.status);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    
+    #[tokio::test]
+    async fn test_delivery() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let webhook = Webhook::start(&url).unwrap();
+        webhook.send(WebhookEvent::UserJoined { user_id: "alice".into(), users: 2 });
+        
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        let mut buf = [0u8; 1024];
+        while !request.ends_with(b"}") {
+            let n = stream.read(&mut buf).await.unwrap();
+            assert!(n > 0);
+            request.extend_from_slice(&buf[..n]);
+        }
+        stream.write_all(b"HTTP/1.1 204 No Content\r\n\r\n").await.unwrap();
+        drop(stream);
+        
+        let request = String::from_utf8(request).unwrap();
+        assert!(request.starts_with("POST /hook HTTP/1.1\r\n"));
+        let body: serde_json::Value = serde_json::from_str(request.split("\r\n\r\n").nth(1).unwrap()).unwrap();
+        assert_eq!(body["event"], "user-joined");
+        assert_eq!(body["user_id"], "alice");
+        assert_eq!(body["text"], "alice joined (2 reading)");
+    }
+}
@@ -0,0 +1,279 @@
+use super::compression::{self, Compression, MAX_MESSAGE_SIZE};
+use super::protocol::SyncMessage;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// How messages are framed and encoded on a connection
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    /// One JSON object per line (the default, readable with netcat)
+    #[default]
+    Json,
+    /// MessagePack in frames prefixed with their length as a big-endian u32
+    Msgpack,
+}
+
+/// What a connection settled on in the `Hello` / `Welcome` exchange
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WireFormat {
+    pub encoding: Encoding,
+    pub compression: Option<Compression>,
+}
+
+/// Flag byte at the start of a binary frame's body: the payload is DEFLATE compressed
+const FLAG_DEFLATE: u8 = 1;
+
+/// Pick the encoding to use from a client's offer
+pub fn negotiate_encoding(offered: &[Encoding]) -> Encoding {
+    offered.iter().copied().find(|e| *e == Encoding::Msgpack).unwrap_or_default()
+}
+
+/// Encode a message ready to be written to the connection
+///
+/// Binary frames are `[length: u32 BE][flags: u8][payload]`, where the
+/// length covers the flags byte and payload.
+pub fn encode(message: &SyncMessage, format: WireFormat) -> Result<Vec<u8>> {
+    match format.encoding {
+        Encoding::Json => Ok(compression::encode_message(message, format.compression)?.into_bytes()),
+        Encoding::Msgpack => {
+            let payload = rmp_serde::to_vec_named(message)?;
+            let (flags, payload) = match compression::compress(&payload, format.compression) {
+                Some(compressed) => (FLAG_DEFLATE, compressed),
+                None => (0, payload),
+            };
+            
+            let mut frame = Vec::with_capacity(payload.len() + 5);
+            frame.extend_from_slice(&(payload.len() as u32 + 1).to_be_bytes());
+            frame.push(flags);
+            frame.extend_from_slice(&payload);
+            Ok(frame)
+        }
+    }
+}
+
+/// One received message, not yet decoded
+#[derive(Debug)]
+pub struct Frame {
+    bytes: Vec<u8>, // A trimmed line, or a binary frame's flags and payload
+    format: WireFormat,
+}
+
+impl Frame {
+    pub fn decode(&self) -> Result<SyncMessage> {
+        match self.format.encoding {
+            Encoding::Json => compression::decode_message(self.text()?, self.format.compression),
+            Encoding::Msgpack => Ok(rmp_serde::from_slice(&self.payload()?)?),
+        }
+    }
+    
+    /// The message as JSON text, whatever the wire encoding
+    pub fn to_json(&self) -> Result<String> {
+        match self.format.encoding {
+            Encoding::Json => Ok(compression::decode_line(self.text()?, self.format.compression)?.into_owned()),
+            Encoding::Msgpack => Ok(rmp_serde::from_slice::<serde_json::Value>(&self.payload()?)?.to_string()),
+        }
+    }
+    
+    /// Short printable form for log messages about frames that failed to decode
+    pub fn describe(&self) -> String {
+        match self.format.encoding {
+            Encoding::Json => String::from_utf8_lossy(&self.bytes).into_owned(),
+            Encoding::Msgpack => format!("<{} byte frame>", self.bytes.len()),
+        }
+    }
+    
+    fn text(&self) -> Result<&str> {
+        std::str::from_utf8(&self.bytes).context("Message is not UTF-8")
+    }
+    
+    /// A binary frame's payload, decompressed
+    fn payload(&self) -> Result<Cow<'_, [u8]>> {
+        let (&flags, payload) = self.bytes.split_first().context("Empty frame")?;
+        match flags {
+            0 => Ok(Cow::Borrowed(payload)),
+            FLAG_DEFLATE => {
+                let compression = self.format.compression.context("Compressed frame, but compression wasn't agreed")?;
+                Ok(Cow::Owned(compression::decompress(payload, compression)?))
+            }
+            other => anyhow::bail!("Unknown frame flags 0x{:02x}", other),
+        }
+    }
+}
+
+/// Splits a connection into frames
+///
+/// `next_frame` only ever awaits a plain read into an internal buffer, so
+/// it is cancel safe and can sit in a `select!` without losing data.
+pub struct FrameReader<R> {
+    reader: R,
+    buffer: Vec<u8>,
+    format: WireFormat,
+    limit: usize,
+    scanned: usize, // Bytes at the front of the buffer already searched for a newline
+}
+
+impl<R: AsyncRead + Unpin> FrameReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self::with_limit(reader, MAX_MESSAGE_SIZE)
+    }
+    
+    /// A reader that fails as soon as a frame is longer than `limit` bytes,
+    /// without buffering the rest of it
+    pub fn with_limit(reader: R, limit: usize) -> Self {
+        Self { reader, buffer: Vec::new(), format: WireFormat::default(), limit, scanned: 0 }
+    }
+    
+    /// Read frames after the ones already returned in this format
+    pub fn set_format(&mut self, format: WireFormat) {
+        self.format = format;
+        self.scanned = 0;
+    }
+    
+    /// The next frame, or `None` once the peer closed the connection
+    pub async fn next_frame(&mut self) -> Result<Option<Frame>> {
+        loop {
+            if let Some(frame) = self.split_frame()? {
+                return Ok(Some(frame));
+            }
+            
+            self.buffer.reserve(4096);
+            if self.reader.read_buf(&mut self.buffer).await? == 0 {
+                // A last line without a newline still counts
+                let rest = std::mem::take(&mut self.buffer);
+                self.scanned = 0;
+                let rest = rest.trim_ascii();
+                if self.format.encoding == Encoding::Json && !rest.is_empty() {
+                    return Ok(Some(Frame { bytes: rest.to_vec(), format: self.format }));
+                }
+                return Ok(None);
+            }
+        }
+    }
+    
+    /// Take one complete frame off the front of the buffer, if there is one
+    fn split_frame(&mut self) -> Result<Option<Frame>> {
+        match self.format.encoding {
+            Encoding::Json => {
+                while let Some(offset) = self.buffer[self.scanned..].iter().position(|&b| b == b'\n') {
+                    let end = self.scanned + offset;
+                    anyhow::ensure!(end <= self.limit, "Message longer than {} bytes", self.limit);
+                    self.scanned = 0;
+                    let line: Vec<u8> = self.buffer.drain(..=end).collect();
+                    let line = line.trim_ascii();
+                    if !line.is_empty() {
+                        return Ok(Some(Frame { bytes: line.to_vec(), format: self.format }));
+                    }
+                }
+                self.scanned = self.buffer.len();
+                anyhow::ensure!(self.buffer.len() <= self.limit, "Message longer than {} bytes", self.limit);
+                Ok(None)
+            }
+            Encoding::Msgpack => {
+                let Some(header) = self.buffer.first_chunk::<4>() else {
+                    return Ok(None);
+                };
+                let len = u32::from_be_bytes(*header) as usize;
+                anyhow::ensure!(len > 0 && len <= self.limit, "Invalid frame length {}", len);
+                if self.buffer.len() < 4 + len {
+                    return Ok(None);
+                }
+                
+                let bytes = self.buffer[4..4 + len].to_vec();
+                self.buffer.drain(..4 + len);
+                Ok(Some(Frame { bytes, format: self.format }))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::protocol::{SyncEvent, UserState};
+    
+    #[tokio::test]
+    async fn test_frames_round_trip() {
+        let mut state = UserState::new("alice".to_string());
+        state.current_file_name = Some("line\nbreak.png".to_string());
+        let small = SyncMessage::state_update(state.clone(), 1);
+        state.current_file_name = Some("long-name-".repeat(50));
+        let large = SyncMessage::state_update(state, 2);
+        let stats = SyncMessage::new(SyncEvent::Stats { users: Default::default() }, 3);
+        
+        let json = WireFormat::default();
+        let binary = WireFormat { encoding: Encoding::Msgpack, compression: Some(Compression::Deflate) };
+        
+        // A JSON line, then the switch to binary frames halfway through the stream
+        let mut stream = encode(&small, json).unwrap();
+        stream.extend(encode(&small, binary).unwrap());
+        stream.extend(encode(&large, binary).unwrap());
+        stream.extend(encode(&stats, binary).unwrap());
+        
+        let mut reader = FrameReader::new(stream.as_slice());
+        let first = reader.next_frame().await.unwrap().unwrap();
+        assert_eq!(first.decode().unwrap().sequence, 1);
+        
+        reader.set_format(binary);
+        let second = reader.next_frame().await.unwrap().unwrap();
+        let printed: serde_json::Value = serde_json::from_str(&second.to_json().unwrap()).unwrap();
+        assert_eq!(printed, serde_json::to_value(&small).unwrap());
+        
+        let third = reader.next_frame().await.unwrap().unwrap();
+        assert_eq!(third.bytes[0], FLAG_DEFLATE);
+        assert_eq!(third.decode().unwrap().sequence, 2);
+        
+        assert_eq!(reader.next_frame().await.unwrap().unwrap().decode().unwrap().sequence, 3);
+        assert!(reader.next_frame().await.unwrap().is_none());
+    }
+    
+    #[tokio::test]
+    async fn test_msgpack_keeps_every_event_intact() {
+        let binary = WireFormat { encoding: Encoding::Msgpack, compression: None };
+        let messages = [
+            SyncMessage::user_joined("alice".to_string(), UserState::new("alice".to_string()), 1),
+            SyncMessage::property("alice".to_string(), "video-zoom", serde_json::json!(-0.5), 2),
+            SyncMessage::property("alice".to_string(), "vf", serde_json::json!({ "rotate": [90, null] }), 3),
+            SyncMessage::new(SyncEvent::Sealed { user_id: "alice".to_string(), reliable: true, payload: "AAAA".to_string() }, u64::MAX),
+        ];
+        let mut stream = Vec::new();
+        for message in &messages {
+            stream.extend(encode(message, binary).unwrap());
+        }
+        
+        let mut reader = FrameReader::new(stream.as_slice());
+        reader.set_format(binary);
+        for message in &messages {
+            let decoded = reader.next_frame().await.unwrap().unwrap().decode().unwrap();
+            assert_eq!(serde_json::to_value(&decoded).unwrap(), serde_json::to_value(message).unwrap());
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_rejects_oversized_frames() {
+        let mut reader = FrameReader::new(&[0xff, 0xff, 0xff, 0xff, 0x00][..]);
+        reader.set_format(WireFormat { encoding: Encoding::Msgpack, compression: None });
+        assert!(reader.next_frame().await.is_err());
+        
+        let mut reader = FrameReader::with_limit(&[0x00, 0x00, 0x01, 0x00, 0x00][..], 100);
+        reader.set_format(WireFormat { encoding: Encoding::Msgpack, compression: None });
+        assert!(reader.next_frame().await.is_err());
+    }
+    
+    #[tokio::test]
+    async fn test_json_lines_over_the_limit() {
+        let mut stream = b"{}\n".to_vec();
+        stream.extend(vec![b' '; 50]);
+        stream.extend(b"{}\n");
+        let mut reader = FrameReader::with_limit(stream.as_slice(), 100);
+        assert!(reader.next_frame().await.unwrap().is_some());
+        assert!(reader.next_frame().await.unwrap().is_some());
+        
+        // Fails once the line outgrows the limit, long before it ends
+        let endless = tokio_test::io::Builder::new().read(b"{\"a\":\"").read(&[b'x'; 64]).read(&[b'x'; 64]).build();
+        let mut reader = FrameReader::with_limit(endless, 100);
+        assert!(reader.next_frame().await.is_err());
+    }
+}
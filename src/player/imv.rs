@@ -0,0 +1,228 @@
+//! imv backend: page sync through a lightweight image viewer
+//!
+//! imv listens for commands on `$XDG_RUNTIME_DIR/imv-<pid>.sock` but never
+//! answers on it. To read the current page we have it run a shell command
+//! that writes `$imv_current_index` to a file, and read that back.
+
+use super::MediaPlayer;
+use crate::media::MediaSource;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use tokio::io::AsyncWriteExt;
+use tokio::net::UnixStream;
+use tokio::time::{sleep, Duration, Instant};
+use tracing::{debug, error, info, warn};
+
+/// How long imv gets to report the current page before the read fails
+const QUERY_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// imv showing a playlist of local images
+pub struct ImvPlayer {
+    process: Child,
+    binary: PathBuf,
+    media_files: Vec<MediaSource>,
+    report_path: PathBuf,
+    next_token: u64,
+}
+
+impl ImvPlayer {
+    /// Start imv on `media_files`, which must all be local files
+    pub async fn launch(media_files: &[MediaSource], binary: Option<&Path>) -> Result<Self> {
+        let binary = binary.map_or_else(|| PathBuf::from("imv"), Path::to_path_buf);
+        let process = Self::spawn_process(&binary, media_files)?;
+        
+        let mut player = Self {
+            process,
+            binary,
+            media_files: media_files.to_vec(),
+            report_path: std::env::temp_dir().join(format!("syncread_imv_{}.pos", std::process::id())),
+            next_token: 1,
+        };
+        player.wait_for_socket().await?;
+        Ok(player)
+    }
+    
+    fn spawn_process(binary: &Path, media_files: &[MediaSource]) -> Result<Child> {
+        let mut cmd = Command::new(binary);
+        for file in media_files {
+            let Some(path) = file.as_path() else {
+                anyhow::bail!("imv can only open local files, not {}", file);
+            };
+            cmd.arg(path);
+        }
+        
+        cmd.stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .with_context(|| format!("Failed to start {}", binary.display()))
+    }
+    
+    fn socket_path(&self) -> PathBuf {
+        socket_path_for(self.process.id())
+    }
+    
+    async fn wait_for_socket(&mut self) -> Result<()> {
+        let socket_path = self.socket_path();
+        info!("Waiting for imv IPC at: {}", socket_path.display());
+        
+        for _ in 0..50 { // 5 second timeout
+            if UnixStream::connect(&socket_path).await.is_ok() {
+                info!("Connected to imv IPC");
+                return Ok(());
+            }
+            if let Some(status) = self.exit_status() {
+                anyhow::bail!("imv exited before opening its IPC socket ({})", status);
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+        
+        anyhow::bail!("imv IPC socket not ready after 5 seconds: {}", socket_path.display())
+    }
+    
+    /// Send one command, the way `imv-msg` does: connect, write a line, hang up
+    async fn send(&mut self, command: &str) -> Result<()> {
+        debug!("imv command: {}", command);
+        let mut stream = UnixStream::connect(self.socket_path()).await
+            .context("Failed to connect to imv")?;
+        stream.write_all(format!("{}\n", command).as_bytes()).await?;
+        stream.shutdown().await?;
+        Ok(())
+    }
+}
+
+/// The IPC socket imv opens for the process `pid`
+pub fn socket_path_for(pid: u32) -> PathBuf {
+    let base = std::env::var_os("XDG_RUNTIME_DIR").map_or_else(|| PathBuf::from("/tmp"), PathBuf::from);
+    base.join(format!("imv-{}.sock", pid))
+}
+
+/// Quote `text` as a single word for `sh`
+fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', r"'\''"))
+}
+
+/// The playlist index in a report line, if it answers the query tagged `token`
+///
+/// imv counts from 1; playlist positions count from 0.
+fn parse_report(report: &str, token: u64) -> Option<i32> {
+    report.lines().find_map(|line| {
+        let (tag, index) = line.trim().split_once(' ')?;
+        if tag.parse::<u64>().ok()? != token {
+            return None;
+        }
+        index.parse::<i32>().ok().map(|index| index - 1)
+    })
+}
+
+#[async_trait]
+impl MediaPlayer for ImvPlayer {
+    async fn get_position(&mut self) -> Result<f64> {
+        Ok(0.0)
+    }
+    
+    async fn get_playlist_pos(&mut self) -> Result<i32> {
+        let token = self.next_token;
+        self.next_token += 1;
+        
+        let report = shell_quote(&self.report_path.to_string_lossy());
+        self.send(&format!("exec echo {} \"$imv_current_index\" > {}", token, report)).await?;
+        
+        let deadline = Instant::now() + QUERY_TIMEOUT;
+        loop {
+            let content = tokio::fs::read_to_string(&self.report_path).await.unwrap_or_default();
+            if let Some(index) = parse_report(&content, token) {
+                return Ok(index);
+            }
+            if Instant::now() >= deadline {
+                anyhow::bail!("imv didn't report its current image");
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+    }
+    
+    /// Images don't play, so imv is always paused as far as sync is concerned
+    async fn is_paused(&mut self) -> Result<bool> {
+        Ok(true)
+    }
+    
+    async fn play(&mut self) -> Result<()> {
+        Ok(())
+    }
+    
+    async fn pause(&mut self) -> Result<()> {
+        Ok(())
+    }
+    
+    async fn seek_to(&mut self, _seconds: f64) -> Result<()> {
+        Ok(())
+    }
+    
+    async fn set_playlist_pos(&mut self, index: i32) -> Result<()> {
+        self.send(&format!("goto {}", index + 1)).await
+    }
+    
+    /// imv has no on-screen messages; they go to the log instead
+    async fn show_text(&mut self, text: &str, _duration_ms: i64) -> Result<()> {
+        info!("{}", text);
+        Ok(())
+    }
+    
+    async fn quit(&mut self) {
+        let _ = self.send("quit").await;
+        for _ in 0..20 {
+            if self.exit_status().is_some() {
+                return;
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+        warn!("imv did not exit after quit command");
+    }
+    
+    fn exit_status(&mut self) -> Option<ExitStatus> {
+        self.process.try_wait().ok().flatten()
+    }
+    
+    /// Start a fresh imv on the original playlist (it opens at the first image)
+    async fn restart(&mut self) -> Result<()> {
+        if self.exit_status().is_none() {
+            let _ = self.process.kill();
+            let _ = self.process.wait();
+        }
+        self.process = Self::spawn_process(&self.binary, &self.media_files)?;
+        self.wait_for_socket().await
+    }
+}
+
+impl Drop for ImvPlayer {
+    fn drop(&mut self) {
+        if self.exit_status().is_none() {
+            if let Err(e) = self.process.kill() {
+                error!("Failed to kill imv process: {}", e);
+            }
+            let _ = self.process.wait();
+        }
+        let _ = std::fs::remove_file(&self.report_path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_parse_report() {
+        assert_eq!(parse_report("7 3\n", 7), Some(2));
+        assert_eq!(parse_report("6 3\n", 7), None); // An older query's answer
+        assert_eq!(parse_report("", 7), None);
+        assert_eq!(parse_report("7 \n", 7), None); // Still being written
+    }
+    
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("/tmp/a b"), "'/tmp/a b'");
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+    }
+}
@@ -0,0 +1,178 @@
+use crate::media::MediaSource;
+use crate::mpv::MpvEvent;
+use crate::network::protocol::ViewTransform;
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::Value;
+use std::path::Path;
+use std::process::ExitStatus;
+use tokio::sync::broadcast;
+
+#[cfg(unix)]
+pub mod imv;
+
+/// Which program shows the media (`--viewer`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Viewer {
+    /// MPV, with keybinds, the HUD, spreads and video
+    #[default]
+    Mpv,
+    /// imv, a lightweight image viewer: page sync only
+    Imv,
+}
+
+/// One entry of a file's chapter list
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct Chapter {
+    #[serde(default)]
+    pub title: Option<String>,
+    pub time: f64, // Start, in seconds
+}
+
+/// A viewer that SyncClient can read positions from and steer
+///
+/// MPV is the main implementation (`MpvController`). Only reading the
+/// position and the basic transport controls are required; the rest have
+/// defaults for viewers without the feature, so a simple image viewer
+/// still syncs pages like MPV does.
+#[async_trait]
+pub trait MediaPlayer: Send {
+    /// Playback time in the current file, in seconds (0 for images)
+    async fn get_position(&mut self) -> Result<f64>;
+
+    /// Current page: the playlist index of what's being shown
+    async fn get_playlist_pos(&mut self) -> Result<i32>;
+
+    async fn is_paused(&mut self) -> Result<bool>;
+
+    async fn play(&mut self) -> Result<()>;
+
+    async fn pause(&mut self) -> Result<()>;
+
+    /// Seek to an absolute time in the current file
+    async fn seek_to(&mut self, seconds: f64) -> Result<()>;
+
+    /// Jump to a page
+    async fn set_playlist_pos(&mut self, index: i32) -> Result<()>;
+
+    /// Flash a message over the media for `duration_ms`
+    async fn show_text(&mut self, text: &str, duration_ms: i64) -> Result<()>;
+
+    /// Close the viewer, ending the session
+    async fn quit(&mut self);
+
+    /// Jump to a page and, once it has loaded, to a time within it
+    async fn restore_position(&mut self, index: i32, seconds: f64) -> Result<()> {
+        self.set_playlist_pos(index).await?;
+        if seconds >= 0.5 {
+            self.seek_to(seconds).await?;
+        }
+        Ok(())
+    }
+
+    /// Chapter of the current file being played, if it has chapters
+    async fn get_chapter(&mut self) -> Result<Option<i32>> {
+        Ok(None)
+    }
+    
+    /// The current file's chapters, empty if it has none
+    async fn get_chapters(&mut self) -> Result<Vec<Chapter>> {
+        Ok(Vec::new())
+    }
+    
+    /// Set the playback speed multiplier (1.0 is normal)
+    async fn set_speed(&mut self, _speed: f64) -> Result<()> {
+        Ok(())
+    }
+
+    /// Read any property by its MPV name, for syncing properties SyncClient has no method for
+    async fn get_property(&mut self, name: &str) -> Result<Value> {
+        anyhow::bail!("this viewer has no '{}' property", name)
+    }
+    
+    /// Set any property by its MPV name
+    async fn set_property(&mut self, name: &str, _value: Value) -> Result<()> {
+        anyhow::bail!("this viewer has no '{}' property", name)
+    }
+    
+    /// Keep multi-line ASS markup in a corner until replaced or hidden
+    async fn show_overlay(&mut self, _ass: &str) -> Result<()> {
+        Ok(())
+    }
+
+    /// Remove the text put up by `show_overlay`
+    async fn hide_overlay(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Send a message to a script running inside the viewer, such as the HUD
+    async fn script_message_to(&mut self, _script: &str, _args: &[&str]) -> Result<()> {
+        Ok(())
+    }
+
+    /// Current zoom and pan
+    async fn get_view(&mut self) -> Result<ViewTransform> {
+        Ok(ViewTransform::default())
+    }
+    
+    /// Zoom and pan to show the same crop as someone else
+    async fn set_view(&mut self, _view: ViewTransform) -> Result<()> {
+        Ok(())
+    }
+    
+    /// Save what's on screen (without the OSD) as an image at `path`
+    async fn screenshot(&mut self, _path: &Path) -> Result<()> {
+        anyhow::bail!("this viewer can't take screenshots")
+    }
+    
+    /// Add a file to the playlist at `index`, moving later entries along
+    async fn insert_file(&mut self, _index: usize, _file: MediaSource) -> Result<()> {
+        anyhow::bail!("this viewer can't add to its playlist")
+    }
+
+    /// Take the entry at `index` out of the playlist
+    async fn remove_file(&mut self, _index: usize) -> Result<()> {
+        anyhow::bail!("this viewer can't remove from its playlist")
+    }
+    
+    /// Move the entry at `from` so that it ends up at `to`
+    async fn move_file(&mut self, _from: usize, _to: usize) -> Result<()> {
+        anyhow::bail!("this viewer can't reorder its playlist")
+    }
+    
+    /// Whether pages are shown as double-page spreads
+    fn spreads_enabled(&self) -> bool {
+        false
+    }
+
+    /// Shift spread pairing by one page; false when spreads aren't in use
+    async fn realign_spreads(&mut self) -> Result<bool> {
+        Ok(false)
+    }
+
+    /// Exit status of the viewer's process, if it has exited
+    fn exit_status(&mut self) -> Option<ExitStatus> {
+        None
+    }
+
+    /// Start the viewer again after it crashed, on its original playlist
+    async fn restart(&mut self) -> Result<()> {
+        anyhow::bail!("this viewer can't be restarted")
+    }
+
+    /// Sync actions triggered from inside the viewer since the last call (action name first, then its arguments)
+    fn take_script_actions(&mut self) -> Vec<Vec<String>> {
+        Vec::new()
+    }
+
+    /// Events the viewer sends as they're read, for viewers that send any (MPV's end-file, shutdown, ...)
+    fn subscribe_events(&self) -> Option<broadcast::Receiver<MpvEvent>> {
+        None
+    }
+
+    /// Whether the viewer closed its connection to us, as it does when quitting or crashing
+    fn hung_up(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,117 @@
+use super::{data_dir, load_json, save_json};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// A marked page, shared with everyone reading the same playlist
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Bookmark {
+    pub playlist_position: i32,
+    pub label: Option<String>,
+    pub created_by: String,
+    pub created_at: u64, // Unix timestamp
+}
+
+impl Bookmark {
+    pub fn new(playlist_position: i32, label: Option<String>, created_by: String) -> Self {
+        Self {
+            playlist_position,
+            label,
+            created_by,
+            created_at: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+        }
+    }
+    
+    /// One-line description for OSD and log output ("p.12 alice: cliffhanger")
+    pub fn format_for_display(&self) -> String {
+        match &self.label {
+            Some(label) => format!("p.{} {}: {}", self.playlist_position + 1, self.created_by, label),
+            None => format!("p.{} {}", self.playlist_position + 1, self.created_by),
+        }
+    }
+}
+
+/// Bookmarks per playlist fingerprint, stored as JSON in the data dir
+#[derive(Debug, Clone, Default)]
+pub struct BookmarkStore {
+    path: PathBuf,
+    entries: HashMap<String, Vec<Bookmark>>,
+}
+
+impl BookmarkStore {
+    /// Open the default store (`bookmarks.json` in the data dir)
+    pub fn open_default() -> Result<Self> {
+        Self::open(data_dir().join("bookmarks.json"))
+    }
+    
+    /// Open a store at the given path, starting empty if it doesn't exist
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let entries = load_json(&path)?;
+        Ok(Self { path, entries })
+    }
+    
+    /// Bookmarks for a playlist, ordered by page
+    pub fn get(&self, fingerprint: &str) -> &[Bookmark] {
+        self.entries.get(fingerprint).map(Vec::as_slice).unwrap_or_default()
+    }
+    
+    /// Add a bookmark and write the store to disk
+    ///
+    /// Returns false (and leaves the file alone) if the same page already
+    /// carries the same label, which is how bookmarks replayed on join
+    /// and re-shared ones are ignored.
+    pub fn add(&mut self, fingerprint: &str, bookmark: Bookmark) -> Result<bool> {
+        let bookmarks = self.entries.entry(fingerprint.to_string()).or_default();
+        let duplicate = bookmarks.iter().any(|b| {
+            b.playlist_position == bookmark.playlist_position && b.label == bookmark.label
+        });
+        if duplicate {
+            return Ok(false);
+        }
+        
+        bookmarks.push(bookmark);
+        bookmarks.sort_by_key(|b| (b.playlist_position, b.created_at));
+        
+        save_json(&self.path, &self.entries)?;
+        Ok(true)
+    }
+    
+    /// First bookmark after `page` in a playlist, wrapping around to the first one
+    pub fn next_after(&self, fingerprint: &str, page: i32) -> Option<&Bookmark> {
+        let bookmarks = self.get(fingerprint);
+        bookmarks.iter()
+            .find(|b| b.playlist_position > page)
+            .or_else(|| bookmarks.first())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_add_dedupe_and_reload() {
+        let path = std::env::temp_dir().join(format!("syncread_test_bookmarks_{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        
+        let mut store = BookmarkStore::open(&path).unwrap();
+        assert!(store.add("abc", Bookmark::new(9, Some("fight".into()), "bob".into())).unwrap());
+        assert!(store.add("abc", Bookmark::new(2, None, "alice".into())).unwrap());
+        assert!(!store.add("abc", Bookmark::new(9, Some("fight".into()), "alice".into())).unwrap());
+        
+        let reloaded = BookmarkStore::open(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        
+        let pages: Vec<i32> = reloaded.get("abc").iter().map(|b| b.playlist_position).collect();
+        assert_eq!(pages, vec![2, 9]);
+        assert!(reloaded.get("other").is_empty());
+        
+        assert_eq!(reloaded.next_after("abc", 2).unwrap().playlist_position, 9);
+        assert_eq!(reloaded.next_after("abc", 9).unwrap().playlist_position, 2);
+    }
+}
@@ -0,0 +1,64 @@
+pub mod bookmarks;
+pub mod resume;
+
+pub use bookmarks::{Bookmark, BookmarkStore};
+pub use resume::{ResumeEntry, ResumeStore};
+
+use anyhow::{Context, Result};
+use serde::{de::DeserializeOwned, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Per-user data directory for syncread (created on demand)
+///
+/// Honors `SYNCREAD_DATA_DIR`, then the platform convention:
+/// `$XDG_DATA_HOME/syncread`, `~/Library/Application Support/syncread`,
+/// or `%APPDATA%\syncread`.
+pub fn data_dir() -> PathBuf {
+    if let Some(dir) = std::env::var_os("SYNCREAD_DATA_DIR") {
+        return PathBuf::from(dir);
+    }
+    
+    #[cfg(windows)]
+    let base = std::env::var_os("APPDATA").map(PathBuf::from);
+    
+    #[cfg(target_os = "macos")]
+    let base = std::env::var_os("HOME")
+        .map(|home| PathBuf::from(home).join("Library").join("Application Support"));
+    
+    #[cfg(all(unix, not(target_os = "macos")))]
+    let base = std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("share")));
+    
+    base.unwrap_or_else(std::env::temp_dir).join("syncread")
+}
+
+/// Load a JSON file, returning the default value if it doesn't exist yet
+pub fn load_json<T: DeserializeOwned + Default>(path: &Path) -> Result<T> {
+    if !path.exists() {
+        return Ok(T::default());
+    }
+    
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {:?}", path))?;
+    serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse {:?}", path))
+}
+
+/// Write a JSON file atomically (write to a temp file, then rename)
+pub fn save_json<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory {:?}", parent))?;
+    }
+    
+    let tmp_path = path.with_extension("json.tmp");
+    let content = serde_json::to_string_pretty(value)?;
+    fs::write(&tmp_path, content)
+        .with_context(|| format!("Failed to write {:?}", tmp_path))?;
+    fs::rename(&tmp_path, path)
+        .with_context(|| format!("Failed to replace {:?}", path))?;
+    
+    Ok(())
+}
@@ -0,0 +1,176 @@
+use super::{data_dir, load_json};
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+use tracing::info;
+
+/// Saved progress through one playlist
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResumeEntry {
+    pub playlist_position: i32,
+    pub playback_time: f64,
+    pub finished: bool, // Reached the last item at some point
+    pub files: Vec<String>, // The playlist as opened, to reopen it with `syncread resume`
+    pub updated_at: u64, // Unix timestamp of the last save
+}
+
+/// Progress per playlist fingerprint, kept in an SQLite database in the data dir
+pub struct ResumeStore {
+    db: Mutex<Connection>, // Locked only so the store can be shared with the update task
+}
+
+impl ResumeStore {
+    /// Open the default store (`progress.db` in the data dir), bringing in positions from an old `resume.json`
+    pub fn open_default() -> Result<Self> {
+        let store = Self::open(data_dir().join("progress.db"))?;
+        
+        let legacy = data_dir().join("resume.json");
+        if legacy.exists() {
+            store.import_json(&legacy)?;
+            std::fs::rename(&legacy, legacy.with_extension("json.imported"))
+                .with_context(|| format!("Failed to rename {:?}", legacy))?;
+        }
+        Ok(store)
+    }
+    
+    /// Open a store at the given path, creating it if it doesn't exist yet
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory {:?}", parent))?;
+        }
+    
+        let db = Connection::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+        db.execute_batch(
+            "CREATE TABLE IF NOT EXISTS progress (
+                fingerprint TEXT PRIMARY KEY,
+                playlist_position INTEGER NOT NULL DEFAULT 0,
+                playback_time REAL NOT NULL DEFAULT 0,
+                finished INTEGER NOT NULL DEFAULT 0,
+                files TEXT NOT NULL DEFAULT '[]',
+                updated_at INTEGER NOT NULL
+            )",
+        ).with_context(|| format!("Failed to set up {:?}", path))?;
+        Ok(Self { db: Mutex::new(db) })
+    }
+    
+    /// Saved progress for a playlist, if any
+    pub fn get(&self, fingerprint: &str) -> Option<ResumeEntry> {
+        self.db.lock().unwrap().query_row(
+            "SELECT playlist_position, playback_time, finished, files, updated_at FROM progress WHERE fingerprint = ?1",
+            params![fingerprint],
+            row_to_entry,
+        ).optional().ok().flatten().map(|(_, entry)| entry)
+    }
+    
+    /// The playlist saved most recently that can be reopened, with its fingerprint
+    pub fn most_recent(&self) -> Result<Option<(String, ResumeEntry)>> {
+        self.db.lock().unwrap().query_row(
+            "SELECT playlist_position, playback_time, finished, files, updated_at, fingerprint FROM progress
+             WHERE files != '[]' ORDER BY updated_at DESC LIMIT 1",
+            [],
+            row_to_entry,
+        ).optional().context("Failed to read saved progress")
+    }
+    
+    /// Remember which files make up a playlist, so it can be reopened later
+    pub fn record_files(&mut self, fingerprint: &str, files: &[String]) -> Result<()> {
+        let files = serde_json::to_string(files)?;
+        self.db.lock().unwrap().execute(
+            "INSERT INTO progress (fingerprint, files, updated_at) VALUES (?1, ?2, ?3)
+             ON CONFLICT (fingerprint) DO UPDATE SET files = excluded.files, updated_at = excluded.updated_at",
+            params![fingerprint, files, now()],
+        ).context("Failed to save playlist")?;
+        Ok(())
+    }
+    
+    /// Record a position, and whether the playlist has been finished
+    pub fn record(&mut self, fingerprint: &str, playlist_position: i32, playback_time: f64, finished: bool) -> Result<()> {
+        self.db.lock().unwrap().execute(
+            "INSERT INTO progress (fingerprint, playlist_position, playback_time, finished, updated_at) VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT (fingerprint) DO UPDATE SET playlist_position = excluded.playlist_position,
+                 playback_time = excluded.playback_time, finished = finished OR excluded.finished, updated_at = excluded.updated_at",
+            params![fingerprint, playlist_position, playback_time, finished, now()],
+        ).context("Failed to save position")?;
+        Ok(())
+    }
+    
+    /// Bring in positions saved by versions that kept them in `resume.json`
+    fn import_json(&self, path: &Path) -> Result<()> {
+        #[derive(serde::Deserialize)]
+        struct Legacy {
+            playlist_position: i32,
+            playback_time: f64,
+            updated_at: u64,
+        }
+        
+        let entries: HashMap<String, Legacy> = load_json(path)?;
+        for (fingerprint, entry) in &entries {
+            self.db.lock().unwrap().execute(
+                "INSERT OR IGNORE INTO progress (fingerprint, playlist_position, playback_time, updated_at) VALUES (?1, ?2, ?3, ?4)",
+                params![fingerprint, entry.playlist_position, entry.playback_time, entry.updated_at as i64],
+            ).context("Failed to import saved positions")?;
+        }
+        info!("Imported {} saved positions from {:?}", entries.len(), path);
+        Ok(())
+    }
+}
+
+/// Read `playlist_position, playback_time, finished, files, updated_at` and, if selected, `fingerprint`
+fn row_to_entry(row: &rusqlite::Row) -> rusqlite::Result<(String, ResumeEntry)> {
+    let files: String = row.get(3)?;
+    let entry = ResumeEntry {
+        playlist_position: row.get(0)?,
+        playback_time: row.get(1)?,
+        finished: row.get(2)?,
+        files: serde_json::from_str(&files).unwrap_or_default(),
+        updated_at: row.get::<_, i64>(4)? as u64,
+    };
+    Ok((row.get(5).unwrap_or_default(), entry))
+}
+
+/// Unix time in seconds, as SQLite stores it
+fn now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_record_and_reload() {
+        let path = std::env::temp_dir().join(format!("syncread_test_resume_{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        
+        let mut store = ResumeStore::open(&path).unwrap();
+        assert!(store.get("abc").is_none());
+        store.record("abc", 12, 34.5, false).unwrap();
+        
+        let reloaded = ResumeStore::open(&path).unwrap();
+        let entry = reloaded.get("abc").unwrap();
+        assert_eq!(entry.playlist_position, 12);
+        assert_eq!(entry.playback_time, 34.5);
+        assert!(!entry.finished);
+        
+        // Only playlists with their files saved can be reopened
+        assert!(reloaded.most_recent().unwrap().is_none());
+        store.record_files("abc", &["/scans/p1.png".to_string()]).unwrap();
+        store.record("abc", 13, 0.0, true).unwrap();
+        store.record("abc", 2, 0.0, false).unwrap();
+        let (fingerprint, entry) = store.most_recent().unwrap().unwrap();
+        let _ = std::fs::remove_file(&path);
+        
+        assert_eq!(fingerprint, "abc");
+        assert_eq!(entry.files, vec!["/scans/p1.png".to_string()]);
+        // Going back doesn't undo finishing
+        assert_eq!(entry.playlist_position, 2);
+        assert!(entry.finished);
+    }
+}
@@ -0,0 +1,233 @@
+//! Redrawing the status display in whatever terminal we were started from
+//!
+//! The display clears the screen and colors names with ANSI escapes. Windows
+//! consoles only understand those once virtual terminal processing is turned
+//! on, and legacy consoles, pipes and `TERM=dumb` not at all; there the
+//! display is appended instead of redrawn, without color.
+//!
+//! The client also reads keys from the terminal, which needs it in raw
+//! mode (`RawMode`); while it is, output goes through `Stdout`.
+//!
+//! Emoji and box-drawing decorations come out as ASCII where the terminal
+//! probably can't draw them (`--ascii`, or a locale without UTF-8); the
+//! display and logs pick theirs through `decoration`.
+
+use crate::network::appearance::UserColor;
+use crossterm::cursor::MoveTo;
+use crossterm::terminal::{Clear, ClearType};
+use std::ffi::OsString;
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+
+/// Decorations and what they become in ASCII
+///
+/// Icons in front of a line are looked up with their space so that it goes too.
+const ASCII_DECORATIONS: &[(&str, &str)] = &[
+    ("⚠️", "[!]"), ("⬆️", "^"), ("⬇️", "v"), ("✅", "[ok]"), ("🏁", "[done]"),
+    ("⏸", "||"), ("▶", "> "), ("●", "@"), ("─", "-"), ("—", "-"), ("…", "~"), ("±", "+/-"),
+    ("👤", ">>"), ("📍", "*"), ("💬", "[chat]"), ("📝", "Note:"), ("🔗 ", ""),
+    ("🎬 ", ""), ("📊 ", ""), ("🔑 ", ""), ("🚀 ", ""), ("🌉 ", ""), ("📖 ", ""), ("🧪 ", ""),
+    ("🤝 ", ""), ("🌐 ", ""), ("🔍 ", ""), ("📤 ", ""),
+];
+
+static ASCII: OnceLock<bool> = OnceLock::new();
+
+/// Turn ASCII decorations on or off; only works before the first output
+pub fn set_ascii(ascii: bool) {
+    let _ = ASCII.set(ascii);
+}
+
+/// Whether decorations should be plain ASCII, which they aren't until `set_ascii` says so
+pub fn ascii_only() -> bool {
+    ASCII.get().copied().unwrap_or(false)
+}
+
+/// Whether the terminal can probably draw emoji and box drawing, for when `--ascii` isn't given
+#[cfg(unix)]
+pub fn unicode_terminal() -> bool {
+    // The Linux console's fonts have no emoji even in a UTF-8 locale
+    let console = std::env::var_os("TERM").is_some_and(|term| term == "linux");
+    utf8_locale(|name| std::env::var_os(name)) && !console
+}
+
+/// Legacy consoles are stuck with a code page
+#[cfg(windows)]
+pub fn unicode_terminal() -> bool {
+    !std::io::stdout().is_terminal() || ansi_enabled()
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn unicode_terminal() -> bool {
+    true
+}
+
+/// Whether the locale in effect (`LC_ALL`, then `LC_CTYPE`, then `LANG`) is UTF-8
+#[cfg_attr(not(unix), allow(dead_code))]
+fn utf8_locale(var: impl Fn(&str) -> Option<OsString>) -> bool {
+    ["LC_ALL", "LC_CTYPE", "LANG"].into_iter()
+        .filter_map(var)
+        .find(|value| !value.is_empty())
+        .is_some_and(|locale| {
+            let locale = locale.to_string_lossy().to_ascii_lowercase();
+            locale.contains("utf-8") || locale.contains("utf8")
+        })
+}
+
+/// `decoration` as it should be drawn, in ASCII if the terminal needs it
+///
+/// Only for what the display adds itself: names and chat go out as they were given.
+pub fn decoration(decoration: &'static str) -> &'static str {
+    if ascii_only() {
+        to_ascii(decoration)
+    } else {
+        decoration
+    }
+}
+
+fn to_ascii(decoration: &'static str) -> &'static str {
+    ASCII_DECORATIONS.iter()
+        .find(|(unicode, _)| *unicode == decoration)
+        .map_or(decoration, |(_, ascii)| ascii)
+}
+
+/// Whether stdout understands ANSI escapes, turning them on in Windows consoles the first time
+pub fn ansi_enabled() -> bool {
+    static ANSI: OnceLock<bool> = OnceLock::new();
+    *ANSI.get_or_init(|| {
+        let dumb = std::env::var_os("TERM").is_some_and(|term| term == "dumb");
+        std::io::stdout().is_terminal() && !dumb && enable_virtual_terminal()
+    })
+}
+
+/// Consoles from before Windows 10 can't be switched to virtual terminal processing
+#[cfg(windows)]
+fn enable_virtual_terminal() -> bool {
+    crossterm::ansi_support::supports_ansi()
+}
+
+#[cfg(not(windows))]
+fn enable_virtual_terminal() -> bool {
+    true
+}
+
+/// `text` in `color`, or as it is where the terminal can't show colors
+pub fn paint(color: UserColor, text: &str) -> String {
+    if ansi_enabled() {
+        color.paint(text)
+    } else {
+        text.to_string()
+    }
+}
+
+/// Start drawing the display again: clear the screen, or leave a blank line after the last one
+pub fn clear_screen() {
+    let mut stdout = Stdout;
+    if ansi_enabled() {
+        let _ = crossterm::execute!(stdout, Clear(ClearType::All), MoveTo(0, 0));
+    } else {
+        let _ = writeln!(stdout);
+        let _ = stdout.flush();
+    }
+}
+
+/// Set while a `RawMode` is held
+static RAW: AtomicBool = AtomicBool::new(false);
+
+/// Keys arrive one at a time and aren't echoed while this is held; dropping it restores the terminal
+///
+/// Ctrl+C arrives as a key instead of interrupting, and the terminal no
+/// longer starts a line at `\n` by itself, so output goes through `Stdout`.
+pub struct RawMode(());
+
+impl RawMode {
+    /// Switch the terminal over, or None if stdin isn't one
+    pub fn enable() -> Option<Self> {
+        if !std::io::stdin().is_terminal() {
+            return None;
+        }
+        crossterm::terminal::enable_raw_mode().ok()?;
+        RAW.store(true, Ordering::Relaxed);
+        Some(Self(()))
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = crossterm::terminal::disable_raw_mode();
+        RAW.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Standard output, ending lines with `\r\n` while raw mode is on
+pub struct Stdout;
+
+impl Write for Stdout {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut stdout = io::stdout().lock();
+        if !RAW.load(Ordering::Relaxed) {
+            return stdout.write(buf);
+        }
+        for line in buf.split_inclusive(|&byte| byte == b'\n') {
+            match line.strip_suffix(b"\n") {
+                Some(line) => {
+                    stdout.write_all(line)?;
+                    stdout.write_all(b"\r\n")?;
+                }
+                None => stdout.write_all(line)?,
+            }
+        }
+        Ok(buf.len())
+    }
+    
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+}
+
+/// A display redrawn on a timer, printed again only when it changed if the terminal can't redraw it
+#[derive(Default)]
+pub struct Screen {
+    shown: Option<String>,
+}
+
+impl Screen {
+    pub fn show(&mut self, frame: String) {
+        if !ansi_enabled() && self.shown.as_ref() == Some(&frame) {
+            return;
+        }
+        clear_screen();
+        let _ = write!(Stdout, "{}", frame);
+        let _ = Stdout.flush();
+        self.shown = Some(frame);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_to_ascii() {
+        assert_eq!(format!("{}SyncRead Server - {} In Sync", to_ascii("🎬 "), to_ascii("✅")), "SyncRead Server - [ok] In Sync");
+        assert_eq!(to_ascii("⚠️"), "[!]");
+        assert_eq!(to_ascii("⬆️"), "^");
+        assert_eq!(to_ascii("─"), "-");
+        assert_eq!(to_ascii("⏸"), "||");
+        // Anything else isn't one of ours
+        assert_eq!(to_ascii("🐱"), "🐱");
+        assert_eq!(to_ascii("🎬"), "🎬");
+    }
+    
+    #[test]
+    fn test_utf8_locale() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| vars.iter().find(|(var, _)| *var == name).map(|(_, value)| OsString::from(value))
+        };
+        assert!(utf8_locale(env(&[("LANG", "en_US.UTF-8")])));
+        assert!(utf8_locale(env(&[("LC_ALL", ""), ("LC_CTYPE", "de_DE.utf8"), ("LANG", "C")])));
+        assert!(!utf8_locale(env(&[("LC_ALL", "C"), ("LANG", "en_US.UTF-8")])));
+        assert!(!utf8_locale(env(&[("LANG", "en_US.ISO-8859-1")])));
+        assert!(!utf8_locale(env(&[])));
+    }
+}
@@ -0,0 +1,921 @@
+//! Whole sessions run in-process: a SyncServer and several SyncClients,
+//! each driving a mock MPV instead of a real one.
+#![cfg(unix)]
+
+use async_trait::async_trait;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use syncread::error::{Error, ProtocolError};
+use syncread::media::MediaSource;
+use syncread::mpv::mock::MockMpv;
+use syncread::network::event_log::EventLog;
+use syncread::network::identity::{Identity, KeyPins};
+use syncread::network::protocol::{FileChecksum, PlaylistEdit, SessionState};
+use syncread::network::{request_session, send_admin_command, AdminCommand, AllowList, DuplicateIds, SyncClient, SyncEvent, SyncMessage, SyncServer, UserState};
+use syncread::player::MediaPlayer;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::RwLock;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Duration, Instant};
+
+/// How long a session gets to converge before a test gives up
+const CONVERGE_TIMEOUT: Duration = Duration::from_secs(5);
+
+const PAGES: usize = 5;
+
+/// Properties a client reads from MPV for each state update
+const STATE_QUERIES: usize = 3;
+
+/// A client in the session and the mock MPV it drives
+struct Reader {
+    mpv: MockMpv,
+    session: Arc<RwLock<SessionState>>,
+    task: JoinHandle<syncread::error::Result<()>>,
+}
+
+/// The least a viewer needs to take part: a page number and a pause flag
+#[derive(Clone, Default)]
+struct DummyPlayer {
+    state: Arc<Mutex<(i32, bool)>>,
+}
+
+#[async_trait]
+impl MediaPlayer for DummyPlayer {
+    async fn get_position(&mut self) -> anyhow::Result<f64> {
+        Ok(0.0)
+    }
+    
+    async fn get_playlist_pos(&mut self) -> anyhow::Result<i32> {
+        Ok(self.state.lock().unwrap().0)
+    }
+    
+    async fn is_paused(&mut self) -> anyhow::Result<bool> {
+        Ok(self.state.lock().unwrap().1)
+    }
+    
+    async fn play(&mut self) -> anyhow::Result<()> {
+        self.state.lock().unwrap().1 = false;
+        Ok(())
+    }
+    
+    async fn pause(&mut self) -> anyhow::Result<()> {
+        self.state.lock().unwrap().1 = true;
+        Ok(())
+    }
+    
+    async fn seek_to(&mut self, _seconds: f64) -> anyhow::Result<()> {
+        Ok(())
+    }
+    
+    async fn set_playlist_pos(&mut self, index: i32) -> anyhow::Result<()> {
+        self.state.lock().unwrap().0 = index;
+        Ok(())
+    }
+    
+    async fn show_text(&mut self, _text: &str, _duration_ms: i64) -> anyhow::Result<()> {
+        Ok(())
+    }
+    
+    async fn quit(&mut self) {}
+}
+
+/// Start a server on a free local port
+async fn start_server() -> (SocketAddr, Arc<RwLock<SessionState>>) {
+    start_server_with(|_| {}).await
+}
+
+/// Start a server like `start_server`, with `configure` applied to it first
+async fn start_server_with(configure: impl FnOnce(&mut SyncServer)) -> (SocketAddr, Arc<RwLock<SessionState>>) {
+    let addr = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let mut server = SyncServer::new();
+    server.set_advertise(false);
+    configure(&mut server);
+    let session = server.session_state();
+    tokio::spawn(async move { server.start(&[addr]).await });
+    
+    let deadline = Instant::now() + CONVERGE_TIMEOUT;
+    while tokio::net::TcpStream::connect(addr).await.is_err() {
+        assert!(Instant::now() < deadline, "server never started listening");
+        sleep(Duration::from_millis(20)).await;
+    }
+    (addr, session)
+}
+
+/// Playlist every reader in these tests has open
+fn playlist() -> Vec<MediaSource> {
+    (1..=PAGES).map(|page| MediaSource::Local(PathBuf::from(format!("p{}.png", page)))).collect()
+}
+
+/// Join the session as `user_id`, with `configure` applied to the client first
+async fn join(server: SocketAddr, test: &str, user_id: &str, configure: impl FnOnce(&mut SyncClient)) -> Reader {
+    join_with(server, test, user_id, playlist(), configure).await
+}
+
+/// Join the session as `user_id` with a playlist of their own
+async fn join_with(server: SocketAddr, test: &str, user_id: &str, files: Vec<MediaSource>, configure: impl FnOnce(&mut SyncClient)) -> Reader {
+    let mpv = MockMpv::start(&format!("{}_{}", test, user_id), files.len()).unwrap();
+    let controller = mpv.controller(&files).await.unwrap();
+    
+    let mut client = SyncClient::new(user_id.to_string());
+    client.set_update_interval(Duration::from_millis(100));
+    configure(&mut client);
+    let session = client.session_state();
+    let task = tokio::spawn(async move { client.connect_and_sync(server, Box::new(controller), files, true).await });
+    
+    // Joining reads MPV's state once; what users do only counts once polling has started after that
+    eventually(&format!("{} to start polling MPV", user_id), || mpv.state().queries > 2 * STATE_QUERIES).await;
+    Reader { mpv, session, task }
+}
+
+/// Wait for `check` to hold, failing the test after `CONVERGE_TIMEOUT`
+async fn eventually(what: &str, check: impl Fn() -> bool) {
+    let deadline = Instant::now() + CONVERGE_TIMEOUT;
+    while !check() {
+        assert!(Instant::now() < deadline, "timed out waiting for {}", what);
+        sleep(Duration::from_millis(50)).await;
+    }
+}
+
+/// Page `user_id` is on in `session`, if they're in it
+fn page_of(session: &Arc<RwLock<SessionState>>, user_id: &str) -> Option<i32> {
+    session.try_read().ok()?.users.get(user_id).map(|user| user.playlist_position)
+}
+
+/// Join over a bare connection as `user_id` and return the reason the server gives for turning us away
+async fn rejection(server: SocketAddr, user_id: &str) -> Option<String> {
+    rejection_of(server, SyncMessage::user_joined(user_id.into(), UserState::new(user_id.into()), 1)).await
+}
+
+/// Send `join` over a bare connection and return the reason the server gives for turning us away
+async fn rejection_of(server: SocketAddr, join: SyncMessage) -> Option<String> {
+    let stream = tokio::net::TcpStream::connect(server).await.unwrap();
+    let (reader, mut writer) = stream.into_split();
+    writer.write_all(format!("{}\n", serde_json::to_string(&join).unwrap()).as_bytes()).await.unwrap();
+    let mut lines = BufReader::new(reader).lines();
+    tokio::time::timeout(CONVERGE_TIMEOUT, async {
+        while let Some(line) = lines.next_line().await.unwrap() {
+            if let Ok(SyncMessage { event: SyncEvent::Kicked { reason, .. }, .. }) = serde_json::from_str(&line) {
+                return reason;
+            }
+        }
+        panic!("connection closed without a reason");
+    }).await.expect("timed out waiting to be turned away")
+}
+
+/// Forwards connections to a server, so a test can cut them as if the network dropped
+struct Relay {
+    addr: SocketAddr,
+    accept: JoinHandle<()>,
+    pipes: Arc<Mutex<Vec<JoinHandle<()>>>>,
+}
+
+impl Relay {
+    /// Listen on `addr` and forward everything to `server`
+    async fn start(addr: SocketAddr, server: SocketAddr) -> Self {
+        let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+        let pipes = Arc::new(Mutex::new(Vec::new()));
+        let accepted = pipes.clone();
+        let accept = tokio::spawn(async move {
+            while let Ok((mut client, _)) = listener.accept().await {
+                let pipe = tokio::spawn(async move {
+                    if let Ok(mut upstream) = tokio::net::TcpStream::connect(server).await {
+                        let _ = tokio::io::copy_bidirectional(&mut client, &mut upstream).await;
+                    }
+                });
+                accepted.lock().unwrap().push(pipe);
+            }
+        });
+        Relay { addr, accept, pipes }
+    }
+    
+    /// Drop every connection and stop listening
+    fn cut(&self) {
+        self.accept.abort();
+        for pipe in self.pipes.lock().unwrap().drain(..) {
+            pipe.abort();
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_page_turns_reach_everyone() {
+    let (server, server_session) = start_server().await;
+    let alice = join(server, "pages", "alice", |_| {}).await;
+    let bob = join(server, "pages", "bob", |_| {}).await;
+    
+    eventually("both readers to join", || {
+        page_of(&server_session, "alice") == Some(0) && page_of(&alice.session, "bob") == Some(0)
+    }).await;
+    
+    alice.mpv.set_playlist_pos(3);
+    eventually("alice's page turn to reach bob", || page_of(&bob.session, "alice") == Some(3)).await;
+    assert_eq!(page_of(&server_session, "alice"), Some(3));
+    
+    // Only alice turned the page; bob is still on the first
+    assert_eq!(bob.mpv.state().playlist_pos, 0);
+    assert_eq!(page_of(&alice.session, "bob"), Some(0));
+}
+
+#[tokio::test]
+async fn test_shared_pause() {
+    let (server, _) = start_server().await;
+    let alice = join(server, "pause", "alice", |client| client.set_shared_pause(true)).await;
+    let bob = join(server, "pause", "bob", |client| client.set_shared_pause(true)).await;
+    eventually("both readers to join", || page_of(&alice.session, "bob").is_some()).await;
+    
+    alice.mpv.set_paused(false);
+    eventually("bob to resume with alice", || !bob.mpv.state().paused).await;
+    
+    bob.mpv.set_paused(true);
+    eventually("alice to pause with bob", || alice.mpv.state().paused).await;
+}
+
+#[tokio::test]
+async fn test_passphrase_hides_pages_from_server() {
+    let (server, server_session) = start_server().await;
+    let alice = join(server, "e2e", "alice", |client| client.set_passphrase("tea and biscuits")).await;
+    let bob = join(server, "e2e", "bob", |client| client.set_passphrase("tea and biscuits")).await;
+    let mallory = join(server, "e2e", "mallory", |client| client.set_passphrase("guess")).await;
+    eventually("everyone to join", || page_of(&bob.session, "mallory").is_some() && page_of(&bob.session, "alice").is_some()).await;
+    
+    alice.mpv.set_playlist_pos(3);
+    eventually("alice's page turn to reach bob", || page_of(&bob.session, "alice") == Some(3)).await;
+    assert_eq!(page_of(&server_session, "alice"), Some(0));
+    
+    // With another passphrase, mallory never learns where anyone is
+    assert_eq!(page_of(&mallory.session, "alice"), None);
+}
+
+#[tokio::test]
+async fn test_pinned_user_id_needs_its_key() {
+    let dir = std::env::temp_dir().join(format!("syncread_test_identity_{}", std::process::id()));
+    let pins_path = dir.join("pins");
+    let _ = std::fs::remove_dir_all(&dir);
+    let pins = KeyPins::load(pins_path.clone(), false).unwrap();
+    let (server, server_session) = start_server_with(move |server| server.set_key_pins(pins)).await;
+    
+    let key = Identity::load_or_create(&dir.join("alice.key")).unwrap();
+    let public_key = key.public_key();
+    let _alice = join(server, "identity", "alice", |client| client.set_identity(key)).await;
+    eventually("alice to join", || page_of(&server_session, "alice").is_some()).await;
+    let pinned = std::fs::read_to_string(&pins_path).unwrap();
+    assert!(pinned.contains(&format!("{} alice", public_key)), "{}", pinned);
+    
+    // Someone else claiming to be alice, without her key
+    let reason = rejection(server, "alice").await;
+    let _ = std::fs::remove_dir_all(&dir);
+    assert_eq!(reason.as_deref(), Some("alice has to sign in with their key (--identity)"));
+}
+
+#[tokio::test]
+async fn test_join_must_carry_its_own_state() {
+    let (server, server_session) = start_server().await;
+    let alice = join(server, "join_ids", "alice", |_| {}).await;
+    eventually("alice to join", || page_of(&server_session, "alice").is_some()).await;
+    let page = page_of(&server_session, "alice");
+    
+    // Joining as mallory, with alice's entry on another page
+    let mut state = UserState::new("alice".into());
+    state.playlist_position = 3;
+    let reason = rejection_of(server, SyncMessage::user_joined("mallory".into(), state, 1)).await;
+    assert_eq!(reason.as_deref(), Some("the join's user ids don't match"));
+    
+    sleep(Duration::from_millis(200)).await;
+    assert_eq!(page_of(&server_session, "mallory"), None);
+    assert_eq!(page_of(&server_session, "alice"), page);
+    assert_eq!(page_of(&alice.session, "alice"), page);
+}
+
+#[tokio::test]
+async fn test_allowlist_turns_others_away() {
+    let path = std::env::temp_dir().join(format!("syncread_test_allowlist_{}.toml", std::process::id()));
+    std::fs::write(&path, "users = [\"alice\"]\n").unwrap();
+    let allow_list = AllowList::load(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    let (server, server_session) = start_server_with(move |server| server.set_allow_list(allow_list)).await;
+    
+    let _alice = join(server, "allowlist", "alice", |_| {}).await;
+    eventually("alice to join", || page_of(&server_session, "alice").is_some()).await;
+    assert_eq!(rejection(server, "mallory").await.as_deref(), Some("mallory is not on this server's allowlist"));
+    assert_eq!(page_of(&server_session, "mallory"), None);
+}
+
+#[tokio::test]
+async fn test_turned_away_clients_cannot_send_updates() {
+    let path = std::env::temp_dir().join(format!("syncread_test_allowlist_updates_{}.toml", std::process::id()));
+    std::fs::write(&path, "users = [\"alice\"]\n").unwrap();
+    let allow_list = AllowList::load(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    let (server, server_session) = start_server_with(move |server| server.set_allow_list(allow_list)).await;
+    
+    let alice = join(server, "allowlist_updates", "alice", |_| {}).await;
+    eventually("alice to join", || page_of(&alice.session, "alice").is_some()).await;
+    let page = page_of(&server_session, "alice");
+    
+    let stream = tokio::net::TcpStream::connect(server).await.unwrap();
+    let (reader, mut writer) = stream.into_split();
+    let join = SyncMessage::user_joined("mallory".into(), UserState::new("mallory".into()), 1);
+    writer.write_all(format!("{}\n", serde_json::to_string(&join).unwrap()).as_bytes()).await.unwrap();
+    let mut lines = BufReader::new(reader).lines();
+    tokio::time::timeout(CONVERGE_TIMEOUT, async {
+        while let Some(line) = lines.next_line().await.unwrap() {
+            if let Ok(SyncMessage { event: SyncEvent::Kicked { .. }, .. }) = serde_json::from_str(&line) {
+                return;
+            }
+        }
+        panic!("connection closed without a reason");
+    }).await.expect("timed out waiting to be turned away");
+    
+    // Whether as itself or as someone in the session, nothing it says gets through
+    for user_id in ["mallory", "alice"] {
+        let mut state = UserState::new(user_id.into());
+        state.playlist_position = 3;
+        let update = SyncMessage::state_update(state, 2);
+        let _ = writer.write_all(format!("{}\n", serde_json::to_string(&update).unwrap()).as_bytes()).await;
+    }
+    let closed = tokio::time::timeout(CONVERGE_TIMEOUT, async {
+        while let Ok(Some(_)) = lines.next_line().await {}
+    }).await;
+    assert!(closed.is_ok(), "the server kept the connection open");
+    
+    sleep(Duration::from_millis(200)).await;
+    assert_eq!(page_of(&server_session, "mallory"), None);
+    assert_eq!(page_of(&alice.session, "mallory"), None);
+    assert_eq!(page_of(&server_session, "alice"), page);
+    assert_eq!(page_of(&alice.session, "alice"), page);
+}
+
+#[tokio::test]
+async fn test_joining_again_replaces_the_old_connection() {
+    let (server, server_session) = start_server().await;
+    let stale = join(server, "replace", "alice", |_| {}).await;
+    eventually("alice to join", || page_of(&server_session, "alice").is_some()).await;
+    
+    let alice = join(server, "replace_again", "alice", |_| {}).await;
+    eventually("the old connection to be dropped", || stale.task.is_finished()).await;
+    alice.mpv.set_playlist_pos(2);
+    eventually("the new connection's page turn", || page_of(&server_session, "alice") == Some(2)).await;
+}
+
+#[tokio::test]
+async fn test_duplicate_user_ids_are_renamed_or_rejected() {
+    let (server, server_session) = start_server_with(|server| server.set_duplicate_ids(DuplicateIds::Rename)).await;
+    let alice = join(server, "rename", "alice", |_| {}).await;
+    eventually("alice to join", || page_of(&server_session, "alice").is_some()).await;
+    let second = join(server, "rename_again", "alice", |_| {}).await;
+    second.mpv.set_playlist_pos(1);
+    eventually("alice to see the newcomer renamed", || page_of(&alice.session, "alice (2)") == Some(1)).await;
+    assert_eq!(page_of(&server_session, "alice"), Some(0));
+    
+    let (server, server_session) = start_server_with(|server| server.set_duplicate_ids(DuplicateIds::Reject)).await;
+    let _alice = join(server, "reject", "alice", |_| {}).await;
+    eventually("alice to join", || page_of(&server_session, "alice").is_some()).await;
+    assert_eq!(rejection(server, "alice").await.as_deref(), Some("alice is already in the session"));
+}
+
+#[tokio::test]
+async fn test_event_log_records_the_session() {
+    let path = std::env::temp_dir().join(format!("syncread_test_session_events_{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let log = Arc::new(EventLog::open(&path).unwrap());
+    let server_log = log.clone();
+    let (server, _) = start_server_with(move |server| server.set_event_log(server_log)).await;
+    
+    let alice = join(server, "events", "alice", |_| {}).await;
+    let bob = join(server, "events", "bob", |_| {}).await;
+    eventually("both readers to join", || page_of(&alice.session, "bob").is_some()).await;
+    alice.mpv.set_playlist_pos(2);
+    eventually("bob to see alice turn the page", || page_of(&bob.session, "alice") == Some(2)).await;
+    
+    bob.task.abort();
+    eventually("bob's leave to be logged", || {
+        log.events_since(0).unwrap().iter().any(|event| event.user_id == "bob" && event.kind == "leave")
+    }).await;
+    let events = log.events_since(0).unwrap();
+    let _ = std::fs::remove_file(&path);
+    
+    assert!(events.iter().any(|event| event.user_id == "alice" && event.kind == "join"));
+    assert!(events.iter().any(|event| event.user_id == "alice" && event.kind == "page"
+        && event.page == Some(2) && event.detail.as_deref() == Some("p3.png")));
+}
+
+#[tokio::test]
+async fn test_shutdown_tells_everyone_and_saves_the_session() {
+    let path = std::env::temp_dir().join(format!("syncread_test_shutdown_{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let mut server = SyncServer::new();
+    server.set_advertise(false);
+    server.set_state_file(path.clone());
+    let server_session = server.session_state();
+    
+    // Bound first, as the sockets systemd passes in would be
+    let listeners = SyncServer::bind(&["127.0.0.1:0".parse().unwrap()]).unwrap();
+    let addr = listeners[0].local_addr().unwrap();
+    let server = Arc::new(server);
+    let serving = server.clone();
+    tokio::spawn(async move { serving.serve(listeners).await });
+    
+    let alice = join(addr, "shutdown", "alice", |_| {}).await;
+    alice.mpv.set_playlist_pos(2);
+    eventually("alice's page turn", || page_of(&server_session, "alice") == Some(2)).await;
+    
+    server.shutdown().await;
+    eventually("alice to hear the server is going", || {
+        alice.mpv.state().osd_messages.iter().any(|text| text == "The server is shutting down")
+    }).await;
+    let saved: SessionState = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(saved.users["alice"].playlist_position, 2);
+}
+
+#[tokio::test]
+async fn test_end_session_writes_summary() {
+    let path = std::env::temp_dir().join(format!("syncread_test_summary_{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let summary_path = path.clone();
+    let (server, _) = start_server_with(move |server| {
+        server.set_admin_token("secret".to_string());
+        server.set_summary_path(summary_path).unwrap();
+    }).await;
+    
+    let alice = join(server, "summary", "alice", |_| {}).await;
+    let bob = join(server, "summary", "bob", |_| {}).await;
+    eventually("both readers to join", || page_of(&alice.session, "bob").is_some()).await;
+    alice.mpv.set_playlist_pos(1);
+    eventually("alice's page turn to reach bob", || page_of(&bob.session, "alice") == Some(1)).await;
+    alice.mpv.press(&["note", "nice", "panel"]);
+    eventually("bob to get alice's note", || {
+        bob.session.try_read().is_ok_and(|session| !session.annotations.is_empty())
+    }).await;
+    
+    let reply = send_admin_command(server, "secret", AdminCommand::EndSession).await.unwrap();
+    assert!(reply.contains("2 readers"), "{}", reply);
+    eventually("bob to hear the session ended", || {
+        bob.mpv.state().osd_messages.iter().any(|text| text.contains("The session has ended"))
+    }).await;
+    
+    let summary: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    let _ = std::fs::remove_file(&path);
+    let participants: Vec<&str> = summary["participants"].as_array().unwrap().iter()
+        .map(|participant| participant["user_id"].as_str().unwrap())
+        .collect();
+    assert_eq!(participants, vec!["alice", "bob"]);
+    assert_eq!(summary["transcript"][0]["text"], "nice panel");
+    assert_eq!(summary["transcript"][0]["page"], 2);
+}
+
+#[tokio::test]
+async fn test_status_request_returns_session() {
+    let (server, server_session) = start_server().await;
+    let alice = join(server, "status", "alice", |_| {}).await;
+    alice.mpv.set_playlist_pos(3);
+    eventually("the server to see alice's page", || page_of(&server_session, "alice") == Some(3)).await;
+    
+    let session = request_session(server).await.unwrap();
+    assert_eq!(session.users["alice"].playlist_position, 3);
+    assert!(session.format_table(Duration::from_secs(300), 0).lines().nth(1).unwrap().starts_with("alice  4 "));
+}
+
+#[tokio::test]
+async fn test_leaving_reader_is_removed() {
+    let (server, server_session) = start_server().await;
+    let alice = join(server, "leave", "alice", |_| {}).await;
+    let bob = join(server, "leave", "bob", |_| {}).await;
+    eventually("both readers to join", || page_of(&alice.session, "bob").is_some()).await;
+    
+    bob.task.abort();
+    eventually("bob to leave the server's session", || {
+        server_session.try_read().is_ok_and(|session| !session.users.contains_key("bob"))
+    }).await;
+    eventually("alice to see bob leave", || {
+        alice.session.try_read().is_ok_and(|session| !session.users.contains_key("bob"))
+    }).await;
+    assert_eq!(page_of(&alice.session, "alice"), Some(0));
+}
+
+#[tokio::test]
+async fn test_only_the_host_edits_the_playlist() {
+    let (server, server_session) = start_server().await;
+    let alice = join(server, "host", "alice", |_| {}).await;
+    eventually("alice to become the host", || {
+        alice.session.try_read().is_ok_and(|session| session.host.as_deref() == Some("alice"))
+    }).await;
+    
+    let stream = tokio::net::TcpStream::connect(server).await.unwrap();
+    let (reader, mut writer) = stream.into_split();
+    let join = SyncMessage::user_joined("bob".into(), UserState::new("bob".into()), 1);
+    let edit = SyncMessage::new(SyncEvent::PlaylistEdit { user_id: "bob".into(), edit: PlaylistEdit::Remove { index: 0, name: "p1.png".into() } }, 2);
+    for message in [join, edit] {
+        writer.write_all(format!("{}\n", serde_json::to_string(&message).unwrap()).as_bytes()).await.unwrap();
+    }
+    let mut lines = BufReader::new(reader).lines();
+    let (mut host, mut refusal) = (None, None);
+    tokio::time::timeout(CONVERGE_TIMEOUT, async {
+        while let Some(line) = lines.next_line().await.unwrap() {
+            match serde_json::from_str(&line) {
+                Ok(SyncMessage { event: SyncEvent::Host { user_id }, .. }) => host = Some(user_id),
+                Ok(SyncMessage { event: SyncEvent::Announcement { text }, .. }) => refusal = Some(text),
+                _ => {}
+            }
+            if host.is_some() && refusal.is_some() {
+                return;
+            }
+        }
+        panic!("connection closed");
+    }).await.expect("timed out waiting to be told who the host is");
+    assert_eq!(host.as_deref(), Some("alice"));
+    assert_eq!(refusal.as_deref(), Some("Only the host, alice, can edit the playlist"));
+    
+    // Hosting passes on to whoever has been here longest
+    alice.task.abort();
+    eventually("bob to become the host", || {
+        server_session.try_read().is_ok_and(|session| session.host.as_deref() == Some("bob"))
+    }).await;
+}
+
+#[tokio::test]
+async fn test_full_session_is_a_retryable_error() {
+    let (server, _) = start_server_with(|server| server.set_max_clients(1)).await;
+    let _alice = join(server, "full", "alice", |_| {}).await;
+    
+    let mpv = MockMpv::start("full_bob", PAGES).unwrap();
+    let controller = mpv.controller(&playlist()).await.unwrap();
+    let mut client = SyncClient::new("bob".to_string());
+    let result = tokio::time::timeout(CONVERGE_TIMEOUT, client.connect_and_sync(server, Box::new(controller), playlist(), true)).await;
+    let error = result.expect("bob was never turned away").unwrap_err();
+    assert!(matches!(error, Error::Protocol(ProtocolError::SessionFull)));
+    assert!(error.is_retryable());
+}
+
+#[tokio::test]
+async fn test_closing_mpv_ends_the_session() {
+    let (server, server_session) = start_server().await;
+    let alice = join(server, "close", "alice", |_| {}).await;
+    alice.mpv.set_playlist_pos(3);
+    eventually("alice's page turn", || page_of(&server_session, "alice") == Some(3)).await;
+    
+    // Not our process to watch exit, but MPV says it's quitting
+    alice.mpv.close();
+    eventually("alice's client to stop", || alice.task.is_finished()).await;
+    eventually("alice to leave the server's session", || page_of(&server_session, "alice").is_none()).await;
+    
+    // Hanging up without a word ends it too, rather than sending p.1 from a dead connection
+    let bob = join(server, "close", "bob", |_| {}).await;
+    bob.mpv.set_playlist_pos(2);
+    eventually("bob's page turn", || page_of(&server_session, "bob") == Some(2)).await;
+    bob.mpv.kill();
+    eventually("bob's client to stop", || bob.task.is_finished()).await;
+    assert!(bob.task.await.unwrap().is_ok());
+    assert!(page_of(&server_session, "bob").is_none_or(|page| page == 2));
+}
+
+#[tokio::test]
+async fn test_pause_on_disconnect() {
+    let (server, server_session) = start_server().await;
+    let free = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let relay = Relay::start(free, server).await;
+    let alice = join(relay.addr, "disconnect", "alice", |client| client.set_pause_on_disconnect(true)).await;
+    alice.mpv.set_paused(false);
+    let playing = |session: &Arc<RwLock<SessionState>>| {
+        session.try_read().is_ok_and(|session| session.users.get("alice").is_some_and(|user| !user.is_paused))
+    };
+    eventually("alice to be playing", || playing(&server_session)).await;
+    
+    relay.cut();
+    eventually("alice to be paused", || alice.mpv.state().paused).await;
+    assert!(alice.mpv.state().osd_messages.iter().any(|m| m.starts_with("Lost the connection")));
+    
+    // Same server, back on the same address
+    let _relay = Relay::start(relay.addr, server).await;
+    eventually("alice to resume", || !alice.mpv.state().paused).await;
+    assert!(alice.mpv.state().osd_messages.iter().any(|m| m.starts_with("Reconnected to the session")));
+    eventually("alice to be playing in the session again", || playing(&server_session)).await;
+    assert!(!alice.task.is_finished());
+}
+
+#[tokio::test]
+async fn test_catching_up_after_reconnecting() {
+    let (server, server_session) = start_server().await;
+    let free = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let relay = Relay::start(free, server).await;
+    let alice = join(relay.addr, "catch_up", "alice", |client| client.set_pause_on_disconnect(true)).await;
+    let carol = join(server, "catch_up", "carol", |_| {}).await;
+    eventually("alice to see carol", || page_of(&alice.session, "carol").is_some()).await;
+    
+    // Alice reads on and carol leaves while alice can't hear about it
+    relay.cut();
+    eventually("alice to be paused", || alice.mpv.state().paused).await;
+    alice.mpv.set_playlist_pos(3);
+    carol.task.abort();
+    eventually("carol to leave", || page_of(&server_session, "carol").is_none()).await;
+    sleep(Duration::from_millis(300)).await;
+    
+    let _relay = Relay::start(relay.addr, server).await;
+    eventually("the server to hear where alice is", || page_of(&server_session, "alice") == Some(3)).await;
+    eventually("alice to hear carol left", || page_of(&alice.session, "carol").is_none()).await;
+}
+
+#[tokio::test]
+async fn test_server_keeps_everyone_on_its_copy() {
+    let (server, server_session) = start_server_with(|server| server.set_resync_interval(Some(Duration::from_millis(300)))).await;
+    let alice = join(server, "resync", "alice", |_| {}).await;
+    
+    // An update that can't be right for the playlist it claims is refused
+    let stream = tokio::net::TcpStream::connect(server).await.unwrap();
+    let (_reader, mut writer) = stream.into_split();
+    let mut mallory = UserState::new("mallory".into());
+    mallory.set_playlist(&playlist());
+    let join = SyncMessage::user_joined("mallory".into(), mallory.clone(), 1);
+    mallory.playlist_position = PAGES as i32 + 3;
+    let update = SyncMessage::state_update(mallory, 2);
+    for message in [join, update] {
+        writer.write_all(format!("{}\n", serde_json::to_string(&message).unwrap()).as_bytes()).await.unwrap();
+    }
+    eventually("mallory to join", || page_of(&server_session, "mallory").is_some()).await;
+    sleep(Duration::from_millis(200)).await;
+    assert_eq!(page_of(&server_session, "mallory"), Some(0));
+    
+    // Whatever alice missed turns up with the next full copy of the session
+    let mut ghost = UserState::new("ghost".into());
+    ghost.playlist_position = 2;
+    server_session.write().await.update_user(ghost);
+    eventually("alice to hear of ghost", || page_of(&alice.session, "ghost") == Some(2)).await;
+}
+
+#[tokio::test]
+async fn test_newcomers_see_everyone_at_once() {
+    let (server, server_session) = start_server().await;
+    
+    // Carol joins on p.3 and sends nothing more
+    let stream = tokio::net::TcpStream::connect(server).await.unwrap();
+    let (_reader, mut writer) = stream.into_split();
+    let mut carol = UserState::new("carol".into());
+    carol.playlist_position = 2;
+    let joined = SyncMessage::user_joined("carol".into(), carol, 1);
+    writer.write_all(format!("{}\n", serde_json::to_string(&joined).unwrap()).as_bytes()).await.unwrap();
+    eventually("carol to join", || page_of(&server_session, "carol").is_some()).await;
+    
+    let alice = join(server, "snapshot", "alice", |_| {}).await;
+    eventually("alice to see carol", || page_of(&alice.session, "carol") == Some(2)).await;
+}
+
+#[tokio::test]
+async fn test_other_players_sync_like_mpv() {
+    let (server, _) = start_server().await;
+    let alice = join(server, "dummy", "alice", |_| {}).await;
+    
+    let viewer = DummyPlayer::default();
+    let mut client = SyncClient::new("carol".to_string());
+    client.set_update_interval(Duration::from_millis(100));
+    let carol = client.session_state();
+    let player = Box::new(viewer.clone());
+    tokio::spawn(async move { client.connect_and_sync(server, player, playlist(), true).await });
+    eventually("carol to join", || page_of(&alice.session, "carol") == Some(0)).await;
+    
+    viewer.state.lock().unwrap().0 = 2;
+    eventually("carol's page turn to reach alice", || page_of(&alice.session, "carol") == Some(2)).await;
+    
+    alice.mpv.set_playlist_pos(4);
+    eventually("alice's page turn to reach carol", || page_of(&carol, "alice") == Some(4)).await;
+}
+
+#[tokio::test]
+async fn test_synced_properties() {
+    let (server, _) = start_server().await;
+    let synced = || vec!["sid".to_string(), "playlist-pos".to_string()];
+    let alice = join(server, "properties", "alice", |client| client.set_synced_properties(synced())).await;
+    let bob = join(server, "properties", "bob", |client| client.set_synced_properties(synced())).await;
+    let carol = join(server, "properties", "carol", |_| {}).await;
+    eventually("everyone to join", || page_of(&alice.session, "carol").is_some() && page_of(&carol.session, "bob").is_some()).await;
+    
+    alice.mpv.set_property("sid", serde_json::json!(2));
+    eventually("bob to switch subtitles with alice", || bob.mpv.state().properties.get("sid") == Some(&serde_json::json!(2))).await;
+    
+    bob.mpv.set_playlist_pos(3);
+    eventually("alice to turn the page with bob", || alice.mpv.state().playlist_pos == 3).await;
+    
+    // Carol doesn't sync either, so she keeps her own page and subtitles
+    assert_eq!(carol.mpv.state().playlist_pos, 0);
+    assert_eq!(carol.mpv.state().properties.get("sid"), Some(&serde_json::json!(false)));
+}
+
+#[tokio::test]
+async fn test_chapter_mode() {
+    let (server, _) = start_server().await;
+    let alice = join(server, "chapters", "alice", |client| client.set_chapter_mode(true)).await;
+    let bob = join(server, "chapters", "bob", |_| {}).await;
+    
+    let chapters = serde_json::json!([{ "title": "Opening", "time": 0.0 }, { "title": "The Heist", "time": 600.0 }]);
+    alice.mpv.set_property("chapter-list", chapters);
+    alice.mpv.set_property("chapter", serde_json::json!(1));
+    eventually("alice's chapter to reach bob", || {
+        bob.session.try_read().is_ok_and(|session| {
+            session.users.get("alice").and_then(|alice| alice.chapter.as_ref()).is_some_and(|chapter| chapter.label() == "ch. 2/2 The Heist")
+        })
+    }).await;
+    
+    // Without chapter mode there's nothing to report
+    assert!(bob.session.try_read().is_ok_and(|session| session.users["bob"].chapter.is_none()));
+}
+
+#[tokio::test]
+async fn test_ab_loop_is_shared() {
+    let (server, _) = start_server().await;
+    let alice = join(server, "abloop", "alice", |_| {}).await;
+    let bob = join(server, "abloop", "bob", |_| {}).await;
+    eventually("both readers to join", || page_of(&alice.session, "bob").is_some()).await;
+    
+    alice.mpv.seek(10.0);
+    alice.mpv.press(&["ab-loop"]);
+    eventually("alice to set A", || alice.mpv.state().properties["ab-loop-a"] == serde_json::json!(10.0)).await;
+    alice.mpv.seek(20.0);
+    alice.mpv.press(&["ab-loop"]);
+    
+    eventually("bob to loop with alice", || {
+        let state = bob.mpv.state();
+        state.properties["ab-loop-a"] == serde_json::json!(10.0) && state.properties["ab-loop-b"] == serde_json::json!(20.0)
+    }).await;
+    let state = bob.mpv.state();
+    assert_eq!(state.playback_time, 10.0);
+    assert!(state.osd_messages.contains(&"alice set loop 00:10-00:20".to_string()));
+    
+    // Both readers start the loop from A
+    assert_eq!(alice.mpv.state().playback_time, 10.0);
+}
+
+#[tokio::test]
+async fn test_group_screenshot() {
+    let (server, _) = start_server().await;
+    let dir = std::env::temp_dir().join("syncread_test_screenshots");
+    let alice = join(server, "screenshot", "alice", |client| client.set_screenshot_dir(dir.clone())).await;
+    let bob = join(server, "screenshot", "bob", |client| client.set_screenshot_dir(dir.clone())).await;
+    eventually("both readers to join", || page_of(&alice.session, "bob").is_some()).await;
+    
+    bob.mpv.set_playlist_pos(3);
+    eventually("bob's page turn to reach alice", || page_of(&alice.session, "bob") == Some(3)).await;
+    alice.mpv.press(&["screenshot"]);
+    eventually("bob to take a screenshot too", || !bob.mpv.state().screenshots.is_empty()).await;
+    
+    let (ours, theirs) = (alice.mpv.state().screenshots, bob.mpv.state().screenshots);
+    assert!(ours[0].ends_with("_alice_p1.png"), "{}", ours[0]);
+    assert!(theirs[0].ends_with("_bob_p4.png"), "{}", theirs[0]);
+    
+    // Both are named after the same moment
+    let moment = |path: &str| PathBuf::from(path).file_name().unwrap().to_string_lossy().split('_').next().unwrap().to_string();
+    assert_eq!(moment(&ours[0]), moment(&theirs[0]));
+}
+
+#[tokio::test]
+async fn test_presenter_view_is_followed() {
+    let (server, _) = start_server().await;
+    let alice = join(server, "present", "alice", |client| client.set_presenting(true)).await;
+    let carol = join(server, "present", "carol", |client| client.set_follow_presenter(false)).await;
+    eventually("carol to join", || page_of(&alice.session, "carol").is_some()).await;
+    
+    alice.mpv.set_property("video-zoom", serde_json::json!(1.5));
+    alice.mpv.set_property("video-pan-x", serde_json::json!(-0.25));
+    
+    // Someone joining after the zoom gets the presenter's view too
+    let bob = join(server, "present", "bob", |_| {}).await;
+    eventually("bob to follow alice's view", || {
+        let state = bob.mpv.state();
+        state.properties["video-zoom"] == serde_json::json!(1.5) && state.properties["video-pan-x"] == serde_json::json!(-0.25)
+    }).await;
+    assert_eq!(carol.mpv.state().properties["video-zoom"], serde_json::json!(0.0));
+}
+
+#[tokio::test]
+async fn test_dual_windows_turn_as_one_page() {
+    let (server, server_session) = start_server().await;
+    let bob = join(server, "dual", "bob", |_| {}).await;
+    
+    // alice reads in two windows, the second showing the page after the first's
+    let first = MockMpv::start("dual_alice", PAGES).unwrap();
+    let second = MockMpv::start("dual_alice_next", PAGES).unwrap();
+    let controller = first.controller(&playlist()).await.unwrap()
+        .pair(second.controller(&playlist()).await.unwrap()).await.unwrap();
+    assert_eq!(second.state().playlist_pos, 1);
+    let mut client = SyncClient::new("alice".to_string());
+    client.set_update_interval(Duration::from_millis(100));
+    let _task = tokio::spawn(async move { client.connect_and_sync(server, Box::new(controller), playlist(), true).await });
+    eventually("alice to start polling MPV", || first.state().queries > 2 * STATE_QUERIES).await;
+    
+    // Turning the page in the second window turns the pair; the session only sees the first window's page
+    second.set_playlist_pos(2);
+    eventually("alice's pair to turn", || page_of(&server_session, "alice") == Some(2)).await;
+    assert_eq!((first.state().playlist_pos, second.state().playlist_pos), (2, 3));
+    
+    // Pages turned for alice move both windows
+    bob.mpv.press(&["slideshow", "1"]);
+    eventually("the slideshow to turn alice's pair", || {
+        let (page, next) = (first.state().playlist_pos, second.state().playlist_pos);
+        page != 2 && next == page + 1
+    }).await;
+}
+
+#[tokio::test]
+async fn test_slideshow_turns_pages_for_everyone() {
+    let (server, _) = start_server().await;
+    let alice = join(server, "slideshow", "alice", |_| {}).await;
+    let bob = join(server, "slideshow", "bob", |_| {}).await;
+    eventually("both readers to join", || page_of(&alice.session, "bob").is_some()).await;
+    
+    alice.mpv.set_playlist_pos(2);
+    alice.mpv.press(&["slideshow", "1"]);
+    eventually("the slideshow to turn bob's page", || bob.mpv.state().playlist_pos == 3).await;
+    assert!(bob.mpv.state().osd_messages.contains(&"alice started a slideshow (1s per page)".to_string()));
+    
+    // Pausing holds everyone on the page
+    bob.mpv.press(&["slideshow"]);
+    eventually("alice to hear bob paused", || alice.mpv.state().osd_messages.contains(&"bob paused the slideshow".to_string())).await;
+    let paused_on = alice.mpv.state().playlist_pos;
+    sleep(Duration::from_millis(1500)).await;
+    assert_eq!(alice.mpv.state().playlist_pos, paused_on);
+    
+    alice.mpv.press(&["slideshow", "resume"]);
+    eventually("the slideshow to reach the last page", || {
+        alice.mpv.state().playlist_pos == 4 && bob.mpv.state().playlist_pos == 4
+    }).await;
+    eventually("the end to be announced", || bob.mpv.state().osd_messages.iter().any(|m| m.contains("Slideshow finished"))).await;
+}
+
+#[tokio::test]
+async fn test_playlist_edits_reach_everyone() {
+    let downloads = std::env::temp_dir().join(format!("syncread_test_playlist_edit_{}", std::process::id()));
+    std::fs::create_dir_all(&downloads).unwrap();
+    let extra = downloads.join("extra.png");
+    std::fs::write(&extra, b"").unwrap();
+    
+    let (server, _) = start_server().await;
+    let alice = join(server, "playlist_edit", "alice", |_| {}).await;
+    let bob = join(server, "playlist_edit", "bob", |client| client.set_download_dir(downloads.clone())).await;
+    eventually("both readers to join", || page_of(&alice.session, "bob").is_some()).await;
+    eventually("bob to hear alice is the host", || {
+        bob.session.try_read().is_ok_and(|session| session.host.as_deref() == Some("alice"))
+    }).await;
+    alice.mpv.set_playlist_pos(2);
+    bob.mpv.set_playlist_pos(2);
+    
+    // Bob gets his own copy, found by name in his downloads
+    alice.mpv.press(&["playlist-add", extra.to_str().unwrap()]);
+    eventually("the entry to be added for both", || {
+        alice.mpv.state().playlist_count == PAGES + 1 && bob.mpv.state().playlist_count == PAGES + 1
+    }).await;
+    // The message goes up just after the edit
+    eventually("bob to be told", || bob.mpv.state().osd_messages.contains(&"alice added extra.png".to_string())).await;
+    
+    // Removing a page before ours keeps us on the same file
+    alice.mpv.press(&["playlist-remove", "1"]);
+    eventually("the first page to be removed for both", || {
+        alice.mpv.state().playlist_count == PAGES && bob.mpv.state().playlist_count == PAGES
+    }).await;
+    assert_eq!((alice.mpv.state().playlist_pos, bob.mpv.state().playlist_pos), (1, 1));
+    
+    // Only the host edits; URLs aren't passed on
+    bob.mpv.press(&["playlist-move", "5", "1"]);
+    eventually("bob to be refused", || bob.mpv.state().osd_messages.contains(&"Only the host, alice, can edit the playlist".to_string())).await;
+    alice.mpv.press(&["playlist-add", "https://example.com/more.png"]);
+    eventually("alice to add the URL", || alice.mpv.state().playlist_count == PAGES + 1).await;
+    sleep(Duration::from_millis(200)).await;
+    assert_eq!(bob.mpv.state().playlist_count, PAGES);
+    assert_eq!((alice.mpv.state().playlist_pos, bob.mpv.state().playlist_pos), (1, 1));
+    
+    let _ = std::fs::remove_dir_all(&downloads);
+}
+
+#[tokio::test]
+async fn test_page_turns_follow_file_names() {
+    let (server, _) = start_server().await;
+    let synced = |client: &mut SyncClient| client.set_synced_properties(vec!["playlist-pos".to_string()]);
+    let alice = join(server, "page_names", "alice", synced).await;
+    
+    // Bob's copy has a credits page first and different names
+    let mut files = vec![MediaSource::Local(PathBuf::from("scans/credits.png"))];
+    files.extend((1..=PAGES).map(|page| MediaSource::Local(PathBuf::from(format!("scans/P_{:02}.jpg", page)))));
+    let bob = join_with(server, "page_names", "bob", files, synced).await;
+    eventually("both readers to join", || page_of(&alice.session, "bob").is_some()).await;
+    
+    alice.mpv.set_playlist_pos(2);
+    eventually("bob to turn to the same page", || bob.mpv.state().playlist_pos == 3).await;
+    
+    bob.mpv.set_playlist_pos(5);
+    eventually("alice to turn to the same page", || alice.mpv.state().playlist_pos == 4).await;
+}
+
+#[tokio::test]
+async fn test_checksums_report_different_copies() {
+    let (server, _) = start_server().await;
+    let hashes = |p2: u64| {
+        vec![FileChecksum { name: "p1.png".to_string(), hash: 1 }, FileChecksum { name: "p2.png".to_string(), hash: p2 }]
+    };
+    let alice = join(server, "checksums", "alice", |client| client.set_checksums(hashes(2))).await;
+    let _carol = join(server, "checksums", "carol", |client| client.set_checksums(hashes(2))).await;
+    eventually("alice to hear the copies match", || alice.mpv.state().osd_messages.contains(&"Everyone's copies match".to_string())).await;
+    
+    // Bob's p2 is a different scan
+    let bob = join(server, "checksums", "bob", |client| client.set_checksums(hashes(3))).await;
+    let report = "1 page(s) differ between readers:\np2.png: bob";
+    eventually("alice to hear bob's copy differs", || alice.mpv.state().osd_messages.contains(&report.to_string())).await;
+    eventually("bob to hear his copy differs", || bob.mpv.state().osd_messages.iter().any(|m| m.ends_with("p2.png: you"))).await;
+}